@@ -13,11 +13,11 @@ layout(location = 2) out vec3 out_Tint;
 layout(location = 3) out float out_Radius;
 
 void main() {
-    out_Radius = Rendering.FluidParticleRadius;
+    out_Radius = Rendering.FluidParticleRadiusFactor * FluidParticleSpacing * FluidGridToWorldScale;
     vec3 velocity =
         vec3(ParticleBufferVelocityX[gl_InstanceIndex].w, ParticleBufferVelocityY[gl_InstanceIndex].w, ParticleBufferVelocityZ[gl_InstanceIndex].w);
     out_Tint = colormapHeat(length(velocity) * Rendering.VelocityVisualizationScale);
-    out_ParticleWorldPosition = Particles[gl_InstanceIndex].Position * Rendering.FluidGridToWorldScale + Rendering.FluidWorldOrigin;
+    out_ParticleWorldPosition = Particles[gl_InstanceIndex].Position * FluidGridToWorldScale + FluidWorldOrigin;
     out_WorldPosition = spanParticle(out_ParticleWorldPosition, out_Radius);
     gl_Position = Camera.ViewProjection * vec4(out_WorldPosition, 1.0);
 }
@@ -18,6 +18,12 @@ layout(set = 1, binding = 6) uniform texture3D VelocityVolumeZ;
 layout(set = 1, binding = 7) uniform texture3D MarkerVolume;
 layout(set = 1, binding = 8) uniform texture3D PressureVolume_Velocity;
 layout(set = 1, binding = 9) uniform texture3D PressureVolume_Density;
+// Each fluid domain sits at its own place in the world.
+layout(set = 1, binding = 10) uniform FluidRenderInfo {
+    vec3 FluidWorldOrigin;
+    float FluidGridToWorldScale;
+    float FluidParticleSpacing; // Average distance between particles at rest in grid cells, depends on the domain's particles per cell.
+};
 
 ivec3 getVolumeCoordinate(uint positionIndex) {
     ivec3 volumeSize = textureSize(PressureVolume_Velocity, 0).xyz;
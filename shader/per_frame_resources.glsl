@@ -25,10 +25,20 @@ struct TimerData {
 };
 
 struct GlobalRenderingSettings {
-    vec3 FluidWorldOrigin;
-    float FluidGridToWorldScale;
     float VelocityVisualizationScale;
-    float FluidParticleRadius; // particle size in world space
+    float FluidParticleRadiusFactor; // particle size relative to the distance between particles at rest
+    float FluidParticleRadius;       // particle size in world space of the finest fluid domain (for screen space filtering)
+    float _padding0;
+
+    // See Tonemapper
+    float ExposureEv;                  // log2 of the exposure scale, comes on top of auto exposure if enabled
+    uint TonemapOperator;              // see TonemapOperator
+    uint AutoExposureEnabled;
+    float AutoExposureAdaptationRate;  // in 1/s
+    float AutoExposureMinLogLuminance; // log2 luminance range of the auto exposure histogram
+    float AutoExposureMaxLogLuminance;
+    float ColorGradingLutStrength;     // blend between ungraded (0) and graded (1)
+    float _padding1;
 };
 
 struct ScreenData {
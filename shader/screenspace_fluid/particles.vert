@@ -12,8 +12,8 @@ layout(location = 1) out vec3 out_ParticleWorldPosition;
 layout(location = 2) out float out_Radius;
 
 void main() {
-    out_Radius = Rendering.FluidParticleRadius;
-    out_ParticleWorldPosition = Particles[gl_InstanceIndex].Position * Rendering.FluidGridToWorldScale + Rendering.FluidWorldOrigin;
+    out_Radius = Rendering.FluidParticleRadiusFactor * FluidParticleSpacing * FluidGridToWorldScale;
+    out_ParticleWorldPosition = Particles[gl_InstanceIndex].Position * FluidGridToWorldScale + FluidWorldOrigin;
     out_WorldPosition = spanParticle(out_ParticleWorldPosition, out_Radius);
     gl_Position = Camera.ViewProjection * vec4(out_WorldPosition, 1.0);
 }
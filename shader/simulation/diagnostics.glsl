@@ -0,0 +1,26 @@
+// Simulation diagnostics as written by the diagnostics gather passes (per workgroup) and diagnostics_reduce.comp (final result).
+
+struct VolumeDiagnosticsPartial {
+    uint NumFluidCells;
+    uint NumParticles;
+    float DensityErrorSum;
+    float DensityErrorMax;
+    uint NumFluidCellsDivergence; // Fluid cells before advection, i.e. the ones the divergence was computed for.
+    float DivergenceSquaredSum;
+    float DivergenceMax;
+    float _Dummy;
+};
+
+// Every particle has unit mass, everything is in grid space and angular momentum is around the grid origin.
+struct ParticleDiagnosticsPartial {
+    vec3 LinearMomentum;
+    float KineticEnergy;
+    vec3 AngularMomentum;
+    float _Dummy;
+};
+
+// Needs to match the layout of DiagnosticsResult in diagnostics.rs
+struct DiagnosticsResult {
+    VolumeDiagnosticsPartial Volume;
+    ParticleDiagnosticsPartial Particles;
+};
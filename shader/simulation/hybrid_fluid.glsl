@@ -6,9 +6,18 @@
 layout(set = 1, binding = 0) uniform SimulationProperties {
     vec3 GravityGridSpace;
     uint NumParticles;
+    uint ParticlesPerCell; // Particles a fluid cell starts out with, defines the rest density.
+    uint TransferScheme;
 };
+
+// The gather shaders follow each linked list for at most this many particles.
+#define MAX_PARTICLES_PER_LINKED_LIST (ParticlesPerCell + ParticlesPerCell / 2)
 #endif
 
+// Values of TransferScheme, see TransferScheme in hybrid_fluid.rs
+#define TRANSFER_SCHEME_PIC 0
+#define TRANSFER_SCHEME_APIC 1
+
 // Boundary is zero, so texel fetch outside of the domain always gives us boundary cells.
 #define CELL_SOLID 0.0 // A couple of things rely on this being zero! (sampling images out of bounds returns zero)
 #define CELL_FLUID 1.0
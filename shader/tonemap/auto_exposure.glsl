@@ -0,0 +1,39 @@
+#ifndef INCLUDE_AUTO_EXPOSURE
+#define INCLUDE_AUTO_EXPOSURE
+
+#include "../per_frame_resources.glsl"
+
+// See Tonemapper::AUTO_EXPOSURE_NUM_BINS
+#define AUTO_EXPOSURE_NUM_BINS 128
+
+// Darkest and brightest pixels don't count for the average luminance, so that a few highlights or shadows don't throw off exposure.
+#define AUTO_EXPOSURE_LOW_PERCENTILE 0.1
+#define AUTO_EXPOSURE_HIGH_PERCENTILE 0.9
+
+struct AutoExposureState {
+    float AdaptedLogLuminance; // log2 of the average luminance exposure is adapted to.
+    uint Initialized;          // 0 until the first average is known, which is then taken over without adaption.
+};
+
+float luminance(vec3 linearRgb) { return dot(linearRgb, vec3(0.2126, 0.7152, 0.0722)); }
+
+uint luminanceToHistogramBin(float luminance) {
+    float t = (log2(luminance) - Rendering.AutoExposureMinLogLuminance) /
+              (Rendering.AutoExposureMaxLogLuminance - Rendering.AutoExposureMinLogLuminance);
+    return uint(clamp(t * AUTO_EXPOSURE_NUM_BINS, 0.0, AUTO_EXPOSURE_NUM_BINS - 1));
+}
+
+float histogramBinToLogLuminance(uint bin) {
+    float t = (float(bin) + 0.5) / AUTO_EXPOSURE_NUM_BINS;
+    return mix(Rendering.AutoExposureMinLogLuminance, Rendering.AutoExposureMaxLogLuminance, t);
+}
+
+// Scale factor for the hdr backbuffer. Auto exposure maps the adapted luminance to middle grey, manual exposure comes on top.
+float exposure(AutoExposureState state) {
+    float exposure = exp2(Rendering.ExposureEv);
+    if (Rendering.AutoExposureEnabled != 0 && state.Initialized != 0)
+        exposure *= 0.18 / exp2(state.AdaptedLogLuminance);
+    return exposure;
+}
+
+#endif // INCLUDE_AUTO_EXPOSURE
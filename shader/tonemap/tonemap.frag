@@ -0,0 +1,81 @@
+// Maps the linear hdr backbuffer to the ldr (srgb) backbuffer: exposure, tonemapping operator and color grading lut.
+
+#version 460
+
+#include "../per_frame_resources.glsl"
+#include "../utilities.glsl"
+#include "auto_exposure.glsl"
+
+layout(set = 1, binding = 0) uniform texture2D HdrBackbuffer;
+layout(set = 1, binding = 1) buffer restrict readonly AutoExposureState_ { AutoExposureState State; };
+layout(set = 2, binding = 0) uniform texture3D ColorGradingLut;
+
+layout(location = 0) out vec4 out_Color;
+
+// See TonemapOperator
+#define TONEMAP_OPERATOR_CLAMP 0
+#define TONEMAP_OPERATOR_REINHARD 1
+#define TONEMAP_OPERATOR_UNCHARTED2 2
+#define TONEMAP_OPERATOR_ACES_FILMIC 3
+
+// Reinhard on luminance, so hues stay as they are.
+vec3 tonemapReinhard(vec3 color) { return color / (1.0 + luminance(color)); }
+
+// John Hable's filmic curve from Uncharted 2 (http://filmicworlds.com/blog/filmic-tonemapping-operators/)
+vec3 uncharted2Curve(vec3 x) {
+    const float A = 0.15; // shoulder strength
+    const float B = 0.50; // linear strength
+    const float C = 0.10; // linear angle
+    const float D = 0.20; // toe strength
+    const float E = 0.02; // toe numerator
+    const float F = 0.30; // toe denominator
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+vec3 tonemapUncharted2(vec3 color) {
+    const float ExposureBias = 2.0;
+    const float WhitePoint = 11.2;
+    return uncharted2Curve(color * ExposureBias) / uncharted2Curve(vec3(WhitePoint));
+}
+
+// Stephen Hill's fit of the ACES reference rendering & output transforms (https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl)
+// sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+const mat3 AcesInputMatrix = mat3(0.59719, 0.07600, 0.02840, 0.35458, 0.90834, 0.13383, 0.04823, 0.01566, 0.83777);
+// ODT_SAT => XYZ => D60_2_D65 => sRGB
+const mat3 AcesOutputMatrix = mat3(1.60475, -0.10208, -0.00327, -0.53108, 1.10813, -0.07276, -0.07367, -0.00605, 1.07602);
+vec3 rrtAndOdtFit(vec3 v) {
+    vec3 a = v * (v + 0.0245786) - 0.000090537;
+    vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return a / b;
+}
+vec3 tonemapAcesFilmic(vec3 color) { return AcesOutputMatrix * rrtAndOdtFit(AcesInputMatrix * color); }
+
+vec3 linearToSrgb(vec3 linear) { return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, greaterThan(linear, vec3(0.0031308))); }
+vec3 srgbToLinear(vec3 srgb) { return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), greaterThan(srgb, vec3(0.04045))); }
+
+// Luts are made for srgb encoded in & output. If none is loaded, this is an identity lut.
+vec3 applyColorGradingLut(vec3 color) {
+    float lutSize = float(textureSize(ColorGradingLut, 0).x);
+    // Map to texel centers of the first and last entry.
+    vec3 lutCoord = linearToSrgb(color) * ((lutSize - 1.0) / lutSize) + 0.5 / lutSize;
+    vec3 graded = srgbToLinear(texture(sampler3D(ColorGradingLut, SamplerTrilinearClamp), lutCoord).rgb);
+    return mix(color, graded, Rendering.ColorGradingLutStrength);
+}
+
+void main() {
+    vec4 hdrColor = texelFetch(HdrBackbuffer, ivec2(gl_FragCoord.xy), 0);
+    vec3 color = hdrColor.rgb * exposure(State);
+
+    switch (Rendering.TonemapOperator) {
+    case TONEMAP_OPERATOR_REINHARD:
+        color = tonemapReinhard(color);
+        break;
+    case TONEMAP_OPERATOR_UNCHARTED2:
+        color = tonemapUncharted2(color);
+        break;
+    case TONEMAP_OPERATOR_ACES_FILMIC:
+        color = tonemapAcesFilmic(color);
+        break;
+    }
+
+    out_Color = vec4(applyColorGradingLut(saturate(color)), hdrColor.a);
+}
@@ -29,9 +29,9 @@ void main() {
 
     float marker = texelFetch(MarkerVolume, volumeCoordinate, 0).x;
 
-    vec3 cellCenter = (volumeCoordinate + vec3(0.5)) * Rendering.FluidGridToWorldScale + Rendering.FluidWorldOrigin;
+    vec3 cellCenter = (volumeCoordinate + vec3(0.5)) * FluidGridToWorldScale + FluidWorldOrigin;
     vec3 linePosition = cellCenter;
-    addToChannel(linePosition, 0.5 * Rendering.FluidGridToWorldScale, channel);
+    addToChannel(linePosition, 0.5 * FluidGridToWorldScale, channel);
 
     float velocity = 0.0;
     float neighborMarker = CELL_SOLID;
@@ -57,7 +57,7 @@ void main() {
     if (isnan(velocity))
         scale = 0.0;
     if (gl_VertexIndex == 0) {
-        addToChannel(linePosition, scale * Rendering.FluidGridToWorldScale, channel);
+        addToChannel(linePosition, scale * FluidGridToWorldScale, channel);
     }
 
     out_Color = vec4(colormapCoolToWarm(scale), 1.0);
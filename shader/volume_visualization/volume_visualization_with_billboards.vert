@@ -49,21 +49,21 @@ void main() {
             divergence += computeDivergenceForDirection(volumeCoordinate, VelocityVolumeZ, markerZ0, 2);
         }
 
-        scale = clamp(divergence * 10.0 * Rendering.FluidGridToWorldScale, -1.0, 1.0);
+        scale = clamp(divergence * 10.0 * FluidGridToWorldScale, -1.0, 1.0);
         out_Tint = colormapCoolToWarm(scale);
         break;
 
     case VISUALIZE_PRESSURE_VELOCITY:
         float pressureV = marker == CELL_FLUID ? texelFetch(PressureVolume_Velocity, volumeCoordinate, 0).x : 0.0;
         // pressureV *= 0.1;
-        scale = pressureV * Rendering.FluidGridToWorldScale;
+        scale = pressureV * FluidGridToWorldScale;
         out_Tint = colormapCoolToWarm(pressureV).rgb;
         break;
 
     case VISUALIZE_PRESSURE_DENSITY:
         float pressureD = marker == CELL_FLUID ? texelFetch(PressureVolume_Density, volumeCoordinate, 0).x : 0.0;
         pressureD *= 2.0;
-        scale = pressureD * Rendering.FluidGridToWorldScale;
+        scale = pressureD * FluidGridToWorldScale;
         out_Tint = colormapCoolToWarm(pressureD).rgb;
         break;
 
@@ -78,8 +78,8 @@ void main() {
     }
     scale = saturate(abs(scale));
 
-    out_ParticleWorldPosition = (volumeCoordinate + vec3(0.5)) * Rendering.FluidGridToWorldScale + Rendering.FluidWorldOrigin;
-    out_Radius = scale * 0.5 * Rendering.FluidGridToWorldScale;
+    out_ParticleWorldPosition = (volumeCoordinate + vec3(0.5)) * FluidGridToWorldScale + FluidWorldOrigin;
+    out_Radius = scale * 0.5 * FluidGridToWorldScale;
     out_WorldPosition = spanParticle(out_ParticleWorldPosition, out_Radius);
     gl_Position = Camera.ViewProjection * vec4(out_WorldPosition, 1.0);
 }
@@ -2,7 +2,13 @@ use super::timer::Timer;
 use super::wgpu_utils::uniformbuffer::*;
 use cgmath::prelude::*;
 use enumflags2::BitFlags;
-use winit::event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+use winit::event::{DeviceEvent, ElementState, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent};
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 const OPENGL_PROJECTION_TO_WGPU_PROJECTION: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -12,7 +18,27 @@ const OPENGL_PROJECTION_TO_WGPU_PROJECTION: cgmath::Matrix4<f32> = cgmath::Matri
     0.0, 0.0, 0.5, 1.0,
 );
 
-const VERTICAL_FOV: cgmath::Deg<f32> = cgmath::Deg(80f32);
+const DEFAULT_VERTICAL_FOV: cgmath::Deg<f32> = cgmath::Deg(80f32);
+
+// Distances of the projection's clip planes.
+pub const NEAR_PLANE: f32 = 0.01;
+pub const FAR_PLANE: f32 = 1000.0;
+
+const MIN_ORBIT_DISTANCE: f32 = 0.01;
+// Cosine of the angle between view direction and up beyond which there is no well defined right vector anymore.
+const MAX_UP_ALIGNMENT: f32 = 0.99;
+// Factor the orbit distance is multiplied with per scroll wheel line.
+const ORBIT_ZOOM_PER_LINE: f32 = 0.9;
+// Scroll distance in (logical) pixels that counts as one line for touchpads.
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+
+#[derive(Clone, Copy, Debug, EnumIter, PartialEq, Eq)]
+pub enum CameraMode {
+    // WASD movement, mouse look while the right mouse button is held.
+    Fly,
+    // Rotates around a center while the right mouse button is held, pans with the middle mouse button, zooms with the scroll wheel.
+    Orbit,
+}
 
 #[derive(BitFlags, Copy, Clone, Debug, PartialEq)]
 enum MoveCommands {
@@ -23,17 +49,69 @@ enum MoveCommands {
     SpeedUp = 0b1_0000,
 }
 
+fn default_vertical_fov_degrees() -> f32 {
+    DEFAULT_VERTICAL_FOV.0
+}
+
+// What goes into camera files and scene camera presets, everything else is transient input state.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CameraViewpoint {
+    // Only used for presets.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub position: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+    #[serde(default = "default_vertical_fov_degrees")]
+    pub vertical_fov_degrees: f32,
+}
+
+impl CameraViewpoint {
+    // Parameters that don't describe a usable camera.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.direction.magnitude2() == 0.0 {
+            problems.push("direction can't be zero".to_owned());
+        } else if self.direction.normalize().dot(cgmath::Vector3::unit_y()).abs() >= MAX_UP_ALIGNMENT {
+            // Cameras always rotate around the y axis.
+            problems.push(format!("direction can't point straight up or down, is {:?}", self.direction));
+        }
+        if self.vertical_fov_degrees <= 0.0 || self.vertical_fov_degrees >= 180.0 {
+            problems.push(format!(
+                "vertical_fov_degrees needs to be between 0 and 180, is {}",
+                self.vertical_fov_degrees
+            ));
+        }
+        problems
+    }
+}
+
 pub struct Camera {
     pub position: cgmath::Point3<f32>,
     pub direction: cgmath::Vector3<f32>,
+    pub vertical_fov: cgmath::Deg<f32>,
     rotational_up: cgmath::Vector3<f32>,
 
+    mode: CameraMode,
+    // The orbit center is this far in front of the camera. Not part of the viewpoint, so that jumping to viewpoints works the same in all modes.
+    orbit_distance: f32,
+
     movement_locked: bool,
+    panning: bool,
     active_move_commands: BitFlags<MoveCommands>,
     mouse_delta: (f64, f64),
+    scroll_lines: f32,
 
     translation_speed: f32,
     rotation_speed: f32,
+    orbit_rotation_speed: f32,
+    pan_speed: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Camera {
@@ -42,15 +120,79 @@ impl Camera {
         Camera {
             position,
             direction: (cgmath::Point3::new(0f32, 0.0, 0.0) - position).normalize(),
+            vertical_fov: DEFAULT_VERTICAL_FOV,
             rotational_up: cgmath::Vector3::unit_y(),
 
+            mode: CameraMode::Fly,
+            orbit_distance: position.to_vec().magnitude(),
+
             movement_locked: true,
+            panning: false,
             active_move_commands: Default::default(),
             mouse_delta: (0.0, 0.0),
+            scroll_lines: 0.0,
 
             translation_speed: 0.5,
             rotation_speed: 0.001,
+            orbit_rotation_speed: 0.005,
+            pan_speed: 0.001,
+        }
+    }
+
+    pub fn from_viewpoint(viewpoint: &CameraViewpoint) -> Camera {
+        let mut camera = Camera::new();
+        camera.set_viewpoint(viewpoint);
+        camera
+    }
+
+    // Jumps to the given viewpoint, input state is kept.
+    pub fn set_viewpoint(&mut self, viewpoint: &CameraViewpoint) {
+        self.position = viewpoint.position;
+        self.direction = viewpoint.direction.normalize();
+        self.vertical_fov = cgmath::Deg(viewpoint.vertical_fov_degrees);
+    }
+
+    pub fn viewpoint(&self, name: String) -> CameraViewpoint {
+        CameraViewpoint {
+            name,
+            position: self.position,
+            direction: self.direction,
+            vertical_fov_degrees: self.vertical_fov.0,
+        }
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    // Keeps the current view. When switching to orbit, the center is the point on the view ray closest to the given pivot.
+    pub fn set_mode(&mut self, mode: CameraMode, orbit_pivot: cgmath::Point3<f32>) {
+        if mode == CameraMode::Orbit && self.mode != CameraMode::Orbit {
+            self.orbit_distance = (orbit_pivot - self.position).dot(self.direction).max(MIN_ORBIT_DISTANCE);
+        }
+        self.mode = mode;
+    }
+
+    // Moves back along the view direction until the given box fits into the view and makes its center the orbit center.
+    pub fn frame_bounds(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) {
+        let center = min.midpoint(max);
+        let radius = (max - min).magnitude() * 0.5;
+        self.orbit_distance = (radius / (self.vertical_fov * 0.5).sin()).max(MIN_ORBIT_DISTANCE);
+        self.position = center - self.direction * self.orbit_distance;
+    }
+
+    pub fn load(path: &Path) -> Result<Camera, io::Error> {
+        let viewpoint: CameraViewpoint = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let problems = viewpoint.problems();
+        if !problems.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, problems.join(", ")));
         }
+        Ok(Camera::from_viewpoint(&viewpoint))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &self.viewpoint(String::new()))?;
+        Ok(())
     }
 
     pub fn on_window_event(&mut self, event: &WindowEvent) {
@@ -79,10 +221,16 @@ impl Camera {
                     ElementState::Released => self.active_move_commands.remove(direction),
                 };
             }
-            WindowEvent::MouseInput { button, state, .. } => {
-                if *button == winit::event::MouseButton::Right {
-                    self.movement_locked = *state == ElementState::Released;
-                }
+            WindowEvent::MouseInput { button, state, .. } => match button {
+                winit::event::MouseButton::Right => self.movement_locked = *state == ElementState::Released,
+                winit::event::MouseButton::Middle => self.panning = *state == ElementState::Pressed,
+                _ => {}
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_lines += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_SCROLL_LINE) as f32,
+                };
             }
             _ => {}
         }
@@ -98,6 +246,16 @@ impl Camera {
     }
 
     pub fn update(&mut self, timer: &Timer) {
+        match self.mode {
+            CameraMode::Fly => self.update_fly(timer),
+            CameraMode::Orbit => self.update_orbit(),
+        }
+
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_lines = 0.0;
+    }
+
+    fn update_fly(&mut self, timer: &Timer) {
         if self.movement_locked == false {
             let right = self.direction.cross(self.rotational_up).normalize();
 
@@ -119,8 +277,30 @@ impl Camera {
 
             self.position += translation;
         }
+    }
 
-        self.mouse_delta = (0.0, 0.0);
+    fn update_orbit(&mut self) {
+        let right = self.direction.cross(self.rotational_up).normalize();
+        let up = right.cross(self.direction).normalize();
+        let mut center = self.position + self.direction * self.orbit_distance;
+
+        if self.movement_locked == false {
+            let rotation_leftright =
+                cgmath::Quaternion::from_axis_angle(self.rotational_up, cgmath::Rad(-self.mouse_delta.0 as f32 * self.orbit_rotation_speed));
+            let rotation_updown = cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(-self.mouse_delta.1 as f32 * self.orbit_rotation_speed));
+            self.direction = rotation_leftright.rotate_vector(self.direction).normalize();
+            // Stop short of looking straight up or down, the orbit would flip over otherwise.
+            let direction_updown = rotation_updown.rotate_vector(self.direction).normalize();
+            if direction_updown.dot(self.rotational_up).abs() < MAX_UP_ALIGNMENT {
+                self.direction = direction_updown;
+            }
+        }
+        if self.panning {
+            center += (up * self.mouse_delta.1 as f32 - right * self.mouse_delta.0 as f32) * self.orbit_distance * self.pan_speed;
+        }
+        self.orbit_distance = (self.orbit_distance * ORBIT_ZOOM_PER_LINE.powf(self.scroll_lines)).max(MIN_ORBIT_DISTANCE);
+
+        self.position = center - self.direction * self.orbit_distance;
     }
 
     pub fn fill_global_uniform_buffer(&self, aspect_ratio: f32) -> CameraUniformBufferContent {
@@ -128,7 +308,7 @@ impl Camera {
         let up = right.cross(self.direction).normalize();
 
         let view = cgmath::Matrix4::look_at_dir(self.position, self.direction, self.rotational_up);
-        let projection = OPENGL_PROJECTION_TO_WGPU_PROJECTION * cgmath::perspective(VERTICAL_FOV, aspect_ratio, 0.01, 1000.0);
+        let projection = OPENGL_PROJECTION_TO_WGPU_PROJECTION * cgmath::perspective(self.vertical_fov, aspect_ratio, NEAR_PLANE, FAR_PLANE);
         let view_projection = projection * view;
         let inverse_projection = projection.invert().unwrap();
         //let inverse_view_projection = view_projection.invert().unwrap();
@@ -143,8 +323,8 @@ impl Camera {
             up: up.into(),
             direction: self.direction.into(),
             ndc_camera_space_projected: ndc_camera_space_projected.into(),
-            tan_half_vertical_fov: (VERTICAL_FOV * 0.5).tan(),
-            inv_tan_half_vertical_fov: 1.0 / (VERTICAL_FOV * 0.5).tan(),
+            tan_half_vertical_fov: (self.vertical_fov * 0.5).tan(),
+            inv_tan_half_vertical_fov: 1.0 / (self.vertical_fov * 0.5).tan(),
         }
     }
 }
@@ -0,0 +1,219 @@
+// Camera animation over simulated time, so that recordings don't depend on live camera input.
+//
+// Paths are recorded live from the camera or put together from keyframes and saved as json files.
+// Positions follow a Catmull-Rom spline through the keyframes, directions are slerped, the field of view is interpolated linearly.
+
+use crate::{
+    camera::{Camera, CameraViewpoint},
+    timeline::catmull_rom,
+};
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CameraPathKeyframe {
+    // Simulated time in seconds.
+    pub time: f32,
+    pub viewpoint: CameraViewpoint,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CameraPath {
+    // Sorted by time.
+    pub keyframes: Vec<CameraPathKeyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<CameraPath, io::Error> {
+        let camera_path: CameraPath = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let problems = camera_path.problems();
+        if !problems.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, problems.join(", ")));
+        }
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (keyframe_index, keyframe) in self.keyframes.iter().enumerate() {
+            if keyframe.time < 0.0 {
+                problems.push(format!("keyframes[{}]: time can't be negative, is {}", keyframe_index, keyframe.time));
+            }
+            if keyframe_index > 0 && keyframe.time <= self.keyframes[keyframe_index - 1].time {
+                problems.push(format!("keyframes[{}]: times need to be increasing", keyframe_index));
+            }
+            problems.extend(
+                keyframe
+                    .viewpoint
+                    .problems()
+                    .iter()
+                    .map(|problem| format!("keyframes[{}]: {}", keyframe_index, problem)),
+            );
+        }
+        problems
+    }
+
+    // Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Adds a keyframe at the given time, replacing any keyframe at the same time.
+    pub fn insert_keyframe(&mut self, time: f32, viewpoint: CameraViewpoint) {
+        let index = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time >= time)
+            .unwrap_or(self.keyframes.len());
+        let keyframe = CameraPathKeyframe { time, viewpoint };
+        match self.keyframes.get_mut(index) {
+            Some(existing) if existing.time == time => *existing = keyframe,
+            _ => self.keyframes.insert(index, keyframe),
+        }
+    }
+
+    // Viewpoint at the given time in seconds, None if there are no keyframes. Constant before the first and after the last keyframe.
+    pub fn evaluate(&self, time: f32) -> Option<CameraViewpoint> {
+        let next_index = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time)
+            .unwrap_or(self.keyframes.len());
+        if next_index == 0 {
+            return self.keyframes.first().map(|keyframe| keyframe.viewpoint.clone());
+        }
+        if next_index == self.keyframes.len() {
+            return Some(self.keyframes[next_index - 1].viewpoint.clone());
+        }
+
+        let previous = &self.keyframes[next_index - 1].viewpoint;
+        let next = &self.keyframes[next_index].viewpoint;
+        let t = (time - self.keyframes[next_index - 1].time) / (self.keyframes[next_index].time - self.keyframes[next_index - 1].time);
+
+        // Missing neighbors at the ends are replaced by the keyframes themselves.
+        let p0 = self.keyframes[next_index.saturating_sub(2)].viewpoint.position.to_vec();
+        let p3 = self.keyframes[(next_index + 1).min(self.keyframes.len() - 1)].viewpoint.position.to_vec();
+        let position = catmull_rom(p0, previous.position.to_vec(), next.position.to_vec(), p3, t);
+
+        // Camera has no roll, so the shortest arc between the directions is all there is to the orientation.
+        let previous_direction = previous.direction.normalize();
+        let rotation = cgmath::Quaternion::from_arc(previous_direction, next.direction.normalize(), Some(cgmath::Vector3::unit_y()));
+        let direction = cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0)
+            .slerp(rotation, t)
+            .rotate_vector(previous_direction);
+
+        Some(CameraViewpoint {
+            name: String::new(),
+            position: cgmath::Point3::from_vec(position),
+            direction,
+            vertical_fov_degrees: previous.vertical_fov_degrees + (next.vertical_fov_degrees - previous.vertical_fov_degrees) * t,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, PartialEq, Eq)]
+pub enum CameraPathMode {
+    // Camera is controlled by input only.
+    Off,
+    // Samples the camera into the path while the simulation runs.
+    Record,
+    // Camera follows the path, input is ignored.
+    Playback,
+}
+
+// Records and plays back the camera path of an interactive application.
+// Playback follows simulated time, so a recording with fixed frame length gets the same framing every time.
+pub struct CameraPathController {
+    pub path: CameraPath,
+    pub mode: CameraPathMode,
+    // Simulated time in seconds between keyframes when recording.
+    pub record_interval: f32,
+
+    output_dir: PathBuf,
+    next_save_index: usize,
+}
+
+impl CameraPathController {
+    pub fn new(output_dir: &Path) -> Self {
+        let mut next_save_index = 0;
+        for i in 1..usize::MAX {
+            if !Self::save_path(output_dir, i).exists() {
+                next_save_index = i;
+                break;
+            }
+        }
+
+        CameraPathController {
+            path: CameraPath::default(),
+            mode: CameraPathMode::Off,
+            record_interval: 0.25,
+            output_dir: output_dir.to_path_buf(),
+            next_save_index,
+        }
+    }
+
+    fn save_path(output_dir: &Path, index: usize) -> PathBuf {
+        output_dir.join(format!("camera_path{}.json", index))
+    }
+
+    // Records or plays back at the given simulated time, call after the camera processed its input.
+    pub fn update(&mut self, camera: &mut Camera, simulated_time: Duration) {
+        let time = simulated_time.as_secs_f32();
+        match self.mode {
+            CameraPathMode::Off => {}
+            CameraPathMode::Record => {
+                // Time went backwards (scene was reset), so whatever comes next replaces what was recorded from here on.
+                self.path.keyframes.retain(|keyframe| keyframe.time <= time);
+                let record = match self.path.keyframes.last() {
+                    Some(last) => time - last.time >= self.record_interval,
+                    None => true,
+                };
+                if record {
+                    self.path.insert_keyframe(time, camera.viewpoint(String::new()));
+                }
+            }
+            CameraPathMode::Playback => {
+                if let Some(viewpoint) = self.path.evaluate(time) {
+                    camera.set_viewpoint(&viewpoint);
+                }
+            }
+        }
+    }
+
+    pub fn add_keyframe(&mut self, camera: &Camera, simulated_time: Duration) {
+        self.path.insert_keyframe(simulated_time.as_secs_f32(), camera.viewpoint(String::new()));
+    }
+
+    pub fn load(&mut self, path: &Path) {
+        match CameraPath::load(path) {
+            Ok(camera_path) => {
+                info!("Loaded camera path with {} keyframes from {:?}", camera_path.keyframes.len(), path);
+                self.path = camera_path;
+                self.mode = CameraPathMode::Playback;
+            }
+            Err(err) => error!("Failed to load camera path from {:?}: {}", path, err),
+        }
+    }
+
+    pub fn save(&mut self) {
+        let path = Self::save_path(&self.output_dir, self.next_save_index);
+        match self.path.save(&path) {
+            Ok(()) => info!("Saved camera path with {} keyframes to {:?}", self.path.keyframes.len(), path),
+            Err(err) => error!("Failed to save camera path to {:?}: {}", path, err),
+        }
+        self.next_save_index += 1;
+    }
+}
@@ -0,0 +1,172 @@
+use blub::{
+    headless::{HeadlessConfig, HeadlessRunLength},
+    render_output::{hdr_capture::HdrImageFormat, video_stream::RecordingFormat},
+    renderer::FluidRenderingMode,
+};
+use std::{path::PathBuf, time::Duration};
+use structopt::StructOpt;
+
+// Everything that can be configured on startup, so that runs can be scripted and reproduced.
+// Anything not given falls back to the defaults of the respective component.
+#[derive(StructOpt)]
+#[structopt(name = "blub", about = "GPU fluid simulation")]
+pub struct CommandLineArguments {
+    /// Scene file to load on startup. Defaults to the first scene in the scene directory.
+    #[structopt(parse(from_os_str))]
+    pub scene: Option<PathBuf>,
+
+    /// Number of simulation steps per simulated second.
+    #[structopt(long)]
+    pub steps_per_second: Option<u64>,
+
+    /// Speed of the simulation relative to realtime.
+    #[structopt(long)]
+    pub time_scale: Option<f32>,
+
+    /// Simulated time in seconds after which the simulation stops.
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    pub stop_time: Option<Duration>,
+
+    /// Starts recording with the given frames per second immediately.
+    #[structopt(long, value_name = "fps")]
+    pub record: Option<f64>,
+
+    /// Renders recordings offscreen at the given resolution instead of capturing the window, e.g. 3840x2160.
+    /// In headless mode, frames are rendered at this resolution alongside all other outputs.
+    #[structopt(long, value_name = "WIDTHxHEIGHT", parse(try_from_str = parse_resolution))]
+    pub render_resolution: Option<winit::dpi::PhysicalSize<u32>>,
+
+    /// Camera file to start with, as written by "Save Camera".
+    #[structopt(long, parse(from_os_str))]
+    pub camera: Option<PathBuf>,
+
+    /// Camera path file to play back, as written by "Save Camera Path". The camera follows it over simulated time.
+    #[structopt(long, value_name = "file", parse(from_os_str))]
+    pub camera_path: Option<PathBuf>,
+
+    /// Output of recordings, one of png_sequence, y4m, encoder, exr_sequence or hdr_sequence.
+    /// Defaults to png_sequence, or encoder if an encoder command is given. exr_sequence and hdr_sequence capture before tonemapping.
+    #[structopt(long)]
+    pub recording_format: Option<RecordingFormat>,
+
+    /// Encoder process that recorded frames are piped into (raw rgb24 on stdin), e.g. "ffmpeg ... -i - {output}".
+    /// {width}, {height}, {fps} and {output} are replaced. Defaults to ffmpeg writing h264 to video.mp4 in the recording directory.
+    #[structopt(long, value_name = "command")]
+    pub encoder_command: Option<String>,
+
+    /// Writes screenshots of the linear image before tonemapping instead of png, one of exr or hdr.
+    #[structopt(long, value_name = "format")]
+    pub hdr_screenshots: Option<HdrImageFormat>,
+
+    /// Adds linear depth to hdr screenshots and recordings, as Z channel for exr or as separate _depth file for hdr.
+    #[structopt(long)]
+    pub hdr_depth: bool,
+
+    /// Directory for recordings, screenshots and exports. Defaults to the working directory (headless_output in headless mode).
+    #[structopt(long, parse(from_os_str))]
+    pub output_dir: Option<PathBuf>,
+
+    #[structopt(long, default_value = "1980")]
+    pub window_width: u32,
+
+    #[structopt(long, default_value = "1080")]
+    pub window_height: u32,
+
+    /// Present mode of the swap chain, one of immediate, mailbox or fifo.
+    #[structopt(long, parse(try_from_str = parse_present_mode))]
+    pub present_mode: Option<wgpu::PresentMode>,
+
+    /// Initial fluid rendering mode, one of none, screen_space_fluid or particles.
+    #[structopt(long)]
+    pub render_mode: Option<FluidRenderingMode>,
+
+    /// Starts a local JSON-RPC server on the given loopback address (e.g. 127.0.0.1:9077) to control the application from scripts, see README.
+    #[structopt(long, value_name = "address")]
+    pub remote_control: Option<String>,
+
+    /// Log filter in env_logger syntax, e.g. "warn,blub=debug". Overrides RUST_LOG.
+    #[structopt(long)]
+    pub log_level: Option<String>,
+
+    /// Runs without window, swap chain or rendering and writes outputs to the output directory.
+    #[structopt(long)]
+    pub headless: bool,
+
+    /// Runs the validation scenes in scenes/validation without window and reports pass/fail for each. Exits with an error if any failed.
+    #[structopt(long)]
+    pub validate: bool,
+
+    /// Runs the parameter sweep described in the given file without window, results are written to the output directory (default sweep_output).
+    #[structopt(long, value_name = "file", parse(from_os_str))]
+    pub sweep: Option<PathBuf>,
+
+    /// Number of simulation steps to perform in headless mode. If not given, runs until the stop time (default 1 second).
+    #[structopt(long)]
+    pub steps: Option<u32>,
+
+    /// Simulated time in seconds between writing outputs in headless mode. Defaults to the recording fps if given, otherwise only the final state is written.
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    pub output_interval: Option<Duration>,
+
+    /// Don't write surface meshes in headless mode.
+    #[structopt(long)]
+    pub no_surface_mesh: bool,
+
+    /// Don't write simulation volumes in headless mode.
+    #[structopt(long)]
+    pub no_volumes: bool,
+
+    /// Don't write the diagnostics of every step (diagnostics.csv) in headless mode.
+    #[structopt(long)]
+    pub no_diagnostics: bool,
+}
+
+impl CommandLineArguments {
+    pub fn headless_config(&self) -> Result<HeadlessConfig, String> {
+        Ok(HeadlessConfig {
+            scene_path: self.scene.clone().ok_or("Headless mode requires a scene")?,
+            run_length: match self.steps {
+                Some(num_steps) => HeadlessRunLength::Steps(num_steps),
+                None => HeadlessRunLength::SimulatedTime(self.stop_time.unwrap_or(Duration::from_secs(1))),
+            },
+            output_dir: self.output_dir.clone().unwrap_or_else(|| PathBuf::from("headless_output")),
+            output_interval: self
+                .output_interval
+                .or_else(|| self.record.map(|recording_fps| Duration::from_secs_f64(1.0 / recording_fps))),
+            simulation_steps_per_second: self.steps_per_second,
+            export_surface_mesh: !self.no_surface_mesh,
+            export_volumes: !self.no_volumes,
+            export_diagnostics: !self.no_diagnostics,
+            render_resolution: self.render_resolution,
+            camera_file: self.camera.clone(),
+            camera_path_file: self.camera_path.clone(),
+            render_hdr_format: self.recording_format.and_then(RecordingFormat::hdr_image_format),
+            render_hdr_depth: self.hdr_depth,
+            fluid_rendering_mode: self.render_mode,
+        })
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("Invalid number of seconds: {}", value)),
+    }
+}
+
+fn parse_resolution(value: &str) -> Result<winit::dpi::PhysicalSize<u32>, String> {
+    let mut dimensions = value.split('x').map(|dimension| dimension.parse::<u32>());
+    match (dimensions.next(), dimensions.next(), dimensions.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => Ok(winit::dpi::PhysicalSize::new(width, height)),
+        _ => Err(format!("Invalid resolution, expected <width>x<height>: {}", value)),
+    }
+}
+
+pub fn parse_present_mode(value: &str) -> Result<wgpu::PresentMode, String> {
+    match value {
+        "immediate" => Ok(wgpu::PresentMode::Immediate),
+        "mailbox" => Ok(wgpu::PresentMode::Mailbox),
+        "fifo" => Ok(wgpu::PresentMode::Fifo),
+        _ => Err(format!("Unknown present mode: {}", value)),
+    }
+}
@@ -0,0 +1,125 @@
+use crate::wgpu_utils::profiler::{ProfilerFrame, ProfilerScope};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+const FRAME_THREAD_ID: u32 = 0;
+const CPU_THREAD_ID: u32 = 1;
+const GPU_THREAD_ID: u32 = 2;
+
+// Complete event ("ph": "X") as described in the Trace Event Format. Times are in microseconds.
+fn scope_event(scope: &ProfilerScope, thread_id: u32, range: &std::ops::Range<f64>, time_offset: f64) -> serde_json::Value {
+    serde_json::json!({
+        "name": scope.label,
+        "ph": "X",
+        "pid": 1,
+        "tid": thread_id,
+        "ts": (range.start + time_offset) * 1.0e6,
+        "dur": (range.end - range.start) * 1.0e6,
+    })
+}
+
+fn collect_scope_events(scopes: &[ProfilerScope], gpu_time_offset: f64, events: &mut Vec<serde_json::Value>) {
+    for scope in scopes.iter() {
+        events.push(scope_event(scope, CPU_THREAD_ID, &scope.cpu, 0.0));
+        if let Some(gpu) = &scope.gpu {
+            events.push(scope_event(scope, GPU_THREAD_ID, gpu, gpu_time_offset));
+        }
+        collect_scope_events(&scope.children, gpu_time_offset, events);
+    }
+}
+
+fn first_gpu_timestamp(scopes: &[ProfilerScope]) -> Option<f64> {
+    scopes
+        .iter()
+        .filter_map(|scope| {
+            let child_start = first_gpu_timestamp(&scope.children);
+            match (&scope.gpu, child_start) {
+                (Some(gpu), Some(child_start)) => Some(gpu.start.min(child_start)),
+                (Some(gpu), None) => Some(gpu.start),
+                (None, child_start) => child_start,
+            }
+        })
+        .fold(None, |min: Option<f64>, start| Some(min.map_or(start, |min| min.min(start))))
+}
+
+// Writes profiler frames to json files that can be opened with chrome://tracing or https://ui.perfetto.dev
+//
+// Cpu and gpu scopes show up as separate threads.
+// Gpu timestamps have no defined relation to cpu time, so the gpu timeline is shifted to start together with the first cpu scope.
+pub struct ChromeTraceExporter {
+    output_dir: PathBuf,
+    next_export_index: usize,
+}
+
+impl ChromeTraceExporter {
+    pub fn new(output_dir: &Path) -> Self {
+        let mut next_export_index = 0;
+        for i in 1..usize::MAX {
+            if !Self::export_path(output_dir, i).exists() {
+                next_export_index = i;
+                break;
+            }
+        }
+
+        ChromeTraceExporter {
+            output_dir: output_dir.to_path_buf(),
+            next_export_index,
+        }
+    }
+
+    fn export_path(output_dir: &Path, index: usize) -> PathBuf {
+        output_dir.join(format!("profile{}.json", index))
+    }
+
+    pub fn export<'a>(&mut self, frames: impl IntoIterator<Item = &'a ProfilerFrame>) {
+        let path = Self::export_path(&self.output_dir, self.next_export_index);
+        self.next_export_index += 1;
+
+        let frames: Vec<&ProfilerFrame> = frames.into_iter().collect();
+        if frames.is_empty() {
+            warn!("No profiler frames to export, enable the profiler first");
+            return;
+        }
+
+        let first_cpu_timestamp = frames[0].cpu.start;
+        let gpu_time_offset = frames
+            .iter()
+            .find_map(|frame| first_gpu_timestamp(&frame.scopes).map(|gpu_start| frame.scopes[0].cpu.start - gpu_start))
+            .unwrap_or(0.0);
+
+        let mut events = vec![
+            serde_json::json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": FRAME_THREAD_ID, "args": { "name": "Frames" } }),
+            serde_json::json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": CPU_THREAD_ID, "args": { "name": "CPU" } }),
+            serde_json::json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": GPU_THREAD_ID, "args": { "name": "GPU" } }),
+        ];
+        for frame in frames.iter() {
+            events.push(serde_json::json!({
+                "name": format!("frame {}", frame.frame_index),
+                "ph": "X",
+                "pid": 1,
+                "tid": FRAME_THREAD_ID,
+                "ts": frame.cpu.start * 1.0e6,
+                "dur": (frame.cpu.end - frame.cpu.start) * 1.0e6,
+            }));
+            collect_scope_events(&frame.scopes, gpu_time_offset, &mut events);
+        }
+
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            serde_json::to_writer(&mut writer, &serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" }))?;
+            writer.flush()
+        };
+        match write() {
+            Ok(()) => info!(
+                "Wrote {} profiler frames starting at {:.3}s to {:?}",
+                frames.len(),
+                first_cpu_timestamp,
+                path
+            ),
+            Err(err) => error!("Failed to write profiler trace to {:?}: {}", path, err),
+        }
+    }
+}
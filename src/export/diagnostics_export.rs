@@ -0,0 +1,140 @@
+use super::fluid_file_prefix;
+use crate::simulation::{DiagnosticsSample, HybridFluid};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn write_csv_header(writer: &mut impl Write) -> std::io::Result<()> {
+    let names: Vec<&str> = DiagnosticsSample::default().named_values().iter().map(|(name, _)| *name).collect();
+    writeln!(writer, "{}", names.join(","))
+}
+
+fn write_csv_row(writer: &mut impl Write, sample: &DiagnosticsSample) -> std::io::Result<()> {
+    let values: Vec<String> = sample.named_values().iter().map(|(_, value)| value.to_string()).collect();
+    writeln!(writer, "{}", values.join(","))
+}
+
+// Writes simulation diagnostics to csv files, one row per simulation step and one file per fluid domain.
+// The simulated_time column is the same as Timer::total_simulated_time after the respective step.
+pub struct DiagnosticsExporter {
+    output_dir: PathBuf,
+    // If true, the diagnostics of every step are written alongside a recording.
+    pub export_during_recording: bool,
+
+    next_regular_export_index: usize,
+    // One file per fluid of the scene, empty if there's no continuous export going on.
+    continuous_export: Vec<(PathBuf, BufWriter<File>)>,
+}
+
+impl DiagnosticsExporter {
+    pub fn new(output_dir: &Path) -> Self {
+        let mut next_regular_export_index = 0;
+        for i in 1..usize::MAX {
+            if !Self::regular_export_path(output_dir, "", i).exists() {
+                next_regular_export_index = i;
+                break;
+            }
+        }
+
+        DiagnosticsExporter {
+            output_dir: output_dir.to_path_buf(),
+            export_during_recording: false,
+            next_regular_export_index,
+            continuous_export: Vec::new(),
+        }
+    }
+
+    fn regular_export_path(output_dir: &Path, file_prefix: &str, index: usize) -> PathBuf {
+        output_dir.join(format!("{}diagnostics{}.csv", file_prefix, index))
+    }
+
+    fn recording_path(recording_output_dir: &Path, file_prefix: &str) -> PathBuf {
+        recording_output_dir.join(format!("{}diagnostics.csv", file_prefix))
+    }
+
+    // Writes the diagnostics history the fluids still hold (the last few steps) to new files.
+    pub fn export_history(&mut self, fluids: &[HybridFluid]) {
+        for (fluid_index, fluid) in fluids.iter().enumerate() {
+            let path = Self::regular_export_path(
+                &self.output_dir,
+                &fluid_file_prefix(fluids.len(), fluid_index),
+                self.next_regular_export_index,
+            );
+            let write = || -> std::io::Result<()> {
+                let mut writer = BufWriter::new(File::create(&path)?);
+                write_csv_header(&mut writer)?;
+                for sample in fluid.diagnostics_history().iter() {
+                    write_csv_row(&mut writer, sample)?;
+                }
+                writer.flush()
+            };
+            match write() {
+                Ok(()) => info!("Wrote {} diagnostics samples to {:?}", fluid.diagnostics_history().len(), path),
+                Err(err) => error!("Failed to write diagnostics to {:?}: {}", path, err),
+            }
+        }
+        self.next_regular_export_index += 1;
+    }
+
+    // Starts writing the diagnostics of every step from now on to files in the given directory, see write_new_samples.
+    pub fn start_continuous_export(&mut self, output_dir: &Path, fluids: &mut [HybridFluid]) {
+        self.stop_continuous_export(fluids);
+
+        let num_fluids = fluids.len();
+        let mut failed = false;
+        for (fluid_index, fluid) in fluids.iter_mut().enumerate() {
+            let path = Self::recording_path(output_dir, &fluid_file_prefix(num_fluids, fluid_index));
+            let writer = File::create(&path).map(BufWriter::new).and_then(|mut writer| {
+                write_csv_header(&mut writer)?;
+                Ok(writer)
+            });
+            match writer {
+                Ok(writer) => {
+                    info!("Writing diagnostics of every step to {:?}", path);
+                    fluid.set_diagnostics_recording(true);
+                    self.continuous_export.push((path, writer));
+                }
+                Err(err) => {
+                    error!("Failed to create diagnostics file {:?}: {}", path, err);
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        // Files need to line up with the fluids, so it's all or nothing.
+        if failed {
+            self.stop_continuous_export(fluids);
+        }
+    }
+
+    pub fn is_exporting_continuously(&self) -> bool {
+        !self.continuous_export.is_empty()
+    }
+
+    // Appends all samples that arrived since the last call. Call regularly (e.g. every frame) during a continuous export.
+    pub fn write_new_samples(&mut self, fluids: &mut [HybridFluid]) {
+        for ((path, writer), fluid) in self.continuous_export.iter_mut().zip(fluids.iter_mut()) {
+            for sample in fluid.take_recorded_diagnostics().iter() {
+                if let Err(err) = write_csv_row(writer, sample) {
+                    error!("Failed to write diagnostics to {:?}: {}", path, err);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Writes out all remaining samples and closes the files. Note that samples of the last few steps may still be in flight,
+    // use HybridFluid::wait_for_pending_diagnostics before if they are needed.
+    pub fn stop_continuous_export(&mut self, fluids: &mut [HybridFluid]) {
+        self.write_new_samples(fluids);
+        for ((path, mut writer), fluid) in self.continuous_export.drain(..).zip(fluids.iter_mut()) {
+            match writer.flush() {
+                Ok(()) => info!("Finished writing diagnostics to {:?}", path),
+                Err(err) => error!("Failed to write diagnostics to {:?}: {}", path, err),
+            }
+            fluid.set_diagnostics_recording(false);
+        }
+    }
+}
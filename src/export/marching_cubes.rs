@@ -0,0 +1,217 @@
+use cgmath::{EuclideanSpace, InnerSpace};
+use std::collections::HashMap;
+
+// Scalar values on a regular grid, x varying fastest.
+pub struct ScalarVolume {
+    pub dimension: cgmath::Vector3<usize>,
+    pub values: Vec<f32>,
+}
+
+impl ScalarVolume {
+    pub fn new(dimension: cgmath::Vector3<usize>) -> Self {
+        ScalarVolume {
+            dimension,
+            values: vec![0.0; dimension.x * dimension.y * dimension.z],
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + (y + z * self.dimension.y) * self.dimension.x
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[self.index(x, y, z)]
+    }
+
+    // Central differences, one sided at the borders.
+    fn gradient(&self, x: usize, y: usize, z: usize) -> cgmath::Vector3<f32> {
+        let d = |lower: f32, upper: f32, lower_idx: usize, upper_idx: usize| (upper - lower) / (upper_idx - lower_idx).max(1) as f32;
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.dimension.x - 1));
+        let (y0, y1) = (y.saturating_sub(1), (y + 1).min(self.dimension.y - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.dimension.z - 1));
+        cgmath::vec3(
+            d(self.get(x0, y, z), self.get(x1, y, z), x0, x1),
+            d(self.get(x, y0, z), self.get(x, y1, z), y0, y1),
+            d(self.get(x, y, z0), self.get(x, y, z1), z0, z1),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<cgmath::Point3<f32>>,
+    // Either empty or one per position.
+    pub normals: Vec<cgmath::Vector3<f32>>,
+    // Counter clockwise when looking at the front side.
+    pub triangles: Vec<[u32; 3]>,
+}
+
+// Corner i of a cube is at offset (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+const CUBE_EDGES: [(usize, usize); 12] = [
+    // along x
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    // along y
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    // along z
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+// Corners of each cube face in counter clockwise order when looking at the face from outside the cube.
+const CUBE_FACES: [[usize; 4]; 6] = [[0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3], [0, 2, 3, 1], [4, 5, 7, 6]];
+
+fn corner_offset(corner: usize) -> cgmath::Vector3<usize> {
+    cgmath::vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1)
+}
+
+fn edge_index(a: usize, b: usize) -> usize {
+    CUBE_EDGES
+        .iter()
+        .position(|&(e0, e1)| (e0 == a && e1 == b) || (e0 == b && e1 == a))
+        .unwrap()
+}
+
+lazy_static! {
+    // Triangles (as edge indices) for each of the 256 inside/outside configurations of a cube.
+    // Instead of the usual hardcoded table, it is derived by walking along the iso-line segments on each face:
+    // Every face contributes segments that go from the edge where the inside region starts to the edge where it ends (counter clockwise).
+    // On ambiguous faces inside corners are kept separate, which makes neighboring cubes always agree.
+    // The segments of all faces form closed loops which are then triangulated as fans.
+    static ref TRIANGLE_TABLE: Vec<Vec<[usize; 3]>> = (0..256).map(triangulate_cube_configuration).collect();
+}
+
+fn triangulate_cube_configuration(configuration: usize) -> Vec<[usize; 3]> {
+    let inside = |corner: usize| configuration & (1 << corner) != 0;
+
+    // Each crossed edge starts exactly one segment (on one of its two faces) and ends exactly one.
+    let mut next_edge: [Option<usize>; 12] = [None; 12];
+    for face in CUBE_FACES.iter() {
+        for i in 0..4 {
+            let corner = face[i];
+            let corner_next = face[(i + 1) % 4];
+            if !inside(corner) || inside(corner_next) {
+                continue;
+            }
+            // Found the end of an inside run, walk back to its start.
+            let mut start = i;
+            while inside(face[(start + 3) % 4]) {
+                start = (start + 3) % 4;
+            }
+            let entry_edge = edge_index(face[(start + 3) % 4], face[start]);
+            let exit_edge = edge_index(corner, corner_next);
+            next_edge[entry_edge] = Some(exit_edge);
+        }
+    }
+
+    let mut triangles = Vec::new();
+    let mut visited = [false; 12];
+    for first_edge in 0..12 {
+        if visited[first_edge] || next_edge[first_edge].is_none() {
+            continue;
+        }
+        let mut polygon = Vec::new();
+        let mut edge = first_edge;
+        while !visited[edge] {
+            visited[edge] = true;
+            polygon.push(edge);
+            edge = next_edge[edge].unwrap();
+        }
+        for i in 1..polygon.len() - 1 {
+            triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+    }
+    triangles
+}
+
+// Extracts the surface where the volume crosses iso_value. Triangles face towards lower values.
+// Positions are in the volume's sample coordinates.
+pub fn extract_isosurface(volume: &ScalarVolume, iso_value: f32, compute_normals: bool) -> TriangleMesh {
+    let mut mesh = TriangleMesh {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        triangles: Vec::new(),
+    };
+    if volume.dimension.x < 2 || volume.dimension.y < 2 || volume.dimension.z < 2 {
+        return mesh;
+    }
+
+    // Vertices are shared between cubes, identified by the sample they start from and their axis.
+    let mut vertex_indices: HashMap<usize, u32> = HashMap::new();
+
+    for z in 0..volume.dimension.z - 1 {
+        for y in 0..volume.dimension.y - 1 {
+            for x in 0..volume.dimension.x - 1 {
+                let base = cgmath::vec3(x, y, z);
+                let mut configuration = 0;
+                for corner in 0..8 {
+                    let c = base + corner_offset(corner);
+                    if volume.get(c.x, c.y, c.z) > iso_value {
+                        configuration |= 1 << corner;
+                    }
+                }
+
+                for triangle in TRIANGLE_TABLE[configuration].iter() {
+                    let mut triangle_vertices = [0; 3];
+                    for (triangle_vertex, &edge) in triangle_vertices.iter_mut().zip(triangle.iter()) {
+                        let (corner_a, corner_b) = CUBE_EDGES[edge];
+                        let a = base + corner_offset(corner_a);
+                        let b = base + corner_offset(corner_b);
+                        let key = volume.index(a.x, a.y, a.z) * 3 + edge / 4;
+
+                        *triangle_vertex = *vertex_indices.entry(key).or_insert_with(|| {
+                            let value_a = volume.get(a.x, a.y, a.z);
+                            let value_b = volume.get(b.x, b.y, b.z);
+                            let t = ((iso_value - value_a) / (value_b - value_a)).max(0.0).min(1.0);
+                            let position_a = cgmath::point3(a.x as f32, a.y as f32, a.z as f32);
+                            let position_b = cgmath::point3(b.x as f32, b.y as f32, b.z as f32);
+                            mesh.positions.push(position_a + (position_b - position_a) * t);
+                            if compute_normals {
+                                let gradient = volume.gradient(a.x, a.y, a.z) * (1.0 - t) + volume.gradient(b.x, b.y, b.z) * t;
+                                let normal = if gradient.magnitude2() > 0.0 {
+                                    -gradient.normalize()
+                                } else {
+                                    cgmath::Vector3::unit_y()
+                                };
+                                mesh.normals.push(normal);
+                            }
+                            (mesh.positions.len() - 1) as u32
+                        });
+                    }
+                    mesh.triangles.push(triangle_vertices);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+impl TriangleMesh {
+    // Applies a uniform scale followed by a translation to all positions.
+    pub fn transform(&mut self, scale: f32, translation: cgmath::Vector3<f32>) {
+        for position in self.positions.iter_mut() {
+            *position = cgmath::Point3::from_vec(position.to_vec() * scale + translation);
+        }
+    }
+
+    // Adds all triangles of another mesh. Both need to agree on whether they have normals.
+    pub fn append(&mut self, other: TriangleMesh) {
+        let index_offset = self.positions.len() as u32;
+        self.positions.extend(other.positions);
+        self.normals.extend(other.normals);
+        self.triangles.extend(
+            other
+                .triangles
+                .iter()
+                .map(|triangle| [triangle[0] + index_offset, triangle[1] + index_offset, triangle[2] + index_offset]),
+        );
+    }
+}
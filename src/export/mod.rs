@@ -0,0 +1,15 @@
+pub mod chrome_trace;
+pub mod diagnostics_export;
+pub mod marching_cubes;
+pub mod surface_mesh;
+pub mod volume_export;
+
+// Scenes with several fluid domains export each domain to its own files, distinguished by this prefix.
+// Single domain scenes keep plain file names.
+pub fn fluid_file_prefix(num_fluids: usize, fluid_index: usize) -> String {
+    if num_fluids > 1 {
+        format!("fluid{}_", fluid_index)
+    } else {
+        String::new()
+    }
+}
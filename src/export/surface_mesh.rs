@@ -0,0 +1,214 @@
+use super::marching_cubes::{extract_isosurface, ScalarVolume, TriangleMesh};
+use crate::{scene::Scene, simulation::ParticleState};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
+
+// Settings for surface mesh extraction.
+#[derive(Clone, Copy)]
+pub struct SurfaceMeshSettings {
+    // Number of marching cubes samples per grid cell along each axis.
+    pub samples_per_cell: u32,
+    // Normalized particle density (1.0 == rest density) at which the surface is placed.
+    pub iso_value: f32,
+    pub write_normals: bool,
+}
+
+impl Default for SurfaceMeshSettings {
+    fn default() -> Self {
+        SurfaceMeshSettings {
+            samples_per_cell: 2,
+            iso_value: 0.5,
+            write_normals: true,
+        }
+    }
+}
+
+// Splats particles into a density volume using the same tent kernel as the simulation (one grid cell radius).
+// The volume has one empty sample layer on each side, so the extracted surface is always closed.
+// Sample (i, j, k) is at grid coordinate ((i, j, k) - 1) / samples_per_cell.
+fn particle_density_volume(
+    particles: &[ParticleState],
+    grid_dimension: wgpu::Extent3d,
+    particles_per_cell: u32,
+    samples_per_cell: u32,
+) -> ScalarVolume {
+    let samples_per_cell = samples_per_cell.max(1) as usize;
+    let mut volume = ScalarVolume::new(cgmath::vec3(
+        grid_dimension.width as usize * samples_per_cell + 3,
+        grid_dimension.height as usize * samples_per_cell + 3,
+        grid_dimension.depth as usize * samples_per_cell + 3,
+    ));
+    let kernel_radius = samples_per_cell as f32;
+    let normalization = 1.0 / particles_per_cell as f32;
+
+    for particle in particles.iter() {
+        let center = particle.position * kernel_radius + cgmath::vec3(1.0, 1.0, 1.0);
+        let min = center.map(|c| (c - kernel_radius).ceil().max(0.0) as usize);
+        let max = cgmath::point3(
+            ((center.x + kernel_radius).floor() as usize).min(volume.dimension.x - 1),
+            ((center.y + kernel_radius).floor() as usize).min(volume.dimension.y - 1),
+            ((center.z + kernel_radius).floor() as usize).min(volume.dimension.z - 1),
+        );
+        for z in min.z..=max.z {
+            let weight_z = (1.0 - (z as f32 - center.z).abs() / kernel_radius).max(0.0);
+            for y in min.y..=max.y {
+                let weight_yz = weight_z * (1.0 - (y as f32 - center.y).abs() / kernel_radius).max(0.0);
+                for x in min.x..=max.x {
+                    let weight = weight_yz * (1.0 - (x as f32 - center.x).abs() / kernel_radius).max(0.0);
+                    let index = volume.index(x, y, z);
+                    volume.values[index] += weight * normalization;
+                }
+            }
+        }
+    }
+
+    volume
+}
+
+// Extracts the fluid surface from a set of particles. Resulting positions are in world space.
+pub fn surface_mesh_from_particles(
+    particles: &[ParticleState],
+    grid_dimension: wgpu::Extent3d,
+    particles_per_cell: u32,
+    world_position: cgmath::Point3<f32>,
+    grid_to_world_scale: f32,
+    settings: &SurfaceMeshSettings,
+) -> TriangleMesh {
+    let samples_per_cell = settings.samples_per_cell.max(1);
+    let volume = particle_density_volume(particles, grid_dimension, particles_per_cell, samples_per_cell);
+    let mut mesh = extract_isosurface(&volume, settings.iso_value, settings.write_normals);
+
+    let sample_to_world_scale = grid_to_world_scale / samples_per_cell as f32;
+    let sample_origin = cgmath::vec3(world_position.x, world_position.y, world_position.z) - cgmath::vec3(1.0, 1.0, 1.0) * sample_to_world_scale;
+    mesh.transform(sample_to_world_scale, sample_origin);
+    mesh
+}
+
+pub fn write_obj(mesh: &TriangleMesh, path: &Path) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "# Blub fluid surface")?;
+    for position in mesh.positions.iter() {
+        writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+    }
+    for normal in mesh.normals.iter() {
+        writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+    }
+    // obj indices are one based.
+    if mesh.normals.is_empty() {
+        for triangle in mesh.triangles.iter() {
+            writeln!(writer, "f {} {} {}", triangle[0] + 1, triangle[1] + 1, triangle[2] + 1)?;
+        }
+    } else {
+        for triangle in mesh.triangles.iter() {
+            writeln!(writer, "f {0}//{0} {1}//{1} {2}//{2}", triangle[0] + 1, triangle[1] + 1, triangle[2] + 1)?;
+        }
+    }
+    writer.flush()
+}
+
+const MAX_PENDING_EXPORTS: usize = 4;
+
+// Reads back particles and writes out surface meshes on a separate thread.
+pub struct SurfaceMeshExporter {
+    output_dir: PathBuf,
+    pub settings: SurfaceMeshSettings,
+    // If true, a mesh is written for every frame of a recording.
+    pub export_during_recording: bool,
+
+    next_regular_export_index: usize,
+    pending_exports: VecDeque<JoinHandle<()>>,
+}
+
+impl SurfaceMeshExporter {
+    pub fn new(output_dir: &Path) -> Self {
+        let mut next_regular_export_index = 0;
+        for i in 1..usize::MAX {
+            if !Self::regular_export_path(output_dir, i).exists() {
+                next_regular_export_index = i;
+                break;
+            }
+        }
+
+        SurfaceMeshExporter {
+            output_dir: output_dir.to_path_buf(),
+            settings: Default::default(),
+            export_during_recording: false,
+            next_regular_export_index,
+            pending_exports: VecDeque::new(),
+        }
+    }
+
+    fn regular_export_path(output_dir: &Path, index: usize) -> PathBuf {
+        output_dir.join(format!("surface{}.obj", index))
+    }
+
+    pub fn recording_frame_path(recording_output_dir: &Path, frame_index: usize) -> PathBuf {
+        recording_output_dir.join(format!("surface{}.obj", frame_index))
+    }
+
+    // Exports the current fluid surface next to where regular screenshots go.
+    pub fn export_next(&mut self, scene: &Scene, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let path = Self::regular_export_path(&self.output_dir, self.next_regular_export_index);
+        self.next_regular_export_index += 1;
+        self.export(scene, device, queue, path);
+    }
+
+    // Reads back the fluid particles (stalls!) and writes the surface mesh to the given path in the background.
+    pub fn export(&mut self, scene: &Scene, device: &wgpu::Device, queue: &wgpu::Queue, path: PathBuf) {
+        // Mesh extraction is much slower than rendering a frame, don't let the backlog grow without bounds.
+        while self.pending_exports.len() >= MAX_PENDING_EXPORTS {
+            self.pending_exports.pop_front().unwrap().join().unwrap();
+        }
+
+        // All fluid domains end up in the same mesh.
+        let fluids: Vec<_> = scene
+            .fluids()
+            .iter()
+            .zip(scene.config().fluids.iter())
+            .map(|(fluid, fluid_config)| {
+                (
+                    fluid.read_particles(device, queue),
+                    fluid.grid_dimension(),
+                    fluid.particles_per_cell(),
+                    fluid_config.world_position,
+                    fluid_config.grid_to_world_scale,
+                )
+            })
+            .collect();
+        let settings = self.settings;
+        self.pending_exports.push_back(std::thread::spawn(move || {
+            let start_time = std::time::Instant::now();
+            let mut mesh = TriangleMesh::default();
+            for (particles, grid_dimension, particles_per_cell, world_position, grid_to_world_scale) in fluids.iter() {
+                mesh.append(surface_mesh_from_particles(
+                    particles,
+                    *grid_dimension,
+                    *particles_per_cell,
+                    *world_position,
+                    *grid_to_world_scale,
+                    &settings,
+                ));
+            }
+            match write_obj(&mesh, &path) {
+                Ok(()) => info!(
+                    "Wrote surface mesh with {} triangles to {:?} (took {:?})",
+                    mesh.triangles.len(),
+                    path,
+                    start_time.elapsed()
+                ),
+                Err(err) => error!("Failed to write surface mesh to {:?}: {}", path, err),
+            }
+        }));
+    }
+
+    pub fn wait_for_pending_exports(&mut self) {
+        for handle in self.pending_exports.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use super::fluid_file_prefix;
+use crate::{
+    scene::Scene,
+    simulation::{DiagnosticsSample, SimulationVolume},
+};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
+use strum::IntoEnumIterator;
+
+#[derive(Clone, Copy, Debug, EnumIter, PartialEq, Eq)]
+pub enum VolumeFileFormat {
+    // VTK XML image data, readable by ParaView & co.
+    // Cell centered volumes go into a single file, each staggered velocity component into a separate one.
+    Vti,
+    // One nrrd file per volume.
+    Nrrd,
+}
+
+struct VolumeData {
+    volume: SimulationVolume,
+    values: Vec<f32>,
+}
+
+// All volumes of a single export, world space placement already resolved.
+struct VolumeSet {
+    dimension: wgpu::Extent3d,
+    world_position: cgmath::Point3<f32>,
+    grid_to_world_scale: f32,
+    volumes: Vec<VolumeData>,
+    // Most recent diagnostics of the fluid, written as metadata. Read back asynchronously, so they may lag a few steps behind the volumes.
+    diagnostics: Option<DiagnosticsSample>,
+}
+
+impl VolumeSet {
+    fn world_origin(&self, volume: SimulationVolume) -> cgmath::Point3<f32> {
+        let offset = volume.sample_origin_grid();
+        self.world_position + cgmath::vec3(offset.x, offset.y, offset.z) * self.grid_to_world_scale
+    }
+}
+
+fn write_vti(path: &Path, volume_set: &VolumeSet, volumes: &[&VolumeData]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let dimension = volume_set.dimension;
+    // All volumes in a file share their sample positions.
+    let origin = volume_set.world_origin(volumes[0].volume);
+    let spacing = volume_set.grid_to_world_scale;
+    let extent = format!("0 {} 0 {} 0 {}", dimension.width - 1, dimension.height - 1, dimension.depth - 1);
+
+    writeln!(writer, "<?xml version=\"1.0\"?>")?;
+    writeln!(
+        writer,
+        "<VTKFile type=\"ImageData\" version=\"1.0\" byte_order=\"LittleEndian\" header_type=\"UInt64\">"
+    )?;
+    writeln!(
+        writer,
+        "  <ImageData WholeExtent=\"{}\" Origin=\"{} {} {}\" Spacing=\"{} {} {}\">",
+        extent, origin.x, origin.y, origin.z, spacing, spacing, spacing
+    )?;
+    if let Some(diagnostics) = &volume_set.diagnostics {
+        writeln!(writer, "    <FieldData>")?;
+        // TimeValue is picked up by ParaView as the time of the dataset.
+        writeln!(
+            writer,
+            "      <DataArray type=\"Float64\" Name=\"TimeValue\" NumberOfTuples=\"1\" format=\"ascii\">{}</DataArray>",
+            diagnostics.simulated_time.as_secs_f64()
+        )?;
+        for (name, value) in diagnostics.named_values().iter() {
+            writeln!(
+                writer,
+                "      <DataArray type=\"Float64\" Name=\"{}\" NumberOfTuples=\"1\" format=\"ascii\">{}</DataArray>",
+                name, value
+            )?;
+        }
+        writeln!(writer, "    </FieldData>")?;
+    }
+    writeln!(writer, "    <Piece Extent=\"{}\">", extent)?;
+    writeln!(writer, "      <PointData Scalars=\"{}\">", volumes[0].volume.name())?;
+    let mut offset = 0;
+    for volume in volumes.iter() {
+        writeln!(
+            writer,
+            "        <DataArray type=\"Float32\" Name=\"{}\" format=\"appended\" offset=\"{}\"/>",
+            volume.volume.name(),
+            offset
+        )?;
+        offset += std::mem::size_of::<u64>() + volume.values.len() * std::mem::size_of::<f32>();
+    }
+    writeln!(writer, "      </PointData>")?;
+    writeln!(writer, "    </Piece>")?;
+    writeln!(writer, "  </ImageData>")?;
+    write!(writer, "  <AppendedData encoding=\"raw\">\n_")?;
+    for volume in volumes.iter() {
+        writer.write_all(&((volume.values.len() * std::mem::size_of::<f32>()) as u64).to_le_bytes())?;
+        for value in volume.values.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writeln!(writer, "\n  </AppendedData>")?;
+    writeln!(writer, "</VTKFile>")?;
+    writer.flush()
+}
+
+fn write_nrrd(path: &Path, volume_set: &VolumeSet, volume: &VolumeData) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let dimension = volume_set.dimension;
+    let origin = volume_set.world_origin(volume.volume);
+    let spacing = volume_set.grid_to_world_scale;
+
+    writeln!(writer, "NRRD0004")?;
+    writeln!(writer, "# Blub simulation volume: {}", volume.volume.name())?;
+    writeln!(writer, "type: float")?;
+    writeln!(writer, "dimension: 3")?;
+    writeln!(writer, "space dimension: 3")?;
+    writeln!(writer, "sizes: {} {} {}", dimension.width, dimension.height, dimension.depth)?;
+    writeln!(writer, "space directions: ({},0,0) (0,{},0) (0,0,{})", spacing, spacing, spacing)?;
+    writeln!(writer, "space origin: ({},{},{})", origin.x, origin.y, origin.z)?;
+    writeln!(writer, "kinds: domain domain domain")?;
+    writeln!(writer, "endian: little")?;
+    writeln!(writer, "encoding: raw")?;
+    if let Some(diagnostics) = &volume_set.diagnostics {
+        for (name, value) in diagnostics.named_values().iter() {
+            writeln!(writer, "{}:={}", name, value)?;
+        }
+    }
+    writeln!(writer)?;
+    for value in volume.values.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+fn write_volume_set(
+    volume_set: &VolumeSet,
+    format: VolumeFileFormat,
+    output_dir: &Path,
+    file_prefix: &str,
+    file_suffix: &str,
+) -> std::io::Result<()> {
+    match format {
+        VolumeFileFormat::Vti => {
+            let (cell_centered, staggered): (Vec<&VolumeData>, Vec<&VolumeData>) = volume_set
+                .volumes
+                .iter()
+                .partition(|volume| volume.volume.sample_origin_grid() == cgmath::point3(0.5, 0.5, 0.5));
+            if !cell_centered.is_empty() {
+                write_vti(
+                    &output_dir.join(format!("{}cells{}.vti", file_prefix, file_suffix)),
+                    volume_set,
+                    &cell_centered,
+                )?;
+            }
+            for volume in staggered {
+                write_vti(
+                    &output_dir.join(format!("{}{}{}.vti", file_prefix, volume.volume.name(), file_suffix)),
+                    volume_set,
+                    &[volume],
+                )?;
+            }
+        }
+        VolumeFileFormat::Nrrd => {
+            for volume in volume_set.volumes.iter() {
+                write_nrrd(
+                    &output_dir.join(format!("{}{}{}.nrrd", file_prefix, volume.volume.name(), file_suffix)),
+                    volume_set,
+                    volume,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+const MAX_PENDING_EXPORTS: usize = 4;
+
+// Reads back simulation grid volumes and writes them to disk on a separate thread.
+pub struct VolumeExporter {
+    output_dir: PathBuf,
+    pub format: VolumeFileFormat,
+    // If true, volumes are written for every frame of a recording.
+    pub export_during_recording: bool,
+    // Divergence and density error are only available if the fluid copies them aside every step.
+    pub capture_debug_volumes: bool,
+
+    next_regular_export_index: usize,
+    pending_exports: VecDeque<JoinHandle<()>>,
+}
+
+impl VolumeExporter {
+    pub fn new(output_dir: &Path) -> Self {
+        let mut next_regular_export_index = 0;
+        for i in 1..usize::MAX {
+            if !Self::regular_export_dir(output_dir, i).exists() {
+                next_regular_export_index = i;
+                break;
+            }
+        }
+
+        VolumeExporter {
+            output_dir: output_dir.to_path_buf(),
+            format: VolumeFileFormat::Vti,
+            export_during_recording: false,
+            capture_debug_volumes: false,
+            next_regular_export_index,
+            pending_exports: VecDeque::new(),
+        }
+    }
+
+    fn regular_export_dir(output_dir: &Path, index: usize) -> PathBuf {
+        output_dir.join(format!("volumes{}", index))
+    }
+
+    // Exports all volumes into a new directory.
+    pub fn export_next(&mut self, scene: &Scene, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let output_dir = Self::regular_export_dir(&self.output_dir, self.next_regular_export_index);
+        self.next_regular_export_index += 1;
+        if let Err(err) = std::fs::create_dir_all(&output_dir) {
+            error!("Failed to create volume export directory {:?}: {}", output_dir, err);
+            return;
+        }
+        self.export(scene, device, queue, &output_dir, "");
+    }
+
+    // Exports all volumes into a recording directory, file names are suffixed with the frame index.
+    pub fn export_recording_frame(
+        &mut self,
+        scene: &Scene,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        recording_output_dir: &Path,
+        frame_index: usize,
+    ) {
+        self.export(scene, device, queue, recording_output_dir, &frame_index.to_string());
+    }
+
+    // Reads back the volumes (stalls!) and writes them in the background.
+    fn export(&mut self, scene: &Scene, device: &wgpu::Device, queue: &wgpu::Queue, output_dir: &Path, file_suffix: &str) {
+        while self.pending_exports.len() >= MAX_PENDING_EXPORTS {
+            self.pending_exports.pop_front().unwrap().join().unwrap();
+        }
+
+        let num_fluids = scene.fluids().len();
+        let mut volume_sets = Vec::new();
+        for (fluid_index, (fluid, fluid_config)) in scene.fluids().iter().zip(scene.config().fluids.iter()).enumerate() {
+            let mut volumes = Vec::new();
+            for volume in SimulationVolume::iter() {
+                match fluid.read_volume(device, queue, volume) {
+                    Some(values) => volumes.push(VolumeData { volume, values }),
+                    None => warn!(
+                        "Skipping export of {}, enable capture of divergence & density error and step the simulation first",
+                        volume.name()
+                    ),
+                }
+            }
+            let volume_set = VolumeSet {
+                dimension: fluid.grid_dimension(),
+                world_position: fluid_config.world_position,
+                grid_to_world_scale: fluid_config.grid_to_world_scale,
+                volumes,
+                diagnostics: fluid.diagnostics_history().back().cloned(),
+            };
+            volume_sets.push((fluid_file_prefix(num_fluids, fluid_index), volume_set));
+        }
+
+        let format = self.format;
+        let output_dir = output_dir.to_path_buf();
+        let file_suffix = file_suffix.to_owned();
+        self.pending_exports.push_back(std::thread::spawn(move || {
+            let start_time = std::time::Instant::now();
+            let result = volume_sets
+                .iter()
+                .try_for_each(|(file_prefix, volume_set)| write_volume_set(volume_set, format, &output_dir, file_prefix, &file_suffix));
+            match result {
+                Ok(()) => info!("Wrote simulation volumes to {:?} (took {:?})", output_dir, start_time.elapsed()),
+                Err(err) => error!("Failed to write simulation volumes to {:?}: {}", output_dir, err),
+            }
+        }));
+    }
+
+    pub fn wait_for_pending_exports(&mut self) {
+        for handle in self.pending_exports.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
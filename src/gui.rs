@@ -1,17 +1,31 @@
-use crate::renderer::{FluidRenderingMode, SceneRenderer, VolumeVisualizationMode};
-use crate::simulation_controller::{SimulationController, SimulationControllerStatus};
-use crate::{
-    render_output::screen::Screen,
-    scene::Scene,
-    simulation::{HybridFluid, SolverConfig, SolverStatisticSample},
-    ApplicationEvent,
+use crate::ApplicationEvent;
+use blub::renderer::{FluidRenderingMode, SceneRenderer, TonemapOperator, VolumeVisualizationMode};
+use blub::simulation_controller::{SimulationController, SimulationControllerStatus};
+use blub::{
+    camera::{Camera, CameraMode},
+    camera_path::{CameraPathController, CameraPathMode},
+    export::{
+        diagnostics_export::DiagnosticsExporter,
+        surface_mesh::SurfaceMeshExporter,
+        volume_export::{VolumeExporter, VolumeFileFormat},
+    },
+    render_output::{hdr_capture::HdrImageFormat, screen::Screen, screenshot_recorder::ScreenshotRecorder, video_stream::RecordingFormat},
+    scene::{Scene, SceneLoadError, SimulationSettings},
+    simulation::{DiagnosticsSample, HybridFluid, SolverConfig, SolverStatisticSample},
+    wgpu_utils::profiler::{GpuProfiler, ProfilerScope},
 };
+use cgmath::{EuclideanSpace, InnerSpace};
 use imgui::im_str;
-use std::{borrow::Cow, collections::VecDeque, path::PathBuf, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use strum::IntoEnumIterator;
 use winit::event_loop::EventLoopProxy;
 
-const SCENE_DIRECTORY: &str = "scenes";
+pub const SCENE_DIRECTORY: &str = "scenes";
 
 fn list_scene_files() -> Vec<PathBuf> {
     let files: Vec<PathBuf> = std::fs::read_dir(SCENE_DIRECTORY)
@@ -32,8 +46,17 @@ fn list_scene_files() -> Vec<PathBuf> {
 pub struct GUIState {
     fast_forward_length_seconds: f32,
     video_fps: i32,
+    record_offscreen: bool,
+    offscreen_resolution: [i32; 2],
+    cpu_reference_num_steps: i32,
     selected_scene_idx: usize,
     known_scene_files: Vec<PathBuf>,
+    scene_load_error: Option<SceneLoadError>,
+    camera_preset_name: imgui::ImString,
+    camera_path_file: imgui::ImString,
+    color_grading_lut_file: imgui::ImString,
+    // Scrub target while the timeline slider is dragged.
+    timeline_scrub_time: Option<f32>,
     wait_for_vblank: bool,
 }
 pub struct GUI {
@@ -45,7 +68,13 @@ pub struct GUI {
 }
 
 impl GUI {
-    pub fn new(device: &wgpu::Device, window: &winit::window::Window, command_queue: &mut wgpu::Queue) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        window: &winit::window::Window,
+        command_queue: &mut wgpu::Queue,
+        initial_scene: Option<&Path>,
+        present_mode: wgpu::PresentMode,
+    ) -> Self {
         let mut imgui_context = imgui::Context::create();
 
         let mut imgui_platform = imgui_winit_support::WinitPlatform::init(&mut imgui_context);
@@ -67,6 +96,16 @@ impl GUI {
 
         let imgui_renderer = imgui_wgpu::Renderer::new(&mut imgui_context, device, command_queue, Screen::FORMAT_BACKBUFFER, None);
 
+        // Scenes from outside the scene directory are added to the list, so they can be reloaded the same way.
+        let mut known_scene_files = list_scene_files();
+        let selected_scene_idx = match initial_scene {
+            Some(initial_scene) => known_scene_files.iter().position(|path| path == initial_scene).unwrap_or_else(|| {
+                known_scene_files.push(initial_scene.to_path_buf());
+                known_scene_files.len() - 1
+            }),
+            None => 0,
+        };
+
         GUI {
             imgui_context,
             imgui_platform,
@@ -75,9 +114,17 @@ impl GUI {
             state: GUIState {
                 fast_forward_length_seconds: 5.0,
                 video_fps: 60,
-                selected_scene_idx: 0,
-                known_scene_files: list_scene_files(),
-                wait_for_vblank: Screen::DEFAULT_PRESENT_MODE == wgpu::PresentMode::Fifo,
+                record_offscreen: false,
+                offscreen_resolution: [3840, 2160],
+                cpu_reference_num_steps: 10,
+                selected_scene_idx,
+                known_scene_files,
+                scene_load_error: None,
+                camera_preset_name: imgui::ImString::with_capacity(64),
+                camera_path_file: imgui::ImString::with_capacity(256),
+                color_grading_lut_file: imgui::ImString::with_capacity(256),
+                timeline_scrub_time: None,
+                wait_for_vblank: present_mode == wgpu::PresentMode::Fifo,
             },
         }
     }
@@ -86,8 +133,43 @@ impl GUI {
         &self.state.known_scene_files[self.state.selected_scene_idx]
     }
 
+    pub fn known_scene_files(&self) -> &[PathBuf] {
+        &self.state.known_scene_files
+    }
+
+    // Selects a scene that was loaded without going through the gui.
+    pub fn select_scene(&mut self, scene_path: &Path) {
+        if let Some(idx) = self.state.known_scene_files.iter().position(|path| path == scene_path) {
+            self.state.selected_scene_idx = idx;
+        }
+    }
+
+    // Shown in its own window until the next successful scene load or until it is closed.
+    pub fn set_scene_load_error(&mut self, error: Option<SceneLoadError>) {
+        self.state.scene_load_error = error;
+    }
+
     const DEFAULT_BUTTON_HEIGHT: f32 = 19.0;
 
+    fn setup_ui_scene_load_error(ui: &imgui::Ui, state: &mut GUIState) {
+        let mut opened = true;
+        if let Some(error) = &state.scene_load_error {
+            imgui::Window::new(im_str!("Scene Errors"))
+                .position([400.0, 0.0], imgui::Condition::FirstUseEver)
+                .always_auto_resize(true)
+                .opened(&mut opened)
+                .build(&ui, || {
+                    ui.text(im_str!("Failed to load {:?}", error.scene_path));
+                    for problem in error.problems.iter() {
+                        ui.text_colored([1.0, 0.4, 0.4, 1.0], im_str!("{}", problem));
+                    }
+                });
+        }
+        if !opened {
+            state.scene_load_error = None;
+        }
+    }
+
     fn setup_ui_timer(
         ui: &imgui::Ui,
         state: &mut GUIState,
@@ -133,6 +215,55 @@ impl GUI {
         ));
     }
 
+    fn setup_ui_timeline(
+        ui: &imgui::Ui,
+        state: &mut GUIState,
+        simulation_controller: &SimulationController,
+        scene: &Scene,
+        event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
+    ) {
+        let timeline = &scene.config().timeline;
+        if timeline.is_empty() {
+            ui.text_disabled(im_str!("scene has no timeline"));
+            return;
+        }
+
+        // Dragging the slider doesn't do anything until it's released. Simulation can only go forward, so going back means a reset.
+        let current_time = simulation_controller.timer().total_simulated_time().as_secs_f32();
+        let duration = timeline.iter().map(|track| track.duration()).fold(0.0, f32::max);
+        let mut scrub_time = state.timeline_scrub_time.unwrap_or(current_time);
+        ui.set_next_item_width(300.0);
+        imgui::Slider::new(im_str!("simulated time"))
+            .range(0.0..=duration.max(current_time))
+            .display_format(im_str!("%.2fs"))
+            .build(&ui, &mut scrub_time);
+        if ui.is_item_active() {
+            state.timeline_scrub_time = Some(scrub_time);
+        } else if let Some(scrub_time) = state.timeline_scrub_time.take() {
+            let jump_start = if scrub_time < current_time {
+                event_loop_proxy.send_event(ApplicationEvent::ResetScene).unwrap();
+                0.0
+            } else {
+                current_time
+            };
+            if scrub_time > jump_start {
+                event_loop_proxy
+                    .send_event(ApplicationEvent::FastForwardSimulation(Duration::from_secs_f32(scrub_time - jump_start)))
+                    .unwrap();
+            }
+        }
+
+        for track in timeline.iter() {
+            let value = track.evaluate(current_time);
+            if track.property.is_vector() {
+                let value = value.vector();
+                ui.text(im_str!("{}: ({:.3}, {:.3}, {:.3})", track.label(), value.x, value.y, value.z));
+            } else {
+                ui.text(im_str!("{}: {}", track.label(), value.scalar()));
+            }
+        }
+    }
+
     fn setup_ui_solver_stats(ui: &imgui::Ui, stats: &VecDeque<SolverStatisticSample>, max_iterations: i32, target_mse: f32) {
         let newest_sample = match stats.back() {
             Some(&sample) => sample,
@@ -180,6 +311,13 @@ impl GUI {
         }
     }
 
+    // Panels that exist per fluid domain are only titled if there is more than one domain.
+    fn setup_ui_fluid_domain_title(ui: &imgui::Ui, num_fluids: usize, fluid_index: usize) {
+        if num_fluids > 1 {
+            ui.text(im_str!("fluid domain {}", fluid_index));
+        }
+    }
+
     fn setup_ui_solver(ui: &imgui::Ui, fluid: &mut HybridFluid) {
         let stack_token = ui.push_id(1);
         {
@@ -200,10 +338,154 @@ impl GUI {
         }
     }
 
+    fn setup_ui_diagnostics(ui: &imgui::Ui, history: &VecDeque<DiagnosticsSample>) {
+        let newest_sample = match history.back() {
+            Some(&sample) => sample,
+            None => Default::default(),
+        };
+        let plot = |label: &imgui::ImStr, value: &dyn Fn(&DiagnosticsSample) -> f32| {
+            ui.plot_lines(label, &history.iter().map(value).collect::<Vec<f32>>())
+                .graph_size([300.0, 40.0])
+                .build();
+        };
+
+        ui.text(im_str!(
+            "simulated time of last sample: {:.2}",
+            newest_sample.simulated_time.as_secs_f64()
+        ));
+        plot(&im_str!("# fluid cells - {}", newest_sample.num_fluid_cells), &|sample| {
+            sample.num_fluid_cells as f32
+        });
+        plot(&im_str!("# particles - {}", newest_sample.num_particles), &|sample| {
+            sample.num_particles as f32
+        });
+        plot(&im_str!("mean density error - {:.4}", newest_sample.density_error_mean), &|sample| {
+            sample.density_error_mean
+        });
+        plot(&im_str!("max density error - {:.4}", newest_sample.density_error_max), &|sample| {
+            sample.density_error_max
+        });
+        plot(&im_str!("divergence rms - {:.4}", newest_sample.divergence_rms), &|sample| {
+            sample.divergence_rms
+        });
+        plot(&im_str!("divergence max - {:.4}", newest_sample.divergence_max), &|sample| {
+            sample.divergence_max
+        });
+        ui.separator();
+        plot(&im_str!("kinetic energy - {:.1}", newest_sample.kinetic_energy), &|sample| {
+            sample.kinetic_energy
+        });
+        let linear_momentum = newest_sample.linear_momentum;
+        plot(
+            &im_str!(
+                "linear momentum - ({:.1}, {:.1}, {:.1})",
+                linear_momentum.x,
+                linear_momentum.y,
+                linear_momentum.z
+            ),
+            &|sample| sample.linear_momentum.magnitude(),
+        );
+        let angular_momentum = newest_sample.angular_momentum;
+        plot(
+            &im_str!(
+                "angular momentum - ({:.1}, {:.1}, {:.1})",
+                angular_momentum.x,
+                angular_momentum.y,
+                angular_momentum.z
+            ),
+            &|sample| sample.angular_momentum.magnitude(),
+        );
+    }
+
+    // Scopes with the same label (e.g. several simulation steps in a frame) are merged since averages are kept per scope path anyways.
+    fn setup_ui_profiler_scopes(ui: &imgui::Ui, profiler: &GpuProfiler, scopes: &[&ProfilerScope], parent_path: &str, parent_time: Option<f64>) {
+        let mut labels: Vec<&str> = Vec::new();
+        for scope in scopes.iter() {
+            if !labels.contains(&scope.label.as_str()) {
+                labels.push(&scope.label);
+            }
+        }
+
+        for label in labels.iter() {
+            let path = GpuProfiler::scope_path(parent_path, label);
+            let (cpu, gpu) = match profiler.scope_average(&path) {
+                Some(average) => average,
+                None => continue,
+            };
+            let same_label_scopes = scopes.iter().filter(|scope| scope.label == *label);
+            let num_occurrences = same_label_scopes.clone().count();
+            let children: Vec<&ProfilerScope> = same_label_scopes.flat_map(|scope| scope.children.iter()).collect();
+
+            // Gpu time is what we're usually after, cpu time is only a fallback.
+            let time = gpu.unwrap_or(cpu);
+            let mut text = match gpu {
+                Some(gpu) => format!("{} - gpu {:.3}ms, cpu {:.3}ms", label, gpu * 1000.0, cpu * 1000.0),
+                None => format!("{} - cpu {:.3}ms", label, cpu * 1000.0),
+            };
+            if let Some(parent_time) = parent_time {
+                if parent_time > 0.0 {
+                    text += &format!(" ({:.1}%)", time / parent_time * 100.0);
+                }
+            }
+            if num_occurrences > 1 {
+                text += &format!(" x{}", num_occurrences);
+            }
+
+            let id = imgui::ImString::new(path.clone());
+            let text = imgui::ImString::new(text);
+            imgui::TreeNode::new(&id).label(&text).leaf(children.is_empty()).build(ui, || {
+                Self::setup_ui_profiler_scopes(ui, profiler, &children, &path, Some(time));
+            });
+        }
+    }
+
+    fn setup_ui_profiler(ui: &imgui::Ui, profiler: &mut GpuProfiler, event_loop_proxy: &EventLoopProxy<ApplicationEvent>) {
+        ui.checkbox(im_str!("enable profiler"), &mut profiler.enabled);
+        if !profiler.timestamps_supported() {
+            ui.text(im_str!("adapter doesn't support timestamp queries, cpu timings only"));
+        }
+        if ui.button(im_str!("Export Profile (Chrome Trace)"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::ExportProfilerTrace).unwrap();
+        }
+        ui.separator();
+
+        let finished_frames = profiler.finished_frames();
+        match finished_frames.back() {
+            Some(newest_frame) => {
+                let average_frame_time =
+                    finished_frames.iter().map(|frame| frame.cpu.end - frame.cpu.start).sum::<f64>() / finished_frames.len() as f64;
+                ui.text(im_str!(
+                    "frame {}, averaged over {} frames: {:.3}ms",
+                    newest_frame.frame_index,
+                    finished_frames.len(),
+                    average_frame_time * 1000.0
+                ));
+                let scopes: Vec<&ProfilerScope> = newest_frame.scopes.iter().collect();
+                Self::setup_ui_profiler_scopes(ui, profiler, &scopes, "", None);
+            }
+            None => ui.text(im_str!("no profiled frames yet")),
+        }
+    }
+
+    fn setup_ui_cpu_reference(ui: &imgui::Ui, state: &mut GUIState, event_loop_proxy: &EventLoopProxy<ApplicationEvent>) {
+        ui.text(im_str!("cpu reference (slow! results are logged)"));
+        imgui::Drag::new(im_str!("steps to compare"))
+            .range(1..=1000)
+            .build(&ui, &mut state.cpu_reference_num_steps);
+        if ui.button(im_str!("Compare with CPU"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy
+                .send_event(ApplicationEvent::CompareWithCpuReference {
+                    num_steps: state.cpu_reference_num_steps as u32,
+                })
+                .unwrap();
+        }
+    }
+
     fn setup_ui_simulation_control(
         ui: &imgui::Ui,
         state: &mut GUIState,
         simulation_controller: &mut SimulationController,
+        screenshot_recorder: &mut ScreenshotRecorder,
         event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
     ) {
         ui.text(im_str!(
@@ -228,7 +510,8 @@ impl GUI {
             .enter_returns_true(true)
             .build()
         {
-            simulation_controller.set_simulation_steps_per_second(simulation_steps_per_second.max(20).min(60 * 20) as u64);
+            let range = SimulationSettings::STEPS_PER_SECOND_RANGE;
+            simulation_controller.set_simulation_steps_per_second((simulation_steps_per_second.max(0) as u64).max(*range.start()).min(*range.end()));
         }
 
         if ui
@@ -237,7 +520,8 @@ impl GUI {
             .enter_returns_true(true)
             .build()
         {
-            simulation_controller.time_scale = simulation_controller.time_scale.max(0.01).min(100.0);
+            let range = SimulationSettings::TIME_SCALE_RANGE;
+            simulation_controller.time_scale = simulation_controller.time_scale.max(*range.start()).min(*range.end());
         }
 
         {
@@ -277,6 +561,10 @@ impl GUI {
             ui.text_disabled(im_str!("last jump took {:?}", simulation_controller.computation_time_last_fast_forward()));
         }
 
+        if ui.button(im_str!("Save Settings to Scene"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::SaveSimulationSettings).unwrap();
+        }
+
         if let SimulationControllerStatus::RecordingWithFixedFrameLength { .. } = simulation_controller.status() {
             if ui.button(im_str!("End Recording"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
                 simulation_controller.pause_or_resume();
@@ -286,6 +574,14 @@ impl GUI {
                 event_loop_proxy
                     .send_event(ApplicationEvent::ResetAndStartRecording {
                         recording_fps: state.video_fps as f64,
+                        offscreen_resolution: if state.record_offscreen {
+                            Some(winit::dpi::PhysicalSize::new(
+                                state.offscreen_resolution[0] as u32,
+                                state.offscreen_resolution[1] as u32,
+                            ))
+                        } else {
+                            None
+                        },
                     })
                     .unwrap();
             }
@@ -293,10 +589,63 @@ impl GUI {
             ui.set_next_item_width(40.0);
 
             imgui::Drag::new(im_str!("video fps")).range(10..=300).build(&ui, &mut state.video_fps);
+
+            ui.checkbox(im_str!("render offscreen"), &mut state.record_offscreen);
+            if state.record_offscreen {
+                ui.same_line(0.0);
+                ui.set_next_item_width(100.0);
+                imgui::Drag::new(im_str!("resolution"))
+                    .range(16..=8192)
+                    .build_array(&ui, &mut state.offscreen_resolution);
+            }
+
+            let mut current_format = screenshot_recorder.recording_format as usize;
+            ui.set_next_item_width(150.0);
+            imgui::ComboBox::new(im_str!("recording format")).build_simple(
+                ui,
+                &mut current_format,
+                &RecordingFormat::iter().collect::<Vec<RecordingFormat>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            screenshot_recorder.recording_format = RecordingFormat::iter().skip(current_format).next().unwrap();
+            if screenshot_recorder.recording_format == RecordingFormat::Encoder {
+                let mut encoder_command = imgui::ImString::with_capacity(screenshot_recorder.encoder_command.len() + 256);
+                encoder_command.push_str(&screenshot_recorder.encoder_command);
+                ui.set_next_item_width(400.0);
+                if ui.input_text(im_str!("encoder"), &mut encoder_command).build() {
+                    screenshot_recorder.encoder_command = encoder_command.to_str().to_owned();
+                }
+                ui.text_disabled(im_str!("raw rgb24 frames on stdin, {width} {height} {fps} {output} are replaced"));
+            }
+
+            // Png or one of the hdr formats, which capture before tonemapping.
+            let mut current_screenshot_format = screenshot_recorder.hdr_screenshot_format.map_or(0, |format| format as usize + 1);
+            ui.set_next_item_width(150.0);
+            imgui::ComboBox::new(im_str!("screenshot format")).build_simple(
+                ui,
+                &mut current_screenshot_format,
+                &std::iter::once(None).chain(HdrImageFormat::iter().map(Some)).collect::<Vec<_>>(),
+                &|value| match value {
+                    Some(format) => Cow::from(im_str!("{:?}", format)),
+                    None => Cow::from(im_str!("Png")),
+                },
+            );
+            screenshot_recorder.hdr_screenshot_format = match current_screenshot_format {
+                0 => None,
+                index => HdrImageFormat::iter().nth(index - 1),
+            };
+            if screenshot_recorder.hdr_screenshot_format.is_some() || screenshot_recorder.recording_format.hdr_image_format().is_some() {
+                ui.checkbox(im_str!("include depth in hdr captures"), &mut screenshot_recorder.hdr_include_depth);
+            }
         }
     }
 
-    fn setup_ui_rendersettings(ui: &imgui::Ui, scene_renderer: &mut SceneRenderer) {
+    fn setup_ui_rendersettings(
+        ui: &imgui::Ui,
+        state: &mut GUIState,
+        scene_renderer: &mut SceneRenderer,
+        event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
+    ) {
         {
             let mut current_fluid_rendering = scene_renderer.fluid_rendering_mode as usize;
             imgui::ComboBox::new(im_str!("Fluid Rendering")).build_simple(
@@ -331,14 +680,237 @@ impl GUI {
             .display_format(im_str!("%.3f"))
             .build(&ui, &mut scene_renderer.velocity_visualization_scale);
         ui.checkbox(im_str!("Show Fluid Domain Bounds"), &mut scene_renderer.enable_box_lines);
+        if ui.button(im_str!("Save Camera"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::SaveCamera).unwrap();
+        }
+
+        ui.separator();
+        let tonemapper = &mut scene_renderer.tonemapper;
+        {
+            let mut current_operator = tonemapper.operator as usize;
+            imgui::ComboBox::new(im_str!("Tonemapping")).build_simple(
+                ui,
+                &mut current_operator,
+                &TonemapOperator::iter().collect::<Vec<TonemapOperator>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            tonemapper.operator = TonemapOperator::iter().skip(current_operator).next().unwrap();
+        }
+        imgui::Drag::new(im_str!("Exposure (EV)"))
+            .range(-10.0..=10.0)
+            .speed(0.05)
+            .display_format(im_str!("%.2f"))
+            .build(&ui, &mut tonemapper.exposure_ev);
+        ui.checkbox(im_str!("Auto Exposure"), &mut tonemapper.auto_exposure);
+        if tonemapper.auto_exposure {
+            imgui::Drag::new(im_str!("Adaptation Rate"))
+                .range(0.1..=20.0)
+                .speed(0.05)
+                .display_format(im_str!("%.2f"))
+                .build(&ui, &mut tonemapper.auto_exposure_adaptation_rate);
+            imgui::Drag::new(im_str!("Min Log Luminance"))
+                .range(-20.0..=tonemapper.auto_exposure_max_log_luminance - 1.0)
+                .speed(0.1)
+                .display_format(im_str!("%.1f"))
+                .build(&ui, &mut tonemapper.auto_exposure_min_log_luminance);
+            imgui::Drag::new(im_str!("Max Log Luminance"))
+                .range(tonemapper.auto_exposure_min_log_luminance + 1.0..=20.0)
+                .speed(0.1)
+                .display_format(im_str!("%.1f"))
+                .build(&ui, &mut tonemapper.auto_exposure_max_log_luminance);
+        }
+
+        match tonemapper.color_grading_lut_path() {
+            Some(path) => ui.text(im_str!("color grading lut: {:?}", path)),
+            None => ui.text(im_str!("color grading lut: none")),
+        }
+        ui.set_next_item_width(150.0);
+        ui.input_text(im_str!("Lut File (.cube)"), &mut state.color_grading_lut_file).build();
+        if ui.button(im_str!("Load Lut"), [100.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            let path = PathBuf::from(state.color_grading_lut_file.to_str().trim());
+            event_loop_proxy.send_event(ApplicationEvent::LoadColorGradingLut(path)).unwrap();
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear Lut"), [100.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::ClearColorGradingLut).unwrap();
+        }
+        imgui::Slider::new(im_str!("Lut Strength"))
+            .range(0.0..=1.0)
+            .display_format(im_str!("%.2f"))
+            .build(&ui, &mut tonemapper.color_grading_lut_strength);
+    }
+
+    fn setup_ui_camera(ui: &imgui::Ui, camera: &mut Camera, scene: &Scene) {
+        let (bounds_min, bounds_max) = scene.world_bounds();
+        {
+            let mut current_mode = camera.mode() as usize;
+            ui.set_next_item_width(150.0);
+            if imgui::ComboBox::new(im_str!("Camera Mode")).build_simple(
+                ui,
+                &mut current_mode,
+                &CameraMode::iter().collect::<Vec<CameraMode>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            ) {
+                let mode = CameraMode::iter().skip(current_mode).next().unwrap();
+                camera.set_mode(mode, bounds_min.midpoint(bounds_max));
+            }
+        }
+        if ui.button(im_str!("Frame Fluid Domain"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            camera.frame_bounds(bounds_min, bounds_max);
+        }
+    }
+
+    fn setup_ui_camera_presets(ui: &imgui::Ui, state: &mut GUIState, scene: &Scene, event_loop_proxy: &EventLoopProxy<ApplicationEvent>) {
+        ui.text(im_str!("camera presets"));
+        if let Some(viewpoint) = &scene.config().camera {
+            if ui.button(im_str!("Scene Start"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+                event_loop_proxy
+                    .send_event(ApplicationEvent::SetCameraViewpoint(viewpoint.clone()))
+                    .unwrap();
+            }
+        }
+        for (preset_index, preset) in scene.config().camera_presets.iter().enumerate() {
+            let stack_token = ui.push_id(preset_index as i32);
+            if ui.button(&im_str!("{}", preset.name), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+                event_loop_proxy.send_event(ApplicationEvent::SetCameraViewpoint(preset.clone())).unwrap();
+            }
+            stack_token.pop(ui);
+        }
+
+        ui.set_next_item_width(150.0);
+        ui.input_text(im_str!("Preset Name"), &mut state.camera_preset_name).build();
+        let name = state.camera_preset_name.to_str().trim().to_owned();
+        if ui.button(im_str!("Save Camera as Preset"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            if name.is_empty() {
+                error!("Camera preset needs a name");
+            } else {
+                event_loop_proxy.send_event(ApplicationEvent::SaveCameraPreset(name)).unwrap();
+                state.camera_preset_name.clear();
+            }
+        }
+    }
+
+    fn setup_ui_camera_path(
+        ui: &imgui::Ui,
+        state: &mut GUIState,
+        camera_path_controller: &mut CameraPathController,
+        event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
+    ) {
+        ui.text(im_str!(
+            "{} keyframes, {:.2}s",
+            camera_path_controller.path.keyframes.len(),
+            camera_path_controller.path.duration()
+        ));
+        {
+            let mut current_mode = camera_path_controller.mode as usize;
+            ui.set_next_item_width(150.0);
+            imgui::ComboBox::new(im_str!("Mode")).build_simple(
+                ui,
+                &mut current_mode,
+                &CameraPathMode::iter().collect::<Vec<CameraPathMode>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            camera_path_controller.mode = CameraPathMode::iter().skip(current_mode).next().unwrap();
+        }
+        ui.text_disabled(im_str!("playback follows simulated time,\nfor videos use a fixed fps recording"));
+        imgui::Drag::new(im_str!("Record Interval (s)"))
+            .range(0.01..=5.0)
+            .speed(0.01)
+            .display_format(im_str!("%.2f"))
+            .build(&ui, &mut camera_path_controller.record_interval);
+        if ui.button(im_str!("Add Keyframe"), [100.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::AddCameraPathKeyframe).unwrap();
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear"), [100.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            camera_path_controller.path.keyframes.clear();
+        }
+        if ui.button(im_str!("Save Camera Path"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::SaveCameraPath).unwrap();
+        }
+        ui.set_next_item_width(150.0);
+        ui.input_text(im_str!("File"), &mut state.camera_path_file).build();
+        if ui.button(im_str!("Load Camera Path"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            let path = PathBuf::from(state.camera_path_file.to_str().trim());
+            event_loop_proxy.send_event(ApplicationEvent::LoadCameraPath(path)).unwrap();
+        }
+    }
+
+    fn setup_ui_export(
+        ui: &imgui::Ui,
+        surface_mesh_exporter: &mut SurfaceMeshExporter,
+        volume_exporter: &mut VolumeExporter,
+        diagnostics_exporter: &mut DiagnosticsExporter,
+        event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
+    ) {
+        ui.text(im_str!("surface mesh (marching cubes over particle density)"));
+        ui.checkbox(
+            im_str!("write mesh for every recorded frame"),
+            &mut surface_mesh_exporter.export_during_recording,
+        );
+        ui.checkbox(im_str!("write normals"), &mut surface_mesh_exporter.settings.write_normals);
+        let mut samples_per_cell = surface_mesh_exporter.settings.samples_per_cell as i32;
+        if imgui::Drag::new(im_str!("samples per grid cell"))
+            .range(1..=4)
+            .build(&ui, &mut samples_per_cell)
+        {
+            surface_mesh_exporter.settings.samples_per_cell = samples_per_cell as u32;
+        }
+        imgui::Drag::new(im_str!("iso density"))
+            .range(0.05..=1.0)
+            .speed(0.01)
+            .display_format(im_str!("%.2f"))
+            .build(&ui, &mut surface_mesh_exporter.settings.iso_value);
+        if ui.button(im_str!("Export Surface Mesh (OBJ)"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::ExportSurfaceMesh).unwrap();
+        }
+        ui.separator();
+        ui.text(im_str!("simulation volumes (velocity, marker, pressure, ...)"));
+        ui.checkbox(
+            im_str!("write volumes for every recorded frame"),
+            &mut volume_exporter.export_during_recording,
+        );
+        ui.checkbox(
+            im_str!("capture divergence & density error (slower)"),
+            &mut volume_exporter.capture_debug_volumes,
+        );
+        {
+            let mut current_format = volume_exporter.format as usize;
+            imgui::ComboBox::new(im_str!("Volume File Format")).build_simple(
+                ui,
+                &mut current_format,
+                &VolumeFileFormat::iter().collect::<Vec<VolumeFileFormat>>(),
+                &|value| Cow::from(im_str!("{:?}", *value)),
+            );
+            volume_exporter.format = VolumeFileFormat::iter().skip(current_format).next().unwrap();
+        }
+        if ui.button(im_str!("Export Simulation Volumes"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::ExportSimulationVolumes).unwrap();
+        }
+        ui.separator();
+        ui.text(im_str!("diagnostics (density error, energy, momentum, ...)"));
+        ui.checkbox(
+            im_str!("write diagnostics of every step during recording"),
+            &mut diagnostics_exporter.export_during_recording,
+        );
+        if ui.button(im_str!("Export Diagnostics History (CSV)"), [208.0, Self::DEFAULT_BUTTON_HEIGHT]) {
+            event_loop_proxy.send_event(ApplicationEvent::ExportDiagnostics).unwrap();
+        }
     }
 
     fn setup_ui(
         ui: &imgui::Ui,
         state: &mut GUIState,
         simulation_controller: &mut SimulationController,
+        screenshot_recorder: &mut ScreenshotRecorder,
         scene_renderer: &mut SceneRenderer,
         scene: &mut Scene,
+        surface_mesh_exporter: &mut SurfaceMeshExporter,
+        volume_exporter: &mut VolumeExporter,
+        diagnostics_exporter: &mut DiagnosticsExporter,
+        camera: &mut Camera,
+        camera_path_controller: &mut CameraPathController,
+        profiler: &mut GpuProfiler,
         event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
     ) {
         let window = imgui::Window::new(im_str!("Blub"));
@@ -349,13 +921,36 @@ impl GUI {
                 Self::setup_ui_timer(ui, state, simulation_controller, event_loop_proxy);
 
                 if imgui::CollapsingHeader::new(im_str!("Solver")).build(&ui) {
-                    Self::setup_ui_solver(ui, scene.fluid_mut());
+                    let num_fluids = scene.fluids().len();
+                    for (fluid_index, fluid) in scene.fluids_mut().iter_mut().enumerate() {
+                        let stack_token = ui.push_id(fluid_index as i32);
+                        Self::setup_ui_fluid_domain_title(ui, num_fluids, fluid_index);
+                        Self::setup_ui_solver(ui, fluid);
+                        stack_token.pop(ui);
+                        ui.separator();
+                    }
+                    Self::setup_ui_cpu_reference(ui, state, event_loop_proxy);
+                }
+                if imgui::CollapsingHeader::new(im_str!("Diagnostics")).build(&ui) {
+                    let num_fluids = scene.fluids().len();
+                    for (fluid_index, fluid) in scene.fluids().iter().enumerate() {
+                        let stack_token = ui.push_id(fluid_index as i32);
+                        Self::setup_ui_fluid_domain_title(ui, num_fluids, fluid_index);
+                        Self::setup_ui_diagnostics(ui, fluid.diagnostics_history());
+                        stack_token.pop(ui);
+                    }
+                }
+                if imgui::CollapsingHeader::new(im_str!("Profiler")).build(&ui) {
+                    Self::setup_ui_profiler(ui, profiler, event_loop_proxy);
+                }
+                if imgui::CollapsingHeader::new(im_str!("Timeline")).build(&ui) {
+                    Self::setup_ui_timeline(ui, state, simulation_controller, scene, event_loop_proxy);
                 }
                 if imgui::CollapsingHeader::new(im_str!("Simulation Controller & Recording"))
                     .default_open(true)
                     .build(&ui)
                 {
-                    Self::setup_ui_simulation_control(ui, state, simulation_controller, event_loop_proxy);
+                    Self::setup_ui_simulation_control(ui, state, simulation_controller, screenshot_recorder, event_loop_proxy);
                 }
                 if imgui::CollapsingHeader::new(im_str!("Scene Settings")).build(&ui) {
                     ui.set_next_item_width(150.0);
@@ -369,11 +964,20 @@ impl GUI {
                             .send_event(ApplicationEvent::LoadScene(state.known_scene_files[state.selected_scene_idx].clone()))
                             .unwrap();
                     }
+                    Self::setup_ui_camera(ui, camera, scene);
+                    Self::setup_ui_camera_presets(ui, state, scene, event_loop_proxy);
+                }
+                if imgui::CollapsingHeader::new(im_str!("Camera Path")).build(&ui) {
+                    Self::setup_ui_camera_path(ui, state, camera_path_controller, event_loop_proxy);
                 }
                 if imgui::CollapsingHeader::new(im_str!("Rendering Settings")).build(&ui) {
-                    Self::setup_ui_rendersettings(ui, scene_renderer);
+                    Self::setup_ui_rendersettings(ui, state, scene_renderer, event_loop_proxy);
+                }
+                if imgui::CollapsingHeader::new(im_str!("Export")).build(&ui) {
+                    Self::setup_ui_export(ui, surface_mesh_exporter, volume_exporter, diagnostics_exporter, event_loop_proxy);
                 }
             });
+        Self::setup_ui_scene_load_error(ui, state);
     }
 
     pub fn draw(
@@ -384,8 +988,15 @@ impl GUI {
         queue: &wgpu::Queue,
         view: &wgpu::TextureView,
         simulation_controller: &mut SimulationController,
+        screenshot_recorder: &mut ScreenshotRecorder,
         scene_renderer: &mut SceneRenderer,
         scene: &mut Scene,
+        surface_mesh_exporter: &mut SurfaceMeshExporter,
+        volume_exporter: &mut VolumeExporter,
+        diagnostics_exporter: &mut DiagnosticsExporter,
+        camera: &mut Camera,
+        camera_path_controller: &mut CameraPathController,
+        profiler: &mut GpuProfiler,
         event_loop_proxy: &EventLoopProxy<ApplicationEvent>,
     ) {
         let context = &mut self.imgui_context;
@@ -395,13 +1006,32 @@ impl GUI {
             .prepare_frame(context.io_mut(), window)
             .expect("Failed to prepare imgui frame");
         let ui = context.frame();
-        Self::setup_ui(&ui, state, simulation_controller, scene_renderer, scene, event_loop_proxy);
+        Self::setup_ui(
+            &ui,
+            state,
+            simulation_controller,
+            screenshot_recorder,
+            scene_renderer,
+            scene,
+            surface_mesh_exporter,
+            volume_exporter,
+            diagnostics_exporter,
+            camera,
+            camera_path_controller,
+            profiler,
+            event_loop_proxy,
+        );
         self.imgui_platform.prepare_render(&ui, &window);
         self.imgui_renderer
             .render(ui.render(), &device, encoder, queue, view)
             .expect("IMGUI rendering failed");
     }
 
+    // True if the mouse is over a gui window, mouse input shouldn't go anywhere else then.
+    pub fn wants_mouse_input(&self) -> bool {
+        self.imgui_context.io().want_capture_mouse
+    }
+
     pub fn handle_event<T>(&mut self, window: &winit::window::Window, event: &winit::event::Event<T>) {
         self.imgui_platform.handle_event(self.imgui_context.io_mut(), window, event);
     }
@@ -0,0 +1,300 @@
+use crate::{
+    camera::Camera,
+    camera_path::CameraPath,
+    export::{diagnostics_export::DiagnosticsExporter, surface_mesh::SurfaceMeshExporter, volume_export::VolumeExporter},
+    per_frame_resources::PerFrameResources,
+    render_output::{hdr_capture::HdrImageFormat, offscreen_renderer::OffscreenRenderer, screenshot_recorder::ScreenshotRecorder},
+    renderer::FluidRenderingMode,
+    scene::Scene,
+    simulation::DiagnosticsSample,
+    simulation_controller::SimulationController,
+    wgpu_utils::{self, pipelines::PipelineManager, profiler::GpuProfiler, shader::ShaderDirectory},
+};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+// Headless mode runs a scene without window or swap chain, rendering only offscreen if at all.
+// Meant for compute servers and automated runs, all results are written to disk.
+
+// Creates a device without any surface, shared by everything that runs without window.
+pub async fn create_device() -> Result<(wgpu::Device, wgpu::Queue), String> {
+    let wgpu_instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+    // Without a surface any adapter will do, including software implementations like SwiftShader or lavapipe.
+    let adapter = wgpu_instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or("No suitable graphics adapter found")?;
+    info!("Running headless on {:?}", adapter.get_info());
+
+    adapter
+        .request_device(&wgpu_utils::device_descriptor(&adapter), None)
+        .await
+        .map_err(|err| format!("Failed to create device: {:?}", err))
+}
+
+pub enum HeadlessRunLength {
+    Steps(u32),
+    SimulatedTime(Duration),
+}
+
+pub struct HeadlessConfig {
+    pub scene_path: PathBuf,
+    pub run_length: HeadlessRunLength,
+    pub output_dir: PathBuf,
+    // Outputs are written after every interval of simulated time. If None, only the final state is written.
+    pub output_interval: Option<Duration>,
+    pub simulation_steps_per_second: Option<u64>,
+    pub export_surface_mesh: bool,
+    pub export_volumes: bool,
+    pub export_diagnostics: bool,
+    // If set, a rendered image is written along with every output.
+    pub render_resolution: Option<winit::dpi::PhysicalSize<u32>>,
+    pub camera_file: Option<PathBuf>,
+    // Camera follows this path over simulated time, replaces the camera file.
+    pub camera_path_file: Option<PathBuf>,
+    // If set, rendered images are captured before tonemapping and written in this format instead of png.
+    pub render_hdr_format: Option<HdrImageFormat>,
+    // Adds linear depth to hdr images.
+    pub render_hdr_depth: bool,
+    pub fluid_rendering_mode: Option<FluidRenderingMode>,
+}
+
+// Outcome of a finished headless run.
+pub struct HeadlessRunSummary {
+    pub num_steps: u32,
+    pub simulated_time: Duration,
+    pub computation_time: Duration,
+    // Diagnostics of the last step for every fluid domain, None if none arrived.
+    pub final_diagnostics: Vec<Option<DiagnosticsSample>>,
+}
+
+pub struct HeadlessApplication {
+    config: HeadlessConfig,
+
+    device: wgpu::Device,
+    command_queue: wgpu::Queue,
+
+    pipeline_manager: PipelineManager,
+    scene: Scene,
+    simulation_controller: SimulationController,
+    per_frame_resources: PerFrameResources,
+    // Never enabled, there is nobody to look at the results.
+    profiler: GpuProfiler,
+
+    surface_mesh_exporter: SurfaceMeshExporter,
+    volume_exporter: VolumeExporter,
+    diagnostics_exporter: DiagnosticsExporter,
+    offscreen_renderer: Option<OffscreenRenderer>,
+    camera: Camera,
+    camera_path: Option<CameraPath>,
+}
+
+impl HeadlessApplication {
+    pub async fn new(config: HeadlessConfig) -> Result<HeadlessApplication, String> {
+        let (device, command_queue) = create_device().await?;
+
+        let shader_dir = ShaderDirectory::new(Path::new("shader"));
+        let mut pipeline_manager = PipelineManager::new();
+        let per_frame_resources = PerFrameResources::new(&device);
+        let profiler = GpuProfiler::new(&device, &command_queue);
+        let mut scene = Scene::new(
+            &config.scene_path,
+            &device,
+            &command_queue,
+            &shader_dir,
+            &mut pipeline_manager,
+            per_frame_resources.bind_group_layout(),
+        )
+        .map_err(|err| err.to_string())?;
+        for fluid in scene.fluids_mut().iter_mut() {
+            fluid.set_debug_volume_capture(config.export_volumes);
+        }
+
+        let mut surface_mesh_exporter = SurfaceMeshExporter::new(&config.output_dir);
+        surface_mesh_exporter.export_during_recording = config.export_surface_mesh;
+        let mut volume_exporter = VolumeExporter::new(&config.output_dir);
+        volume_exporter.export_during_recording = config.export_volumes;
+        volume_exporter.capture_debug_volumes = config.export_volumes;
+        let diagnostics_exporter = DiagnosticsExporter::new(&config.output_dir);
+
+        let camera = match &config.camera_file {
+            Some(camera_file) => Camera::load(camera_file).map_err(|err| format!("Failed to load camera from {:?}: {}", camera_file, err))?,
+            None => scene.config().camera.as_ref().map_or_else(Camera::new, Camera::from_viewpoint),
+        };
+        let camera_path = match &config.camera_path_file {
+            Some(camera_path_file) => {
+                Some(CameraPath::load(camera_path_file).map_err(|err| format!("Failed to load camera path from {:?}: {}", camera_path_file, err))?)
+            }
+            None => None,
+        };
+        let offscreen_renderer = config.render_resolution.map(|resolution| {
+            let mut offscreen_renderer = OffscreenRenderer::new(
+                &device,
+                &command_queue,
+                resolution,
+                &shader_dir,
+                &mut pipeline_manager,
+                per_frame_resources.bind_group_layout(),
+                &scene,
+            );
+            if let Some(fluid_rendering_mode) = config.fluid_rendering_mode {
+                offscreen_renderer.scene_renderer_mut().fluid_rendering_mode = fluid_rendering_mode;
+            }
+            offscreen_renderer
+        });
+
+        let mut simulation_controller = SimulationController::new();
+        simulation_controller.apply_scene_settings(&scene.config().simulation);
+        if let Some(steps_per_second) = config.simulation_steps_per_second {
+            simulation_controller.set_simulation_steps_per_second(steps_per_second);
+        }
+
+        Ok(HeadlessApplication {
+            config,
+
+            device,
+            command_queue,
+
+            pipeline_manager,
+            scene,
+            simulation_controller,
+            per_frame_resources,
+            profiler,
+
+            surface_mesh_exporter,
+            volume_exporter,
+            diagnostics_exporter,
+            offscreen_renderer,
+            camera,
+            camera_path,
+        })
+    }
+
+    // Runs the simulation until the configured run length is reached, writing outputs along the way.
+    pub fn run(&mut self) -> Result<HeadlessRunSummary, String> {
+        let simulation_delta = self.simulation_controller.timer().simulation_delta();
+        let total_simulation_time = match self.config.run_length {
+            HeadlessRunLength::Steps(num_steps) => simulation_delta * num_steps,
+            HeadlessRunLength::SimulatedTime(duration) => duration,
+        };
+        // Otherwise nothing would be simulated and nothing written.
+        if total_simulation_time < simulation_delta {
+            return Err(format!(
+                "Run length of {:?} is shorter than one simulation step ({:?})",
+                total_simulation_time, simulation_delta
+            ));
+        }
+        std::fs::create_dir_all(&self.config.output_dir)
+            .map_err(|err| format!("Failed to create output directory {:?}: {}", self.config.output_dir, err))?;
+
+        let output_interval = match self.config.output_interval {
+            Some(interval) if interval > Duration::from_secs(0) => interval,
+            _ => total_simulation_time,
+        };
+        info!(
+            "Simulating {:?} ({} steps) of {:?}, writing outputs to {:?}",
+            total_simulation_time,
+            total_simulation_time.as_nanos() / simulation_delta.as_nanos(),
+            self.config.scene_path,
+            self.config.output_dir
+        );
+
+        if self.config.export_diagnostics {
+            self.diagnostics_exporter
+                .start_continuous_export(&self.config.output_dir, self.scene.fluids_mut());
+        }
+
+        let start_time = Instant::now();
+        let mut output_index = 0;
+        // Fast forward always performs at least one step, so stop once there is no full step left.
+        while total_simulation_time - self.simulation_controller.timer().total_simulated_time() >= simulation_delta {
+            let remaining_time = total_simulation_time - self.simulation_controller.timer().total_simulated_time();
+
+            self.per_frame_resources
+                .update_gpu_data_simulation_only(&self.command_queue, self.simulation_controller.timer().fill_global_uniform_buffer());
+            self.simulation_controller.fast_forward_steps(
+                output_interval.min(remaining_time),
+                &self.device,
+                &self.command_queue,
+                &mut self.scene,
+                &self.pipeline_manager,
+                self.per_frame_resources.bind_group(),
+                &mut self.profiler,
+            );
+
+            self.write_outputs(output_index);
+            self.diagnostics_exporter.write_new_samples(self.scene.fluids_mut());
+            output_index += 1;
+        }
+        for fluid in self.scene.fluids_mut().iter_mut() {
+            fluid.wait_for_pending_diagnostics(&self.device);
+        }
+        self.diagnostics_exporter.stop_continuous_export(self.scene.fluids_mut());
+        self.surface_mesh_exporter.wait_for_pending_exports();
+        self.volume_exporter.wait_for_pending_exports();
+        if let Some(offscreen_renderer) = &mut self.offscreen_renderer {
+            offscreen_renderer.wait_for_pending_frames(&self.device);
+        }
+
+        let summary = HeadlessRunSummary {
+            num_steps: self.simulation_controller.timer().num_simulation_steps_performed(),
+            simulated_time: self.simulation_controller.timer().total_simulated_time(),
+            computation_time: start_time.elapsed(),
+            final_diagnostics: self
+                .scene
+                .fluids()
+                .iter()
+                .map(|fluid| fluid.diagnostics_history().back().copied())
+                .collect(),
+        };
+        info!(
+            "Headless run finished after {} steps, simulated {:?} in {:?}",
+            summary.num_steps, summary.simulated_time, summary.computation_time
+        );
+        Ok(summary)
+    }
+
+    fn write_outputs(&mut self, output_index: usize) {
+        if self.surface_mesh_exporter.export_during_recording {
+            self.surface_mesh_exporter.export(
+                &self.scene,
+                &self.device,
+                &self.command_queue,
+                SurfaceMeshExporter::recording_frame_path(&self.config.output_dir, output_index),
+            );
+        }
+        if self.volume_exporter.export_during_recording {
+            self.volume_exporter
+                .export_recording_frame(&self.scene, &self.device, &self.command_queue, &self.config.output_dir, output_index);
+        }
+        if let Some(offscreen_renderer) = &mut self.offscreen_renderer {
+            if let Some(viewpoint) = self
+                .camera_path
+                .as_ref()
+                .and_then(|camera_path| camera_path.evaluate(self.simulation_controller.timer().total_simulated_time().as_secs_f32()))
+            {
+                self.camera.set_viewpoint(&viewpoint);
+            }
+            let extension = self.config.render_hdr_format.map_or("png", HdrImageFormat::extension);
+            offscreen_renderer.render_and_capture(
+                ScreenshotRecorder::file_capture_target(
+                    ScreenshotRecorder::recording_frame_path(&self.config.output_dir, output_index, extension),
+                    self.config.render_hdr_depth,
+                ),
+                &self.scene,
+                &self.camera,
+                self.simulation_controller.timer(),
+                &self.device,
+                &self.command_queue,
+                &self.pipeline_manager,
+                &mut self.profiler,
+                &mut self.per_frame_resources,
+            );
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! GPU fluid simulation on top of wgpu.
+//!
+//! The viewer application (`src/main.rs`) is a thin layer over this library, everything it shows can be embedded into other wgpu applications.
+//!
+//! Embedding a fluid:
+//! * Create the device with [`wgpu_utils::device_descriptor`], the simulation relies on push constants.
+//! * Create a [`wgpu_utils::shader::ShaderDirectory`] pointing to a copy of the `shader` directory (compiled at runtime, hot reloadable)
+//!   and a [`wgpu_utils::pipelines::PipelineManager`].
+//! * Create [`per_frame_resources::PerFrameResources`]. The simulation only reads the simulation delta from it,
+//!   fill it with [`per_frame_resources::PerFrameResources::update_gpu_data_simulation_only`] and a [`timer::Timer`] if you don't render with it.
+//! * Describe the fluid with a [`scene::FluidConfig`] (either by hand or as part of a scene file), convert its fluid cubes and regions to grid space with
+//!   [`scene::FluidConfig::fluid_regions`] and create the fluid with [`scene::FluidConfig::create_fluid`].
+//!   Alternatively, use [`simulation::HybridFluid::new`] and [`simulation::HybridFluid::add_fluid_region`] directly.
+//! * Record steps into your own command encoder with [`simulation::HybridFluid::step`].
+//!   After submitting, call [`simulation::HybridFluid::update_statistics`] to keep solver statistics and diagnostics coming.
+//! * Render with [`simulation::HybridFluid::bind_group_renderer`] (layout from [`simulation::HybridFluid::get_or_create_group_layout_renderer`])
+//!   or use the renderers in [`renderer`].
+//! * Read back particles with [`simulation::HybridFluid::read_particles`], grid volumes with [`simulation::HybridFluid::read_volume`].
+//!
+//! To run whole scenes (several fluid domains, timeline), use [`scene::Scene`] with a [`simulation_controller::SimulationController`],
+//! or [`headless::HeadlessApplication`] for runs without any window.
+
+#[macro_use]
+extern crate more_asserts;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate strum_macros;
+#[macro_use]
+pub mod wgpu_utils;
+
+pub mod camera;
+pub mod camera_path;
+pub mod export;
+pub mod headless;
+pub mod per_frame_resources;
+pub mod render_output;
+pub mod renderer;
+pub mod scene;
+pub mod simulation;
+pub mod simulation_controller;
+pub mod sweep;
+pub mod timeline;
+pub mod timer;
+pub mod validation;
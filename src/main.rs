@@ -1,31 +1,39 @@
 #[macro_use]
-extern crate more_asserts;
-#[macro_use]
 extern crate log;
-#[macro_use]
-extern crate strum_macros;
-#[macro_use]
-mod wgpu_utils;
 
-mod camera;
+mod command_line;
 mod gui;
-mod per_frame_resources;
-mod render_output;
-mod renderer;
-mod scene;
-mod simulation;
-mod simulation_controller;
-mod timer;
-
-use per_frame_resources::*;
-use render_output::{hdr_backbuffer::HdrBackbuffer, screen::Screen, screenshot_recorder::ScreenshotRecorder};
-use renderer::SceneRenderer;
-use simulation_controller::SimulationControllerStatus;
+mod remote_control;
+mod scene_watcher;
+
+use blub::{
+    camera,
+    camera_path::CameraPathController,
+    export::{
+        chrome_trace::ChromeTraceExporter, diagnostics_export::DiagnosticsExporter, surface_mesh::SurfaceMeshExporter, volume_export::VolumeExporter,
+    },
+    headless,
+    per_frame_resources::*,
+    render_output::{
+        hdr_backbuffer::HdrBackbuffer, offscreen_renderer::OffscreenRenderer, screen::Screen, screenshot_recorder::ScreenshotRecorder,
+        video_stream::RecordingFormat,
+    },
+    renderer::SceneRenderer,
+    scene, simulation, simulation_controller,
+    simulation_controller::SimulationControllerStatus,
+    sweep,
+    timer::Timer,
+    validation,
+    wgpu_utils::{self, pipelines, profiler::GpuProfiler, shader},
+};
+use command_line::CommandLineArguments;
+use remote_control::{RemoteCall, RemoteRequest};
+use scene_watcher::SceneWatcher;
 use std::{
     path::{Path, PathBuf},
     time::Duration,
 };
-use wgpu_utils::{pipelines, shader};
+use structopt::StructOpt;
 use winit::{
     event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopProxy},
@@ -38,8 +46,29 @@ pub enum ApplicationEvent {
     LoadScene(PathBuf),
     ResetScene,
     FastForwardSimulation(Duration),
-    ResetAndStartRecording { recording_fps: f64 }, // to stop recording, pause the simulation controller.
+    // To stop recording, pause the simulation controller. If an offscreen resolution is given, frames are rendered offscreen instead of captured from the window.
+    ResetAndStartRecording {
+        recording_fps: f64,
+        offscreen_resolution: Option<winit::dpi::PhysicalSize<u32>>,
+    },
     ChangePresentMode(wgpu::PresentMode),
+    SaveCamera,
+    SetCameraViewpoint(camera::CameraViewpoint),
+    SaveCameraPreset(String),
+    AddCameraPathKeyframe,
+    SaveCameraPath,
+    LoadCameraPath(PathBuf),
+    LoadColorGradingLut(PathBuf),
+    ClearColorGradingLut,
+    SaveSimulationSettings,
+    ExportSurfaceMesh,
+    ExportSimulationVolumes,
+    ExportDiagnostics,
+    ExportProfilerTrace,
+    CompareWithCpuReference {
+        num_steps: u32,
+    },
+    RemoteControl(RemoteCall),
 }
 
 struct Application {
@@ -48,11 +77,17 @@ struct Application {
     screen: Screen,
     hdr_backbuffer: HdrBackbuffer,
     screenshot_recorder: ScreenshotRecorder,
+    offscreen_renderer: Option<OffscreenRenderer>,
+    surface_mesh_exporter: SurfaceMeshExporter,
+    volume_exporter: VolumeExporter,
+    diagnostics_exporter: DiagnosticsExporter,
+    chrome_trace_exporter: ChromeTraceExporter,
 
     device: wgpu::Device,
     command_queue: wgpu::Queue,
 
     shader_dir: shader::ShaderDirectory,
+    scene_watcher: SceneWatcher,
     pipeline_manager: pipelines::PipelineManager,
     scene: scene::Scene,
     scene_renderer: SceneRenderer,
@@ -60,16 +95,19 @@ struct Application {
     gui: gui::GUI,
 
     camera: camera::Camera,
+    camera_path_controller: CameraPathController,
     per_frame_resources: PerFrameResources,
+    profiler: GpuProfiler,
+    output_dir: PathBuf,
 }
 
 impl Application {
-    async fn new(event_loop: &EventLoop<ApplicationEvent>) -> Application {
+    async fn new(event_loop: &EventLoop<ApplicationEvent>, arguments: &CommandLineArguments) -> Application {
         let wgpu_instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY); //wgpu::BackendBit::DX12);
         let window = WindowBuilder::new()
             .with_title("Blub")
             .with_resizable(true)
-            .with_inner_size(winit::dpi::LogicalSize::new(1980, 1080))
+            .with_inner_size(winit::dpi::LogicalSize::new(arguments.window_width, arguments.window_height))
             .build(&event_loop)
             .unwrap();
 
@@ -84,14 +122,7 @@ impl Application {
 
         let (device, mut command_queue) = adapter
             .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::PUSH_CONSTANTS,
-                    limits: wgpu::Limits {
-                        max_push_constant_size: 8,
-                        ..Default::default()
-                    },
-                    shader_validation: true,
-                },
+                &wgpu_utils::device_descriptor(&adapter),
                 None, //Some(Path::new("C:/dev/blub/trace")),
             )
             .await
@@ -100,10 +131,11 @@ impl Application {
         let shader_dir = shader::ShaderDirectory::new(Path::new("shader"));
         let mut pipeline_manager = pipelines::PipelineManager::new();
 
-        let screen = Screen::new(&device, &window_surface, Screen::DEFAULT_PRESENT_MODE, window.inner_size(), &shader_dir);
-        let hdr_backbuffer = HdrBackbuffer::new(&device, screen.resolution(), &shader_dir);
+        let present_mode = arguments.present_mode.unwrap_or(Screen::DEFAULT_PRESENT_MODE);
+        let screen = Screen::new(&device, &window_surface, present_mode, window.inner_size(), &shader_dir);
+        let hdr_backbuffer = HdrBackbuffer::new(&device, screen.resolution());
         let per_frame_resources = PerFrameResources::new(&device);
-        let simulation_controller = simulation_controller::SimulationController::new();
+        let profiler = GpuProfiler::new(&device, &command_queue);
         let mut scene_renderer = SceneRenderer::new(
             &device,
             &command_queue,
@@ -112,43 +144,137 @@ impl Application {
             per_frame_resources.bind_group_layout(),
             &hdr_backbuffer,
         );
-        let gui = gui::GUI::new(&device, &window, &mut command_queue);
+        if let Some(render_mode) = arguments.render_mode {
+            scene_renderer.fluid_rendering_mode = render_mode;
+        }
+        let mut gui = gui::GUI::new(&device, &window, &mut command_queue, arguments.scene.as_deref(), present_mode);
 
         // Load initial scene. Gui already needs to list all scenes, so we go there to grab the default selected.
-        let scene = scene::Scene::new(
-            gui.selected_scene(),
-            &device,
-            &command_queue,
-            &shader_dir,
-            &mut pipeline_manager,
-            per_frame_resources.bind_group_layout(),
-        )
-        .unwrap();
+        // If it can't be loaded, its problems are shown in the gui and we fall back to the first other scene that loads.
+        let mut scene_paths = vec![gui.selected_scene().clone()];
+        scene_paths.extend(gui.known_scene_files().iter().filter(|path| *path != gui.selected_scene()).cloned());
+        let mut scene = None;
+        let mut scene_load_error = None;
+        for scene_path in scene_paths {
+            match scene::Scene::new(
+                &scene_path,
+                &device,
+                &command_queue,
+                &shader_dir,
+                &mut pipeline_manager,
+                per_frame_resources.bind_group_layout(),
+            ) {
+                Ok(loaded_scene) => {
+                    scene = Some(loaded_scene);
+                    break;
+                }
+                Err(error) => {
+                    error!("{}", error);
+                    scene_load_error.get_or_insert(error);
+                }
+            }
+        }
+        let scene = scene.unwrap_or_else(|| panic!("None of the known scene files could be loaded"));
+        gui.select_scene(scene.path());
+        gui.set_scene_load_error(scene_load_error);
         scene_renderer.on_new_scene(&command_queue, &scene);
 
+        // Command line overrides the scene's settings.
+        let mut simulation_controller = simulation_controller::SimulationController::new();
+        simulation_controller.apply_scene_settings(&scene.config().simulation);
+        if let Some(steps_per_second) = arguments.steps_per_second {
+            simulation_controller.set_simulation_steps_per_second(steps_per_second);
+        }
+        if let Some(time_scale) = arguments.time_scale {
+            simulation_controller.time_scale = time_scale;
+        }
+        if let Some(stop_time) = arguments.stop_time {
+            simulation_controller.simulation_stop_time = stop_time;
+        }
+
+        let mut scene_watcher = SceneWatcher::new(Path::new(gui::SCENE_DIRECTORY));
+        scene_watcher.watch_scene(scene.path());
+
+        let scene_camera = || {
+            scene
+                .config()
+                .camera
+                .as_ref()
+                .map_or_else(camera::Camera::new, camera::Camera::from_viewpoint)
+        };
+        let camera = match &arguments.camera {
+            Some(camera_path) => camera::Camera::load(camera_path).unwrap_or_else(|err| {
+                error!("Failed to load camera from {:?}: {}", camera_path, err);
+                scene_camera()
+            }),
+            None => scene_camera(),
+        };
+
+        let output_dir = arguments.output_dir.clone().unwrap_or_default();
+        let mut camera_path_controller = CameraPathController::new(&output_dir);
+        if let Some(camera_path_file) = &arguments.camera_path {
+            camera_path_controller.load(camera_path_file);
+        }
+        let mut screenshot_recorder = ScreenshotRecorder::new(&output_dir);
+        if let Some(encoder_command) = &arguments.encoder_command {
+            screenshot_recorder.encoder_command = encoder_command.clone();
+            screenshot_recorder.recording_format = RecordingFormat::Encoder;
+        }
+        if let Some(recording_format) = arguments.recording_format {
+            screenshot_recorder.recording_format = recording_format;
+        }
+        screenshot_recorder.hdr_screenshot_format = arguments.hdr_screenshots;
+        screenshot_recorder.hdr_include_depth = arguments.hdr_depth;
+        let mut offscreen_renderer = None;
+        if let Some(recording_fps) = arguments.record {
+            simulation_controller.start_recording_with_fixed_frame_length(recording_fps);
+            screenshot_recorder.start_next_recording(recording_fps);
+            offscreen_renderer = arguments.render_resolution.map(|resolution| {
+                OffscreenRenderer::new(
+                    &device,
+                    &command_queue,
+                    resolution,
+                    &shader_dir,
+                    &mut pipeline_manager,
+                    per_frame_resources.bind_group_layout(),
+                    &scene,
+                )
+            });
+        }
+
         Application {
             window,
             window_surface,
             screen,
             hdr_backbuffer,
-            screenshot_recorder: ScreenshotRecorder::new(),
+            screenshot_recorder,
+            offscreen_renderer,
+            surface_mesh_exporter: SurfaceMeshExporter::new(&output_dir),
+            volume_exporter: VolumeExporter::new(&output_dir),
+            diagnostics_exporter: DiagnosticsExporter::new(&output_dir),
+            chrome_trace_exporter: ChromeTraceExporter::new(&output_dir),
 
             device,
             command_queue,
 
             shader_dir,
+            scene_watcher,
             pipeline_manager,
             scene,
             scene_renderer,
             simulation_controller,
             gui,
 
-            camera: camera::Camera::new(),
+            camera,
+            camera_path_controller,
             per_frame_resources,
+            profiler,
+            output_dir,
         }
     }
 
-    pub fn load_scene(&mut self, scene_path: &Path) {
+    // Keeps the current scene if loading fails.
+    pub fn load_scene(&mut self, scene_path: &Path) -> Result<(), String> {
         let new_scene = scene::Scene::new(
             scene_path,
             &self.device,
@@ -160,15 +286,35 @@ impl Application {
 
         match new_scene {
             Ok(scene) => {
+                self.gui.set_scene_load_error(None);
                 self.scene = scene;
                 self.scene_renderer.on_new_scene(&self.command_queue, &self.scene);
+                if let Some(offscreen_renderer) = &mut self.offscreen_renderer {
+                    offscreen_renderer.on_new_scene(&self.command_queue, &self.scene);
+                }
+                self.scene_watcher.watch_scene(scene_path);
+                Ok(())
             }
             Err(error) => {
-                error!("Failed to load scene from {:?}: {:?}", scene_path, error);
+                error!("{}", error);
+                let message = error.to_string();
+                self.gui.set_scene_load_error(Some(error));
+                Err(message)
             }
         }
     }
 
+    // Loads a scene with its camera and settings and starts simulating from the beginning.
+    fn switch_scene(&mut self, scene_path: &Path) -> Result<(), String> {
+        self.load_scene(scene_path)?;
+        if let Some(viewpoint) = &self.scene.config().camera {
+            self.camera.set_viewpoint(viewpoint);
+        }
+        self.simulation_controller.apply_scene_settings(&self.scene.config().simulation);
+        self.simulation_controller.restart();
+        Ok(())
+    }
+
     fn run(mut self, event_loop: EventLoop<ApplicationEvent>) {
         let event_loop_proxy = event_loop.create_proxy();
 
@@ -178,55 +324,13 @@ impl Application {
             *control_flow = ControlFlow::Poll;
 
             match &event {
-                Event::UserEvent(event) => match event {
-                    ApplicationEvent::LoadScene(scene_path) => {
-                        self.load_scene(scene_path);
-                        self.simulation_controller.restart();
-                    }
-                    ApplicationEvent::ResetScene => {
-                        self.scene.reset(
-                            &self.device,
-                            &self.command_queue,
-                            &self.shader_dir,
-                            &mut self.pipeline_manager,
-                            self.per_frame_resources.bind_group_layout(),
-                        );
-                        self.simulation_controller.restart();
-                    }
-                    ApplicationEvent::FastForwardSimulation(simulation_jump_length) => {
-                        self.simulation_controller.fast_forward_steps(
-                            *simulation_jump_length,
-                            &self.device,
-                            &self.command_queue,
-                            &mut self.scene,
-                            &self.pipeline_manager,
-                            self.per_frame_resources.bind_group(), // values from last draw are good enough.
-                        );
-                    }
-                    ApplicationEvent::ResetAndStartRecording { recording_fps } => {
-                        self.scene.reset(
-                            &self.device,
-                            &self.command_queue,
-                            &self.shader_dir,
-                            &mut self.pipeline_manager,
-                            self.per_frame_resources.bind_group_layout(),
-                        );
-                        self.simulation_controller.restart();
-                        self.simulation_controller.start_recording_with_fixed_frame_length(*recording_fps);
-                        self.screenshot_recorder.start_next_recording();
-                    }
-                    ApplicationEvent::ChangePresentMode(present_mode) => {
-                        self.screen = Screen::new(
-                            &self.device,
-                            &self.window_surface,
-                            *present_mode,
-                            self.screen.resolution(),
-                            &self.shader_dir,
-                        );
-                    }
-                },
+                Event::UserEvent(event) => self.on_application_event(event),
                 Event::WindowEvent { event, .. } => {
-                    self.camera.on_window_event(&event);
+                    // Scrolling over the gui shouldn't zoom the camera.
+                    let scrolling_gui = matches!(event, WindowEvent::MouseWheel { .. }) && self.gui.wants_mouse_input();
+                    if !scrolling_gui {
+                        self.camera.on_window_event(&event);
+                    }
                     match event {
                         WindowEvent::CloseRequested => {
                             *control_flow = ControlFlow::Exit;
@@ -243,7 +347,10 @@ impl Application {
                             ..
                         } => match virtual_keycode {
                             VirtualKeyCode::Escape => *control_flow = ControlFlow::Exit,
-                            VirtualKeyCode::Snapshot => self.screenshot_recorder.schedule_next_screenshot(), // Bug? doesn't seem to receive a winit::event::ElementState::Pressed event.
+                            // Bug? doesn't seem to receive a winit::event::ElementState::Pressed event.
+                            VirtualKeyCode::Snapshot => {
+                                self.screenshot_recorder.schedule_next_screenshot();
+                            }
                             VirtualKeyCode::Space => {
                                 if let winit::event::ElementState::Pressed = state {
                                     self.simulation_controller.pause_or_resume();
@@ -267,6 +374,16 @@ impl Application {
                 Event::LoopDestroyed => {
                     // workaround for errors on shutdown while recording screenshots
                     self.screen.wait_for_pending_screenshots(&self.device);
+                    if let Some(offscreen_renderer) = &mut self.offscreen_renderer {
+                        offscreen_renderer.wait_for_pending_frames(&self.device);
+                    }
+                    self.screenshot_recorder.finish_video_streams();
+                    self.surface_mesh_exporter.wait_for_pending_exports();
+                    self.volume_exporter.wait_for_pending_exports();
+                    for fluid in self.scene.fluids_mut().iter_mut() {
+                        fluid.wait_for_pending_diagnostics(&self.device);
+                    }
+                    self.diagnostics_exporter.stop_continuous_export(self.scene.fluids_mut());
                 }
                 _ => (),
             }
@@ -275,18 +392,433 @@ impl Application {
         });
     }
 
+    fn on_application_event(&mut self, event: &ApplicationEvent) {
+        match event {
+            ApplicationEvent::LoadScene(scene_path) => {
+                // Problems are logged and shown in the gui already.
+                self.switch_scene(scene_path).ok();
+            }
+            ApplicationEvent::ResetScene => {
+                self.scene.reset(
+                    &self.device,
+                    &self.command_queue,
+                    &self.shader_dir,
+                    &mut self.pipeline_manager,
+                    self.per_frame_resources.bind_group_layout(),
+                );
+                self.simulation_controller.apply_scene_settings(&self.scene.config().simulation);
+                self.simulation_controller.restart();
+            }
+            ApplicationEvent::FastForwardSimulation(simulation_jump_length) => {
+                self.simulation_controller.fast_forward_steps(
+                    *simulation_jump_length,
+                    &self.device,
+                    &self.command_queue,
+                    &mut self.scene,
+                    &self.pipeline_manager,
+                    self.per_frame_resources.bind_group(), // values from last draw are good enough.
+                    &mut self.profiler,
+                );
+            }
+            ApplicationEvent::ResetAndStartRecording {
+                recording_fps,
+                offscreen_resolution,
+            } => {
+                self.scene.reset(
+                    &self.device,
+                    &self.command_queue,
+                    &self.shader_dir,
+                    &mut self.pipeline_manager,
+                    self.per_frame_resources.bind_group_layout(),
+                );
+                self.simulation_controller.apply_scene_settings(&self.scene.config().simulation);
+                self.simulation_controller.restart();
+                self.simulation_controller.start_recording_with_fixed_frame_length(*recording_fps);
+                self.screenshot_recorder.start_next_recording(*recording_fps);
+                self.start_offscreen_rendering(*offscreen_resolution);
+                if self.diagnostics_exporter.export_during_recording {
+                    if let Some((recording_output_dir, _)) = self.screenshot_recorder.current_recording_frame() {
+                        self.diagnostics_exporter
+                            .start_continuous_export(recording_output_dir, self.scene.fluids_mut());
+                    }
+                }
+            }
+            ApplicationEvent::ChangePresentMode(present_mode) => {
+                self.screen = Screen::new(
+                    &self.device,
+                    &self.window_surface,
+                    *present_mode,
+                    self.screen.resolution(),
+                    &self.shader_dir,
+                );
+            }
+            ApplicationEvent::SaveCamera => {
+                self.save_camera();
+            }
+            ApplicationEvent::SetCameraViewpoint(viewpoint) => {
+                self.camera.set_viewpoint(viewpoint);
+            }
+            ApplicationEvent::SaveCameraPreset(name) => {
+                self.save_camera_preset(name.clone());
+            }
+            ApplicationEvent::AddCameraPathKeyframe => {
+                self.camera_path_controller
+                    .add_keyframe(&self.camera, self.simulation_controller.timer().total_simulated_time());
+            }
+            ApplicationEvent::SaveCameraPath => {
+                self.camera_path_controller.save();
+            }
+            ApplicationEvent::LoadCameraPath(path) => {
+                self.camera_path_controller.load(path);
+            }
+            ApplicationEvent::LoadColorGradingLut(path) => {
+                match self
+                    .scene_renderer
+                    .tonemapper
+                    .load_color_grading_lut(&self.device, &self.command_queue, path)
+                {
+                    Ok(()) => info!("loaded color grading lut from {:?}", path),
+                    Err(err) => error!("failed to load color grading lut from {:?}: {}", path, err),
+                }
+            }
+            ApplicationEvent::ClearColorGradingLut => {
+                self.scene_renderer.tonemapper.clear_color_grading_lut(&self.device, &self.command_queue);
+            }
+            ApplicationEvent::SaveSimulationSettings => {
+                self.save_simulation_settings();
+            }
+            ApplicationEvent::ExportSurfaceMesh => {
+                self.surface_mesh_exporter.export_next(&self.scene, &self.device, &self.command_queue);
+            }
+            ApplicationEvent::ExportSimulationVolumes => {
+                self.volume_exporter.export_next(&self.scene, &self.device, &self.command_queue);
+            }
+            ApplicationEvent::ExportDiagnostics => {
+                self.diagnostics_exporter.export_history(self.scene.fluids());
+            }
+            ApplicationEvent::ExportProfilerTrace => {
+                self.chrome_trace_exporter.export(self.profiler.finished_frames());
+            }
+            ApplicationEvent::CompareWithCpuReference { num_steps } => {
+                self.compare_with_cpu_reference(*num_steps);
+            }
+            ApplicationEvent::RemoteControl(call) => {
+                let result = self.on_remote_request(&call.request);
+                call.respond(result);
+            }
+        }
+    }
+
+    // Requests that map to an application event are handled the same way, the rest report errors back instead of only logging them.
+    fn on_remote_request(&mut self, request: &RemoteRequest) -> Result<serde_json::Value, String> {
+        match request {
+            RemoteRequest::LoadScene { path } => {
+                self.switch_scene(path)?;
+            }
+            RemoteRequest::ResetScene => {
+                self.on_application_event(&ApplicationEvent::ResetScene);
+            }
+            RemoteRequest::Pause => {
+                if self.simulation_controller.status() != SimulationControllerStatus::Paused {
+                    self.simulation_controller.pause_or_resume();
+                }
+            }
+            RemoteRequest::Resume => {
+                self.simulation_controller.resume_realtime();
+            }
+            RemoteRequest::FastForward { seconds } => {
+                if !seconds.is_finite() || *seconds < 0.0 {
+                    return Err(format!("seconds needs to be positive, is {}", seconds));
+                }
+                self.on_application_event(&ApplicationEvent::FastForwardSimulation(Duration::from_secs_f64(*seconds)));
+                return Ok(serde_json::json!({
+                    "simulated_time": self.simulation_controller.timer().total_simulated_time().as_secs_f64(),
+                    "computation_time": self.simulation_controller.computation_time_last_fast_forward().as_secs_f64(),
+                }));
+            }
+            RemoteRequest::StartRecording { fps, resolution } => {
+                if !fps.is_finite() || *fps <= 0.0 {
+                    return Err(format!("fps needs to be positive, is {}", fps));
+                }
+                if let Some([width, height]) = resolution.filter(|[width, height]| *width == 0 || *height == 0) {
+                    return Err(format!("Invalid resolution {}x{}", width, height));
+                }
+                self.on_application_event(&ApplicationEvent::ResetAndStartRecording {
+                    recording_fps: *fps,
+                    offscreen_resolution: resolution.map(|[width, height]| winit::dpi::PhysicalSize::new(width, height)),
+                });
+                let recording_output_dir = self.screenshot_recorder.current_recording_frame().map(|(dir, _)| dir.to_path_buf());
+                return Ok(serde_json::json!({ "output_dir": recording_output_dir }));
+            }
+            RemoteRequest::SetSimulationSettings(settings) => {
+                let problems = settings.problems();
+                if !problems.is_empty() {
+                    return Err(problems.join(", "));
+                }
+                self.simulation_controller.apply_scene_settings(settings);
+                for fluid in self.scene.fluids_mut().iter_mut() {
+                    settings.velocity_solver.apply(fluid.pressure_solver_config_velocity());
+                    settings.density_solver.apply(fluid.pressure_solver_config_density());
+                }
+            }
+            RemoteRequest::SaveSimulationSettings => {
+                self.on_application_event(&ApplicationEvent::SaveSimulationSettings);
+            }
+            RemoteRequest::SetCamera(viewpoint) => {
+                let problems = viewpoint.problems();
+                if !problems.is_empty() {
+                    return Err(problems.join(", "));
+                }
+                self.on_application_event(&ApplicationEvent::SetCameraViewpoint(viewpoint.clone()));
+            }
+            RemoteRequest::SaveCamera => {
+                self.on_application_event(&ApplicationEvent::SaveCamera);
+            }
+            RemoteRequest::SaveCameraPreset { name } => {
+                if name.is_empty() {
+                    return Err("Camera presets need a name".to_owned());
+                }
+                self.on_application_event(&ApplicationEvent::SaveCameraPreset(name.clone()));
+            }
+            RemoteRequest::Screenshot { path } => {
+                let path = match path {
+                    Some(path) => {
+                        self.screenshot_recorder.schedule_screenshot(path);
+                        path.clone()
+                    }
+                    None => self.screenshot_recorder.schedule_next_screenshot(),
+                };
+                return Ok(serde_json::json!({ "path": path }));
+            }
+            RemoteRequest::ChangePresentMode { present_mode } => {
+                let present_mode = command_line::parse_present_mode(present_mode)?;
+                self.on_application_event(&ApplicationEvent::ChangePresentMode(present_mode));
+            }
+            RemoteRequest::ExportSurfaceMesh => {
+                self.on_application_event(&ApplicationEvent::ExportSurfaceMesh);
+            }
+            RemoteRequest::ExportSimulationVolumes => {
+                self.on_application_event(&ApplicationEvent::ExportSimulationVolumes);
+            }
+            RemoteRequest::ExportDiagnostics => {
+                self.on_application_event(&ApplicationEvent::ExportDiagnostics);
+            }
+            RemoteRequest::ExportProfilerTrace => {
+                self.on_application_event(&ApplicationEvent::ExportProfilerTrace);
+            }
+            RemoteRequest::CompareWithCpuReference { num_steps } => {
+                self.on_application_event(&ApplicationEvent::CompareWithCpuReference { num_steps: *num_steps });
+            }
+            RemoteRequest::GetStatus => {
+                return Ok(self.remote_status());
+            }
+        }
+        Ok(serde_json::Value::Null)
+    }
+
+    fn remote_status(&mut self) -> serde_json::Value {
+        let fluids: Vec<serde_json::Value> = self
+            .scene
+            .fluids_mut()
+            .iter_mut()
+            .map(|fluid| {
+                let grid_dimension = fluid.grid_dimension();
+                let velocity_solver = scene::SolverSettings::from_config(fluid.pressure_solver_config_velocity());
+                let density_solver = scene::SolverSettings::from_config(fluid.pressure_solver_config_density());
+                let solve_status =
+                    |sample: &simulation::SolverStatisticSample| serde_json::json!({ "iteration_count": sample.iteration_count, "mse": sample.mse });
+                let last_velocity_solve = fluid.pressure_solver_stats_velocity().back().map(solve_status);
+                let last_density_solve = fluid.pressure_solver_stats_density().back().map(solve_status);
+                let diagnostics = fluid.diagnostics_history().back().map(|sample| {
+                    sample
+                        .named_values()
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), serde_json::json!(value)))
+                        .collect::<serde_json::Map<_, _>>()
+                });
+                serde_json::json!({
+                    "num_particles": fluid.num_particles(),
+                    "grid_dimension": [grid_dimension.width, grid_dimension.height, grid_dimension.depth],
+                    "velocity_solver": velocity_solver,
+                    "density_solver": density_solver,
+                    "last_velocity_solve": last_velocity_solve,
+                    "last_density_solve": last_density_solve,
+                    "diagnostics": diagnostics,
+                })
+            })
+            .collect();
+
+        let timer = self.simulation_controller.timer();
+        serde_json::json!({
+            "scene": self.scene.path(),
+            "status": format!("{:?}", self.simulation_controller.status()),
+            "simulated_time": timer.total_simulated_time().as_secs_f64(),
+            "num_steps": timer.num_simulation_steps_performed(),
+            "steps_per_second": self.simulation_controller.simulation_steps_per_second(),
+            "time_scale": self.simulation_controller.time_scale,
+            "camera": self.camera.viewpoint(String::new()),
+            "fluids": fluids,
+        })
+    }
+
+    // Steps freshly reset copies of all fluid domains side by side with a cpu reference and logs how much they deviate.
+    // The running simulation is left untouched. Fluid domains don't interact, so each is compared on its own.
+    fn compare_with_cpu_reference(&mut self, num_steps: u32) {
+        let simulation_delta = self.simulation_controller.timer().simulation_delta();
+        // Simulation shaders only read the simulation delta from the per frame resources.
+        let mut per_frame_resources = PerFrameResources::new(&self.device);
+        per_frame_resources.update_gpu_data_simulation_only(&self.command_queue, Timer::new(simulation_delta).fill_global_uniform_buffer());
+
+        let num_fluids = self.scene.fluids().len();
+        for fluid_index in 0..num_fluids {
+            let mut fluid = self.scene.create_gpu_fluid(
+                fluid_index,
+                &self.device,
+                &self.command_queue,
+                &self.shader_dir,
+                &mut self.pipeline_manager,
+                per_frame_resources.bind_group_layout(),
+            );
+            let mut reference = self.scene.create_cpu_reference_fluid(fluid_index);
+            // Solver settings may have been changed in the gui since the scene was loaded.
+            let live_fluid = &mut self.scene.fluids_mut()[fluid_index];
+            *fluid.pressure_solver_config_velocity() = *live_fluid.pressure_solver_config_velocity();
+            *fluid.pressure_solver_config_density() = *live_fluid.pressure_solver_config_density();
+            *reference.pressure_solver_config_velocity() = *live_fluid.pressure_solver_config_velocity();
+            *reference.pressure_solver_config_density() = *live_fluid.pressure_solver_config_density();
+            fluid.set_debug_volume_capture(true);
+
+            let tolerances = simulation::ComparisonTolerances::default();
+            let mut simulation = simulation::GpuFluidSimulation {
+                fluid: &mut fluid,
+                device: &self.device,
+                queue: &self.command_queue,
+                pipeline_manager: &self.pipeline_manager,
+                per_frame_bind_group: per_frame_resources.bind_group(),
+                profiler: &mut self.profiler,
+            };
+            let start_time = std::time::Instant::now();
+            let (num_steps_performed, comparison) =
+                simulation::compare_in_lockstep(&mut simulation, &mut reference, simulation_delta, num_steps, &tolerances);
+            let failures = comparison.failures(&tolerances);
+            let last_iteration_counts = (
+                reference.pressure_solver_stats_velocity().back().map_or(0, |s| s.iteration_count),
+                reference.pressure_solver_stats_density().back().map_or(0, |s| s.iteration_count),
+            );
+            if failures.is_empty() {
+                info!(
+                    "GPU simulation of fluid {} matches cpu reference after {} steps (took {:?}, cpu reference solver iterations {:?}):\n{}",
+                    fluid_index,
+                    num_steps_performed,
+                    start_time.elapsed(),
+                    last_iteration_counts,
+                    comparison
+                );
+            } else {
+                warn!(
+                    "GPU simulation of fluid {} deviates from cpu reference after {} steps (cpu reference solver iterations {:?}):\n{}\n{}",
+                    fluid_index,
+                    num_steps_performed,
+                    last_iteration_counts,
+                    failures.join("\n"),
+                    comparison
+                );
+            }
+        }
+    }
+
+    fn start_offscreen_rendering(&mut self, resolution: Option<winit::dpi::PhysicalSize<u32>>) {
+        self.stop_offscreen_rendering();
+        if let Some(resolution) = resolution {
+            self.offscreen_renderer = Some(OffscreenRenderer::new(
+                &self.device,
+                &self.command_queue,
+                resolution,
+                &self.shader_dir,
+                &mut self.pipeline_manager,
+                self.per_frame_resources.bind_group_layout(),
+                &self.scene,
+            ));
+        }
+    }
+
+    fn stop_offscreen_rendering(&mut self) {
+        if let Some(mut offscreen_renderer) = self.offscreen_renderer.take() {
+            offscreen_renderer.wait_for_pending_frames(&self.device);
+        }
+    }
+
+    fn save_camera_preset(&mut self, name: String) {
+        match self.scene.save_camera_preset(self.camera.viewpoint(name)) {
+            Ok(()) => {
+                self.scene_watcher.ignore_own_write(self.scene.path());
+                info!("Saved camera preset to {:?}", self.scene.path());
+            }
+            Err(err) => error!("Failed to save camera preset to {:?}: {}", self.scene.path(), err),
+        }
+    }
+
+    fn save_simulation_settings(&mut self) {
+        // Solver settings are the same for all fluid domains in the scene file, the gui may have changed them per domain though.
+        let fluid = &mut self.scene.fluids_mut()[0];
+        let settings = scene::SimulationSettings {
+            steps_per_second: Some(self.simulation_controller.simulation_steps_per_second()),
+            time_scale: Some(self.simulation_controller.time_scale),
+            velocity_solver: scene::SolverSettings::from_config(fluid.pressure_solver_config_velocity()),
+            density_solver: scene::SolverSettings::from_config(fluid.pressure_solver_config_density()),
+        };
+        if self.scene.fluids().len() > 1 {
+            warn!("Scene has several fluid domains, saving the solver settings of the first one for all");
+        }
+
+        match self.scene.save_simulation_settings(settings) {
+            Ok(()) => {
+                self.scene_watcher.ignore_own_write(self.scene.path());
+                info!("Saved simulation settings to {:?}", self.scene.path());
+            }
+            Err(err) => error!("Failed to save simulation settings to {:?}: {}", self.scene.path(), err),
+        }
+    }
+
+    fn save_camera(&self) {
+        for i in 1..usize::MAX {
+            let path = self.output_dir.join(format!("camera{}.json", i));
+            if !path.exists() {
+                match self.camera.save(&path) {
+                    Ok(()) => info!("Saved camera to {:?}", path),
+                    Err(err) => error!("Failed to save camera to {:?}: {}", path, err),
+                }
+                break;
+            }
+        }
+    }
+
     fn window_resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         self.screen = Screen::new(&self.device, &self.window_surface, self.screen.present_mode(), size, &self.shader_dir);
-        self.hdr_backbuffer = HdrBackbuffer::new(&self.device, self.screen.resolution(), &self.shader_dir);
+        self.hdr_backbuffer = HdrBackbuffer::new(&self.device, self.screen.resolution());
         self.scene_renderer.on_window_resize(&self.device, &self.hdr_backbuffer);
     }
 
     fn update(&mut self) {
+        self.profiler.process_finished_frames();
         if self.shader_dir.detected_change() {
             info!("reloading shaders...");
             self.pipeline_manager.reload_all(&self.device, &self.shader_dir);
         }
+        if self.scene_watcher.detected_change(self.scene.path()) {
+            info!("reloading scene...");
+            let scene_path = self.scene.path().to_path_buf();
+            if self.load_scene(&scene_path).is_ok() {
+                self.simulation_controller.apply_scene_settings(&self.scene.config().simulation);
+                self.simulation_controller.restart();
+            }
+        }
         self.camera.update(self.simulation_controller.timer());
+        self.camera_path_controller
+            .update(&mut self.camera, self.simulation_controller.timer().total_simulated_time());
+        for fluid in self.scene.fluids_mut().iter_mut() {
+            fluid.set_debug_volume_capture(self.volume_exporter.capture_debug_volumes);
+        }
 
         self.per_frame_resources.update_gpu_data(
             &self.command_queue,
@@ -301,10 +833,19 @@ impl Application {
             &self.command_queue,
             &self.pipeline_manager,
             self.per_frame_resources.bind_group(),
+            &mut self.profiler,
         );
+        self.diagnostics_exporter.write_new_samples(self.scene.fluids_mut());
 
         if self.simulation_controller.status() == SimulationControllerStatus::Paused {
             self.screenshot_recorder.stop_recording();
+            self.stop_offscreen_rendering();
+            if self.diagnostics_exporter.is_exporting_continuously() {
+                for fluid in self.scene.fluids_mut().iter_mut() {
+                    fluid.wait_for_pending_diagnostics(&self.device);
+                }
+                self.diagnostics_exporter.stop_continuous_export(self.scene.fluids_mut());
+            }
         }
     }
 
@@ -316,6 +857,26 @@ impl Application {
             self.window_resize(window_size);
         }
 
+        // Offscreen frames are submitted on their own, before the per frame resources are set up for the window.
+        let mut recording_frame_rendered_offscreen = false;
+        if let Some(offscreen_renderer) = &mut self.offscreen_renderer {
+            if let Some(target) = self.screenshot_recorder.recording_frame_target(offscreen_renderer.resolution()) {
+                offscreen_renderer.scene_renderer_mut().copy_settings_from(&self.scene_renderer);
+                offscreen_renderer.render_and_capture(
+                    target,
+                    &self.scene,
+                    &self.camera,
+                    self.simulation_controller.timer(),
+                    &self.device,
+                    &self.command_queue,
+                    &self.pipeline_manager,
+                    &mut self.profiler,
+                    &mut self.per_frame_resources,
+                );
+                recording_frame_rendered_offscreen = true;
+            }
+        }
+
         let frame = self.screen.start_frame(&self.device, &self.window_surface);
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -333,14 +894,41 @@ impl Application {
             &self.scene,
             &mut encoder,
             &self.pipeline_manager,
+            &mut self.profiler,
             self.hdr_backbuffer.texture_view(),
             self.screen.depthbuffer(),
             self.per_frame_resources.bind_group(),
         );
 
-        self.hdr_backbuffer.tonemap(&self.screen.backbuffer(), &mut encoder);
+        self.scene_renderer.tonemap(
+            &mut encoder,
+            &self.pipeline_manager,
+            &mut self.profiler,
+            self.per_frame_resources.bind_group(),
+            self.screen.backbuffer(),
+        );
 
-        self.screenshot_recorder.capture_screenshot(&mut self.screen, &self.device, &mut encoder);
+        if let Some((recording_output_dir, frame_index)) = self.screenshot_recorder.current_recording_frame() {
+            if self.surface_mesh_exporter.export_during_recording {
+                self.surface_mesh_exporter.export(
+                    &self.scene,
+                    &self.device,
+                    &self.command_queue,
+                    SurfaceMeshExporter::recording_frame_path(recording_output_dir, frame_index),
+                );
+            }
+            if self.volume_exporter.export_during_recording {
+                self.volume_exporter
+                    .export_recording_frame(&self.scene, &self.device, &self.command_queue, recording_output_dir, frame_index);
+            }
+        }
+        self.screenshot_recorder.capture_screenshot(
+            &mut self.screen,
+            &self.hdr_backbuffer,
+            recording_frame_rendered_offscreen,
+            &self.device,
+            &mut encoder,
+        );
 
         self.gui.draw(
             &self.device,
@@ -349,22 +937,74 @@ impl Application {
             &self.command_queue,
             &self.screen.backbuffer(),
             &mut self.simulation_controller,
+            &mut self.screenshot_recorder,
             &mut self.scene_renderer,
             &mut self.scene,
+            &mut self.surface_mesh_exporter,
+            &mut self.volume_exporter,
+            &mut self.diagnostics_exporter,
+            &mut self.camera,
+            &mut self.camera_path_controller,
+            &mut self.profiler,
             event_loop_proxy,
         );
 
-        self.screen.copy_to_swapchain(&frame, &mut encoder);
+        self.screen.copy_to_swapchain(&frame, &mut encoder, &mut self.profiler);
+        self.profiler.resolve_queries(&mut encoder);
         self.command_queue.submit(Some(encoder.finish()));
+        self.profiler.end_frame();
         self.screen.end_frame(frame);
         self.simulation_controller.on_frame_submitted();
     }
 }
 
 fn main() {
+    let arguments = CommandLineArguments::from_args();
+
     // Silence warnings from `naga::front::spirv` for now since as of writing it doesn't know enough spirv yet.
-    env_logger::init_from_env(env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "warn,blub=info"));
+    match &arguments.log_level {
+        Some(log_level) => env_logger::Builder::new().parse_filters(log_level).init(),
+        None => env_logger::init_from_env(env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "warn,blub=info")),
+    }
+
+    if arguments.validate {
+        let result = futures::executor::block_on(validation::ValidationRunner::new()).and_then(|mut runner| runner.run_all());
+        if let Err(err) = result {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(sweep_path) = &arguments.sweep {
+        let output_dir = arguments.output_dir.clone().unwrap_or_else(|| PathBuf::from("sweep_output"));
+        let result = sweep::SweepRunner::new(sweep_path, &output_dir).and_then(|runner| runner.run());
+        if let Err(err) = result {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if arguments.headless {
+        let result = arguments
+            .headless_config()
+            .and_then(|config| futures::executor::block_on(headless::HeadlessApplication::new(config)))
+            .and_then(|mut application| application.run());
+        if let Err(err) = result {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = EventLoop::<ApplicationEvent>::with_user_event();
-    let application = futures::executor::block_on(Application::new(&event_loop));
+    if let Some(address) = &arguments.remote_control {
+        if let Err(err) = remote_control::start_server(address, event_loop.create_proxy()) {
+            error!("Failed to start remote control on {}: {}", address, err);
+            std::process::exit(1);
+        }
+    }
+    let application = futures::executor::block_on(Application::new(&event_loop, &arguments));
     application.run(event_loop);
 }
@@ -85,6 +85,13 @@ impl PerFrameResources {
         );
     }
 
+    // Simulation shaders only ever read the time section, without anything to render the rest can stay zero.
+    pub fn update_gpu_data_simulation_only(&mut self, queue: &wgpu::Queue, time: timer::FrameTimeUniformBufferContent) {
+        let mut content: PerFrameUniformBufferContent = bytemuck::Zeroable::zeroed();
+        content.time = time;
+        self.ubo.update_content(queue, content);
+    }
+
     pub fn bind_group(&self) -> &wgpu::BindGroup {
         &self.bind_group
     }
@@ -0,0 +1,201 @@
+use crate::ApplicationEvent;
+use blub::{camera::CameraViewpoint, scene::SimulationSettings};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+use winit::event_loop::EventLoopProxy;
+
+// Local JSON-RPC 2.0 server to automate a running instance from scripts and test harnesses, enabled with --remote-control <address>.
+// Newline delimited: every request is a single line of json, so is every response. Requests without id are notifications and get no response.
+// Requests are passed to the event loop and processed in between frames, the response is sent once the request is done.
+//
+// E.g. {"jsonrpc": "2.0", "id": 1, "method": "fast_forward", "params": {"seconds": 2.0}}
+
+// Error codes as defined by JSON-RPC 2.0, APPLICATION_ERROR is from the range reserved for implementation defined errors.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const APPLICATION_ERROR: i64 = -32000;
+
+// Everything that can be requested. Method names are the variant names in snake case, params the fields.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RemoteRequest {
+    LoadScene {
+        path: PathBuf,
+    },
+    ResetScene,
+    Pause,
+    Resume,
+    FastForward {
+        seconds: f64,
+    },
+    // Resets the scene and records with a fixed frame rate until paused. Frames are rendered offscreen if a resolution is given.
+    StartRecording {
+        fps: f64,
+        #[serde(default)]
+        resolution: Option<[u32; 2]>,
+    },
+    // Same format as the simulation settings in scene files, solver settings apply to all fluid domains. Anything not given stays as is.
+    SetSimulationSettings(SimulationSettings),
+    SaveSimulationSettings,
+    SetCamera(CameraViewpoint),
+    SaveCamera,
+    SaveCameraPreset {
+        name: String,
+    },
+    // Captures the next frame. Without path it is written to the output directory, like screenshots taken with the print key.
+    Screenshot {
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+    // One of immediate, mailbox or fifo.
+    ChangePresentMode {
+        present_mode: String,
+    },
+    ExportSurfaceMesh,
+    ExportSimulationVolumes,
+    ExportDiagnostics,
+    ExportProfilerTrace,
+    CompareWithCpuReference {
+        num_steps: u32,
+    },
+    // Simulation status, camera and statistics of every fluid domain.
+    GetStatus,
+}
+
+// A request on its way through the event loop, which sends back the result.
+#[derive(Debug, Clone)]
+pub struct RemoteCall {
+    pub request: RemoteRequest,
+    response: mpsc::Sender<Result<serde_json::Value, String>>,
+}
+
+impl RemoteCall {
+    pub fn respond(&self, result: Result<serde_json::Value, String>) {
+        // Fails only if the connection is gone already, nothing left to do then.
+        self.response.send(result).ok();
+    }
+}
+
+// Starts accepting connections in the background.
+// Only loopback addresses are accepted: there is no authentication and requests can read & write arbitrary files.
+pub fn start_server(address: &str, event_loop_proxy: EventLoopProxy<ApplicationEvent>) -> std::io::Result<()> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    if let Some(non_local_address) = addresses.iter().find(|address| !address.ip().is_loopback()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} is reachable from other machines, remote control is only available on loopback addresses (e.g. 127.0.0.1)",
+                non_local_address
+            ),
+        ));
+    }
+    let listener = TcpListener::bind(&addresses[..])?;
+    info!("Remote control listening on {}", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let event_loop_proxy = event_loop_proxy.clone();
+                    thread::spawn(move || handle_connection(stream, event_loop_proxy));
+                }
+                Err(err) => error!("Remote control failed to accept connection: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, event_loop_proxy: EventLoopProxy<ApplicationEvent>) {
+    let peer_address = stream.peer_addr().map_or_else(|_| "unknown".to_owned(), |address| address.to_string());
+    info!("Remote control connection from {}", peer_address);
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => {
+            error!("Remote control failed to set up connection from {}: {}", peer_address, err);
+            return;
+        }
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Remote control failed to read from {}: {}", peer_address, err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_message(&line, &event_loop_proxy) {
+            if let Err(err) = writeln!(writer, "{}", response) {
+                error!("Remote control failed to write to {}: {}", peer_address, err);
+                break;
+            }
+        }
+    }
+    info!("Remote control connection from {} closed", peer_address);
+}
+
+// Returns the response to send back, None for notifications.
+fn handle_message(line: &str, event_loop_proxy: &EventLoopProxy<ApplicationEvent>) -> Option<serde_json::Value> {
+    let message: serde_json::Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(err) => return Some(error_response(serde_json::Value::Null, PARSE_ERROR, err.to_string())),
+    };
+    let result = parse_request(&message).and_then(|request| call(request, event_loop_proxy));
+
+    let id = message.get("id").cloned()?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error_response(id, code, message),
+    })
+}
+
+fn error_response(id: serde_json::Value, code: i64, message: String) -> serde_json::Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn parse_request(message: &serde_json::Value) -> Result<RemoteRequest, (i64, String)> {
+    let method = message
+        .get("method")
+        .and_then(|method| method.as_str())
+        .ok_or((INVALID_REQUEST, "Request needs a method".to_owned()))?;
+
+    // Methods without parameters may have them missing or null.
+    let mut tagged_request = serde_json::Map::new();
+    tagged_request.insert("method".to_owned(), method.into());
+    if let Some(params) = message.get("params").filter(|params| !params.is_null()) {
+        tagged_request.insert("params".to_owned(), params.clone());
+    }
+    serde_json::from_value(serde_json::Value::Object(tagged_request)).map_err(|err| {
+        if err.to_string().starts_with("unknown variant") {
+            (METHOD_NOT_FOUND, format!("Unknown method {}", method))
+        } else {
+            (INVALID_PARAMS, format!("Invalid params for {}: {}", method, err))
+        }
+    })
+}
+
+// Hands the request to the event loop and waits for it to be processed.
+fn call(request: RemoteRequest, event_loop_proxy: &EventLoopProxy<ApplicationEvent>) -> Result<serde_json::Value, (i64, String)> {
+    let (response, result) = mpsc::channel();
+    event_loop_proxy
+        .send_event(ApplicationEvent::RemoteControl(RemoteCall { request, response }))
+        .map_err(|_| (INTERNAL_ERROR, "Application is shutting down".to_owned()))?;
+    result
+        .recv()
+        .map_err(|_| (INTERNAL_ERROR, "Application is shutting down".to_owned()))?
+        .map_err(|message| (APPLICATION_ERROR, message))
+}
@@ -0,0 +1,333 @@
+use super::screenshot_capture::round_to_multiple;
+use crate::camera::{FAR_PLANE, NEAR_PLANE};
+use exr::prelude::{f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage};
+use futures::*;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+// Captures the linear HdrBackbuffer before tonemapping for compositing in other tools.
+// Color is written as is (alpha is dropped), depth is optional and written as linear distance from the camera plane in world units.
+
+#[derive(Clone, Copy, Debug, EnumIter, EnumString, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum HdrImageFormat {
+    // OpenEXR, losslessly compressed half float rgb. Depth goes into a float Z channel.
+    Exr,
+    // Radiance rgbe. Has no room for depth, which is written to a separate greyscale file next to it.
+    Hdr,
+}
+
+impl HdrImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            HdrImageFormat::Exr => "exr",
+            HdrImageFormat::Hdr => "hdr",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<HdrImageFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "exr" => Some(HdrImageFormat::Exr),
+            "hdr" => Some(HdrImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+const BYTES_PER_COLOR_PIXEL: usize = 8; // Rgba16Float
+const BYTES_PER_DEPTH_PIXEL: usize = 4; // Depth32Float
+
+// Readback buffers are big, so unlike for screenshots they are only created once needed.
+const MAX_NUM_READBACK_BUFFERS: usize = 4;
+
+struct ReadbackBuffers {
+    color: wgpu::Buffer,
+    depth: wgpu::Buffer,
+}
+
+struct PendingHdrCapture {
+    copy_operation: Option<Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>>>>>,
+    buffers: ReadbackBuffers,
+    path: PathBuf,
+    include_depth: bool,
+}
+
+impl PendingHdrCapture {
+    fn spawn_write_thread_if_ready(
+        mut self,
+        resolution: winit::dpi::PhysicalSize<u32>,
+        completion_sender: &Sender<ReadbackBuffers>,
+    ) -> Option<PendingHdrCapture> {
+        if self.copy_operation.is_none() {
+            let color_mapping = self.buffers.color.slice(..).map_async(wgpu::MapMode::Read);
+            self.copy_operation = Some(if self.include_depth {
+                let depth_mapping = self.buffers.depth.slice(..).map_async(wgpu::MapMode::Read);
+                future::try_join(color_mapping, depth_mapping).map_ok(|_| ()).boxed()
+            } else {
+                color_mapping.boxed()
+            });
+        }
+        if (&mut self.copy_operation.as_mut().unwrap()).now_or_never().is_none() {
+            return Some(self);
+        }
+
+        let PendingHdrCapture {
+            buffers,
+            path,
+            include_depth,
+            ..
+        } = self;
+        let completion_sender = completion_sender.clone();
+        std::thread::spawn(move || {
+            let start_time = std::time::Instant::now();
+
+            let color = read_color(&buffers.color, resolution);
+            buffers.color.unmap();
+            let depth = if include_depth {
+                let depth = read_linear_depth(&buffers.depth, resolution);
+                buffers.depth.unmap();
+                Some(depth)
+            } else {
+                None
+            };
+            completion_sender.send(buffers).unwrap();
+
+            match write_hdr_image(&path, resolution, &color, depth.as_deref()) {
+                Ok(()) => info!("Wrote hdr screenshot to {:?} (took {:?})", path, start_time.elapsed()),
+                Err(err) => error!("Failed to write hdr screenshot to {:?}: {}", path, err),
+            }
+        });
+        None
+    }
+}
+
+pub struct HdrCapture {
+    unused_buffers: Vec<ReadbackBuffers>,
+    num_buffers: usize,
+    pending_captures: VecDeque<PendingHdrCapture>,
+    completion_receiver: Receiver<ReadbackBuffers>,
+    completion_sender: Sender<ReadbackBuffers>,
+
+    resolution: winit::dpi::PhysicalSize<u32>,
+}
+
+impl HdrCapture {
+    pub fn new(resolution: winit::dpi::PhysicalSize<u32>) -> Self {
+        let (completion_sender, completion_receiver) = channel();
+        HdrCapture {
+            unused_buffers: Vec::new(),
+            num_buffers: 0,
+            pending_captures: VecDeque::new(),
+            completion_receiver,
+            completion_sender,
+
+            resolution,
+        }
+    }
+
+    fn bytes_per_padded_row(resolution: winit::dpi::PhysicalSize<u32>, bytes_per_pixel: usize) -> usize {
+        round_to_multiple(resolution.width as usize * bytes_per_pixel, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize)
+    }
+
+    fn create_readback_buffers(&self, device: &wgpu::Device) -> ReadbackBuffers {
+        let create_buffer = |bytes_per_pixel: usize, label: &str| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                size: Self::bytes_per_padded_row(self.resolution, bytes_per_pixel) as u64 * self.resolution.height as u64,
+                usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                label: Some(label),
+                mapped_at_creation: false,
+            })
+        };
+        ReadbackBuffers {
+            color: create_buffer(BYTES_PER_COLOR_PIXEL, &format!("Buffer: Hdr color readback buffer {}", self.num_buffers)),
+            depth: create_buffer(BYTES_PER_DEPTH_PIXEL, &format!("Buffer: Hdr depth readback buffer {}", self.num_buffers)),
+        }
+    }
+
+    pub fn process_pending_captures(&mut self) {
+        if let Some(pending_capture) = self.pending_captures.pop_front() {
+            if let Some(still_pending_capture) = pending_capture.spawn_write_thread_if_ready(self.resolution, &self.completion_sender) {
+                self.pending_captures.push_front(still_pending_capture);
+            }
+        }
+        if let Ok(received_unused_buffers) = self.completion_receiver.try_recv() {
+            self.unused_buffers.push(received_unused_buffers);
+        }
+    }
+
+    pub fn wait_for_pending_captures(&mut self, device: &wgpu::Device) {
+        while self.unused_buffers.len() < self.num_buffers {
+            device.poll(wgpu::Maintain::Poll);
+            self.process_pending_captures();
+            std::thread::yield_now();
+        }
+    }
+
+    // Writes the hdr texture (and the depth texture if requested) to the given path, format is determined by the extension.
+    // Both textures need to be of this capture's resolution and have COPY_SRC usage.
+    pub fn capture(
+        &mut self,
+        path: PathBuf,
+        include_depth: bool,
+        hdr_texture: &wgpu::Texture,
+        depth_texture: &wgpu::Texture,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.unused_buffers.is_empty() {
+            device.poll(wgpu::Maintain::Poll);
+            self.process_pending_captures();
+        }
+        if self.unused_buffers.is_empty() {
+            if self.num_buffers < MAX_NUM_READBACK_BUFFERS {
+                let buffers = self.create_readback_buffers(device);
+                self.unused_buffers.push(buffers);
+                self.num_buffers += 1;
+            } else {
+                warn!("No more unused hdr readback buffers available. Waiting for GPU/writer to catch up...");
+                while self.unused_buffers.is_empty() {
+                    std::thread::yield_now();
+                    device.poll(wgpu::Maintain::Poll);
+                    self.process_pending_captures();
+                }
+            }
+        }
+        let buffers = self.unused_buffers.pop().unwrap();
+
+        let size = wgpu::Extent3d {
+            width: self.resolution.width,
+            height: self.resolution.height,
+            depth: 1,
+        };
+        let mut copy_to_buffer = |texture: &wgpu::Texture, buffer: &wgpu::Buffer, bytes_per_pixel: usize| {
+            encoder.copy_texture_to_buffer(
+                wgpu::TextureCopyView {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::BufferCopyView {
+                    buffer,
+                    layout: wgpu::TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: Self::bytes_per_padded_row(self.resolution, bytes_per_pixel) as u32,
+                        rows_per_image: 0,
+                    },
+                },
+                size,
+            )
+        };
+        copy_to_buffer(hdr_texture, &buffers.color, BYTES_PER_COLOR_PIXEL);
+        if include_depth {
+            copy_to_buffer(depth_texture, &buffers.depth, BYTES_PER_DEPTH_PIXEL);
+        }
+
+        self.pending_captures.push_back(PendingHdrCapture {
+            copy_operation: None,
+            buffers,
+            path,
+            include_depth,
+        });
+    }
+}
+
+// Rgb of every pixel as half float bits, top to bottom.
+fn read_color(buffer: &wgpu::Buffer, resolution: winit::dpi::PhysicalSize<u32>) -> Vec<[u16; 3]> {
+    let padded_buffer = buffer.slice(..).get_mapped_range();
+    let row_size = resolution.width as usize * BYTES_PER_COLOR_PIXEL;
+    let mut color = Vec::with_capacity(resolution.width as usize * resolution.height as usize);
+    for row in padded_buffer
+        .chunks(HdrCapture::bytes_per_padded_row(resolution, BYTES_PER_COLOR_PIXEL))
+        .take(resolution.height as usize)
+    {
+        for pixel in row[..row_size].chunks(BYTES_PER_COLOR_PIXEL) {
+            let channel = |i: usize| u16::from_le_bytes([pixel[i * 2], pixel[i * 2 + 1]]);
+            color.push([channel(0), channel(1), channel(2)]);
+        }
+    }
+    color
+}
+
+// Depth buffer values converted back to distances from the camera plane, the background ends up at the far plane.
+fn read_linear_depth(buffer: &wgpu::Buffer, resolution: winit::dpi::PhysicalSize<u32>) -> Vec<f32> {
+    let padded_buffer = buffer.slice(..).get_mapped_range();
+    let row_size = resolution.width as usize * BYTES_PER_DEPTH_PIXEL;
+    let mut depth = Vec::with_capacity(resolution.width as usize * resolution.height as usize);
+    for row in padded_buffer
+        .chunks(HdrCapture::bytes_per_padded_row(resolution, BYTES_PER_DEPTH_PIXEL))
+        .take(resolution.height as usize)
+    {
+        for pixel in row[..row_size].chunks(BYTES_PER_DEPTH_PIXEL) {
+            let ndc_depth = f32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            depth.push(NEAR_PLANE * FAR_PLANE / (FAR_PLANE - ndc_depth * (FAR_PLANE - NEAR_PLANE)));
+        }
+    }
+    depth
+}
+
+fn write_hdr_image(path: &Path, resolution: winit::dpi::PhysicalSize<u32>, color: &[[u16; 3]], depth: Option<&[f32]>) -> io::Result<()> {
+    match HdrImageFormat::from_path(path) {
+        Some(HdrImageFormat::Exr) => write_exr(path, resolution, color, depth),
+        Some(HdrImageFormat::Hdr) => {
+            let pixels = color
+                .iter()
+                .map(|pixel| {
+                    image::Rgb([
+                        f16::from_bits(pixel[0]).to_f32(),
+                        f16::from_bits(pixel[1]).to_f32(),
+                        f16::from_bits(pixel[2]).to_f32(),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            write_radiance_hdr(path, resolution, &pixels)?;
+            if let Some(depth) = depth {
+                let pixels = depth.iter().map(|&depth| image::Rgb([depth, depth, depth])).collect::<Vec<_>>();
+                write_radiance_hdr(&depth_image_path(path), resolution, &pixels)?;
+            }
+            Ok(())
+        }
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "hdr captures need an exr or hdr extension")),
+    }
+}
+
+// Where depth goes for formats without depth channel, e.g. screenshot1_depth.hdr for screenshot1.hdr.
+pub fn depth_image_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let extension = path
+        .extension()
+        .map_or_else(String::new, |extension| extension.to_string_lossy().into_owned());
+    path.with_file_name(format!("{}_depth.{}", stem, extension))
+}
+
+fn write_radiance_hdr(path: &Path, resolution: winit::dpi::PhysicalSize<u32>, pixels: &[image::Rgb<f32>]) -> io::Result<()> {
+    image::hdr::HdrEncoder::new(BufWriter::new(File::create(path)?))
+        .encode(pixels, resolution.width as usize, resolution.height as usize)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+// Half float color is written as is, no need to go through f32.
+fn write_exr(path: &Path, resolution: winit::dpi::PhysicalSize<u32>, color: &[[u16; 3]], depth: Option<&[f32]>) -> io::Result<()> {
+    let color_channel =
+        |name: &str, channel: usize| AnyChannel::new(name, FlatSamples::F16(color.iter().map(|pixel| f16::from_bits(pixel[channel])).collect()));
+    let mut channels = vec![color_channel("R", 0), color_channel("G", 1), color_channel("B", 2)];
+    if let Some(depth) = depth {
+        channels.push(AnyChannel::new("Z", FlatSamples::F32(depth.to_vec())));
+    }
+
+    let layer = Layer::new(
+        (resolution.width as usize, resolution.height as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels.into()),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+}
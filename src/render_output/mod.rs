@@ -1,4 +1,7 @@
 pub mod hdr_backbuffer;
+pub mod hdr_capture;
+pub mod offscreen_renderer;
 pub mod screen;
 pub mod screenshot_capture;
 pub mod screenshot_recorder;
+pub mod video_stream;
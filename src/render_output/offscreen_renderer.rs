@@ -0,0 +1,164 @@
+use super::{
+    hdr_backbuffer::HdrBackbuffer,
+    hdr_capture::HdrCapture,
+    screen::{Screen, ScreenUniformBufferContent},
+    screenshot_capture::{CaptureTarget, ScreenshotCapture},
+};
+use crate::{
+    camera::Camera,
+    per_frame_resources::PerFrameResources,
+    renderer::SceneRenderer,
+    scene::Scene,
+    timer::Timer,
+    wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler, shader::ShaderDirectory},
+};
+
+// Renders the scene into image files or video streams at an arbitrary resolution, independent of any window.
+// Has its own scene renderer since some of its resources depend on the output resolution.
+pub struct OffscreenRenderer {
+    resolution: winit::dpi::PhysicalSize<u32>,
+
+    backbuffer: wgpu::Texture,
+    backbuffer_view: wgpu::TextureView,
+    depth_texture: wgpu::Texture,
+    depth_view: wgpu::TextureView,
+    hdr_backbuffer: HdrBackbuffer,
+
+    scene_renderer: SceneRenderer,
+    screenshot_capture: ScreenshotCapture,
+    hdr_capture: HdrCapture,
+}
+
+impl OffscreenRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        resolution: winit::dpi::PhysicalSize<u32>,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        per_frame_bind_group_layout: &wgpu::BindGroupLayout,
+        scene: &Scene,
+    ) -> Self {
+        info!("creating offscreen renderer with {:?}", resolution);
+
+        let size = wgpu::Extent3d {
+            width: resolution.width,
+            height: resolution.height,
+            depth: 1,
+        };
+        let backbuffer = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture: Offscreen Backbuffer"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Screen::FORMAT_BACKBUFFER,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let backbuffer_view = backbuffer.create_view(&Default::default());
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture: Offscreen DepthBuffer"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Screen::FORMAT_DEPTH,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+
+        let hdr_backbuffer = HdrBackbuffer::new(device, resolution);
+        let mut scene_renderer = SceneRenderer::new(device, queue, shader_dir, pipeline_manager, per_frame_bind_group_layout, &hdr_backbuffer);
+        scene_renderer.on_new_scene(queue, scene);
+
+        OffscreenRenderer {
+            resolution,
+
+            backbuffer,
+            backbuffer_view,
+            depth_view: depth_texture.create_view(&Default::default()),
+            depth_texture,
+            hdr_backbuffer,
+
+            scene_renderer,
+            screenshot_capture: ScreenshotCapture::new(device, resolution),
+            hdr_capture: HdrCapture::new(resolution),
+        }
+    }
+
+    pub fn resolution(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.resolution
+    }
+
+    pub fn scene_renderer_mut(&mut self) -> &mut SceneRenderer {
+        &mut self.scene_renderer
+    }
+
+    pub fn on_new_scene(&mut self, queue: &wgpu::Queue, scene: &Scene) {
+        self.scene_renderer.on_new_scene(queue, scene);
+    }
+
+    // Renders & tonemaps the scene and writes the result to the given target in the background. Hdr captures skip tonemapping.
+    // Submits its own command buffer since the per frame resources are overwritten with offscreen camera & resolution.
+    pub fn render_and_capture(
+        &mut self,
+        target: CaptureTarget,
+        scene: &Scene,
+        camera: &Camera,
+        timer: &Timer,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_manager: &PipelineManager,
+        profiler: &mut GpuProfiler,
+        per_frame_resources: &mut PerFrameResources,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Encoder: Offscreen Frame"),
+        });
+
+        per_frame_resources.update_gpu_data(
+            queue,
+            camera.fill_global_uniform_buffer(self.resolution.width as f32 / self.resolution.height as f32),
+            timer.fill_global_uniform_buffer(),
+            self.scene_renderer.fill_global_uniform_buffer(scene),
+            ScreenUniformBufferContent::new(self.resolution),
+        );
+        self.scene_renderer.draw(
+            scene,
+            &mut encoder,
+            pipeline_manager,
+            profiler,
+            self.hdr_backbuffer.texture_view(),
+            &self.depth_view,
+            per_frame_resources.bind_group(),
+        );
+        match target {
+            CaptureTarget::HdrFile { path, include_depth } => self.hdr_capture.capture(
+                path,
+                include_depth,
+                self.hdr_backbuffer.texture(),
+                &self.depth_texture,
+                device,
+                &mut encoder,
+            ),
+            target => {
+                self.scene_renderer.tonemap(
+                    &mut encoder,
+                    pipeline_manager,
+                    profiler,
+                    per_frame_resources.bind_group(),
+                    &self.backbuffer_view,
+                );
+                self.screenshot_capture.capture_screenshot(target, &self.backbuffer, device, &mut encoder);
+            }
+        }
+
+        queue.submit(Some(encoder.finish()));
+        self.screenshot_capture.process_pending_screenshots();
+        self.hdr_capture.process_pending_captures();
+    }
+
+    pub fn wait_for_pending_frames(&mut self, device: &wgpu::Device) {
+        self.screenshot_capture.wait_for_pending_screenshots(device);
+        self.hdr_capture.wait_for_pending_captures(device);
+    }
+}
@@ -1,8 +1,13 @@
-use super::screenshot_capture::ScreenshotCapture;
+use super::{
+    hdr_backbuffer::HdrBackbuffer,
+    hdr_capture::HdrCapture,
+    screenshot_capture::{CaptureTarget, ScreenshotCapture},
+};
 use crate::wgpu_utils::binding_builder::*;
 use crate::wgpu_utils::shader::*;
 use crate::wgpu_utils::*;
 use pipelines::*;
+use profiler::GpuProfiler;
 use std::path::Path;
 
 pub struct Screen {
@@ -12,12 +17,14 @@ pub struct Screen {
 
     backbuffer: wgpu::Texture,
     backbuffer_view: wgpu::TextureView,
+    depth_texture: wgpu::Texture,
     depth_view: wgpu::TextureView,
 
     read_backbuffer_bind_group: wgpu::BindGroup,
     copy_to_swapchain_pipeline: wgpu::RenderPipeline,
 
     screenshot_capture: ScreenshotCapture,
+    hdr_capture: HdrCapture,
 }
 
 #[repr(C)]
@@ -27,6 +34,15 @@ pub struct ScreenUniformBufferContent {
     resolution_inv: cgmath::Point2<f32>,
 }
 
+impl ScreenUniformBufferContent {
+    pub fn new(resolution: winit::dpi::PhysicalSize<u32>) -> Self {
+        ScreenUniformBufferContent {
+            resolution: cgmath::point2(resolution.width as f32, resolution.height as f32),
+            resolution_inv: cgmath::point2(1.0 / resolution.width as f32, 1.0 / resolution.height as f32),
+        }
+    }
+}
+
 impl Screen {
     pub const FORMAT_BACKBUFFER: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
     const FORMAT_SWAPCHAIN: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
@@ -77,7 +93,7 @@ impl Screen {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: Self::FORMAT_DEPTH,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
         });
 
         let bind_group_layout = BindGroupLayoutBuilder::new()
@@ -127,10 +143,12 @@ impl Screen {
             backbuffer,
             backbuffer_view,
             depth_view: depth_texture.create_view(&Default::default()),
+            depth_texture,
 
             read_backbuffer_bind_group,
             copy_to_swapchain_pipeline,
             screenshot_capture: ScreenshotCapture::new(device, resolution),
+            hdr_capture: HdrCapture::new(resolution),
         }
     }
 
@@ -154,8 +172,21 @@ impl Screen {
         self.present_mode
     }
 
-    pub fn capture_screenshot(&mut self, path: &Path, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
-        self.screenshot_capture.capture_screenshot(path, &self.backbuffer, device, encoder);
+    // Hdr captures are taken from the given hdr backbuffer, which needs to be of the screen's resolution.
+    pub fn capture_screenshot(
+        &mut self,
+        target: CaptureTarget,
+        hdr_backbuffer: &HdrBackbuffer,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        match target {
+            CaptureTarget::HdrFile { path, include_depth } => {
+                self.hdr_capture
+                    .capture(path, include_depth, hdr_backbuffer.texture(), &self.depth_texture, device, encoder)
+            }
+            target => self.screenshot_capture.capture_screenshot(target, &self.backbuffer, device, encoder),
+        }
     }
 
     pub fn start_frame(&mut self, device: &wgpu::Device, window_surface: &wgpu::Surface) -> wgpu::SwapChainTexture {
@@ -184,8 +215,8 @@ impl Screen {
         }
     }
 
-    pub fn copy_to_swapchain(&mut self, output: &wgpu::SwapChainTexture, encoder: &mut wgpu::CommandEncoder) {
-        wgpu_scope!(encoder, "Screen.copy_to_swapchain");
+    pub fn copy_to_swapchain(&mut self, output: &wgpu::SwapChainTexture, encoder: &mut wgpu::CommandEncoder, profiler: &mut GpuProfiler) {
+        wgpu_scope!(encoder, profiler, "Screen.copy_to_swapchain");
 
         // why this extra copy?
         // Webgpu doesn't allow us to do anything with the swapchain target but read from it!
@@ -213,16 +244,15 @@ impl Screen {
     pub fn end_frame(&mut self, frame: wgpu::SwapChainTexture) {
         std::mem::drop(frame);
         self.screenshot_capture.process_pending_screenshots();
+        self.hdr_capture.process_pending_captures();
     }
 
     pub fn wait_for_pending_screenshots(&mut self, device: &wgpu::Device) {
         self.screenshot_capture.wait_for_pending_screenshots(device);
+        self.hdr_capture.wait_for_pending_captures(device);
     }
 
     pub fn fill_global_uniform_buffer(&self) -> ScreenUniformBufferContent {
-        ScreenUniformBufferContent {
-            resolution: cgmath::point2(self.resolution.width as f32, self.resolution.height as f32),
-            resolution_inv: cgmath::point2(1.0 / self.resolution.width as f32, 1.0 / self.resolution.height as f32),
-        }
+        ScreenUniformBufferContent::new(self.resolution)
     }
 }
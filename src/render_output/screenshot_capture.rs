@@ -1,13 +1,24 @@
+use super::video_stream::VideoFrame;
 use futures::*;
 use std::collections::VecDeque;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::mpsc::{channel, Receiver, Sender};
 
+// Where a captured frame goes.
+pub enum CaptureTarget {
+    // Png file, written on its own thread.
+    File(PathBuf),
+    // Next frame of a video stream (see VideoStream::frame_sender). Frames arrive there in capture order.
+    VideoFrame(Sender<VideoFrame>),
+    // OpenEXR or Radiance HDR file (by extension) of the hdr backbuffer before tonemapping, see HdrCapture.
+    HdrFile { path: PathBuf, include_depth: bool },
+}
+
 struct PendingScreenshot {
     copy_operation: Option<Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>>>>>,
     buffer: wgpu::Buffer,
-    target_path: PathBuf,
+    target: CaptureTarget,
 }
 
 impl PendingScreenshot {
@@ -24,7 +35,24 @@ impl PendingScreenshot {
         let val = (&mut self.copy_operation.as_mut().unwrap()).now_or_never();
         if val.is_some() {
             let buffer = self.buffer;
-            let target_path = self.target_path;
+            let target_path = match self.target {
+                CaptureTarget::File(target_path) => target_path,
+                CaptureTarget::VideoFrame(frame_sender) => {
+                    let frame = VideoFrame {
+                        buffer,
+                        resolution,
+                        padded_row_size: ScreenshotCapture::screenshot_buffer_bytes_per_padded_row(resolution),
+                        completion_sender: completion_sender.clone(),
+                    };
+                    if let Err(std::sync::mpsc::SendError(frame)) = frame_sender.send(frame) {
+                        error!("Video stream is gone, dropping frame");
+                        frame.buffer.unmap();
+                        completion_sender.send(frame.buffer).unwrap();
+                    }
+                    return None;
+                }
+                CaptureTarget::HdrFile { .. } => unreachable!("hdr captures are read back from the hdr backbuffer by HdrCapture"),
+            };
             let completion_sender_clone = completion_sender.clone();
 
             std::thread::spawn(move || {
@@ -119,7 +147,13 @@ impl ScreenshotCapture {
         }
     }
 
-    pub fn capture_screenshot(&mut self, path: &Path, backbuffer: &wgpu::Texture, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+    pub fn capture_screenshot(
+        &mut self,
+        target: CaptureTarget,
+        backbuffer: &wgpu::Texture,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
         if self.unused_screenshot_buffers.len() == 0 {
             device.poll(wgpu::Maintain::Poll);
             self.process_pending_screenshots();
@@ -159,11 +193,11 @@ impl ScreenshotCapture {
         self.pending_screenshots.push_back(PendingScreenshot {
             copy_operation: None,
             buffer,
-            target_path: path.into(),
+            target,
         });
     }
 }
 
-fn round_to_multiple(value: usize, multiple: usize) -> usize {
+pub fn round_to_multiple(value: usize, multiple: usize) -> usize {
     (value + multiple - 1) / multiple * multiple
 }
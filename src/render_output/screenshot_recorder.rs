@@ -1,79 +1,204 @@
-use super::screen::Screen;
+use super::{
+    hdr_backbuffer::HdrBackbuffer,
+    hdr_capture::HdrImageFormat,
+    screen::Screen,
+    screenshot_capture::CaptureTarget,
+    video_stream::{RecordingFormat, VideoStream, DEFAULT_ENCODER_COMMAND},
+};
 use std::path::{Path, PathBuf};
+use strum::IntoEnumIterator;
 
 pub struct ScreenshotRecorder {
+    output_dir: PathBuf,
+    // Applies to recordings started from now on.
+    pub recording_format: RecordingFormat,
+    // Command line for RecordingFormat::Encoder, see DEFAULT_ENCODER_COMMAND.
+    pub encoder_command: String,
+    // If set, screenshots are taken from the hdr backbuffer before tonemapping and written in this format instead of png.
+    pub hdr_screenshot_format: Option<HdrImageFormat>,
+    // Adds linear depth to hdr screenshots and recordings.
+    pub hdr_include_depth: bool,
+
     next_regular_screenshot_index: usize,
     scheduled_screenshot: Option<PathBuf>,
 
     next_recording_screenshot_index: usize,
     recording_output_dir: Option<PathBuf>,
+    recording_fps: f64,
+    // Opened with the first frame of a recording since the resolution isn't known before. None for png sequences.
+    video_stream: Option<VideoStream>,
+    // Streams of stopped recordings, their frames may still be in flight.
+    finishing_video_streams: Vec<VideoStream>,
 }
 
 impl ScreenshotRecorder {
-    pub fn new() -> Self {
+    pub fn new(output_dir: &Path) -> Self {
         let mut next_regular_screenshot_index = 0;
         for i in 1..usize::MAX {
-            if !Self::regular_screenshot_path(i).exists() {
+            let extensions = std::iter::once("png").chain(HdrImageFormat::iter().map(HdrImageFormat::extension));
+            if !extensions
+                .map(|extension| Self::regular_screenshot_path(output_dir, i, extension))
+                .any(|path| path.exists())
+            {
                 next_regular_screenshot_index = i;
                 break;
             }
         }
 
         ScreenshotRecorder {
+            output_dir: output_dir.to_path_buf(),
+            recording_format: RecordingFormat::PngSequence,
+            encoder_command: DEFAULT_ENCODER_COMMAND.to_owned(),
+            hdr_screenshot_format: None,
+            hdr_include_depth: false,
+
             next_regular_screenshot_index,
             scheduled_screenshot: None,
 
             next_recording_screenshot_index: 0,
             recording_output_dir: None,
+            recording_fps: 60.0,
+            video_stream: None,
+            finishing_video_streams: Vec::new(),
         }
     }
 
-    fn regular_screenshot_path(index: usize) -> PathBuf {
-        PathBuf::from(format!("screenshot{}.png", index))
+    fn regular_screenshot_path(output_dir: &Path, index: usize, extension: &str) -> PathBuf {
+        output_dir.join(format!("screenshot{}.{}", index, extension))
     }
 
-    pub fn start_next_recording(&mut self) {
+    // Frame rate of the recording, only used for video streams.
+    pub fn start_next_recording(&mut self, fps: f64) {
         for i in 0..usize::MAX {
-            let recording_output_dir = PathBuf::from(format!("recording{}", i));
+            let recording_output_dir = self.output_dir.join(format!("recording{}", i));
             if !recording_output_dir.exists() {
-                self.start_recording(&recording_output_dir);
+                self.start_recording(&recording_output_dir, fps);
                 break;
             }
         }
     }
 
-    fn start_recording(&mut self, recording_output_dir: &Path) {
-        std::fs::create_dir(&recording_output_dir).unwrap();
+    fn start_recording(&mut self, recording_output_dir: &Path, fps: f64) {
+        self.stop_recording();
+        std::fs::create_dir_all(&recording_output_dir).unwrap();
         self.next_recording_screenshot_index = 0;
         self.recording_output_dir = Some(recording_output_dir.into());
+        self.recording_fps = fps;
     }
 
     pub fn stop_recording(&mut self) {
         self.recording_output_dir = None;
+        if let Some(video_stream) = self.video_stream.take() {
+            self.finishing_video_streams.push(video_stream);
+        }
+    }
+
+    // Stops recording and waits until all videos are written. All captures need to be through readback before.
+    pub fn finish_video_streams(&mut self) {
+        self.stop_recording();
+        for video_stream in self.finishing_video_streams.drain(..) {
+            video_stream.finish();
+        }
     }
 
-    pub fn schedule_next_screenshot(&mut self) {
-        self.schedule_screenshot(&Self::regular_screenshot_path(self.next_regular_screenshot_index));
+    // Output directory and index of the frame that is captured next, if there is an active recording.
+    pub fn current_recording_frame(&self) -> Option<(&Path, usize)> {
+        self.recording_output_dir
+            .as_ref()
+            .map(|dir| (dir.as_path(), self.next_recording_screenshot_index))
+    }
+
+    // Extension is png or that of an HdrImageFormat.
+    pub fn recording_frame_path(recording_output_dir: &Path, frame_index: usize, extension: &str) -> PathBuf {
+        recording_output_dir.join(format!("screenshot{}.{}", frame_index, extension))
+    }
+
+    // Png file, or hdr capture if the path has the extension of an HdrImageFormat.
+    pub fn file_capture_target(path: PathBuf, hdr_include_depth: bool) -> CaptureTarget {
+        match HdrImageFormat::from_path(&path) {
+            Some(_) => CaptureTarget::HdrFile {
+                path,
+                include_depth: hdr_include_depth,
+            },
+            None => CaptureTarget::File(path),
+        }
+    }
+
+    // Where the current recording frame goes, None if there is no active recording.
+    // For video recordings, the stream is opened with the first frame. If that fails, the recording falls back to png files.
+    pub fn recording_frame_target(&mut self, resolution: winit::dpi::PhysicalSize<u32>) -> Option<CaptureTarget> {
+        let recording_output_dir = self.recording_output_dir.clone()?;
+        if self.next_recording_screenshot_index == 0 && self.video_stream.is_none() {
+            let video_stream = match self.recording_format {
+                RecordingFormat::PngSequence | RecordingFormat::ExrSequence | RecordingFormat::HdrSequence => None,
+                RecordingFormat::Y4m => Some(VideoStream::create_y4m(
+                    &recording_output_dir.join("video.y4m"),
+                    resolution,
+                    self.recording_fps,
+                )),
+                RecordingFormat::Encoder => Some(VideoStream::spawn_encoder(
+                    &self.encoder_command,
+                    &recording_output_dir.join("video.mp4"),
+                    resolution,
+                    self.recording_fps,
+                )),
+            };
+            match video_stream {
+                Some(Ok(video_stream)) => self.video_stream = Some(video_stream),
+                Some(Err(err)) => error!(
+                    "Failed to start {:?} video stream, recording png files instead: {}",
+                    self.recording_format, err
+                ),
+                None => {}
+            }
+        }
+
+        Some(match &self.video_stream {
+            Some(video_stream) => CaptureTarget::VideoFrame(video_stream.frame_sender()),
+            None => {
+                let extension = self.recording_format.hdr_image_format().map_or("png", HdrImageFormat::extension);
+                Self::file_capture_target(
+                    Self::recording_frame_path(&recording_output_dir, self.next_recording_screenshot_index, extension),
+                    self.hdr_include_depth,
+                )
+            }
+        })
+    }
+
+    // Returns where the screenshot is going to be written.
+    pub fn schedule_next_screenshot(&mut self) -> PathBuf {
+        let extension = self.hdr_screenshot_format.map_or("png", HdrImageFormat::extension);
+        let path = Self::regular_screenshot_path(&self.output_dir, self.next_regular_screenshot_index, extension);
+        self.schedule_screenshot(&path);
         self.next_regular_screenshot_index += 1;
+        path
     }
 
-    fn schedule_screenshot(&mut self, path: &Path) {
+    // Captures the next frame to the given path. Paths with exr or hdr extension capture the hdr backbuffer.
+    pub fn schedule_screenshot(&mut self, path: &Path) {
         self.scheduled_screenshot = Some(path.into());
     }
 
-    pub fn capture_screenshot(&mut self, screen: &mut Screen, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
-        if let Some(ref scheduled_screenshot) = self.scheduled_screenshot {
-            screen.capture_screenshot(&scheduled_screenshot, device, encoder);
+    // If the recording frame was already rendered offscreen (see current_recording_frame), it is not captured from the screen again.
+    pub fn capture_screenshot(
+        &mut self,
+        screen: &mut Screen,
+        hdr_backbuffer: &HdrBackbuffer,
+        recording_frame_rendered_offscreen: bool,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if let Some(scheduled_screenshot) = self.scheduled_screenshot.take() {
+            let target = Self::file_capture_target(scheduled_screenshot, self.hdr_include_depth);
+            screen.capture_screenshot(target, hdr_backbuffer, device, encoder);
         }
-        if let Some(ref recording_output_dir) = self.recording_output_dir {
-            screen.capture_screenshot(
-                &recording_output_dir.join(format!("screenshot{}.png", self.next_recording_screenshot_index)),
-                device,
-                encoder,
-            );
+        if self.recording_output_dir.is_some() {
+            if !recording_frame_rendered_offscreen {
+                if let Some(target) = self.recording_frame_target(screen.resolution()) {
+                    screen.capture_screenshot(target, hdr_backbuffer, device, encoder);
+                }
+            }
             self.next_recording_screenshot_index += 1;
         }
-
-        self.scheduled_screenshot = None;
     }
 }
@@ -0,0 +1,207 @@
+use super::hdr_capture::HdrImageFormat;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::JoinHandle,
+};
+
+// Default for RecordingFormat::Encoder. {width}, {height}, {fps} and {output} are replaced, raw rgb24 frames arrive on stdin.
+pub const DEFAULT_ENCODER_COMMAND: &str =
+    "ffmpeg -y -loglevel error -f rawvideo -pix_fmt rgb24 -video_size {width}x{height} -framerate {fps} -i - -c:v libx264 -pix_fmt yuv420p -crf 18 {output}";
+
+#[derive(Clone, Copy, Debug, EnumIter, EnumString, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum RecordingFormat {
+    // One png per frame.
+    PngSequence,
+    // Single uncompressed Y4M file (4:4:4, BT.601 limited range), huge but readable by about every video tool.
+    Y4m,
+    // Frames are piped into an external encoder process.
+    Encoder,
+    // One OpenEXR file per frame, linear color before tonemapping.
+    ExrSequence,
+    // One Radiance HDR file per frame, linear color before tonemapping.
+    HdrSequence,
+}
+
+impl RecordingFormat {
+    // Format of the frame files for recordings of the hdr backbuffer, None for all others.
+    pub fn hdr_image_format(self) -> Option<HdrImageFormat> {
+        match self {
+            RecordingFormat::ExrSequence => Some(HdrImageFormat::Exr),
+            RecordingFormat::HdrSequence => Some(HdrImageFormat::Hdr),
+            RecordingFormat::PngSequence | RecordingFormat::Y4m | RecordingFormat::Encoder => None,
+        }
+    }
+}
+
+// Readback buffer of a captured frame, handed back once its content is copied out.
+pub struct VideoFrame {
+    pub buffer: wgpu::Buffer,
+    pub resolution: winit::dpi::PhysicalSize<u32>,
+    pub padded_row_size: usize,
+    pub completion_sender: Sender<wgpu::Buffer>,
+}
+
+enum FrameSink {
+    Y4m(BufWriter<File>),
+    Encoder(Child),
+}
+
+impl FrameSink {
+    fn write_frame(&mut self, rgb: &[u8], resolution: winit::dpi::PhysicalSize<u32>) -> io::Result<()> {
+        match self {
+            FrameSink::Y4m(writer) => {
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&rgb_to_ycbcr_planes(rgb, resolution))
+            }
+            FrameSink::Encoder(child) => child.stdin.as_mut().unwrap().write_all(rgb),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            FrameSink::Y4m(mut writer) => writer.flush(),
+            FrameSink::Encoder(mut child) => {
+                // Closing stdin tells the encoder that there are no more frames.
+                drop(child.stdin.take());
+                let status = child.wait()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(io::ErrorKind::Other, format!("encoder exited with {}", status)))
+                }
+            }
+        }
+    }
+}
+
+// Full range rgb to BT.601 limited range Y, Cb and Cr planes, which is what decoders assume for Y4M.
+fn rgb_to_ycbcr_planes(rgb: &[u8], resolution: winit::dpi::PhysicalSize<u32>) -> Vec<u8> {
+    let num_pixels = resolution.width as usize * resolution.height as usize;
+    let mut planes = vec![0u8; num_pixels * 3];
+    for (pixel_index, pixel) in rgb.chunks(3).enumerate() {
+        let (r, g, b) = (pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0);
+        planes[pixel_index] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+        planes[num_pixels + pixel_index] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+        planes[num_pixels * 2 + pixel_index] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+    }
+    planes
+}
+
+// Frame rate as fraction, Y4M doesn't do decimals.
+fn fps_fraction(fps: f64) -> (u64, u64) {
+    if fps.fract() == 0.0 {
+        (fps as u64, 1)
+    } else {
+        ((fps * 1000.0).round() as u64, 1000)
+    }
+}
+
+// Writes frames in the order they arrive into a single video on a background thread.
+// Frames are converted and written there as well, the readback buffers go back as soon as their content is copied.
+pub struct VideoStream {
+    frame_sender: Sender<VideoFrame>,
+    writer_thread: JoinHandle<()>,
+}
+
+impl VideoStream {
+    pub fn create_y4m(path: &Path, resolution: winit::dpi::PhysicalSize<u32>, fps: f64) -> io::Result<VideoStream> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (fps_numerator, fps_denominator) = fps_fraction(fps);
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444 XCOLORRANGE=LIMITED",
+            resolution.width, resolution.height, fps_numerator, fps_denominator
+        )?;
+        Ok(Self::start(FrameSink::Y4m(writer), path.to_path_buf(), resolution))
+    }
+
+    // Runs the given command line (see DEFAULT_ENCODER_COMMAND for the placeholders) without shell.
+    pub fn spawn_encoder(command: &str, output_path: &Path, resolution: winit::dpi::PhysicalSize<u32>, fps: f64) -> io::Result<VideoStream> {
+        let arguments: Vec<String> = command
+            .split_whitespace()
+            .map(|argument| {
+                argument
+                    .replace("{width}", &resolution.width.to_string())
+                    .replace("{height}", &resolution.height.to_string())
+                    .replace("{fps}", &fps.to_string())
+                    .replace("{output}", &output_path.to_string_lossy())
+            })
+            .collect();
+        let (program, arguments) = arguments
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "encoder command is empty"))?;
+        let child = Command::new(program).args(arguments).stdin(Stdio::piped()).spawn()?;
+        info!("Started encoder: {} {}", program, arguments.join(" "));
+        Ok(Self::start(FrameSink::Encoder(child), output_path.to_path_buf(), resolution))
+    }
+
+    fn start(sink: FrameSink, path: PathBuf, resolution: winit::dpi::PhysicalSize<u32>) -> VideoStream {
+        let (frame_sender, frame_receiver) = channel();
+        let writer_thread = std::thread::spawn(move || Self::write_frames(sink, frame_receiver, &path, resolution));
+        VideoStream { frame_sender, writer_thread }
+    }
+
+    fn write_frames(mut sink: FrameSink, frame_receiver: Receiver<VideoFrame>, path: &Path, resolution: winit::dpi::PhysicalSize<u32>) {
+        let start_time = std::time::Instant::now();
+        let mut num_frames = 0;
+        let mut failed = false;
+        let mut rgb = Vec::with_capacity(resolution.width as usize * resolution.height as usize * 3);
+
+        // Ends once all senders are gone, i.e. the stream is finished and there are no more frames in flight.
+        for frame in frame_receiver.iter() {
+            rgb.clear();
+            {
+                let padded_buffer = frame.buffer.slice(..).get_mapped_range();
+                let row_size = frame.resolution.width as usize * 4;
+                for row in padded_buffer.chunks(frame.padded_row_size).take(frame.resolution.height as usize) {
+                    for pixel in row[..row_size].chunks(4) {
+                        rgb.extend_from_slice(&pixel[..3]);
+                    }
+                }
+            }
+            frame.buffer.unmap();
+            // Fails only if the capture is gone already, which doesn't need its buffers back then.
+            frame.completion_sender.send(frame.buffer).ok();
+
+            if failed {
+                continue;
+            }
+            if frame.resolution != resolution {
+                error!(
+                    "Video stream {:?} is {}x{}, can't add a frame of {}x{}. Skipping frame",
+                    path, resolution.width, resolution.height, frame.resolution.width, frame.resolution.height
+                );
+                continue;
+            }
+            match sink.write_frame(&rgb, resolution) {
+                Ok(()) => num_frames += 1,
+                Err(err) => {
+                    error!("Failed to write frame to video stream {:?}, dropping all further frames: {}", path, err);
+                    failed = true;
+                }
+            }
+        }
+
+        match sink.finish() {
+            Ok(()) => info!("Wrote {} frames to {:?} (took {:?})", num_frames, path, start_time.elapsed()),
+            Err(err) => error!("Failed to finish video stream {:?}: {}", path, err),
+        }
+    }
+
+    pub fn frame_sender(&self) -> Sender<VideoFrame> {
+        self.frame_sender.clone()
+    }
+
+    // Blocks until all frames sent so far are written. Frames that are still being read back need to be through the capture first.
+    pub fn finish(self) {
+        drop(self.frame_sender);
+        if self.writer_thread.join().is_err() {
+            error!("Video stream writer thread panicked");
+        }
+    }
+}
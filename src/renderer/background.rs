@@ -2,7 +2,7 @@ use crate::{
     render_output::hdr_backbuffer::HdrBackbuffer,
     render_output::screen::Screen,
     wgpu_utils::uniformbuffer::PaddedVector3,
-    wgpu_utils::{binding_builder::*, binding_glsl, pipelines::*, shader::ShaderDirectory, uniformbuffer::UniformBuffer},
+    wgpu_utils::{binding_builder::*, binding_glsl, pipelines::*, profiler::GpuProfiler, shader::ShaderDirectory, uniformbuffer::UniformBuffer},
 };
 use image::hdr::{HdrDecoder, Rgbe8Pixel};
 use serde::Deserialize;
@@ -161,8 +161,8 @@ impl Background {
         })
     }
 
-    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, pipeline_manager: &'a PipelineManager) {
-        wgpu_scope!(rpass, "CubemapRenderer.draw");
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, pipeline_manager: &'a PipelineManager, profiler: &mut GpuProfiler) {
+        wgpu_scope!(rpass, profiler, "CubemapRenderer.draw");
         rpass.set_bind_group(1, &self.bind_group, &[]);
         rpass.set_pipeline(pipeline_manager.get_render(&self.pipeline));
         rpass.draw(0..3, 0..1);
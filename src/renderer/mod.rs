@@ -3,9 +3,12 @@ mod particle_renderer;
 mod scene_renderer;
 mod screenspace_fluid;
 mod static_line_renderer;
+mod tonemapper;
 mod volume_renderer;
 
 pub use scene_renderer::FluidRenderingMode;
 pub use scene_renderer::GlobalRenderSettingsUniformBufferContent;
 pub use scene_renderer::SceneRenderer;
+pub use tonemapper::TonemapOperator;
+pub use tonemapper::Tonemapper;
 pub use volume_renderer::VolumeVisualizationMode;
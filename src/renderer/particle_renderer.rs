@@ -2,7 +2,7 @@ use crate::wgpu_utils::pipelines::*;
 use crate::{
     render_output::{hdr_backbuffer::HdrBackbuffer, screen::Screen},
     simulation::HybridFluid,
-    wgpu_utils::shader::*,
+    wgpu_utils::{profiler::GpuProfiler, shader::*},
 };
 use std::{path::Path, rc::Rc};
 
@@ -37,8 +37,14 @@ impl ParticleRenderer {
         ParticleRenderer { render_pipeline }
     }
 
-    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, pipeline_manager: &'a PipelineManager, fluid: &'a HybridFluid) {
-        wgpu_scope!(rpass, "ParticleRenderer.draw");
+    pub fn draw<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        profiler: &mut GpuProfiler,
+        fluid: &'a HybridFluid,
+    ) {
+        wgpu_scope!(rpass, profiler, "ParticleRenderer.draw");
         rpass.set_pipeline(pipeline_manager.get_render(&self.render_pipeline));
         rpass.set_bind_group(1, fluid.bind_group_renderer(), &[]);
         rpass.draw(0..4, 0..fluid.num_particles());
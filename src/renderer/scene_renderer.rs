@@ -4,15 +4,17 @@ use super::background::Background;
 use super::particle_renderer::ParticleRenderer;
 use super::screenspace_fluid::ScreenSpaceFluid;
 use super::static_line_renderer::{LineVertex, StaticLineRenderer};
+use super::tonemapper::Tonemapper;
 use super::volume_renderer::{VolumeRenderer, VolumeVisualizationMode};
 use crate::{
     render_output::hdr_backbuffer::HdrBackbuffer,
     scene::Scene,
     simulation::HybridFluid,
-    wgpu_utils::{pipelines::PipelineManager, shader::ShaderDirectory},
+    wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler, shader::ShaderDirectory},
 };
 use cgmath::EuclideanSpace;
-#[derive(Clone, Copy, Debug, EnumIter)]
+#[derive(Clone, Copy, Debug, EnumIter, EnumString)]
+#[strum(serialize_all = "snake_case")]
 pub enum FluidRenderingMode {
     None,
     ScreenSpaceFluid,
@@ -22,11 +24,19 @@ pub enum FluidRenderingMode {
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct GlobalRenderSettingsUniformBufferContent {
-    fluid_origin: cgmath::Point3<f32>,
-    fluid_grid_to_world_scale: f32,
     velocity_visualization_scale: f32,
+    fluid_particle_radius_factor: f32,
     fluid_particle_radius: f32,
-    padding: cgmath::Point2<f32>,
+    padding0: f32,
+
+    exposure_ev: f32,
+    tonemap_operator: u32,
+    auto_exposure_enabled: u32,
+    auto_exposure_adaptation_rate: f32,
+    auto_exposure_min_log_luminance: f32,
+    auto_exposure_max_log_luminance: f32,
+    color_grading_lut_strength: f32,
+    padding1: f32,
 }
 
 // What renders the scene (so everything except ui!)
@@ -43,6 +53,7 @@ pub struct SceneRenderer {
     pub particle_radius_factor: f32,
     pub enable_box_lines: bool,
     pub velocity_visualization_scale: f32,
+    pub tonemapper: Tonemapper,
 }
 
 impl SceneRenderer {
@@ -90,7 +101,7 @@ impl SceneRenderer {
                 per_frame_bind_group_layout,
                 fluid_renderer_group_layout,
             ),
-            bounds_line_renderer: StaticLineRenderer::new(device, shader_dir, pipeline_manager, per_frame_bind_group_layout, 128),
+            bounds_line_renderer: StaticLineRenderer::new(device, shader_dir, pipeline_manager, per_frame_bind_group_layout, 512),
             background,
 
             fluid_rendering_mode: FluidRenderingMode::ScreenSpaceFluid,
@@ -98,19 +109,30 @@ impl SceneRenderer {
             particle_radius_factor: 0.7,
             enable_box_lines: true,
             velocity_visualization_scale: 0.008,
+            tonemapper: Tonemapper::new(device, queue, shader_dir, pipeline_manager, per_frame_bind_group_layout, backbuffer),
         }
     }
 
+    // Takes over all user configurable settings, e.g. for a second renderer with a different output resolution.
+    pub fn copy_settings_from(&mut self, other: &SceneRenderer) {
+        self.fluid_rendering_mode = other.fluid_rendering_mode;
+        self.volume_visualization = other.volume_visualization;
+        self.particle_radius_factor = other.particle_radius_factor;
+        self.enable_box_lines = other.enable_box_lines;
+        self.velocity_visualization_scale = other.velocity_visualization_scale;
+        self.tonemapper.copy_settings_from(&other.tonemapper);
+    }
+
     // Needs to be called whenever immutable scene properties change.
     pub fn on_new_scene(&mut self, queue: &wgpu::Queue, scene: &Scene) {
         let line_color = cgmath::vec3(0.0, 0.0, 0.0);
-        let grid_extent = scene.config().fluid.grid_dimension;
-        let min = scene.config().fluid.world_position;
-        let max = min + grid_extent.cast().unwrap().to_vec() * scene.config().fluid.grid_to_world_scale;
+        let mut lines = Vec::new();
+        for fluid_config in scene.config().fluids.iter() {
+            let grid_extent = fluid_config.grid_dimension;
+            let min = fluid_config.world_position;
+            let max = min + grid_extent.cast().unwrap().to_vec() * fluid_config.grid_to_world_scale;
 
-        self.bounds_line_renderer.clear_lines();
-        self.bounds_line_renderer.add_lines(
-            &[
+            lines.extend_from_slice(&[
                 // left
                 LineVertex::new(cgmath::point3(min.x, min.y, max.z), line_color),
                 LineVertex::new(cgmath::point3(max.x, min.y, max.z), line_color),
@@ -138,26 +160,42 @@ impl SceneRenderer {
                 LineVertex::new(cgmath::point3(max.x, max.y, max.z), line_color),
                 LineVertex::new(cgmath::point3(min.x, max.y, min.z), line_color),
                 LineVertex::new(cgmath::point3(min.x, max.y, max.z), line_color),
-            ],
-            queue,
-        );
+            ]);
+        }
+
+        self.bounds_line_renderer.clear_lines();
+        self.bounds_line_renderer.add_lines(&lines, queue);
     }
 
     pub fn fill_global_uniform_buffer(&self, scene: &Scene) -> GlobalRenderSettingsUniformBufferContent {
-        let fluid_particle_radius =
-            scene.config().fluid.grid_to_world_scale / (HybridFluid::PARTICLES_PER_GRID_CELL as f32).powf(1.0 / 3.0) * self.particle_radius_factor;
+        // Screen space filtering happens for all fluids at once, go with the finest one.
+        let finest_particle_spacing = scene
+            .config()
+            .fluids
+            .iter()
+            .map(|fluid_config| HybridFluid::particle_spacing(fluid_config.particles_per_cell) * fluid_config.grid_to_world_scale)
+            .fold(f32::INFINITY, f32::min);
 
         GlobalRenderSettingsUniformBufferContent {
-            fluid_origin: scene.config().fluid.world_position,
-            fluid_grid_to_world_scale: scene.config().fluid.grid_to_world_scale,
             velocity_visualization_scale: self.velocity_visualization_scale,
-            fluid_particle_radius,
-            padding: cgmath::point2(0.0, 0.0),
+            fluid_particle_radius_factor: self.particle_radius_factor,
+            fluid_particle_radius: self.particle_radius_factor * finest_particle_spacing,
+            padding0: 0.0,
+
+            exposure_ev: self.tonemapper.exposure_ev,
+            tonemap_operator: self.tonemapper.operator as u32,
+            auto_exposure_enabled: self.tonemapper.auto_exposure as u32,
+            auto_exposure_adaptation_rate: self.tonemapper.auto_exposure_adaptation_rate,
+            auto_exposure_min_log_luminance: self.tonemapper.auto_exposure_min_log_luminance,
+            auto_exposure_max_log_luminance: self.tonemapper.auto_exposure_max_log_luminance,
+            color_grading_lut_strength: self.tonemapper.color_grading_lut_strength,
+            padding1: 0.0,
         }
     }
 
     pub fn on_window_resize(&mut self, device: &wgpu::Device, backbuffer: &HdrBackbuffer) {
         self.screenspace_fluid.on_window_resize(device, backbuffer);
+        self.tonemapper.on_window_resize(device, backbuffer);
     }
 
     pub fn draw(
@@ -165,14 +203,15 @@ impl SceneRenderer {
         scene: &Scene,
         encoder: &mut wgpu::CommandEncoder,
         pipeline_manager: &PipelineManager,
+        profiler: &mut GpuProfiler,
         backbuffer: &wgpu::TextureView,
         depthbuffer: &wgpu::TextureView,
         per_frame_bind_group: &wgpu::BindGroup,
     ) {
-        wgpu_scope!(encoder, "SceneRenderer.draw");
+        wgpu_scope!(encoder, profiler, "SceneRenderer.draw");
         {
             // Opaque
-            wgpu_scope!(encoder, "opaque", || {
+            wgpu_scope!(encoder, profiler, "opaque", || {
                 let mut rpass_backbuffer = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
                         attachment: backbuffer,
@@ -199,34 +238,51 @@ impl SceneRenderer {
                         // Handled earlier!
                     }
                     FluidRenderingMode::Particles => {
-                        self.particle_renderer.draw(&mut rpass_backbuffer, pipeline_manager, &scene.fluid());
+                        for fluid in scene.fluids().iter() {
+                            self.particle_renderer.draw(&mut rpass_backbuffer, pipeline_manager, profiler, fluid);
+                        }
                     }
                 }
 
-                self.volume_renderer
-                    .draw(&mut rpass_backbuffer, pipeline_manager, &scene.fluid(), self.volume_visualization);
+                for fluid in scene.fluids().iter() {
+                    self.volume_renderer
+                        .draw(&mut rpass_backbuffer, pipeline_manager, profiler, fluid, self.volume_visualization);
+                }
 
                 if self.enable_box_lines {
-                    self.bounds_line_renderer.draw(&mut rpass_backbuffer, pipeline_manager);
+                    self.bounds_line_renderer.draw(&mut rpass_backbuffer, pipeline_manager, profiler);
                 }
 
                 // Background.. not really opaque but we re-use the same rpass.
-                self.background.draw(&mut rpass_backbuffer, pipeline_manager);
+                self.background.draw(&mut rpass_backbuffer, pipeline_manager, profiler);
             });
 
             // Transparent
-            wgpu_scope!(encoder, "transparent", || {
+            wgpu_scope!(encoder, profiler, "transparent", || {
                 if let FluidRenderingMode::ScreenSpaceFluid = self.fluid_rendering_mode {
                     self.screenspace_fluid.draw(
                         &mut encoder,
                         pipeline_manager,
+                        profiler,
                         depthbuffer,
                         per_frame_bind_group,
                         self.background.bind_group(),
-                        &scene.fluid(),
+                        scene.fluids(),
                     );
                 }
             });
         }
     }
+
+    // Resolves the HdrBackbuffer the scene was drawn to into the given ldr target.
+    pub fn tonemap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline_manager: &PipelineManager,
+        profiler: &mut GpuProfiler,
+        per_frame_bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+    ) {
+        self.tonemapper.tonemap(encoder, pipeline_manager, profiler, per_frame_bind_group, target);
+    }
 }
@@ -7,6 +7,7 @@ use crate::{
         self,
         binding_builder::{BindGroupBuilder, BindGroupLayoutBuilder, BindGroupLayoutWithDesc},
         binding_glsl,
+        profiler::GpuProfiler,
         shader::*,
     },
 };
@@ -318,12 +319,13 @@ impl ScreenSpaceFluid {
         &'a self,
         encoder: &mut wgpu::CommandEncoder,
         pipeline_manager: &'a PipelineManager,
+        profiler: &mut GpuProfiler,
         depthbuffer: &wgpu::TextureView,
         per_frame_bind_group: &wgpu::BindGroup,
         sky_bind_group: &wgpu::BindGroup,
-        fluid: &HybridFluid,
+        fluids: &[HybridFluid],
     ) {
-        wgpu_scope!(encoder, "ScreenSpaceFluid.draw");
+        wgpu_scope!(encoder, profiler, "ScreenSpaceFluid.draw");
 
         // Set some depth value that is beyond the far plane. (could do infinity, but don't trust this is passed down correctly)
         let depth_clear_color = wgpu::Color {
@@ -333,7 +335,7 @@ impl ScreenSpaceFluid {
             a: 999999.0,
         };
 
-        wgpu_scope!(encoder, "particles", || {
+        wgpu_scope!(encoder, profiler, "particles", || {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[
                     wgpu::RenderPassColorAttachmentDescriptor {
@@ -363,12 +365,15 @@ impl ScreenSpaceFluid {
                 }),
             });
             rpass.set_bind_group(0, &per_frame_bind_group, &[]);
-            rpass.set_bind_group(1, fluid.bind_group_renderer(), &[]);
             rpass.set_pipeline(pipeline_manager.get_render(&self.screen_independent.pipeline_render_particles));
-            rpass.draw(0..4, 0..fluid.num_particles());
+            // All fluids go into the same depth & thickness targets and are filtered together.
+            for fluid in fluids.iter() {
+                rpass.set_bind_group(1, fluid.bind_group_renderer(), &[]);
+                rpass.draw(0..4, 0..fluid.num_particles());
+            }
         });
 
-        wgpu_scope!(encoder, "clear intermediate blur targets", || {
+        wgpu_scope!(encoder, profiler, "clear intermediate blur targets", || {
             {
                 encoder
                     .begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -401,10 +406,11 @@ impl ScreenSpaceFluid {
             }
         });
 
-        wgpu_scope!(encoder, "fluid filters & render", || {
+        wgpu_scope!(encoder, profiler, "fluid filters & render", || {
             let mut cpass = encoder.begin_compute_pass();
             cpass.set_bind_group(0, &per_frame_bind_group, &[]);
-            cpass.set_bind_group(1, fluid.bind_group_renderer(), &[]);
+            // Filters don't access any fluid resources, but the pipeline layout requires a fluid bind group.
+            cpass.set_bind_group(1, fluids[0].bind_group_renderer(), &[]);
 
             const LOCAL_SIZE_FILTER_1D_X: wgpu::Extent3d = wgpu::Extent3d {
                 width: 64,
@@ -419,8 +425,8 @@ impl ScreenSpaceFluid {
             let work_group_filter_1d_x = wgpu_utils::compute_group_size(self.screen_dependent.target_textures_resolution, LOCAL_SIZE_FILTER_1D_X);
             let work_group_filter_1d_y = wgpu_utils::compute_group_size(self.screen_dependent.target_textures_resolution, LOCAL_SIZE_FILTER_1D_Y);
 
-            wgpu_scope!(cpass, "depth filter", || {
-                wgpu_scope!(cpass, "filter 1D", || {
+            wgpu_scope!(cpass, profiler, "depth filter", || {
+                wgpu_scope!(cpass, profiler, "filter 1D", || {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.screen_independent.pipeline_narrow_range_filter_1d));
 
                     // Filter Y
@@ -432,7 +438,7 @@ impl ScreenSpaceFluid {
                     cpass.set_push_constants(0, &bytemuck::bytes_of(&[0 as u32]));
                     cpass.dispatch(work_group_filter_1d_x.width, work_group_filter_1d_x.height, work_group_filter_1d_x.depth);
                 });
-                wgpu_scope!(cpass, "filter 2D", || {
+                wgpu_scope!(cpass, profiler, "filter 2D", || {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.screen_independent.pipeline_narrow_range_filter_2d));
                     cpass.set_bind_group(2, &self.screen_dependent.bind_group_narrow_range_filter[0], &[]);
                     const LOCAL_SIZE_FILTER_2D: wgpu::Extent3d = wgpu::Extent3d {
@@ -444,7 +450,7 @@ impl ScreenSpaceFluid {
                     cpass.dispatch(work_group.width, work_group.height, work_group.depth);
                 });
             });
-            wgpu_scope!(cpass, "thickness filter", || {
+            wgpu_scope!(cpass, profiler, "thickness filter", || {
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.screen_independent.pipeline_thickness_filter));
 
                 // Filter Y
@@ -457,7 +463,7 @@ impl ScreenSpaceFluid {
                 cpass.dispatch(work_group_filter_1d_x.width, work_group_filter_1d_x.height, work_group_filter_1d_x.depth);
             });
 
-            wgpu_scope!(cpass, "compose & render", || {
+            wgpu_scope!(cpass, profiler, "compose & render", || {
                 const LOCAL_SIZE_COMPOSE: wgpu::Extent3d = wgpu::Extent3d {
                     width: 32,
                     height: 32,
@@ -1,7 +1,7 @@
 use crate::wgpu_utils::pipelines::*;
 use crate::{
     render_output::{hdr_backbuffer::HdrBackbuffer, screen::Screen},
-    wgpu_utils::shader::*,
+    wgpu_utils::{profiler::GpuProfiler, shader::*},
 };
 use std::{path::Path, rc::Rc};
 
@@ -107,8 +107,8 @@ impl StaticLineRenderer {
         self.num_lines += lines.len();
     }
 
-    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, pipeline_manager: &'a PipelineManager) {
-        wgpu_scope!(rpass, "StaticLineRenderer.draw");
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, pipeline_manager: &'a PipelineManager, profiler: &mut GpuProfiler) {
+        wgpu_scope!(rpass, profiler, "StaticLineRenderer.draw");
         rpass.set_pipeline(pipeline_manager.get_render(&self.render_pipeline));
         let num_vertices = self.num_lines * 2;
         rpass.set_vertex_buffer(0, self.vertex_buffer.slice(0..(num_vertices as u64 * LINE_VERTEX_SIZE as u64)));
@@ -0,0 +1,434 @@
+use crate::{
+    render_output::{hdr_backbuffer::HdrBackbuffer, screen::Screen},
+    wgpu_utils::{self, binding_builder::*, binding_glsl, pipelines::*, profiler::GpuProfiler, shader::ShaderDirectory},
+};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+// Maps the linear HdrBackbuffer to the ldr backbuffer: exposure (manual and/or automatic), tonemapping operator and color grading lut.
+// Settings reach the shaders via the global render settings in the per frame uniform buffer.
+
+#[derive(Clone, Copy, Debug, EnumIter, EnumString, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum TonemapOperator {
+    // Everything above 1 is cut off.
+    Clamp,
+    // Reinhard on luminance.
+    Reinhard,
+    // John Hable's filmic curve from Uncharted 2.
+    Uncharted2,
+    // Fit of the ACES reference rendering & output transforms.
+    AcesFilmic,
+}
+
+// Bins of the auto exposure luminance histogram, see shader/tonemap/auto_exposure.glsl
+const AUTO_EXPOSURE_NUM_BINS: u64 = 128;
+// See AutoExposureState in shader/tonemap/auto_exposure.glsl
+const AUTO_EXPOSURE_STATE_SIZE: u64 = 8;
+
+static mut GROUP_LAYOUT_COLOR_GRADING_LUT: Option<BindGroupLayoutWithDesc> = None;
+
+// 3D lut texture, shared by all tonemappers with the same settings.
+struct ColorGradingLut {
+    // None for the identity lut.
+    path: Option<PathBuf>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ColorGradingLut {
+    fn identity(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let mut entries = Vec::new();
+        for b in 0..2 {
+            for g in 0..2 {
+                for r in 0..2 {
+                    entries.push([r as f32, g as f32, b as f32]);
+                }
+            }
+        }
+        Self::create(device, queue, None, 2, &entries)
+    }
+
+    fn load(device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let (size, entries) = parse_cube_lut(&content)?;
+        Ok(Self::create(device, queue, Some(path.to_path_buf()), size, &entries))
+    }
+
+    // Entries with red changing fastest, then green, then blue.
+    fn create(device: &wgpu::Device, queue: &wgpu::Queue, path: Option<PathBuf>, size: u32, entries: &[[f32; 3]]) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture: Color Grading Lut"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            // Luts stay within [0; 1], 10 bits are plenty and unlike float formats this can be filtered everywhere.
+            format: wgpu::TextureFormat::Rgb10a2Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        let to_unorm10 = |value: f32| (value.max(0.0).min(1.0) * 1023.0).round() as u32;
+        let packed_entries: Vec<u32> = entries
+            .iter()
+            .map(|[r, g, b]| to_unorm10(*r) | to_unorm10(*g) << 10 | to_unorm10(*b) << 20 | 3 << 30)
+            .collect();
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&packed_entries),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: size * std::mem::size_of::<u32>() as u32,
+                rows_per_image: size,
+            },
+            extent,
+        );
+
+        let bind_group = BindGroupBuilder::new(Tonemapper::get_or_create_group_layout_color_grading_lut(device))
+            .texture(&texture.create_view(&Default::default()))
+            .create(device, "BindGroup: Color Grading Lut");
+        ColorGradingLut { path, bind_group }
+    }
+}
+
+// Parses a 3D lut in the .cube format (as written by Resolve, Photoshop & co): LUT_3D_SIZE followed by all entries as "r g b" lines, red changing fastest.
+fn parse_cube_lut(content: &str) -> Result<(u32, Vec<[f32; 3]>), String> {
+    let mut size = None;
+    let mut entries = Vec::new();
+    for (line_index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap();
+        let parse_values = |words: std::str::SplitWhitespace| {
+            words
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|_| format!("line {}: invalid values: {}", line_index + 1, line))
+        };
+        match keyword {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => return Err("1D luts are not supported".to_owned()),
+            "LUT_3D_SIZE" => {
+                size = Some(
+                    words
+                        .next()
+                        .and_then(|size| size.parse::<u32>().ok())
+                        .filter(|size| (2..=256).contains(size))
+                        .ok_or_else(|| format!("line {}: LUT_3D_SIZE needs to be between 2 and 256", line_index + 1))?,
+                );
+            }
+            "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                let expected_value = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                if parse_values(words)?.iter().any(|value| *value != expected_value) {
+                    return Err(format!("line {}: only luts with a domain from 0 to 1 are supported", line_index + 1));
+                }
+            }
+            _ => match parse_values(line.split_whitespace())?.as_slice() {
+                [r, g, b] => entries.push([*r, *g, *b]),
+                _ => return Err(format!("line {}: expected an entry with three values: {}", line_index + 1, line)),
+            },
+        }
+    }
+
+    let size = size.ok_or("LUT_3D_SIZE is missing")?;
+    let expected_num_entries = (size * size * size) as usize;
+    if entries.len() != expected_num_entries {
+        return Err(format!(
+            "expected {} entries for LUT_3D_SIZE {}, got {}",
+            expected_num_entries,
+            size,
+            entries.len()
+        ));
+    }
+    Ok((size, entries))
+}
+
+pub struct Tonemapper {
+    pub operator: TonemapOperator,
+    // log2 of the exposure scale. With auto exposure, this is a compensation on top.
+    pub exposure_ev: f32,
+    pub auto_exposure: bool,
+    // How fast auto exposure follows changes in brightness, in 1/s.
+    pub auto_exposure_adaptation_rate: f32,
+    // log2 luminance range auto exposure considers.
+    pub auto_exposure_min_log_luminance: f32,
+    pub auto_exposure_max_log_luminance: f32,
+    // Blend between the ungraded (0) and graded (1) image.
+    pub color_grading_lut_strength: f32,
+
+    color_grading_lut: Rc<ColorGradingLut>,
+
+    group_layout_tonemap: BindGroupLayoutWithDesc,
+    group_layout_auto_exposure: BindGroupLayoutWithDesc,
+    pipeline_tonemap: RenderPipelineHandle,
+    pipeline_auto_exposure_histogram: ComputePipelineHandle,
+    pipeline_auto_exposure_average: ComputePipelineHandle,
+
+    auto_exposure_histogram: wgpu::Buffer,
+    auto_exposure_state: wgpu::Buffer,
+
+    bind_group_tonemap: wgpu::BindGroup,
+    bind_group_auto_exposure: wgpu::BindGroup,
+    resolution: winit::dpi::PhysicalSize<u32>,
+}
+
+impl Tonemapper {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        per_frame_bind_group_layout: &wgpu::BindGroupLayout,
+        backbuffer: &HdrBackbuffer,
+    ) -> Self {
+        let group_layout_tonemap = BindGroupLayoutBuilder::new()
+            .next_binding_fragment(binding_glsl::texture2D()) // hdr backbuffer
+            .next_binding_fragment(binding_glsl::buffer(true)) // auto exposure state
+            .create(device, "BindGroupLayout: Tonemapper");
+        let group_layout_auto_exposure = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture2D()) // hdr backbuffer
+            .next_binding_compute(binding_glsl::buffer(false)) // histogram
+            .next_binding_compute(binding_glsl::buffer(false)) // auto exposure state
+            .create(device, "BindGroupLayout: Auto Exposure");
+
+        let layout_tonemap = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: Tonemapper"),
+            bind_group_layouts: &[
+                per_frame_bind_group_layout,
+                &group_layout_tonemap.layout,
+                &Self::get_or_create_group_layout_color_grading_lut(device).layout,
+            ],
+            push_constant_ranges: &[],
+        }));
+        let layout_auto_exposure = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: Auto Exposure"),
+            bind_group_layouts: &[per_frame_bind_group_layout, &group_layout_auto_exposure.layout],
+            push_constant_ranges: &[],
+        }));
+
+        let pipeline_tonemap = pipeline_manager.create_render_pipeline(
+            device,
+            shader_dir,
+            RenderPipelineCreationDesc::new(
+                "Tonemapper",
+                layout_tonemap,
+                Path::new("screentri.vert"),
+                Some(Path::new("tonemap/tonemap.frag")),
+                Screen::FORMAT_BACKBUFFER,
+                None,
+            ),
+        );
+        let pipeline_auto_exposure_histogram = pipeline_manager.create_compute_pipeline(
+            device,
+            shader_dir,
+            ComputePipelineCreationDesc::new(
+                "Auto Exposure: Histogram",
+                layout_auto_exposure.clone(),
+                Path::new("tonemap/auto_exposure_histogram.comp"),
+            ),
+        );
+        let pipeline_auto_exposure_average = pipeline_manager.create_compute_pipeline(
+            device,
+            shader_dir,
+            ComputePipelineCreationDesc::new(
+                "Auto Exposure: Average",
+                layout_auto_exposure,
+                Path::new("tonemap/auto_exposure_average.comp"),
+            ),
+        );
+
+        let auto_exposure_histogram = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Auto Exposure Histogram"),
+            size: AUTO_EXPOSURE_NUM_BINS * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let auto_exposure_state = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Auto Exposure State"),
+            size: AUTO_EXPOSURE_STATE_SIZE,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Histogram is cleared after every use, the state starts out uninitialized.
+        queue.write_buffer(&auto_exposure_histogram, 0, &vec![0; (AUTO_EXPOSURE_NUM_BINS * 4) as usize]);
+        queue.write_buffer(&auto_exposure_state, 0, &[0; AUTO_EXPOSURE_STATE_SIZE as usize]);
+
+        let (bind_group_tonemap, bind_group_auto_exposure) = Self::create_bind_groups(
+            device,
+            &group_layout_tonemap,
+            &group_layout_auto_exposure,
+            &auto_exposure_histogram,
+            &auto_exposure_state,
+            backbuffer,
+        );
+
+        Tonemapper {
+            operator: TonemapOperator::AcesFilmic,
+            exposure_ev: 0.0,
+            auto_exposure: false,
+            auto_exposure_adaptation_rate: 2.0,
+            auto_exposure_min_log_luminance: -10.0,
+            auto_exposure_max_log_luminance: 10.0,
+            color_grading_lut_strength: 1.0,
+
+            color_grading_lut: Rc::new(ColorGradingLut::identity(device, queue)),
+
+            group_layout_tonemap,
+            group_layout_auto_exposure,
+            pipeline_tonemap,
+            pipeline_auto_exposure_histogram,
+            pipeline_auto_exposure_average,
+
+            auto_exposure_histogram,
+            auto_exposure_state,
+
+            bind_group_tonemap,
+            bind_group_auto_exposure,
+            resolution: backbuffer.resolution(),
+        }
+    }
+
+    fn get_or_create_group_layout_color_grading_lut(device: &wgpu::Device) -> &BindGroupLayoutWithDesc {
+        unsafe {
+            GROUP_LAYOUT_COLOR_GRADING_LUT.get_or_insert_with(|| {
+                BindGroupLayoutBuilder::new()
+                    .next_binding_fragment(binding_glsl::texture3D())
+                    .create(device, "BindGroupLayout: Color Grading Lut")
+            })
+        }
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        group_layout_tonemap: &BindGroupLayoutWithDesc,
+        group_layout_auto_exposure: &BindGroupLayoutWithDesc,
+        auto_exposure_histogram: &wgpu::Buffer,
+        auto_exposure_state: &wgpu::Buffer,
+        backbuffer: &HdrBackbuffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        (
+            BindGroupBuilder::new(group_layout_tonemap)
+                .texture(backbuffer.texture_view())
+                .resource(auto_exposure_state.as_entire_binding())
+                .create(device, "BindGroup: Tonemapper"),
+            BindGroupBuilder::new(group_layout_auto_exposure)
+                .texture(backbuffer.texture_view())
+                .resource(auto_exposure_histogram.as_entire_binding())
+                .resource(auto_exposure_state.as_entire_binding())
+                .create(device, "BindGroup: Auto Exposure"),
+        )
+    }
+
+    pub fn on_window_resize(&mut self, device: &wgpu::Device, backbuffer: &HdrBackbuffer) {
+        let (bind_group_tonemap, bind_group_auto_exposure) = Self::create_bind_groups(
+            device,
+            &self.group_layout_tonemap,
+            &self.group_layout_auto_exposure,
+            &self.auto_exposure_histogram,
+            &self.auto_exposure_state,
+            backbuffer,
+        );
+        self.bind_group_tonemap = bind_group_tonemap;
+        self.bind_group_auto_exposure = bind_group_auto_exposure;
+        self.resolution = backbuffer.resolution();
+    }
+
+    // Takes over all user configurable settings including the lut. Auto exposure adapts on its own.
+    pub fn copy_settings_from(&mut self, other: &Tonemapper) {
+        self.operator = other.operator;
+        self.exposure_ev = other.exposure_ev;
+        self.auto_exposure = other.auto_exposure;
+        self.auto_exposure_adaptation_rate = other.auto_exposure_adaptation_rate;
+        self.auto_exposure_min_log_luminance = other.auto_exposure_min_log_luminance;
+        self.auto_exposure_max_log_luminance = other.auto_exposure_max_log_luminance;
+        self.color_grading_lut_strength = other.color_grading_lut_strength;
+        self.color_grading_lut = other.color_grading_lut.clone();
+    }
+
+    // Loads a .cube file, the previous lut stays if that fails.
+    pub fn load_color_grading_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<(), String> {
+        self.color_grading_lut = Rc::new(ColorGradingLut::load(device, queue, path)?);
+        Ok(())
+    }
+
+    pub fn clear_color_grading_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.color_grading_lut = Rc::new(ColorGradingLut::identity(device, queue));
+    }
+
+    // None if no lut is loaded.
+    pub fn color_grading_lut_path(&self) -> Option<&Path> {
+        self.color_grading_lut.path.as_deref()
+    }
+
+    pub fn tonemap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline_manager: &PipelineManager,
+        profiler: &mut GpuProfiler,
+        per_frame_bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+    ) {
+        wgpu_scope!(encoder, profiler, "Tonemapper.tonemap");
+
+        if self.auto_exposure {
+            wgpu_scope!(encoder, profiler, "auto exposure", || {
+                let mut cpass = encoder.begin_compute_pass();
+                cpass.set_bind_group(0, per_frame_bind_group, &[]);
+                cpass.set_bind_group(1, &self.bind_group_auto_exposure, &[]);
+
+                wgpu_scope!(cpass, profiler, "histogram", || {
+                    const LOCAL_SIZE_HISTOGRAM: wgpu::Extent3d = wgpu::Extent3d {
+                        width: 16,
+                        height: 16,
+                        depth: 1,
+                    };
+                    let work_group = wgpu_utils::compute_group_size(
+                        wgpu::Extent3d {
+                            width: self.resolution.width,
+                            height: self.resolution.height,
+                            depth: 1,
+                        },
+                        LOCAL_SIZE_HISTOGRAM,
+                    );
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_auto_exposure_histogram));
+                    cpass.dispatch(work_group.width, work_group.height, work_group.depth);
+                });
+                wgpu_scope!(cpass, profiler, "average", || {
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_auto_exposure_average));
+                    cpass.dispatch(1, 1, 1);
+                });
+            });
+        }
+
+        // Note that we can't use a compute shader here since that would require STORAGE usage flag on the final output which we can't do since it's srgb!
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_bind_group(0, per_frame_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group_tonemap, &[]);
+        render_pass.set_bind_group(2, &self.color_grading_lut.bind_group, &[]);
+        render_pass.set_pipeline(pipeline_manager.get_render(&self.pipeline_tonemap));
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -2,7 +2,7 @@ use crate::shader::ShaderDirectory;
 use crate::{
     render_output::{hdr_backbuffer::HdrBackbuffer, screen::Screen},
     simulation::HybridFluid,
-    wgpu_utils::pipelines::*,
+    wgpu_utils::{pipelines::*, profiler::GpuProfiler},
 };
 use std::{path::Path, rc::Rc};
 
@@ -75,10 +75,11 @@ impl VolumeRenderer {
         &'a self,
         rpass: &mut wgpu::RenderPass<'a>,
         pipeline_manager: &'a PipelineManager,
+        profiler: &mut GpuProfiler,
         fluid: &'a HybridFluid,
         mode: VolumeVisualizationMode,
     ) {
-        wgpu_scope!(rpass, "VolumeRenderer.draw");
+        wgpu_scope!(rpass, profiler, "VolumeRenderer.draw");
 
         match mode {
             VolumeVisualizationMode::None => {}
@@ -1,44 +1,557 @@
 use crate::{
-    simulation::HybridFluid,
-    wgpu_utils::{pipelines::PipelineManager, shader::ShaderDirectory},
+    camera::CameraViewpoint,
+    simulation::{ClosedMesh, CpuHybridFluid, FluidRegion, FluidShape, HybridFluid, SolverConfig, TransferScheme},
+    timeline::{AnimatedProperty, TrackConfig},
+    wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler, shader::ShaderDirectory},
 };
 
-use serde::Deserialize;
+use cgmath::{prelude::*, Deg, Quaternion};
+use serde::{Deserialize, Serialize};
 use std::{
+    fmt,
     fs::File,
-    io::{self, BufReader},
-    path::Path,
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
     time::Duration,
 };
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Box {
     pub min: cgmath::Point3<f32>,
     pub max: cgmath::Point3<f32>,
 }
 
-// Data describing a fluid in the scene.
+// Shape of a fluid region. All positions are relative to the fluid domain's world_position.
 #[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum FluidShapeConfig {
+    Box {
+        min: cgmath::Point3<f32>,
+        max: cgmath::Point3<f32>,
+    },
+    OrientedBox {
+        center: cgmath::Point3<f32>,
+        half_extent: cgmath::Vector3<f32>,
+        rotation_axis: cgmath::Vector3<f32>,
+        rotation_angle_degrees: f32,
+    },
+    Sphere {
+        center: cgmath::Point3<f32>,
+        radius: f32,
+    },
+    Cylinder {
+        center: cgmath::Point3<f32>,
+        axis: cgmath::Vector3<f32>,
+        radius: f32,
+        height: f32,
+    },
+    // Closed triangle mesh from an OBJ file. Path is relative to the scene file, vertices are scaled first and then translated.
+    Mesh {
+        path: PathBuf,
+        scale: f32,
+        translation: cgmath::Vector3<f32>,
+    },
+}
+
+impl FluidShapeConfig {
+    fn name(&self) -> &'static str {
+        match self {
+            FluidShapeConfig::Box { .. } => "box",
+            FluidShapeConfig::OrientedBox { .. } => "oriented_box",
+            FluidShapeConfig::Sphere { .. } => "sphere",
+            FluidShapeConfig::Cylinder { .. } => "cylinder",
+            FluidShapeConfig::Mesh { .. } => "mesh",
+        }
+    }
+
+    // Parameters that don't describe a proper shape.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut expect_positive = |name: &str, value: f32| {
+            if value <= 0.0 {
+                problems.push(format!("{} needs to be positive, is {}", name, value));
+            }
+        };
+        match self {
+            FluidShapeConfig::Box { min, max } => return box_problem(*min, *max).into_iter().collect(),
+            FluidShapeConfig::OrientedBox {
+                half_extent, rotation_axis, ..
+            } => {
+                expect_positive("half_extent.x", half_extent.x);
+                expect_positive("half_extent.y", half_extent.y);
+                expect_positive("half_extent.z", half_extent.z);
+                expect_positive("length of rotation_axis", rotation_axis.magnitude());
+            }
+            FluidShapeConfig::Sphere { radius, .. } => expect_positive("radius", *radius),
+            FluidShapeConfig::Cylinder { axis, radius, height, .. } => {
+                expect_positive("radius", *radius);
+                expect_positive("height", *height);
+                expect_positive("length of axis", axis.magnitude());
+            }
+            FluidShapeConfig::Mesh { scale, .. } => expect_positive("scale", *scale),
+        }
+        problems
+    }
+}
+
+fn box_problem(min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) -> Option<String> {
+    if min.x < max.x && min.y < max.y && min.z < max.z {
+        None
+    } else {
+        Some(format!(
+            "min {} needs to be smaller than max {} on every axis",
+            format_point(min),
+            format_point(max)
+        ))
+    }
+}
+
+fn format_point(point: cgmath::Point3<f32>) -> String {
+    format!("({:.3}, {:.3}, {:.3})", point.x, point.y, point.z)
+}
+
+fn zero_vector() -> cgmath::Vector3<f32> {
+    cgmath::Vector3::zero()
+}
+
+// A shape filled with fluid that starts out moving like a rigid body.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FluidRegionConfig {
+    pub shape: FluidShapeConfig,
+    // world space units per second
+    #[serde(default = "zero_vector")]
+    pub linear_velocity: cgmath::Vector3<f32>,
+    // radians per second, around the center of the shape
+    #[serde(default = "zero_vector")]
+    pub angular_velocity: cgmath::Vector3<f32>,
+}
+
+impl FluidRegionConfig {
+    fn to_grid_space(&self, scene_directory: &Path, grid_to_world_scale: f32) -> Result<FluidRegion, io::Error> {
+        let shape = match &self.shape {
+            FluidShapeConfig::Box { min, max } => FluidShape::Box {
+                min: *min / grid_to_world_scale,
+                max: *max / grid_to_world_scale,
+            },
+            FluidShapeConfig::OrientedBox {
+                center,
+                half_extent,
+                rotation_axis,
+                rotation_angle_degrees,
+            } => FluidShape::OrientedBox {
+                center: *center / grid_to_world_scale,
+                half_extent: *half_extent / grid_to_world_scale,
+                rotation: Quaternion::from_axis_angle(rotation_axis.normalize(), Deg(*rotation_angle_degrees)),
+            },
+            FluidShapeConfig::Sphere { center, radius } => FluidShape::Sphere {
+                center: *center / grid_to_world_scale,
+                radius: *radius / grid_to_world_scale,
+            },
+            FluidShapeConfig::Cylinder {
+                center,
+                axis,
+                radius,
+                height,
+            } => FluidShape::Cylinder {
+                center: *center / grid_to_world_scale,
+                axis: axis.normalize(),
+                radius: *radius / grid_to_world_scale,
+                height: *height / grid_to_world_scale,
+            },
+            FluidShapeConfig::Mesh { path, scale, translation } => FluidShape::Mesh(ClosedMesh::load_obj(
+                &scene_directory.join(path),
+                *scale / grid_to_world_scale,
+                *translation / grid_to_world_scale,
+            )?),
+        };
+
+        Ok(FluidRegion {
+            shape,
+            linear_velocity: self.linear_velocity / grid_to_world_scale,
+            angular_velocity: self.angular_velocity,
+        })
+    }
+}
+
+fn default_particles_per_cell() -> u32 {
+    HybridFluid::DEFAULT_PARTICLES_PER_GRID_CELL
+}
+
+// Data describing a fluid domain in the scene.
+// Every domain is simulated on its own grid, there is no interaction between domains.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct FluidConfig {
     pub world_position: cgmath::Point3<f32>,
     pub grid_to_world_scale: f32,
     pub grid_dimension: cgmath::Point3<u32>,
     pub max_num_particles: u32,
+    // Particles a fluid cell starts out with, also defines the rest density of the fluid.
+    // Only cube numbers (1, 8, 27) get stratified initial positions, others are placed randomly within each cell.
+    #[serde(default = "default_particles_per_cell")]
+    pub particles_per_cell: u32,
+    #[serde(default)]
+    pub transfer_scheme: TransferScheme,
+    // Relative to world_position. Shorthand for box shaped fluid_regions without initial velocity.
+    #[serde(default)]
     pub fluid_cubes: Vec<Box>,
+    #[serde(default)]
+    pub fluid_regions: Vec<FluidRegionConfig>,
+}
+
+impl FluidConfig {
+    // Size of the compute workgroups of the simulation shaders. Grids need to be made of whole workgroups.
+    const GRID_DIMENSION_MULTIPLE: u32 = 8;
+
+    // Corners of the grid in world space.
+    pub fn world_bounds(&self) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+        let size = self.grid_dimension.cast::<f32>().unwrap().to_vec() * self.grid_to_world_scale;
+        (self.world_position, self.world_position + size)
+    }
+
+    pub fn grid_extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.grid_dimension.x,
+            height: self.grid_dimension.y,
+            depth: self.grid_dimension.z,
+        }
+    }
+
+    /// All fluid cubes and regions converted to grid space, in the order they are filled, as needed by [`FluidConfig::create_fluid`].
+    /// Mesh paths are relative to the given directory. Fails with everything that is wrong with the domain.
+    pub fn fluid_regions(&self, directory: &Path) -> Result<Vec<FluidRegion>, Vec<String>> {
+        let mut problems = Vec::new();
+        let fluid_regions = self.load_fluid_regions("fluid", directory, &mut problems);
+        if problems.is_empty() {
+            Ok(fluid_regions)
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Creates the simulation of this domain and fills it with the given regions.
+    /// Gravity is in world space, solver settings that are given in `simulation` override the defaults.
+    /// Steps per second and time scale are up to whoever steps the fluid.
+    pub fn create_fluid(
+        &self,
+        fluid_regions: &[FluidRegion],
+        gravity: cgmath::Vector3<f32>,
+        simulation: &SimulationSettings,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        per_frame_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> HybridFluid {
+        let mut hybrid_fluid = HybridFluid::new(
+            device,
+            self.grid_extent(),
+            self.max_num_particles,
+            self.particles_per_cell,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+        );
+        for region in fluid_regions.iter() {
+            hybrid_fluid.add_fluid_region(queue, region);
+        }
+        hybrid_fluid.set_gravity_grid(gravity / self.grid_to_world_scale);
+        hybrid_fluid.set_transfer_scheme(self.transfer_scheme);
+        simulation.velocity_solver.apply(hybrid_fluid.pressure_solver_config_velocity());
+        simulation.density_solver.apply(hybrid_fluid.pressure_solver_config_density());
+        hybrid_fluid.set_world_transform(queue, self.world_position, self.grid_to_world_scale);
+        hybrid_fluid
+    }
+
+    // Cpu reference fluid that starts out with exactly the same state as the fluid created by create_fluid.
+    pub fn create_cpu_reference_fluid(
+        &self,
+        fluid_regions: &[FluidRegion],
+        gravity: cgmath::Vector3<f32>,
+        simulation: &SimulationSettings,
+    ) -> CpuHybridFluid {
+        let mut cpu_fluid = CpuHybridFluid::new(self.grid_extent(), self.max_num_particles, self.particles_per_cell);
+        for region in fluid_regions.iter() {
+            cpu_fluid.add_fluid_region(region);
+        }
+        cpu_fluid.set_gravity_grid(gravity / self.grid_to_world_scale);
+        cpu_fluid.set_transfer_scheme(self.transfer_scheme);
+        simulation.velocity_solver.apply(cpu_fluid.pressure_solver_config_velocity());
+        simulation.density_solver.apply(cpu_fluid.pressure_solver_config_density());
+        cpu_fluid
+    }
+
+    // All fluid cubes and regions converted to grid space, in the order they are filled.
+    // Everything that is wrong with the domain is added to problems, prefixed with the given name.
+    fn load_fluid_regions(&self, name: &str, scene_directory: &Path, problems: &mut Vec<String>) -> Vec<FluidRegion> {
+        let num_previous_problems = problems.len();
+        if self.grid_to_world_scale <= 0.0 {
+            problems.push(format!(
+                "{}: grid_to_world_scale needs to be positive, is {}",
+                name, self.grid_to_world_scale
+            ));
+        }
+        let grid_dimension = self.grid_dimension;
+        if [grid_dimension.x, grid_dimension.y, grid_dimension.z]
+            .iter()
+            .any(|&size| size == 0 || size % Self::GRID_DIMENSION_MULTIPLE != 0)
+        {
+            problems.push(format!(
+                "{}: grid_dimension needs to be a positive multiple of {} on every axis, is {}x{}x{}",
+                name,
+                Self::GRID_DIMENSION_MULTIPLE,
+                grid_dimension.x,
+                grid_dimension.y,
+                grid_dimension.z
+            ));
+        }
+        if self.particles_per_cell == 0 || self.particles_per_cell > HybridFluid::MAX_PARTICLES_PER_GRID_CELL {
+            problems.push(format!(
+                "{}: particles_per_cell needs to be in 1..={}, is {}",
+                name,
+                HybridFluid::MAX_PARTICLES_PER_GRID_CELL,
+                self.particles_per_cell
+            ));
+        }
+        for (cube_index, cube) in self.fluid_cubes.iter().enumerate() {
+            if let Some(problem) = box_problem(cube.min, cube.max) {
+                problems.push(format!("{}.fluid_cubes[{}]: {}", name, cube_index, problem));
+            }
+        }
+        for (region_index, region) in self.fluid_regions.iter().enumerate() {
+            for problem in region.shape.problems() {
+                problems.push(format!("{}.fluid_regions[{}] ({}): {}", name, region_index, region.shape.name(), problem));
+            }
+        }
+        // Regions can't be sampled meaningfully before the basic properties are fixed.
+        if problems.len() != num_previous_problems {
+            return Vec::new();
+        }
+
+        let cubes = self.fluid_cubes.iter().enumerate().map(|(cube_index, cube)| {
+            (
+                format!("{}.fluid_cubes[{}]", name, cube_index),
+                Ok(FluidRegion {
+                    shape: FluidShape::Box {
+                        min: cube.min / self.grid_to_world_scale,
+                        max: cube.max / self.grid_to_world_scale,
+                    },
+                    linear_velocity: zero_vector(),
+                    angular_velocity: zero_vector(),
+                }),
+            )
+        });
+        let regions = self.fluid_regions.iter().enumerate().map(|(region_index, region)| {
+            (
+                format!("{}.fluid_regions[{}] ({})", name, region_index, region.shape.name()),
+                region.to_grid_space(scene_directory, self.grid_to_world_scale),
+            )
+        });
+
+        // Allow for a bit of floating point error when converting to grid space, e.g. 1.28 / 0.01 isn't quite 128.
+        const TOLERANCE: f32 = 1.0e-3;
+        let grid_max = cgmath::point3(grid_dimension.x as f32, grid_dimension.y as f32, grid_dimension.z as f32);
+        let mut fluid_regions = Vec::new();
+        for (region_name, region) in cubes.chain(regions) {
+            match region {
+                Ok(region) => {
+                    let (min, max) = region.shape.bounds();
+                    if min.x < -TOLERANCE
+                        || min.y < -TOLERANCE
+                        || min.z < -TOLERANCE
+                        || max.x > grid_max.x + TOLERANCE
+                        || max.y > grid_max.y + TOLERANCE
+                        || max.z > grid_max.z + TOLERANCE
+                    {
+                        problems.push(format!(
+                            "{} reaches outside of the grid: covers {} to {}, but the grid only covers (0, 0, 0) to {} (relative to world_position)",
+                            region_name,
+                            format_point(min * self.grid_to_world_scale),
+                            format_point(max * self.grid_to_world_scale),
+                            format_point(grid_max * self.grid_to_world_scale)
+                        ));
+                    }
+                    fluid_regions.push(region);
+                }
+                Err(error) => problems.push(format!("{}: {}", region_name, error)),
+            }
+        }
+        if problems.len() != num_previous_problems {
+            return Vec::new();
+        }
+
+        // Needs to sample in the same order as the actual fill since sampling depends on the number of previous particles.
+        let mut num_particles = 0u64;
+        for region in fluid_regions.iter() {
+            num_particles += region.count_particles(self.grid_extent(), self.particles_per_cell, num_particles.min(u32::MAX as u64) as u32);
+        }
+        if num_particles > self.max_num_particles as u64 {
+            problems.push(format!(
+                "{}: fluid cubes and regions need {} particles, but max_num_particles is {}",
+                name, num_particles, self.max_num_particles
+            ));
+        }
+
+        fluid_regions
+    }
+}
+
+// Pressure solver settings, anything not given keeps the solver's default.
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SolverSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_mse: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_num_iterations: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mse_check_frequency: Option<i32>,
+}
+
+impl SolverSettings {
+    pub fn from_config(config: &SolverConfig) -> Self {
+        SolverSettings {
+            target_mse: Some(config.target_mse),
+            max_num_iterations: Some(config.max_num_iterations),
+            mse_check_frequency: Some(config.mse_check_frequency),
+        }
+    }
+
+    pub fn apply(&self, config: &mut SolverConfig) {
+        if let Some(target_mse) = self.target_mse {
+            config.target_mse = target_mse;
+        }
+        if let Some(max_num_iterations) = self.max_num_iterations {
+            config.max_num_iterations = max_num_iterations;
+        }
+        if let Some(mse_check_frequency) = self.mse_check_frequency {
+            config.mse_check_frequency = mse_check_frequency;
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(target_mse) = self.target_mse.filter(|&target_mse| !target_mse.is_finite() || target_mse <= 0.0) {
+            problems.push(format!("target_mse needs to be positive and finite, is {}", target_mse));
+        }
+        if let Some(max_num_iterations) = self.max_num_iterations.filter(|&max_num_iterations| max_num_iterations <= 0) {
+            problems.push(format!("max_num_iterations needs to be positive, is {}", max_num_iterations));
+        }
+        if let Some(mse_check_frequency) = self.mse_check_frequency.filter(|&mse_check_frequency| mse_check_frequency <= 0) {
+            problems.push(format!("mse_check_frequency needs to be positive, is {}", mse_check_frequency));
+        }
+        problems
+    }
+}
+
+// Simulation settings that can also be changed in the gui. Solver settings apply to all fluid domains.
+// Applied whenever the scene is loaded or reset, anything not given keeps the application's defaults.
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steps_per_second: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_scale: Option<f32>,
+    #[serde(default)]
+    pub velocity_solver: SolverSettings,
+    #[serde(default)]
+    pub density_solver: SolverSettings,
+}
+
+impl SimulationSettings {
+    // Same limits as in the gui.
+    pub const STEPS_PER_SECOND_RANGE: std::ops::RangeInclusive<u64> = 20..=1200;
+    pub const TIME_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.01..=100.0;
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(steps_per_second) = self
+            .steps_per_second
+            .filter(|steps_per_second| !Self::STEPS_PER_SECOND_RANGE.contains(steps_per_second))
+        {
+            problems.push(format!(
+                "steps_per_second needs to be in {:?}, is {}",
+                Self::STEPS_PER_SECOND_RANGE,
+                steps_per_second
+            ));
+        }
+        if let Some(time_scale) = self
+            .time_scale
+            .filter(|time_scale| !time_scale.is_finite() || !Self::TIME_SCALE_RANGE.contains(time_scale))
+        {
+            problems.push(format!("time_scale needs to be in {:?}, is {}", Self::TIME_SCALE_RANGE, time_scale));
+        }
+        problems.extend(
+            self.velocity_solver
+                .problems()
+                .iter()
+                .map(|problem| format!("velocity_solver: {}", problem)),
+        );
+        problems.extend(
+            self.density_solver
+                .problems()
+                .iter()
+                .map(|problem| format!("density_solver: {}", problem)),
+        );
+        problems
+    }
 }
 
 // Data describing a scene.
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SceneConfig {
     // global gravity (in world space)
     pub gravity: cgmath::Vector3<f32>,
-    pub fluid: FluidConfig,
+    #[serde(default)]
+    pub fluids: Vec<FluidConfig>,
+    // Scene files from before multiple fluid domains have a single fluid, moved into fluids on load.
+    #[serde(default)]
+    fluid: Option<FluidConfig>,
+    // Camera when the scene is loaded, if not given the current camera stays.
+    #[serde(default)]
+    pub camera: Option<CameraViewpoint>,
+    // Named viewpoints that can be selected in the gui.
+    #[serde(default)]
+    pub camera_presets: Vec<CameraViewpoint>,
+    // Keyframed scene properties, evaluated before every simulation step.
+    #[serde(default)]
+    pub timeline: Vec<TrackConfig>,
+    #[serde(default)]
+    pub simulation: SimulationSettings,
+}
+
+// Everything that is wrong with a scene file. Found before any simulation resources are created.
+#[derive(Debug)]
+pub struct SceneLoadError {
+    pub scene_path: PathBuf,
+    // One human readable message per problem, starting with the location in the file if known.
+    pub problems: Vec<String>,
 }
 
+impl fmt::Display for SceneLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid scene {:?}:", self.scene_path)?;
+        for problem in self.problems.iter() {
+            write!(f, "\n    {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SceneLoadError {}
+
 // Scene data & simulation.
 pub struct Scene {
-    hybrid_fluid: HybridFluid,
+    // One per entry in config.fluids
+    hybrid_fluids: Vec<HybridFluid>,
+    // Initial fluid of every domain in grid space, loaded once so that resets don't need to touch the file system.
+    fluid_regions: Vec<Vec<FluidRegion>>,
     config: SceneConfig,
+    path: PathBuf,
 }
 
 impl Scene {
@@ -49,53 +562,210 @@ impl Scene {
         shader_dir: &ShaderDirectory,
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<Self, io::Error> {
-        let file = File::open(scene_path)?;
-        let reader = BufReader::new(file);
-        let config: SceneConfig = serde_json::from_reader(reader)?;
+    ) -> Result<Self, SceneLoadError> {
+        let (config, fluid_regions) = Self::load_config(scene_path)?;
+
+        let hybrid_fluids = Self::create_fluids(
+            &config,
+            &fluid_regions,
+            device,
+            queue,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+        );
+
+        Ok(Scene {
+            hybrid_fluids,
+            fluid_regions,
+            config,
+            path: scene_path.to_path_buf(),
+        })
+    }
+
+    // Reads and validates a scene file, collecting as many problems as possible at once.
+    // Returns the initial fluid regions of every domain along with the config, doesn't need a device.
+    pub fn load_config(scene_path: &Path) -> Result<(SceneConfig, Vec<Vec<FluidRegion>>), SceneLoadError> {
+        let error = |problems| SceneLoadError {
+            scene_path: scene_path.to_path_buf(),
+            problems,
+        };
+
+        let file = File::open(scene_path).map_err(|err| error(vec![format!("Failed to open scene file: {}", err)]))?;
+        // serde's errors already come with line and column.
+        let mut config: SceneConfig = serde_json::from_reader(BufReader::new(file)).map_err(|err| error(vec![err.to_string()]))?;
+
+        let mut problems = Vec::new();
+        if let Some(fluid) = config.fluid.take() {
+            if config.fluids.is_empty() {
+                config.fluids.push(fluid);
+            } else {
+                problems.push("Scene can't have both fluid and fluids, move fluid into fluids".to_owned());
+            }
+        }
+        if config.fluids.is_empty() {
+            problems.push("Scene needs at least one fluid".to_owned());
+        }
+        if let Some(camera) = &config.camera {
+            problems.extend(camera.problems().iter().map(|problem| format!("camera: {}", problem)));
+        }
+        for (preset_index, preset) in config.camera_presets.iter().enumerate() {
+            if preset.name.is_empty() {
+                problems.push(format!("camera_presets[{}]: name can't be empty", preset_index));
+            }
+            problems.extend(
+                preset
+                    .problems()
+                    .iter()
+                    .map(|problem| format!("camera_presets[{}]: {}", preset_index, problem)),
+            );
+        }
+        problems.extend(config.simulation.problems().iter().map(|problem| format!("simulation: {}", problem)));
+        for (track_index, track) in config.timeline.iter().enumerate() {
+            problems.extend(
+                track
+                    .problems(config.fluids.len())
+                    .iter()
+                    .map(|problem| format!("timeline[{}]: {}", track_index, problem)),
+            );
+        }
+        let scene_directory = scene_path.parent().unwrap_or_else(|| Path::new(""));
+        let fluid_regions = config
+            .fluids
+            .iter()
+            .enumerate()
+            .map(|(fluid_index, fluid_config)| fluid_config.load_fluid_regions(&format!("fluids[{}]", fluid_index), scene_directory, &mut problems))
+            .collect();
 
-        let hybrid_fluid = Self::create_fluid_from_config(&config, device, queue, shader_dir, pipeline_manager, per_frame_bind_group_layout);
+        if problems.is_empty() {
+            Ok((config, fluid_regions))
+        } else {
+            Err(error(problems))
+        }
+    }
 
-        Ok(Scene { hybrid_fluid, config })
+    // Box around all fluid domains in world space.
+    pub fn world_bounds(&self) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+        let mut bounds = self.config.fluids[0].world_bounds();
+        for (min, max) in self.config.fluids.iter().skip(1).map(|fluid| fluid.world_bounds()) {
+            bounds.0 = cgmath::Point3::new(bounds.0.x.min(min.x), bounds.0.y.min(min.y), bounds.0.z.min(min.z));
+            bounds.1 = cgmath::Point3::new(bounds.1.x.max(max.x), bounds.1.y.max(max.y), bounds.1.z.max(max.z));
+        }
+        bounds
+    }
+
+    // File the scene was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Changes the scene file's json, keeping everything else in the file as is.
+    fn modify_scene_file(
+        &self,
+        modify: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>) -> Result<(), io::Error>,
+    ) -> Result<(), io::Error> {
+        let mut scene_json: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(&self.path)?))?;
+        modify(
+            scene_json
+                .as_object_mut()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Scene file doesn't contain an object"))?,
+        )?;
+
+        // Same indentation as the scene files in the repository.
+        let mut content = Vec::new();
+        let mut serializer = serde_json::Serializer::with_formatter(&mut content, serde_json::ser::PrettyFormatter::with_indent(b"    "));
+        scene_json.serialize(&mut serializer)?;
+        content.push(b'\n');
+        File::create(&self.path)?.write_all(&content)
+    }
+
+    // Appends a camera preset to the scene file.
+    pub fn save_camera_preset(&mut self, preset: CameraViewpoint) -> Result<(), io::Error> {
+        self.modify_scene_file(|scene_json| {
+            let presets = scene_json.entry("camera_presets").or_insert_with(|| serde_json::Value::Array(Vec::new()));
+            match presets {
+                serde_json::Value::Array(presets) => presets.push(serde_json::to_value(&preset)?),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "camera_presets in scene file is not an array")),
+            }
+            Ok(())
+        })?;
+        self.config.camera_presets.push(preset);
+        Ok(())
+    }
+
+    // Replaces the simulation settings in the scene file.
+    pub fn save_simulation_settings(&mut self, settings: SimulationSettings) -> Result<(), io::Error> {
+        self.modify_scene_file(|scene_json| {
+            scene_json.insert("simulation".to_owned(), serde_json::to_value(&settings)?);
+            Ok(())
+        })?;
+        self.config.simulation = settings;
+        Ok(())
     }
 
     pub fn config(&self) -> &SceneConfig {
         &self.config
     }
 
-    fn create_fluid_from_config(
+    fn create_fluids(
         config: &SceneConfig,
+        fluid_regions: &[Vec<FluidRegion>],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        per_frame_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Vec<HybridFluid> {
+        let hybrid_fluids = config
+            .fluids
+            .iter()
+            .zip(fluid_regions.iter())
+            .map(|(fluid_config, regions)| {
+                fluid_config.create_fluid(
+                    regions,
+                    config.gravity,
+                    &config.simulation,
+                    device,
+                    queue,
+                    shader_dir,
+                    pipeline_manager,
+                    per_frame_bind_group_layout,
+                )
+            })
+            .collect();
+
+        // Creating fluids is quite heavy, make sure we're done with all the buffer book-keeping before we move on.
+        device.poll(wgpu::Maintain::Wait);
+        hybrid_fluids
+    }
+
+    // Creates a separate gpu fluid with the state of the given fluid of the scene after a reset, the running simulation is left untouched.
+    pub fn create_gpu_fluid(
+        &self,
+        fluid_index: usize,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         shader_dir: &ShaderDirectory,
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> HybridFluid {
-        let mut hybrid_fluid = HybridFluid::new(
+        let fluid = self.config.fluids[fluid_index].create_fluid(
+            &self.fluid_regions[fluid_index],
+            self.config.gravity,
+            &self.config.simulation,
             device,
-            wgpu::Extent3d {
-                width: config.fluid.grid_dimension.x,
-                height: config.fluid.grid_dimension.y,
-                depth: config.fluid.grid_dimension.z,
-            },
-            config.fluid.max_num_particles,
+            queue,
             shader_dir,
             pipeline_manager,
             per_frame_bind_group_layout,
         );
-
-        for cube in config.fluid.fluid_cubes.iter() {
-            hybrid_fluid.add_fluid_cube(
-                queue,
-                cube.min / config.fluid.grid_to_world_scale,
-                cube.max / config.fluid.grid_to_world_scale,
-            );
-        }
-        hybrid_fluid.set_gravity_grid(config.gravity / config.fluid.grid_to_world_scale);
-
-        // Creating the fluid is quite heavy, make sure we're done with all the buffer book-keeping before we move on.
         device.poll(wgpu::Maintain::Wait);
-        hybrid_fluid
+        fluid
+    }
+
+    // Creates a cpu reference fluid that starts out with exactly the same state as the given fluid of the scene after a reset.
+    pub fn create_cpu_reference_fluid(&self, fluid_index: usize) -> CpuHybridFluid {
+        self.config.fluids[fluid_index].create_cpu_reference_fluid(&self.fluid_regions[fluid_index], self.config.gravity, &self.config.simulation)
     }
 
     pub fn reset(
@@ -106,34 +776,71 @@ impl Scene {
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
     ) {
-        self.hybrid_fluid = Self::create_fluid_from_config(&self.config, device, queue, shader_dir, pipeline_manager, per_frame_bind_group_layout);
+        self.hybrid_fluids = Self::create_fluids(
+            &self.config,
+            &self.fluid_regions,
+            device,
+            queue,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+        );
     }
 
+    // Sets all animated properties to their values at the given simulation time.
+    fn apply_timeline(&mut self, simulation_time: Duration) {
+        let time = simulation_time.as_secs_f32();
+        for track in self.config.timeline.iter() {
+            let value = track.evaluate(time);
+            for (fluid_index, (fluid, fluid_config)) in self.hybrid_fluids.iter_mut().zip(self.config.fluids.iter()).enumerate() {
+                if track.fluid.map_or(false, |track_fluid| track_fluid != fluid_index) {
+                    continue;
+                }
+                match track.property {
+                    AnimatedProperty::Gravity => fluid.set_gravity_grid(value.vector() / fluid_config.grid_to_world_scale),
+                    AnimatedProperty::VelocitySolverTargetMse => fluid.pressure_solver_config_velocity().target_mse = value.scalar(),
+                    AnimatedProperty::VelocitySolverMaxIterations => {
+                        fluid.pressure_solver_config_velocity().max_num_iterations = value.scalar() as i32
+                    }
+                    AnimatedProperty::DensitySolverTargetMse => fluid.pressure_solver_config_density().target_mse = value.scalar(),
+                    AnimatedProperty::DensitySolverMaxIterations => fluid.pressure_solver_config_density().max_num_iterations = value.scalar() as i32,
+                }
+            }
+        }
+    }
+
+    // simulation_time is the total simulated time before this step.
     pub fn step(
         &mut self,
+        simulation_time: Duration,
         simulation_delta: Duration,
         device: &wgpu::Device,
         pipeline_manager: &PipelineManager,
         queue: &wgpu::Queue,
         per_frame_bind_group: &wgpu::BindGroup,
+        profiler: &mut GpuProfiler,
     ) {
         // Poll device to update mapped buffers which may feed back into what a step does.
         device.poll(wgpu::Maintain::Poll);
+        self.apply_timeline(simulation_time);
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Encoder: Scene Step"),
         });
-        self.hybrid_fluid
-            .step(simulation_delta, &mut encoder, pipeline_manager, queue, per_frame_bind_group);
+        for hybrid_fluid in self.hybrid_fluids.iter_mut() {
+            hybrid_fluid.step(simulation_delta, &mut encoder, pipeline_manager, queue, per_frame_bind_group, profiler);
+        }
         queue.submit(Some(encoder.finish()));
-        self.hybrid_fluid.update_statistics();
+        for hybrid_fluid in self.hybrid_fluids.iter_mut() {
+            hybrid_fluid.update_statistics();
+        }
     }
 
-    pub fn fluid(&self) -> &HybridFluid {
-        &self.hybrid_fluid
+    pub fn fluids(&self) -> &[HybridFluid] {
+        &self.hybrid_fluids
     }
 
-    pub fn fluid_mut(&mut self) -> &mut HybridFluid {
-        &mut self.hybrid_fluid
+    pub fn fluids_mut(&mut self) -> &mut [HybridFluid] {
+        &mut self.hybrid_fluids
     }
 }
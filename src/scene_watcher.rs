@@ -0,0 +1,79 @@
+use notify::Watcher;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Watches scene files for changes, works the same way as ShaderDirectory.
+// Unlike with shaders, only changes to the active scene are of interest, so it keeps track of which files changed.
+pub struct SceneWatcher {
+    watcher: notify::RecommendedWatcher,
+    changed_files: Arc<Mutex<Vec<PathBuf>>>,
+    directory: PathBuf,
+    // Content of scene files the application wrote itself. Changes that leave a file with exactly this content are ignored.
+    own_writes: HashMap<PathBuf, Vec<u8>>,
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    // Fails for files that don't exist (anymore), which can't be the active scene anyways.
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl SceneWatcher {
+    pub fn new(directory: &Path) -> SceneWatcher {
+        let changed_files = Arc::new(Mutex::new(Vec::new()));
+        let changed_files_evt_ref = changed_files.clone();
+        let mut watcher: notify::RecommendedWatcher = notify::Watcher::new_immediate(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => changed_files_evt_ref.lock().unwrap().extend(event.paths),
+            Err(e) => error!("Failed to create filewatcher: {:?}", e),
+        })
+        .unwrap();
+        watcher.watch(directory, notify::RecursiveMode::Recursive).unwrap();
+
+        SceneWatcher {
+            watcher,
+            changed_files,
+            directory: canonical_path(directory),
+            own_writes: HashMap::new(),
+        }
+    }
+
+    // Makes sure changes to the given scene are picked up even if it lives outside of the scene directory.
+    pub fn watch_scene(&mut self, scene_path: &Path) {
+        if canonical_path(scene_path).starts_with(&self.directory) {
+            return;
+        }
+        if let Err(err) = self.watcher.watch(scene_path, notify::RecursiveMode::NonRecursive) {
+            error!("Failed to watch scene file {:?}: {:?}", scene_path, err);
+        }
+    }
+
+    // Call after writing to a scene file, so that the write doesn't count as a change.
+    pub fn ignore_own_write(&mut self, scene_path: &Path) {
+        match std::fs::read(scene_path) {
+            Ok(content) => {
+                self.own_writes.insert(canonical_path(scene_path), content);
+            }
+            Err(err) => error!("Failed to read back scene file {:?}: {}", scene_path, err),
+        }
+    }
+
+    // Checks if the given scene file changed since the last call. Changes to any other file are discarded.
+    pub fn detected_change(&mut self, scene_path: &Path) -> bool {
+        let changed_files: Vec<PathBuf> = self.changed_files.lock().unwrap().drain(..).collect();
+        if changed_files.is_empty() {
+            return false;
+        }
+        let scene_path = canonical_path(scene_path);
+        if !changed_files.iter().any(|path| canonical_path(path) == scene_path) {
+            return false;
+        }
+
+        if let Some(own_write) = self.own_writes.get(&scene_path) {
+            if std::fs::read(&scene_path).ok().as_ref() == Some(own_write) {
+                return false;
+            }
+            self.own_writes.remove(&scene_path);
+        }
+        true
+    }
+}
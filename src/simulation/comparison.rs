@@ -0,0 +1,310 @@
+use super::{FluidSimulation, SimulationVolume};
+use cgmath::{InnerSpace, MetricSpace};
+use std::{fmt, time::Duration};
+use strum::IntoEnumIterator;
+
+// How far two simulations may deviate from each other, e.g. the gpu fluid from the cpu reference.
+// Floating point summation order differs and the pressure solvers may stop at different iterations, so results are never bit identical.
+#[derive(Clone, Copy)]
+pub struct ComparisonTolerances {
+    // Max distance between positions of the same particle in grid cells.
+    pub particle_position: f32,
+    // Max length of the velocity difference of the same particle in grid cells per second.
+    pub particle_velocity: f32,
+    // Max difference of grid volume values, relative to the largest absolute value of the reference volume.
+    pub volume_relative: f32,
+    // Max fraction of grid cells that may have a different marker.
+    pub marker_mismatch_fraction: f32,
+}
+
+impl Default for ComparisonTolerances {
+    fn default() -> Self {
+        ComparisonTolerances {
+            particle_position: 0.01,
+            particle_velocity: 0.05,
+            volume_relative: 0.05,
+            marker_mismatch_fraction: 0.01,
+        }
+    }
+}
+
+pub struct VolumeDifference {
+    pub volume: SimulationVolume,
+    pub max_difference: f32,
+    pub max_reference_magnitude: f32,
+    pub worst_cell: cgmath::Point3<u32>,
+    // Cells are only compared if both simulations agree on the marker and have finite values.
+    pub num_compared_cells: usize,
+}
+
+impl VolumeDifference {
+    pub fn relative_difference(&self) -> f32 {
+        if self.max_difference == 0.0 {
+            0.0
+        } else {
+            self.max_difference / self.max_reference_magnitude.max(std::f32::EPSILON)
+        }
+    }
+}
+
+pub struct SimulationComparison {
+    pub num_particles: u32,
+    pub num_particles_reference: u32,
+    pub max_particle_position_difference: f32,
+    pub worst_particle_position: usize,
+    pub max_particle_velocity_difference: f32,
+    pub worst_particle_velocity: usize,
+    pub num_cells: usize,
+    pub num_marker_mismatches: usize,
+    // Only volumes that both simulations provided.
+    pub volumes: Vec<VolumeDifference>,
+}
+
+impl SimulationComparison {
+    // Compares particles by index (both simulations need to be set up with identical particles) and all grid volumes cell by cell.
+    pub fn new(simulation: &dyn FluidSimulation, reference: &dyn FluidSimulation) -> Self {
+        let grid_dimension = reference.grid_dimension();
+        assert_eq!(
+            simulation.grid_dimension(),
+            grid_dimension,
+            "Can only compare simulations with the same grid dimension"
+        );
+        let num_cells = (grid_dimension.width * grid_dimension.height * grid_dimension.depth) as usize;
+
+        let particles = simulation.read_particles();
+        let particles_reference = reference.read_particles();
+        let mut comparison = SimulationComparison {
+            num_particles: particles.len() as u32,
+            num_particles_reference: particles_reference.len() as u32,
+            max_particle_position_difference: 0.0,
+            worst_particle_position: 0,
+            max_particle_velocity_difference: 0.0,
+            worst_particle_velocity: 0,
+            num_cells,
+            num_marker_mismatches: 0,
+            volumes: Vec::new(),
+        };
+        // NaN is the largest possible difference, the first one sticks.
+        for (i, (particle, particle_reference)) in particles.iter().zip(particles_reference.iter()).enumerate() {
+            let position_difference = particle.position.distance(particle_reference.position);
+            if !(position_difference <= comparison.max_particle_position_difference) && !comparison.max_particle_position_difference.is_nan() {
+                comparison.max_particle_position_difference = position_difference;
+                comparison.worst_particle_position = i;
+            }
+            let velocity_difference = (particle.velocity - particle_reference.velocity).magnitude();
+            if !(velocity_difference <= comparison.max_particle_velocity_difference) && !comparison.max_particle_velocity_difference.is_nan() {
+                comparison.max_particle_velocity_difference = velocity_difference;
+                comparison.worst_particle_velocity = i;
+            }
+        }
+
+        let marker = simulation.read_volume(SimulationVolume::Marker).unwrap();
+        let marker_reference = reference.read_volume(SimulationVolume::Marker).unwrap();
+        comparison.num_marker_mismatches = marker.iter().zip(marker_reference.iter()).filter(|(a, b)| a != b).count();
+
+        for volume in SimulationVolume::iter().filter(|&v| v != SimulationVolume::Marker) {
+            let (values, values_reference) = match (simulation.read_volume(volume), reference.read_volume(volume)) {
+                (Some(values), Some(values_reference)) => (values, values_reference),
+                _ => continue,
+            };
+
+            let mut difference = VolumeDifference {
+                volume,
+                max_difference: 0.0,
+                max_reference_magnitude: 0.0,
+                worst_cell: cgmath::point3(0, 0, 0),
+                num_compared_cells: 0,
+            };
+            for i in 0..num_cells {
+                if marker[i] != marker_reference[i] || !values[i].is_finite() || !values_reference[i].is_finite() {
+                    continue;
+                }
+                difference.num_compared_cells += 1;
+                difference.max_reference_magnitude = difference.max_reference_magnitude.max(values_reference[i].abs());
+                let cell_difference = (values[i] - values_reference[i]).abs();
+                if cell_difference > difference.max_difference {
+                    difference.max_difference = cell_difference;
+                    difference.worst_cell = cgmath::point3(
+                        i as u32 % grid_dimension.width,
+                        i as u32 / grid_dimension.width % grid_dimension.height,
+                        i as u32 / grid_dimension.width / grid_dimension.height,
+                    );
+                }
+            }
+            comparison.volumes.push(difference);
+        }
+
+        comparison
+    }
+
+    // Human readable description of everything that exceeds the given tolerances. Empty if the simulations match.
+    pub fn failures(&self, tolerances: &ComparisonTolerances) -> Vec<String> {
+        let mut failures = Vec::new();
+        if self.num_particles != self.num_particles_reference {
+            failures.push(format!(
+                "particle count differs: {} vs {} (reference)",
+                self.num_particles, self.num_particles_reference
+            ));
+        }
+        // Written as negated comparisons so that NaN counts as failure.
+        if !(self.max_particle_position_difference <= tolerances.particle_position) {
+            failures.push(format!(
+                "particle {} position differs by {} cells (tolerance {})",
+                self.worst_particle_position, self.max_particle_position_difference, tolerances.particle_position
+            ));
+        }
+        if !(self.max_particle_velocity_difference <= tolerances.particle_velocity) {
+            failures.push(format!(
+                "particle {} velocity differs by {} cells/s (tolerance {})",
+                self.worst_particle_velocity, self.max_particle_velocity_difference, tolerances.particle_velocity
+            ));
+        }
+        if !(self.num_marker_mismatches as f32 <= self.num_cells as f32 * tolerances.marker_mismatch_fraction) {
+            failures.push(format!(
+                "marker differs in {} of {} cells (tolerance {}%)",
+                self.num_marker_mismatches,
+                self.num_cells,
+                tolerances.marker_mismatch_fraction * 100.0
+            ));
+        }
+        for volume in self.volumes.iter() {
+            if !(volume.relative_difference() <= tolerances.volume_relative) {
+                failures.push(format!(
+                    "{} differs by {} at cell {:?}, {}% of max reference value {} (tolerance {}%)",
+                    volume.volume.name(),
+                    volume.max_difference,
+                    volume.worst_cell,
+                    volume.relative_difference() * 100.0,
+                    volume.max_reference_magnitude,
+                    tolerances.volume_relative * 100.0
+                ));
+            }
+        }
+        failures
+    }
+}
+
+impl fmt::Display for SimulationComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "particles: {} vs {} (reference)", self.num_particles, self.num_particles_reference)?;
+        writeln!(
+            f,
+            "max particle position difference: {} (particle {})",
+            self.max_particle_position_difference, self.worst_particle_position
+        )?;
+        writeln!(
+            f,
+            "max particle velocity difference: {} (particle {})",
+            self.max_particle_velocity_difference, self.worst_particle_velocity
+        )?;
+        write!(f, "marker mismatches: {} of {} cells", self.num_marker_mismatches, self.num_cells)?;
+        for volume in self.volumes.iter() {
+            write!(
+                f,
+                "\n{}: max difference {} at cell {:?} ({}% of max reference value, {} cells compared)",
+                volume.volume.name(),
+                volume.max_difference,
+                volume.worst_cell,
+                volume.relative_difference() * 100.0,
+                volume.num_compared_cells
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Steps two simulations side by side and compares them after every step.
+// Stops at the first step that exceeds the tolerances, returns the number of steps performed and the last comparison.
+pub fn compare_in_lockstep(
+    simulation: &mut dyn FluidSimulation,
+    reference: &mut dyn FluidSimulation,
+    simulation_delta: Duration,
+    num_steps: u32,
+    tolerances: &ComparisonTolerances,
+) -> (u32, SimulationComparison) {
+    // Grids hold undefined values before the first step, so there is nothing meaningful to compare before.
+    let num_steps = num_steps.max(1);
+    let mut step = 0;
+    loop {
+        simulation.step(simulation_delta);
+        reference.step(simulation_delta);
+        step += 1;
+
+        let comparison = SimulationComparison::new(&*simulation, &*reference);
+        if step == num_steps || !comparison.failures(tolerances).is_empty() {
+            return (step, comparison);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        headless,
+        per_frame_resources::PerFrameResources,
+        scene::Scene,
+        simulation::GpuFluidSimulation,
+        simulation_controller::SimulationController,
+        timer::Timer,
+        wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler, shader::ShaderDirectory},
+    };
+    use std::path::Path;
+
+    const NUM_STEPS: u32 = 3;
+
+    #[test]
+    fn gpu_matches_cpu_reference_on_single_cell_debug_scene() {
+        // Machines without any graphics adapter (not even a software one) can't run this.
+        let (device, queue) = match futures::executor::block_on(headless::create_device()) {
+            Ok(device_and_queue) => device_and_queue,
+            Err(err) => {
+                eprintln!("Skipping gpu comparison: {}", err);
+                return;
+            }
+        };
+
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let shader_dir = ShaderDirectory::new(&manifest_dir.join("shader"));
+        let mut pipeline_manager = PipelineManager::new();
+        let mut per_frame_resources = PerFrameResources::new(&device);
+        let mut profiler = GpuProfiler::new(&device, &queue);
+        let mut scene = Scene::new(
+            &manifest_dir.join("scenes/single_cell_debug.json"),
+            &device,
+            &queue,
+            &shader_dir,
+            &mut pipeline_manager,
+            per_frame_resources.bind_group_layout(),
+        )
+        .unwrap();
+
+        // Simulation shaders only read the simulation delta from the per frame resources.
+        let simulation_delta = SimulationController::new().timer().simulation_delta();
+        per_frame_resources.update_gpu_data_simulation_only(&queue, Timer::new(simulation_delta).fill_global_uniform_buffer());
+
+        let mut reference = scene.create_cpu_reference_fluid(0);
+        let fluid = &mut scene.fluids_mut()[0];
+        fluid.set_debug_volume_capture(true);
+        let mut simulation = GpuFluidSimulation {
+            fluid,
+            device: &device,
+            queue: &queue,
+            pipeline_manager: &pipeline_manager,
+            per_frame_bind_group: per_frame_resources.bind_group(),
+            profiler: &mut profiler,
+        };
+
+        let tolerances = ComparisonTolerances::default();
+        let (num_steps_performed, comparison) = compare_in_lockstep(&mut simulation, &mut reference, simulation_delta, NUM_STEPS, &tolerances);
+        let failures = comparison.failures(&tolerances);
+        assert!(
+            failures.is_empty(),
+            "gpu deviates from cpu reference after {} steps:\n{}\n{}",
+            num_steps_performed,
+            failures.join("\n"),
+            comparison
+        );
+        assert_eq!(num_steps_performed, NUM_STEPS);
+    }
+}
@@ -0,0 +1,963 @@
+// Pure cpu implementation of what HybridFluid::step does on the gpu.
+//
+// Follows the compute shaders pass by pass, including their quirks, so that results can be compared against gpu readbacks.
+// Wherever the shaders do something questionable, it's mirrored here and marked with a "Same as the shader" comment.
+// Doesn't need a gpu at all, but it's very slow - meant for small debug scenes and tests!
+
+use super::{
+    fluid_region::FluidRegion, pressure_solver::PressureField, FluidSimulation, HybridFluid, ParticleState, SimulationVolume, SolverConfig,
+    SolverStatisticSample, TransferScheme,
+};
+use cgmath::{prelude::*, vec3, Point3, Vector3, Vector4};
+use std::{collections::VecDeque, time::Duration};
+
+// See hybrid_fluid.glsl & particles.glsl
+const CELL_SOLID: f32 = 0.0;
+const CELL_FLUID: f32 = 1.0;
+const CELL_AIR: f32 = -1.0;
+const INVALID_LINKED_LIST_PTR: u32 = 0xFFFFFFFF;
+
+// The gather shaders follow each linked list for at most this many particles (MAX_PARTICLES_PER_LINKED_LIST in hybrid_fluid.glsl).
+fn max_particles_per_linked_list(particles_per_cell: u32) -> usize {
+    (particles_per_cell + particles_per_cell / 2) as usize
+}
+
+// Dual grid cells that hold particles which contribute to a grid cell (gathered from gridCoord - offset).
+const DUAL_CELL_OFFSETS: [(i32, i32, i32); 8] = [(0, 0, 0), (1, 0, 0), (0, 1, 0), (1, 1, 0), (0, 0, 1), (1, 0, 1), (0, 1, 1), (1, 1, 1)];
+
+// Direct neighbors in the order the shaders visit them: X0, X1, Y0, Y1, Z0, Z1
+const NEIGHBOR_OFFSETS: [(i32, i32, i32); 6] = [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)];
+
+type GridCoord = Vector3<i32>;
+
+fn axis(component: usize) -> GridCoord {
+    let mut v = vec3(0, 0, 0);
+    v[component] = 1;
+    v
+}
+
+fn offset_coord(coord: GridCoord, offset: (i32, i32, i32)) -> GridCoord {
+    coord + vec3(offset.0, offset.1, offset.2)
+}
+
+// Equivalent of ivec3(v) in glsl, i.e. truncation towards zero.
+fn to_grid_coord(v: Vector3<f32>) -> GridCoord {
+    vec3(v.x as i32, v.y as i32, v.z as i32)
+}
+
+fn cell_center(coord: GridCoord) -> Vector3<f32> {
+    vec3(coord.x as f32 + 0.5, coord.y as f32 + 0.5, coord.z as f32 + 0.5)
+}
+
+fn saturate(v: Vector3<f32>) -> Vector3<f32> {
+    v.map(|x| x.max(0.0).min(1.0))
+}
+
+fn fract(v: Vector3<f32>) -> Vector3<f32> {
+    v.map(|x| x - x.floor())
+}
+
+fn mix(a: Vector3<f32>, b: Vector3<f32>, t: Vector3<f32>) -> Vector3<f32> {
+    a.mul_element_wise(vec3(1.0, 1.0, 1.0) - t) + b.mul_element_wise(t)
+}
+
+fn mix_scalar(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+// Same kernel as the particle to grid transfers & density gather.
+fn tent_kernel(to_sample_position: Vector3<f32>) -> f32 {
+    let offset = saturate(vec3(1.0, 1.0, 1.0) - to_sample_position.map(f32::abs));
+    offset.x * offset.y * offset.z
+}
+
+fn grid_cells(grid_size: GridCoord) -> impl Iterator<Item = GridCoord> {
+    (0..grid_size.z).flat_map(move |z| (0..grid_size.y).flat_map(move |y| (0..grid_size.x).map(move |x| vec3(x, y, z))))
+}
+
+// Scalar grid, x varying fastest.
+// Like texelFetch/imageLoad, reads outside of the grid return zero and writes outside of the grid are dropped.
+#[derive(Clone)]
+struct Volume<T> {
+    size: GridCoord,
+    values: Vec<T>,
+}
+
+impl<T: Copy + Default> Volume<T> {
+    fn new(size: GridCoord) -> Self {
+        Volume {
+            size,
+            values: vec![T::default(); (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    fn index(&self, coord: GridCoord) -> Option<usize> {
+        if coord.x < 0 || coord.y < 0 || coord.z < 0 || coord.x >= self.size.x || coord.y >= self.size.y || coord.z >= self.size.z {
+            None
+        } else {
+            Some((coord.x + (coord.y + coord.z * self.size.y) * self.size.x) as usize)
+        }
+    }
+
+    fn get(&self, coord: GridCoord) -> T {
+        match self.index(coord) {
+            Some(index) => self.values[index],
+            None => T::default(),
+        }
+    }
+
+    fn set(&mut self, coord: GridCoord, value: T) {
+        if let Some(index) = self.index(coord) {
+            self.values[index] = value;
+        }
+    }
+
+    // Equivalent of imageAtomicExchange.
+    fn exchange(&mut self, coord: GridCoord, value: T) -> T {
+        match self.index(coord) {
+            Some(index) => std::mem::replace(&mut self.values[index], value),
+            None => T::default(),
+        }
+    }
+
+    fn fill(&mut self, value: T) {
+        for v in self.values.iter_mut() {
+            *v = value;
+        }
+    }
+}
+
+// Calls f for every particle that is linked into the dual cells contributing to a grid cell, same limits as in the gather shaders.
+fn for_each_gathered_particle(
+    linked_list_dual_grid: &Volume<u32>,
+    linked_list_next: &[u32],
+    particles_per_cell: u32,
+    coord: GridCoord,
+    mut f: impl FnMut(usize),
+) {
+    for &offset in DUAL_CELL_OFFSETS.iter() {
+        // Indices in the grid are offset by +1, so that empty cells (and reads outside of the grid) turn into invalid pointers.
+        let mut particle_index = linked_list_dual_grid.get(coord - vec3(offset.0, offset.1, offset.2)).wrapping_sub(1);
+        for _ in 0..max_particles_per_linked_list(particles_per_cell) {
+            if particle_index == INVALID_LINKED_LIST_PTR {
+                break;
+            }
+            f(particle_index as usize);
+            particle_index = linked_list_next[particle_index as usize];
+        }
+    }
+}
+
+// Diagonal of the pressure matrix A.
+fn num_non_solid_neighbors(marker: &Volume<f32>, coord: GridCoord) -> f32 {
+    NEIGHBOR_OFFSETS
+        .iter()
+        .filter(|&&offset| marker.get(offset_coord(coord, offset)) != CELL_SOLID)
+        .count() as f32
+}
+
+// Only meaningful if coord is a fluid cell.
+fn multiply_with_coefficient_matrix(marker: &Volume<f32>, coord: GridCoord, volume: &Volume<f32>) -> f32 {
+    let mut result = num_non_solid_neighbors(marker, coord) * volume.get(coord);
+    for &offset in NEIGHBOR_OFFSETS.iter() {
+        let neighbor = offset_coord(coord, offset);
+        if marker.get(neighbor) == CELL_FLUID {
+            result -= volume.get(neighbor);
+        }
+    }
+    result
+}
+
+// Dot product over all fluid cells.
+fn dot_product(marker: &Volume<f32>, a: &Volume<f32>, b: &Volume<f32>) -> f32 {
+    let mut result = 0.0f64;
+    for ((&marker, &a), &b) in marker.values.iter().zip(a.values.iter()).zip(b.values.iter()) {
+        if marker == CELL_FLUID {
+            result += a as f64 * b as f64;
+        }
+    }
+    result as f32
+}
+
+// See pressure_reduce.comp
+fn add_signed_epsilon(x: f32) -> f32 {
+    const EPSILON: f32 = 1e-10;
+    x + if x < 0.0 { -EPSILON } else { EPSILON }
+}
+
+struct CpuPressureField {
+    pressure: Volume<f32>,
+    config: SolverConfig,
+    stats: VecDeque<SolverStatisticSample>,
+    is_first_step: bool,
+}
+
+impl CpuPressureField {
+    fn new(grid_size: GridCoord, config: SolverConfig) -> Self {
+        CpuPressureField {
+            pressure: Volume::new(grid_size),
+            config,
+            stats: VecDeque::new(),
+            is_first_step: true,
+        }
+    }
+}
+
+// Preconditioned conjugate gradient, equivalent to PressureSolver::solve.
+struct CpuPressureSolver {
+    residual: Volume<f32>,
+    search: Volume<f32>,
+    auxiliary: Volume<f32>,
+    temp: Volume<f32>,
+}
+
+impl CpuPressureSolver {
+    fn new(grid_size: GridCoord) -> Self {
+        CpuPressureSolver {
+            residual: Volume::new(grid_size),
+            search: Volume::new(grid_size),
+            auxiliary: Volume::new(grid_size),
+            temp: Volume::new(grid_size),
+        }
+    }
+
+    // See pressure_apply_preconditioner.comp, only writes fluid cells.
+    fn apply_preconditioner(marker: &Volume<f32>, input: &Volume<f32>, output: &mut Volume<f32>) {
+        for (index, &cell_marker) in marker.values.iter().enumerate() {
+            if cell_marker != CELL_FLUID {
+                continue;
+            }
+            // Same as the shader: The lower neighbors (X0, Y0, Z0) are supposed to be subtracted here,
+            // but the shader fetches them from mip level 1 which doesn't exist, so they always read as zero.
+            let coord = vec3(
+                index as i32 % marker.size.x,
+                index as i32 / marker.size.x % marker.size.y,
+                index as i32 / marker.size.x / marker.size.y,
+            );
+            let mut result = input.values[index];
+            let diagonal = num_non_solid_neighbors(marker, coord);
+            if diagonal > 0.0 {
+                result /= diagonal;
+            }
+            output.values[index] = result;
+        }
+    }
+
+    fn precondition_residual_into(&mut self, marker: &Volume<f32>, pass1_output_is_search: bool) {
+        Self::apply_preconditioner(marker, &self.residual, &mut self.temp);
+        if pass1_output_is_search {
+            Self::apply_preconditioner(marker, &self.temp, &mut self.search);
+        } else {
+            Self::apply_preconditioner(marker, &self.temp, &mut self.auxiliary);
+        }
+    }
+
+    // Expects the right hand side in the residual volume (fluid cells only).
+    fn solve(&mut self, simulation_delta: Duration, pressure_field: &mut CpuPressureField, marker: &Volume<f32>) {
+        let grid_size = marker.size;
+        let delta_sq = simulation_delta.as_secs_f32() * simulation_delta.as_secs_f32();
+        let target_mse_per_second = pressure_field.config.target_mse / delta_sq;
+        let pressure = &mut pressure_field.pressure;
+
+        // init
+        // Pressure from last step is used as initial guess, except for non-fluid cells.
+        if pressure_field.is_first_step {
+            pressure.fill(0.0);
+        } else {
+            for coord in grid_cells(grid_size) {
+                if marker.get(coord) != CELL_FLUID {
+                    pressure.set(coord, 0.0);
+                }
+            }
+            for coord in grid_cells(grid_size) {
+                if marker.get(coord) == CELL_FLUID {
+                    let residual = self.residual.get(coord) - multiply_with_coefficient_matrix(marker, coord, pressure);
+                    self.residual.set(coord, residual);
+                }
+            }
+        }
+        self.precondition_residual_into(marker, true);
+        let mut sigma = dot_product(marker, &self.search, &self.residual);
+
+        let mut i = 0;
+        let resulting_sample = loop {
+            // multiply search vector (s) with coefficients (A), store in auxiliary (z)
+            for coord in grid_cells(grid_size) {
+                if marker.get(coord) == CELL_FLUID {
+                    self.auxiliary.set(coord, multiply_with_coefficient_matrix(marker, coord, &self.search));
+                }
+            }
+            let alpha = sigma / add_signed_epsilon(dot_product(marker, &self.search, &self.auxiliary));
+
+            // update pressure field (p) and residual field (r)
+            for (index, &cell_marker) in marker.values.iter().enumerate() {
+                if cell_marker == CELL_FLUID {
+                    pressure.values[index] += alpha * self.search.values[index];
+                    self.residual.values[index] -= alpha * self.auxiliary.values[index];
+                }
+            }
+
+            let iteration_with_mse_computation =
+                pressure_field.config.max_num_iterations == i || (i > 0 && i % pressure_field.config.mse_check_frequency == 0);
+            if iteration_with_mse_computation {
+                let squared_error = dot_product(marker, &self.residual, &self.residual);
+                if squared_error < target_mse_per_second || pressure_field.config.max_num_iterations == i {
+                    break SolverStatisticSample {
+                        mse: squared_error * delta_sq,
+                        iteration_count: i,
+                    };
+                }
+            }
+
+            // preconditioner on (r), store to auxiliary (z)
+            self.precondition_residual_into(marker, false);
+            let sigma_new = dot_product(marker, &self.auxiliary, &self.residual);
+            let beta = sigma_new / add_signed_epsilon(sigma);
+            sigma = sigma_new;
+
+            // update search vector
+            for (index, &cell_marker) in marker.values.iter().enumerate() {
+                if cell_marker == CELL_FLUID {
+                    self.search.values[index] = self.auxiliary.values[index] + beta * self.search.values[index];
+                }
+            }
+
+            i += 1;
+        };
+
+        pressure_field.is_first_step = false;
+        pressure_field.stats.push_back(resulting_sample);
+        while pressure_field.stats.len() > PressureField::SOLVER_STATISTIC_HISTORY_LENGTH {
+            pressure_field.stats.pop_front();
+        }
+    }
+}
+
+pub struct CpuHybridFluid {
+    grid_dimension: wgpu::Extent3d,
+    grid_size: GridCoord,
+    max_num_particles: u32,
+    particles_per_cell: u32,
+    transfer_scheme: TransferScheme,
+    gravity_grid: Vector3<f32>,
+
+    velocity: [Volume<f32>; 3],
+    marker: Volume<f32>,
+    linked_list_dual_grid: Volume<u32>,
+
+    pressure_solver: CpuPressureSolver,
+    pressure_field_from_velocity: CpuPressureField,
+    pressure_field_from_density: CpuPressureField,
+
+    // Intermediate states that are overwritten during a step, equivalent to HybridFluid's debug volume capture (which is always on here).
+    captured_divergence: Volume<f32>,
+    captured_divergence_marker: Volume<f32>,
+    captured_density_error: Volume<f32>,
+    debug_volumes_captured: bool,
+
+    particle_positions: Vec<Point3<f32>>,
+    particle_linked_list_next: Vec<u32>,
+    // Per velocity component, same layout as the gpu buffers: (APIC) affine row in xyz and the actual velocity component in w.
+    particle_velocities: [Vec<Vector4<f32>>; 3],
+}
+
+impl CpuHybridFluid {
+    pub fn new(grid_dimension: wgpu::Extent3d, max_num_particles: u32, particles_per_cell: u32) -> Self {
+        let grid_size = vec3(grid_dimension.width as i32, grid_dimension.height as i32, grid_dimension.depth as i32);
+        CpuHybridFluid {
+            grid_dimension,
+            grid_size,
+            max_num_particles,
+            particles_per_cell,
+            transfer_scheme: TransferScheme::default(),
+            gravity_grid: vec3(0.0, 0.0, 0.0),
+
+            velocity: [Volume::new(grid_size), Volume::new(grid_size), Volume::new(grid_size)],
+            marker: Volume::new(grid_size),
+            linked_list_dual_grid: Volume::new(grid_size),
+
+            pressure_solver: CpuPressureSolver::new(grid_size),
+            pressure_field_from_velocity: CpuPressureField::new(grid_size, HybridFluid::DEFAULT_SOLVER_CONFIG_VELOCITY),
+            pressure_field_from_density: CpuPressureField::new(grid_size, HybridFluid::DEFAULT_SOLVER_CONFIG_DENSITY),
+
+            captured_divergence: Volume::new(grid_size),
+            captured_divergence_marker: Volume::new(grid_size),
+            captured_density_error: Volume::new(grid_size),
+            debug_volumes_captured: false,
+
+            particle_positions: Vec::new(),
+            particle_linked_list_next: Vec::new(),
+            particle_velocities: [Vec::new(), Vec::new(), Vec::new()],
+        }
+    }
+
+    // Adds particles for a region of fluid. Coordinates are in grid space!
+    // Produces exactly the same particles as HybridFluid::add_fluid_region.
+    pub fn add_fluid_region(&mut self, region: &FluidRegion) {
+        let new_particles = region.sample_particles(self.grid_dimension, self.particles_per_cell, self.num_particles(), self.max_num_particles);
+        let num_particles = self.particle_positions.len() + new_particles.len();
+        for &position in new_particles.iter() {
+            let velocity = region.velocity_at(position);
+            for (component, velocities) in self.particle_velocities.iter_mut().enumerate() {
+                velocities.push(Vector4::new(0.0, 0.0, 0.0, velocity[component]));
+            }
+        }
+        self.particle_positions.extend(new_particles);
+        self.particle_linked_list_next.resize(num_particles, INVALID_LINKED_LIST_PTR);
+    }
+
+    pub fn set_gravity_grid(&mut self, gravity: cgmath::Vector3<f32>) {
+        self.gravity_grid = gravity;
+    }
+
+    pub fn set_transfer_scheme(&mut self, transfer_scheme: TransferScheme) {
+        self.transfer_scheme = transfer_scheme;
+    }
+
+    pub fn pressure_solver_config_velocity(&mut self) -> &mut SolverConfig {
+        &mut self.pressure_field_from_velocity.config
+    }
+
+    pub fn pressure_solver_config_density(&mut self) -> &mut SolverConfig {
+        &mut self.pressure_field_from_density.config
+    }
+
+    pub fn pressure_solver_stats_velocity(&self) -> &VecDeque<SolverStatisticSample> {
+        &self.pressure_field_from_velocity.stats
+    }
+
+    pub fn pressure_solver_stats_density(&self) -> &VecDeque<SolverStatisticSample> {
+        &self.pressure_field_from_density.stats
+    }
+
+    // transfer_clear.comp
+    fn transfer_clear(&mut self, velocity_transfer_component: usize) {
+        self.linked_list_dual_grid.fill(0);
+        if velocity_transfer_component == 0 {
+            self.marker.fill(CELL_AIR);
+        }
+    }
+
+    // transfer_build_linkedlist.comp
+    fn transfer_build_linked_list(&mut self, velocity_transfer_component: usize) {
+        for (particle_index, position) in self.particle_positions.iter().enumerate() {
+            if velocity_transfer_component == 0 {
+                self.marker.set(to_grid_coord(position.to_vec()), CELL_FLUID);
+            }
+            let mut offset = vec3(0.5, 0.5, 0.5);
+            offset[velocity_transfer_component] = 1.0;
+            let nearest_dual_grid_cell = to_grid_coord(position.to_vec() - offset);
+            self.particle_linked_list_next[particle_index] = self
+                .linked_list_dual_grid
+                .exchange(nearest_dual_grid_cell, particle_index as u32 + 1)
+                .wrapping_sub(1);
+        }
+    }
+
+    // transfer_set_boundary_marker.comp
+    fn transfer_set_boundary_marker(&mut self) {
+        for coord in grid_cells(self.grid_size) {
+            if coord.x == 0 || coord.y == 0 || coord.z == 0 {
+                self.marker.set(coord, CELL_SOLID);
+            }
+        }
+    }
+
+    // transfer_gather_velocity.comp
+    fn transfer_gather_velocity(&mut self, velocity_transfer_component: usize, simulation_delta: f32) {
+        let c = velocity_transfer_component;
+        for coord in grid_cells(self.grid_size) {
+            // We write velocity only if the velocity value we care about is between at least one fluid cell.
+            let marker_a = self.marker.get(coord);
+            let marker_b = self.marker.get(coord + axis(c));
+            if marker_a != CELL_FLUID && marker_b != CELL_FLUID {
+                continue;
+            }
+
+            let mut staggered_velocity_sample_position = cell_center(coord);
+            staggered_velocity_sample_position[c] += 0.5;
+
+            let mut velocity_component = 0.0;
+            let mut velocity_weight = 0.0;
+            let positions = &self.particle_positions;
+            let velocities = &self.particle_velocities[c];
+            for_each_gathered_particle(
+                &self.linked_list_dual_grid,
+                &self.particle_linked_list_next,
+                self.particles_per_cell,
+                coord,
+                |particle_index| {
+                    let to_sample_position = staggered_velocity_sample_position - positions[particle_index].to_vec();
+                    let weight = tent_kernel(to_sample_position);
+                    velocity_component += weight * velocities[particle_index].dot(to_sample_position.extend(1.0));
+                    velocity_weight += weight;
+                },
+            );
+
+            if velocity_weight > 0.0 {
+                velocity_component /= velocity_weight;
+            }
+            velocity_component += self.gravity_grid[c] * simulation_delta;
+
+            // Don't flow into solid
+            if marker_a == CELL_SOLID {
+                velocity_component = velocity_component.max(0.0);
+            } else if marker_b == CELL_SOLID {
+                velocity_component = velocity_component.min(0.0);
+            }
+            self.velocity[c].set(coord, velocity_component);
+        }
+    }
+
+    // divergence_compute.comp, writes into the pressure solver's residual.
+    fn compute_divergence(&mut self) {
+        for coord in grid_cells(self.grid_size) {
+            if self.marker.get(coord) != CELL_FLUID {
+                continue;
+            }
+            let velocity_positive_boundary = vec3(self.velocity[0].get(coord), self.velocity[1].get(coord), self.velocity[2].get(coord));
+            let velocity_negative_boundary = vec3(
+                self.velocity[0].get(coord - axis(0)),
+                self.velocity[1].get(coord - axis(1)),
+                self.velocity[2].get(coord - axis(2)),
+            );
+
+            let mut divergence = 0.0;
+            for c in 0..3 {
+                divergence += velocity_positive_boundary[c] - velocity_negative_boundary[c];
+            }
+            // Account for solid walls.
+            for c in 0..3 {
+                if self.marker.get(coord - axis(c)) == CELL_SOLID {
+                    divergence += velocity_negative_boundary[c];
+                }
+            }
+            for c in 0..3 {
+                if self.marker.get(coord + axis(c)) == CELL_SOLID {
+                    divergence -= velocity_positive_boundary[c];
+                }
+            }
+            self.pressure_solver.residual.set(coord, divergence);
+        }
+    }
+
+    // divergence_remove.comp
+    fn remove_divergence(&mut self) {
+        let marker = &self.marker;
+        let pressure = &self.pressure_field_from_velocity.pressure;
+        let sample_pressure = |coord: GridCoord, cell_type: f32| if cell_type == CELL_FLUID { pressure.get(coord) } else { 0.0 };
+
+        for coord in grid_cells(self.grid_size) {
+            let center_cell_type = marker.get(coord);
+            let center_pressure = sample_pressure(coord, center_cell_type);
+            for c in 0..3 {
+                let neighbor = coord + axis(c);
+                let neighbor_cell_type = marker.get(neighbor);
+                if center_cell_type == CELL_FLUID || neighbor_cell_type == CELL_FLUID {
+                    let mut velocity = self.velocity[c].get(coord);
+                    if neighbor_cell_type == CELL_SOLID {
+                        velocity = velocity.min(0.0);
+                    } else if center_cell_type == CELL_SOLID {
+                        velocity = velocity.max(0.0);
+                    } else {
+                        velocity -= center_pressure - sample_pressure(neighbor, neighbor_cell_type);
+                    }
+                    self.velocity[c].set(coord, velocity);
+                } else {
+                    self.velocity[c].set(coord, std::f32::NAN);
+                }
+            }
+        }
+    }
+
+    // extrapolate_velocity.comp
+    // Only ever writes velocities that aren't read by other cells, so updating in place is fine.
+    fn extrapolate_velocity(&mut self) {
+        let marker = &self.marker;
+        let is_valid_velocity =
+            |coord: GridCoord, component: usize| marker.get(coord) == CELL_FLUID || marker.get(coord + axis(component)) == CELL_FLUID;
+
+        for coord in grid_cells(self.grid_size) {
+            if marker.get(coord) == CELL_FLUID {
+                continue;
+            }
+            for c in 0..3 {
+                if marker.get(coord + axis(c)) == CELL_FLUID {
+                    continue;
+                }
+                // Direct and diagonal neighbors in the plane of the component axis.
+                let (inner_axis, outer_axis) = match c {
+                    0 => (1, 2),
+                    1 => (0, 2),
+                    _ => (0, 1),
+                };
+                let mut num_velocities = 0.0;
+                let mut velocity_sum = 0.0;
+                for outer in -1..=1 {
+                    for inner in -1..=1 {
+                        if inner == 0 && outer == 0 {
+                            continue;
+                        }
+                        let mut neighbor = coord;
+                        neighbor[inner_axis] += inner;
+                        neighbor[outer_axis] += outer;
+                        if is_valid_velocity(neighbor, c) {
+                            num_velocities += 1.0;
+                            velocity_sum += self.velocity[c].get(neighbor);
+                        }
+                    }
+                }
+                if num_velocities > 0.0 {
+                    self.velocity[c].set(coord, velocity_sum / num_velocities);
+                }
+            }
+        }
+    }
+
+    // advect_particles.comp
+    fn advect_particles(&mut self, simulation_delta: f32) {
+        let grid_size = self.grid_size.map(|x| x as f32);
+        let max_coord = self.grid_size - vec3(1, 1, 1);
+
+        for particle_index in 0..self.particle_positions.len() {
+            let original_position = self.particle_positions[particle_index].to_vec();
+            let offset_positions = [
+                (original_position - vec3(1.0, 0.5, 0.5)).map(|x| x.max(0.0)),
+                (original_position - vec3(0.5, 1.0, 0.5)).map(|x| x.max(0.0)),
+                (original_position - vec3(0.5, 0.5, 1.0)).map(|x| x.max(0.0)),
+            ];
+
+            // Corner i is at offset (i & 1, (i >> 1) & 1, (i >> 2) & 1), each holding the velocity of all three components.
+            let mut v = [Vector3::zero(); 8];
+            for (c, offset_position) in offset_positions.iter().enumerate() {
+                let volume_coord_min = to_grid_coord(*offset_position);
+                let volume_coord_max = vec3(
+                    (volume_coord_min.x + 1).min(max_coord.x),
+                    (volume_coord_min.y + 1).min(max_coord.y),
+                    (volume_coord_min.z + 1).min(max_coord.z),
+                );
+                for (corner, corner_velocity) in v.iter_mut().enumerate() {
+                    let coord = vec3(
+                        if corner & 1 == 0 { volume_coord_min.x } else { volume_coord_max.x },
+                        if corner & 2 == 0 { volume_coord_min.y } else { volume_coord_max.y },
+                        if corner & 4 == 0 { volume_coord_min.z } else { volume_coord_max.z },
+                    );
+                    corner_velocity[c] = self.velocity[c].get(coord);
+                }
+            }
+            let interpolate_trilinear = |interpolants_x: Vector3<f32>, interpolants_y: Vector3<f32>, interpolants_z: Vector3<f32>| {
+                mix(
+                    mix(mix(v[0], v[1], interpolants_x), mix(v[2], v[3], interpolants_x), interpolants_y),
+                    mix(mix(v[4], v[5], interpolants_x), mix(v[6], v[7], interpolants_x), interpolants_y),
+                    interpolants_z,
+                )
+            };
+
+            // Interpolants along one axis for all three component grids.
+            let interpolants_x = fract(vec3(offset_positions[0].x, offset_positions[1].x, offset_positions[2].x));
+            let interpolants_y = fract(vec3(offset_positions[0].y, offset_positions[1].y, offset_positions[2].y));
+            let interpolants_z = fract(vec3(offset_positions[0].z, offset_positions[1].z, offset_positions[2].z));
+            let v_x00 = mix(v[0], v[1], interpolants_x);
+            let v_x10 = mix(v[2], v[3], interpolants_x);
+            let v_x01 = mix(v[4], v[5], interpolants_x);
+            let v_x11 = mix(v[6], v[7], interpolants_x);
+            let v_xy0 = mix(v_x00, v_x10, interpolants_y);
+            let v_xy1 = mix(v_x01, v_x11, interpolants_y);
+            let new_velocity = mix(v_xy0, v_xy1, interpolants_z);
+
+            // APIC affine matrix, the jacobi matrix of the velocity. cx holds the derivatives along x of all three velocity components,
+            // every particle velocity buffer gets the gradient of its component.
+            let cx = mix(mix(v[1], v[3], interpolants_y), mix(v[5], v[7], interpolants_y), interpolants_z)
+                - mix(mix(v[0], v[2], interpolants_y), mix(v[4], v[6], interpolants_y), interpolants_z);
+            let cy = mix(v_x10, v_x11, interpolants_z) - mix(v_x00, v_x01, interpolants_z);
+            let cz = v_xy1 - v_xy0;
+
+            // Runge Kutta 4 confined to the current cell.
+            // Same as the shader: The step vector is added to the interpolants of each axis as a whole,
+            // i.e. the x interpolant of the y velocity grid gets moved by the y movement.
+            let rk_substep = |step: Vector3<f32>| {
+                interpolate_trilinear(
+                    saturate(interpolants_x + step),
+                    saturate(interpolants_y + step),
+                    saturate(interpolants_z + step),
+                )
+            };
+            let k1 = new_velocity;
+            let k2 = rk_substep(k1 * (simulation_delta * 0.5));
+            let k3 = rk_substep(k2 * (simulation_delta * 0.5));
+            let k4 = rk_substep(k3 * simulation_delta);
+            let total_movement = (k1 + (k2 + k3) * 2.0 + k4) * (simulation_delta * (1.0 / 6.0));
+
+            let new_position = (original_position + total_movement).zip(grid_size, |x, size| x.max(1.0001).min(size - 0.0001));
+
+            // Write new linked list & marker grid for density projection step.
+            self.marker.set(to_grid_coord(new_position), CELL_FLUID);
+            let nearest_dual_grid_cell = to_grid_coord(new_position - vec3(0.5, 0.5, 0.5));
+            self.particle_linked_list_next[particle_index] = self
+                .linked_list_dual_grid
+                .exchange(nearest_dual_grid_cell, particle_index as u32 + 1)
+                .wrapping_sub(1);
+
+            self.particle_positions[particle_index] = Point3::from_vec(new_position);
+            for c in 0..3 {
+                self.particle_velocities[c][particle_index] = match self.transfer_scheme {
+                    TransferScheme::Apic => Vector4::new(cx[c], cy[c], cz[c], new_velocity[c]),
+                    TransferScheme::Pic => Vector4::new(0.0, 0.0, 0.0, new_velocity[c]),
+                };
+            }
+        }
+    }
+
+    // density_projection_gather_error.comp, writes into the pressure solver's residual.
+    fn density_projection_gather_error(&mut self) {
+        // Normal density is defined by particles_per_cell evenly distributed particles in a cell.
+        let density0 = self.particles_per_cell as f32;
+        let solid_neighbor_contribution = 0.77 / 8.0 * density0;
+
+        for coord in grid_cells(self.grid_size) {
+            if self.marker.get(coord) != CELL_FLUID {
+                continue;
+            }
+
+            let sample_position = cell_center(coord);
+            let mut density = 0.0;
+            let positions = &self.particle_positions;
+            for_each_gathered_particle(
+                &self.linked_list_dual_grid,
+                &self.particle_linked_list_next,
+                self.particles_per_cell,
+                coord,
+                |particle_index| {
+                    density += tent_kernel(sample_position - positions[particle_index].to_vec());
+                },
+            );
+
+            // Order of the shader: px, py, pz, nx, ny, nz
+            let neighbor_markers = [
+                self.marker.get(coord + axis(0)),
+                self.marker.get(coord + axis(1)),
+                self.marker.get(coord + axis(2)),
+                self.marker.get(coord - axis(0)),
+                self.marker.get(coord - axis(1)),
+                self.marker.get(coord - axis(2)),
+            ];
+            for &neighbor_marker in neighbor_markers.iter() {
+                if neighbor_marker == CELL_SOLID {
+                    density += solid_neighbor_contribution;
+                }
+            }
+            if neighbor_markers.iter().any(|&m| m == CELL_AIR) {
+                density = density.max(density0);
+            }
+            density = density.max(density0 * 0.5).min(density0 * 1.5);
+
+            self.pressure_solver.residual.set(coord, density0 - density);
+        }
+    }
+
+    // density_projection_correct_particles.comp
+    fn density_projection_correct_particles(&mut self, simulation_delta: f32) {
+        let grid_size = self.grid_size.map(|x| x as f32);
+        let max_coord = self.grid_size - vec3(1, 1, 1);
+        let marker = &self.marker;
+        let pressure = &self.pressure_field_from_density.pressure;
+        let sample_pressure = |coord: GridCoord| if marker.get(coord) == CELL_FLUID { pressure.get(coord) } else { 0.0 };
+        let interpolate_bilinear = |p_00: f32, p_10: f32, p_01: f32, p_11: f32, interpolants: (f32, f32)| {
+            mix_scalar(
+                mix_scalar(p_00, p_10, interpolants.0),
+                mix_scalar(p_01, p_11, interpolants.0),
+                interpolants.1,
+            )
+        };
+
+        for position in self.particle_positions.iter_mut() {
+            let original_position = position.to_vec();
+            let offset_position = original_position - vec3(0.5, 0.5, 0.5);
+            // Everything in the 0 boundary is solid and has no pressure, see shader.
+            let volume_coord_min = to_grid_coord(offset_position.map(|x| x.max(1.0)));
+            let volume_coord_max = vec3(
+                (volume_coord_min.x + 1).min(max_coord.x),
+                (volume_coord_min.y + 1).min(max_coord.y),
+                (volume_coord_min.z + 1).min(max_coord.z),
+            );
+            let (min, max) = (volume_coord_min, volume_coord_max);
+
+            let p_000 = sample_pressure(min);
+            let p_100 = sample_pressure(vec3(max.x, min.y, min.z));
+            let p_010 = sample_pressure(vec3(min.x, max.y, min.z));
+            let p_110 = sample_pressure(vec3(max.x, max.y, min.z));
+            let p_001 = sample_pressure(vec3(min.x, min.y, max.z));
+            let p_101 = sample_pressure(vec3(max.x, min.y, max.z));
+            let p_011 = sample_pressure(vec3(min.x, max.y, max.z));
+            let p_111 = sample_pressure(max);
+
+            let i = fract(offset_position);
+            let gradient = vec3(
+                interpolate_bilinear(p_100, p_110, p_101, p_111, (i.y, i.z)) - interpolate_bilinear(p_000, p_010, p_001, p_011, (i.y, i.z)),
+                interpolate_bilinear(p_010, p_110, p_011, p_111, (i.x, i.z)) - interpolate_bilinear(p_000, p_100, p_001, p_101, (i.x, i.z)),
+                interpolate_bilinear(p_001, p_101, p_011, p_111, (i.x, i.y)) - interpolate_bilinear(p_000, p_100, p_010, p_110, (i.x, i.y)),
+            );
+
+            let total_movement = gradient * simulation_delta;
+            let new_position = (original_position + total_movement).zip(grid_size, |x, size| x.max(1.0001).min(size - 0.0001));
+            *position = Point3::from_vec(new_position);
+        }
+    }
+}
+
+impl FluidSimulation for CpuHybridFluid {
+    fn grid_dimension(&self) -> wgpu::Extent3d {
+        self.grid_dimension
+    }
+
+    fn num_particles(&self) -> u32 {
+        self.particle_positions.len() as u32
+    }
+
+    // Same sequence of passes as HybridFluid::step.
+    fn step(&mut self, simulation_delta: Duration) {
+        let delta = simulation_delta.as_secs_f32();
+
+        // transfer particle velocity to grid
+        for c in 0..3 {
+            self.transfer_clear(c);
+            self.transfer_build_linked_list(c);
+            if c == 0 {
+                self.transfer_set_boundary_marker();
+            }
+            self.transfer_gather_velocity(c, delta);
+        }
+        self.compute_divergence();
+        self.captured_divergence = self.pressure_solver.residual.clone();
+        self.captured_divergence_marker = self.marker.clone();
+
+        self.pressure_solver
+            .solve(simulation_delta, &mut self.pressure_field_from_velocity, &self.marker);
+
+        self.remove_divergence();
+        self.extrapolate_velocity();
+        self.transfer_clear(0);
+        self.advect_particles(delta);
+
+        self.transfer_set_boundary_marker();
+        self.density_projection_gather_error();
+        self.captured_density_error = self.pressure_solver.residual.clone();
+        self.debug_volumes_captured = true;
+
+        self.pressure_solver
+            .solve(simulation_delta, &mut self.pressure_field_from_density, &self.marker);
+        self.density_projection_correct_particles(delta);
+    }
+
+    fn read_particles(&self) -> Vec<ParticleState> {
+        self.particle_positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| ParticleState {
+                position,
+                velocity: vec3(
+                    self.particle_velocities[0][i].w,
+                    self.particle_velocities[1][i].w,
+                    self.particle_velocities[2][i].w,
+                ),
+            })
+            .collect()
+    }
+
+    // Same semantics as HybridFluid::read_volume.
+    fn read_volume(&self, volume: SimulationVolume) -> Option<Vec<f32>> {
+        if volume.requires_debug_capture() && !self.debug_volumes_captured {
+            return None;
+        }
+
+        let mask_non_fluid = |values: &Volume<f32>, marker: &Volume<f32>| -> Vec<f32> {
+            values
+                .values
+                .iter()
+                .zip(marker.values.iter())
+                .map(|(&value, &marker)| if marker == CELL_FLUID { value } else { 0.0 })
+                .collect()
+        };
+
+        Some(match volume {
+            SimulationVolume::VelocityX => self.velocity[0].values.clone(),
+            SimulationVolume::VelocityY => self.velocity[1].values.clone(),
+            SimulationVolume::VelocityZ => self.velocity[2].values.clone(),
+            SimulationVolume::Marker => self.marker.values.clone(),
+            SimulationVolume::PressureFromVelocity => self.pressure_field_from_velocity.pressure.values.clone(),
+            SimulationVolume::PressureFromDensity => self.pressure_field_from_density.pressure.values.clone(),
+            SimulationVolume::DensityError => mask_non_fluid(&self.captured_density_error, &self.marker),
+            SimulationVolume::Divergence => mask_non_fluid(&self.captured_divergence, &self.captured_divergence_marker),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scene::Scene, simulation_controller::SimulationController};
+    use std::path::Path;
+
+    const NUM_STEPS: usize = 4;
+
+    fn single_cell_debug_fluid(particles_per_cell: Option<u32>, transfer_scheme: Option<TransferScheme>) -> CpuHybridFluid {
+        let scene_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/single_cell_debug.json");
+        let (mut config, fluid_regions) = Scene::load_config(&scene_path).unwrap();
+        let fluid_config = &mut config.fluids[0];
+        fluid_config.particles_per_cell = particles_per_cell.unwrap_or(fluid_config.particles_per_cell);
+        fluid_config.transfer_scheme = transfer_scheme.unwrap_or(fluid_config.transfer_scheme);
+        fluid_config.create_cpu_reference_fluid(&fluid_regions[0], config.gravity, &config.simulation)
+    }
+
+    #[test]
+    fn single_cell_debug_scene_stays_valid() {
+        step_and_check_invariants(single_cell_debug_fluid(None, None));
+    }
+
+    #[test]
+    fn single_cell_debug_scene_stays_valid_with_apic() {
+        step_and_check_invariants(single_cell_debug_fluid(Some(27), Some(TransferScheme::Apic)));
+    }
+
+    fn step_and_check_invariants(mut fluid: CpuHybridFluid) {
+        let num_particles = fluid.num_particles();
+        assert!(num_particles > 0, "scene should start with particles");
+        let grid_dimension = fluid.grid_dimension();
+        let grid_size = vec3(grid_dimension.width as f32, grid_dimension.height as f32, grid_dimension.depth as f32);
+
+        let simulation_delta = SimulationController::new().timer().simulation_delta();
+        for step in 1..=NUM_STEPS {
+            fluid.step(simulation_delta);
+
+            assert_eq!(fluid.num_particles(), num_particles, "step {}: particle count changed", step);
+            for (i, particle) in fluid.read_particles().iter().enumerate() {
+                let position = particle.position.to_vec();
+                assert!(
+                    (0..3).all(|c| position[c] >= 1.0 && position[c] <= grid_size[c]),
+                    "step {}: particle {} left the grid: {:?}",
+                    step,
+                    i,
+                    particle.position
+                );
+                assert!(
+                    (0..3).all(|c| particle.velocity[c].is_finite()),
+                    "step {}: particle {} has velocity {:?}",
+                    step,
+                    i,
+                    particle.velocity
+                );
+            }
+
+            for stats in [fluid.pressure_solver_stats_velocity(), fluid.pressure_solver_stats_density()].iter() {
+                assert_eq!(stats.len(), step, "step {}: expected one solver statistic sample per step", step);
+                let last_sample = stats.back().unwrap();
+                assert!(last_sample.mse.is_finite(), "step {}: solver mse is {}", step, last_sample.mse);
+                assert!(last_sample.iteration_count >= 0);
+            }
+        }
+
+        for volume in [SimulationVolume::Divergence, SimulationVolume::DensityError].iter() {
+            assert!(fluid.read_volume(*volume).is_some(), "{} should be captured after a step", volume.name());
+        }
+    }
+}
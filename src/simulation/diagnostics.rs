@@ -0,0 +1,403 @@
+use crate::wgpu_utils::{self, binding_builder::*, binding_glsl, pipelines::*, shader::ShaderDirectory};
+use futures::*;
+use std::{collections::VecDeque, path::Path, pin::Pin, rc::Rc, time::Duration};
+
+// Layout of the reduced diagnostics on the gpu, see diagnostics.glsl
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DiagnosticsResult {
+    num_fluid_cells: u32,
+    num_particles: u32,
+    density_error_sum: f32,
+    density_error_max: f32,
+    num_fluid_cells_divergence: u32,
+    divergence_squared_sum: f32,
+    divergence_max: f32,
+    _dummy0: f32,
+
+    linear_momentum: cgmath::Vector3<f32>,
+    kinetic_energy: f32,
+    angular_momentum: cgmath::Vector3<f32>,
+    _dummy1: f32,
+}
+unsafe impl bytemuck::Pod for DiagnosticsResult {}
+unsafe impl bytemuck::Zeroable for DiagnosticsResult {}
+
+// Diagnostics of a single simulation step.
+#[derive(Copy, Clone)]
+pub struct DiagnosticsSample {
+    // Simulated time at the end of the step.
+    pub simulated_time: Duration,
+
+    pub num_fluid_cells: u32,
+    // Particles found in the grid's linked lists, i.e. particles that got lost in solid cells don't show up here.
+    pub num_particles: u32,
+    // Density error relative to the rest density, over all fluid cells after advection. Positive where the fluid is too sparse.
+    pub density_error_mean: f32,
+    pub density_error_max: f32,
+    // Divergence of the velocity grid after pressure projection (grid cells per second), over all fluid cells.
+    pub divergence_rms: f32,
+    pub divergence_max: f32,
+
+    // Particles have unit mass, all values are in grid space.
+    // Includes the contribution of the APIC affine velocity to energy & angular momentum.
+    pub kinetic_energy: f32,
+    pub linear_momentum: cgmath::Vector3<f32>,
+    // Around the grid origin.
+    pub angular_momentum: cgmath::Vector3<f32>,
+}
+
+impl Default for DiagnosticsSample {
+    fn default() -> Self {
+        DiagnosticsSample {
+            simulated_time: Duration::new(0, 0),
+            num_fluid_cells: 0,
+            num_particles: 0,
+            density_error_mean: 0.0,
+            density_error_max: 0.0,
+            divergence_rms: 0.0,
+            divergence_max: 0.0,
+            kinetic_energy: 0.0,
+            linear_momentum: cgmath::Zero::zero(),
+            angular_momentum: cgmath::Zero::zero(),
+        }
+    }
+}
+
+impl DiagnosticsSample {
+    // All values by name, for writing them to files.
+    pub fn named_values(&self) -> [(&'static str, f64); 14] {
+        [
+            ("simulated_time", self.simulated_time.as_secs_f64()),
+            ("num_fluid_cells", self.num_fluid_cells as f64),
+            ("num_particles", self.num_particles as f64),
+            ("density_error_mean", self.density_error_mean as f64),
+            ("density_error_max", self.density_error_max as f64),
+            ("divergence_rms", self.divergence_rms as f64),
+            ("divergence_max", self.divergence_max as f64),
+            ("kinetic_energy", self.kinetic_energy as f64),
+            ("linear_momentum_x", self.linear_momentum.x as f64),
+            ("linear_momentum_y", self.linear_momentum.y as f64),
+            ("linear_momentum_z", self.linear_momentum.z as f64),
+            ("angular_momentum_x", self.angular_momentum.x as f64),
+            ("angular_momentum_y", self.angular_momentum.y as f64),
+            ("angular_momentum_z", self.angular_momentum.z as f64),
+        ]
+    }
+}
+
+struct PendingDiagnosticsReadback {
+    copy_operation: Option<Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>>>>>,
+    buffer: wgpu::Buffer,
+    simulated_time: Duration,
+}
+
+const NUM_DIAGNOSTICS_READBACK_BUFFERS: usize = 32;
+
+// Reduces diagnostics of the simulation grid & particles every step and reads them back asynchronously.
+// Meant to keep track of how well the pressure projections do their job (volume conservation & divergence freedom)
+// and to compare transfer schemes and solvers (energy & momentum).
+// All dispatches expect per frame resources & simulation uniforms in bind group 0 & 1.
+pub struct SimulationDiagnostics {
+    grid_work_groups: wgpu::Extent3d,
+    num_volume_partials: u32,
+
+    bind_group_gather_volume: wgpu::BindGroup,
+    bind_group_gather_particles: wgpu::BindGroup,
+    bind_group_reduce: wgpu::BindGroup,
+    pipeline_gather_volume: ComputePipelineHandle,
+    pipeline_gather_particles: ComputePipelineHandle,
+    pipeline_reduce: ComputePipelineHandle,
+
+    result_buffer: wgpu::Buffer,
+    unused_readback_buffers: Vec<wgpu::Buffer>,
+    unscheduled_readbacks: Vec<PendingDiagnosticsReadback>,
+    pending_readbacks: VecDeque<PendingDiagnosticsReadback>,
+
+    total_simulated_time: Duration,
+    history: VecDeque<DiagnosticsSample>,
+    // Unlike the history, keeps all samples until they are taken. None if not recording.
+    recorded_samples: Option<Vec<DiagnosticsSample>>,
+}
+
+impl SimulationDiagnostics {
+    pub const HISTORY_LENGTH: usize = 100;
+
+    const GATHER_MODE_DIVERGENCE: u32 = 0;
+    const GATHER_MODE_DENSITY_ERROR: u32 = 1;
+
+    const COMPUTE_LOCAL_SIZE_PARTICLES: u32 = 64;
+    const COMPUTE_LOCAL_SIZE_GATHER_VOLUME: wgpu::Extent3d = wgpu::Extent3d {
+        width: 8,
+        height: 8,
+        depth: 8,
+    };
+
+    pub fn new(
+        device: &wgpu::Device,
+        grid_dimension: wgpu::Extent3d,
+        shader_dir: &ShaderDirectory,
+        pipeline_manager: &mut PipelineManager,
+        per_frame_bind_group_layout: &wgpu::BindGroupLayout,
+        group_layout_uniform: &wgpu::BindGroupLayout,
+        volume_marker_view: &wgpu::TextureView,
+        volume_velocity_views: [&wgpu::TextureView; 3],
+        volume_density_error_view: &wgpu::TextureView,
+        volume_linked_lists_view: &wgpu::TextureView,
+        particles_position_llindex: &wgpu::Buffer,
+        particles_velocity: [&wgpu::Buffer; 3],
+        max_num_particles: u32,
+    ) -> Self {
+        let grid_work_groups = wgpu_utils::compute_group_size(grid_dimension, Self::COMPUTE_LOCAL_SIZE_GATHER_VOLUME);
+        let num_volume_partials = grid_work_groups.width * grid_work_groups.height * grid_work_groups.depth;
+        let max_num_particle_partials = wgpu_utils::compute_group_size_1d(max_num_particles, Self::COMPUTE_LOCAL_SIZE_PARTICLES);
+        let result_size = std::mem::size_of::<DiagnosticsResult>() as u64;
+
+        // Volume and particle part are both half of the final result.
+        let volume_partials_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Diagnostics volume partials"),
+            size: num_volume_partials as u64 * result_size / 2,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+        let particle_partials_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Diagnostics particle partials"),
+            size: max_num_particle_partials.max(1) as u64 * result_size / 2,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer: Diagnostics result"),
+            size: result_size,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let unused_readback_buffers = (0..NUM_DIAGNOSTICS_READBACK_BUFFERS)
+            .map(|i| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("Buffer: Diagnostics read-back buffer {}", i)),
+                    size: result_size,
+                    usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        let group_layout_gather_volume = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::texture3D()) // marker volume
+            .next_binding_compute(binding_glsl::texture3D()) // velocityX
+            .next_binding_compute(binding_glsl::texture3D()) // velocityY
+            .next_binding_compute(binding_glsl::texture3D()) // velocityZ
+            .next_binding_compute(binding_glsl::texture3D()) // density error
+            .next_binding_compute(binding_glsl::utexture3D()) // linkedlist_volume
+            .next_binding_compute(binding_glsl::buffer(true)) // particles, position llindex
+            .next_binding_compute(binding_glsl::buffer(false)) // partials
+            .create(device, "BindGroupLayout: Diagnostics gather volume");
+        let group_layout_gather_particles = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(true)) // particles, position llindex
+            .next_binding_compute(binding_glsl::buffer(true)) // particles, velocityX
+            .next_binding_compute(binding_glsl::buffer(true)) // particles, velocityY
+            .next_binding_compute(binding_glsl::buffer(true)) // particles, velocityZ
+            .next_binding_compute(binding_glsl::buffer(false)) // partials
+            .create(device, "BindGroupLayout: Diagnostics gather particles");
+        let group_layout_reduce = BindGroupLayoutBuilder::new()
+            .next_binding_compute(binding_glsl::buffer(true)) // volume partials
+            .next_binding_compute(binding_glsl::buffer(true)) // particle partials
+            .next_binding_compute(binding_glsl::buffer(false)) // result
+            .create(device, "BindGroupLayout: Diagnostics reduce");
+
+        let bind_group_gather_volume = BindGroupBuilder::new(&group_layout_gather_volume)
+            .texture(volume_marker_view)
+            .texture(volume_velocity_views[0])
+            .texture(volume_velocity_views[1])
+            .texture(volume_velocity_views[2])
+            .texture(volume_density_error_view)
+            .texture(volume_linked_lists_view)
+            .resource(particles_position_llindex.as_entire_binding())
+            .resource(volume_partials_buffer.as_entire_binding())
+            .create(device, "BindGroup: Diagnostics gather volume");
+        let bind_group_gather_particles = BindGroupBuilder::new(&group_layout_gather_particles)
+            .resource(particles_position_llindex.as_entire_binding())
+            .resource(particles_velocity[0].as_entire_binding())
+            .resource(particles_velocity[1].as_entire_binding())
+            .resource(particles_velocity[2].as_entire_binding())
+            .resource(particle_partials_buffer.as_entire_binding())
+            .create(device, "BindGroup: Diagnostics gather particles");
+        let bind_group_reduce = BindGroupBuilder::new(&group_layout_reduce)
+            .resource(volume_partials_buffer.as_entire_binding())
+            .resource(particle_partials_buffer.as_entire_binding())
+            .resource(result_buffer.as_entire_binding())
+            .create(device, "BindGroup: Diagnostics reduce");
+
+        // Same push constant range as all other simulation pipelines.
+        let push_constant_ranges = &[wgpu::PushConstantRange {
+            stages: wgpu::ShaderStage::COMPUTE,
+            range: 0..8,
+        }];
+        let layout_gather_volume = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: Diagnostics gather volume"),
+            bind_group_layouts: &[per_frame_bind_group_layout, group_layout_uniform, &group_layout_gather_volume.layout],
+            push_constant_ranges,
+        }));
+        let layout_gather_particles = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: Diagnostics gather particles"),
+            bind_group_layouts: &[per_frame_bind_group_layout, group_layout_uniform, &group_layout_gather_particles.layout],
+            push_constant_ranges,
+        }));
+        let layout_reduce = Rc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PipelineLayout: Diagnostics reduce"),
+            bind_group_layouts: &[per_frame_bind_group_layout, group_layout_uniform, &group_layout_reduce.layout],
+            push_constant_ranges,
+        }));
+
+        SimulationDiagnostics {
+            grid_work_groups,
+            num_volume_partials,
+
+            bind_group_gather_volume,
+            bind_group_gather_particles,
+            bind_group_reduce,
+            pipeline_gather_volume: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Diagnostics: gather volume",
+                    layout_gather_volume,
+                    Path::new("simulation/diagnostics_gather_volume.comp"),
+                ),
+            ),
+            pipeline_gather_particles: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new(
+                    "Diagnostics: gather particles",
+                    layout_gather_particles,
+                    Path::new("simulation/diagnostics_gather_particles.comp"),
+                ),
+            ),
+            pipeline_reduce: pipeline_manager.create_compute_pipeline(
+                device,
+                shader_dir,
+                ComputePipelineCreationDesc::new("Diagnostics: reduce", layout_reduce, Path::new("simulation/diagnostics_reduce.comp")),
+            ),
+
+            result_buffer,
+            unused_readback_buffers,
+            unscheduled_readbacks: Vec::new(),
+            pending_readbacks: VecDeque::new(),
+
+            total_simulated_time: Duration::new(0, 0),
+            history: VecDeque::new(),
+            recorded_samples: None,
+        }
+    }
+
+    pub fn history(&self) -> &VecDeque<DiagnosticsSample> {
+        &self.history
+    }
+
+    // If enabled, all samples are kept until taken, independent of the history length.
+    pub fn set_recording(&mut self, enabled: bool) {
+        if enabled {
+            self.recorded_samples.get_or_insert_with(Vec::new);
+        } else {
+            self.recorded_samples = None;
+        }
+    }
+
+    pub fn take_recorded_samples(&mut self) -> Vec<DiagnosticsSample> {
+        match &mut self.recorded_samples {
+            Some(samples) => std::mem::replace(samples, Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
+    fn gather_volume<'a>(&'a self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager, mode: u32) {
+        cpass.set_bind_group(2, &self.bind_group_gather_volume, &[]);
+        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_gather_volume));
+        cpass.set_push_constants(0, bytemuck::bytes_of(&[mode, self.num_volume_partials]));
+        cpass.dispatch(self.grid_work_groups.width, self.grid_work_groups.height, self.grid_work_groups.depth);
+    }
+
+    // Call after the velocity grid was made divergence free, before the marker grid is cleared for advection.
+    pub fn gather_divergence<'a>(&'a self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager) {
+        self.gather_volume(cpass, pipeline_manager, Self::GATHER_MODE_DIVERGENCE);
+    }
+
+    // Call right after the density error was computed, before the pressure solver overwrites it.
+    pub fn gather_density_error<'a>(&'a self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager) {
+        self.gather_volume(cpass, pipeline_manager, Self::GATHER_MODE_DENSITY_ERROR);
+    }
+
+    // Call once particles are in their final state for this step.
+    pub fn gather_particles<'a>(&'a self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager, num_particles: u32) {
+        cpass.set_bind_group(2, &self.bind_group_gather_particles, &[]);
+        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_gather_particles));
+        cpass.dispatch(wgpu_utils::compute_group_size_1d(num_particles, Self::COMPUTE_LOCAL_SIZE_PARTICLES), 1, 1);
+    }
+
+    // Call after all gathers of a step.
+    pub fn reduce<'a>(&'a self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager) {
+        cpass.set_bind_group(2, &self.bind_group_reduce, &[]);
+        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_reduce));
+        cpass.set_push_constants(0, bytemuck::bytes_of(&[0, self.num_volume_partials]));
+        cpass.dispatch(1, 1, 1);
+    }
+
+    pub fn enqueue_readback(&mut self, encoder: &mut wgpu::CommandEncoder, simulation_delta: Duration) {
+        self.total_simulated_time += simulation_delta;
+
+        if let Some(target_buffer) = self.unused_readback_buffers.pop() {
+            encoder.copy_buffer_to_buffer(&self.result_buffer, 0, &target_buffer, 0, std::mem::size_of::<DiagnosticsResult>() as u64);
+            self.unscheduled_readbacks.push(PendingDiagnosticsReadback {
+                copy_operation: None, // Filled out in start_readbacks
+                buffer: target_buffer,
+                simulated_time: self.total_simulated_time,
+            });
+        } else {
+            warn!("No more read-back buffer available for async copy of simulation diagnostics");
+        }
+    }
+
+    // Call once all command buffers with enqueued readbacks were submitted.
+    pub fn start_readbacks(&mut self) {
+        for mut readback in self.unscheduled_readbacks.drain(..) {
+            readback.copy_operation = Some(readback.buffer.slice(..).map_async(wgpu::MapMode::Read).boxed());
+            self.pending_readbacks.push_back(readback);
+        }
+    }
+
+    pub fn retrieve_new_samples(&mut self, rest_density: f32) {
+        while let Some(mut readback) = self.pending_readbacks.pop_front() {
+            if (&mut readback.copy_operation.as_mut().unwrap()).now_or_never().is_some() {
+                let result = *bytemuck::from_bytes::<DiagnosticsResult>(&readback.buffer.slice(..).get_mapped_range());
+                readback.buffer.unmap();
+                self.unused_readback_buffers.push(readback.buffer);
+
+                let num_fluid_cells = result.num_fluid_cells.max(1) as f32;
+                let sample = DiagnosticsSample {
+                    simulated_time: readback.simulated_time,
+                    num_fluid_cells: result.num_fluid_cells,
+                    num_particles: result.num_particles,
+                    density_error_mean: result.density_error_sum / num_fluid_cells / rest_density,
+                    density_error_max: result.density_error_max / rest_density,
+                    divergence_rms: (result.divergence_squared_sum / result.num_fluid_cells_divergence.max(1) as f32).sqrt(),
+                    divergence_max: result.divergence_max,
+                    kinetic_energy: result.kinetic_energy,
+                    linear_momentum: result.linear_momentum,
+                    angular_momentum: result.angular_momentum,
+                };
+                if let Some(recorded_samples) = &mut self.recorded_samples {
+                    recorded_samples.push(sample);
+                }
+                self.history.push_back(sample);
+                while self.history.len() > Self::HISTORY_LENGTH {
+                    self.history.pop_front();
+                }
+            } else {
+                self.pending_readbacks.push_front(readback);
+                break;
+            }
+        }
+    }
+}
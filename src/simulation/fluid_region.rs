@@ -0,0 +1,413 @@
+// Regions of space that are filled with fluid particles when a scene starts.
+//
+// All coordinates are in grid space, velocities in grid cells per second.
+// Conversion from world space happens when loading the scene.
+
+use cgmath::{prelude::*, Point3, Quaternion, Vector3};
+use rand::prelude::*;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+// Closed triangle mesh that supports inside/outside tests.
+pub struct ClosedMesh {
+    triangles: Vec<[Point3<f32>; 3]>,
+    min: Point3<f32>,
+    max: Point3<f32>,
+
+    // Triangle indices for every unit cell on the yz-plane that the triangle's bounding box overlaps.
+    // Inside tests cast a ray along +x, so they only need to look at a single bucket.
+    buckets: Vec<Vec<u32>>,
+    bucket_min: (i32, i32),
+    bucket_count: (usize, usize),
+}
+
+impl ClosedMesh {
+    pub fn new(triangles: Vec<[Point3<f32>; 3]>) -> Self {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for vertex in triangles.iter().flatten() {
+            min = Point3::new(min.x.min(vertex.x), min.y.min(vertex.y), min.z.min(vertex.z));
+            max = Point3::new(max.x.max(vertex.x), max.y.max(vertex.y), max.z.max(vertex.z));
+        }
+        if triangles.is_empty() {
+            min = Point3::origin();
+            max = Point3::origin();
+        }
+
+        let bucket_min = (min.y.floor() as i32, min.z.floor() as i32);
+        let bucket_count = (
+            (max.y.floor() as i32 - bucket_min.0 + 1) as usize,
+            (max.z.floor() as i32 - bucket_min.1 + 1) as usize,
+        );
+        let mut buckets = vec![Vec::new(); bucket_count.0 * bucket_count.1];
+        for (triangle_index, triangle) in triangles.iter().enumerate() {
+            let min_y = triangle.iter().map(|v| v.y).fold(f32::MAX, f32::min).floor() as i32 - bucket_min.0;
+            let max_y = triangle.iter().map(|v| v.y).fold(f32::MIN, f32::max).floor() as i32 - bucket_min.0;
+            let min_z = triangle.iter().map(|v| v.z).fold(f32::MAX, f32::min).floor() as i32 - bucket_min.1;
+            let max_z = triangle.iter().map(|v| v.z).fold(f32::MIN, f32::max).floor() as i32 - bucket_min.1;
+            for z in min_z..=max_z {
+                for y in min_y..=max_y {
+                    buckets[y as usize + z as usize * bucket_count.0].push(triangle_index as u32);
+                }
+            }
+        }
+
+        ClosedMesh {
+            triangles,
+            min,
+            max,
+            buckets,
+            bucket_min,
+            bucket_count,
+        }
+    }
+
+    // Loads all faces of a Wavefront OBJ file and applies a uniform scale followed by a translation to every vertex.
+    // Polygons are triangulated as fans, everything but vertex positions and faces is ignored.
+    pub fn load_obj(path: &Path, scale: f32, translation: Vector3<f32>) -> Result<Self, io::Error> {
+        let invalid_data = |line_number: usize, message: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{:?} line {}: {}", path, line_number + 1, message))
+        };
+
+        let reader = BufReader::new(File::open(path)?);
+        let mut vertices: Vec<Point3<f32>> = Vec::new();
+        let mut triangles = Vec::new();
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let coordinates: Vec<f32> = tokens
+                        .take(3)
+                        .map(|token| token.parse::<f32>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid_data(line_number, "invalid vertex position"))?;
+                    if coordinates.len() != 3 {
+                        return Err(invalid_data(line_number, "vertex position needs three coordinates"));
+                    }
+                    vertices.push(Point3::new(coordinates[0], coordinates[1], coordinates[2]) * scale + translation);
+                }
+                Some("f") => {
+                    // Face entries look like "v", "v/vt", "v//vn" or "v/vt/vn". Indices start at 1, negative indices are relative to the end.
+                    let indices: Vec<usize> = tokens
+                        .map(|token| {
+                            let index = token.split('/').next().and_then(|index| index.parse::<i64>().ok());
+                            match index {
+                                Some(index) if index > 0 && index as usize <= vertices.len() => Ok(index as usize - 1),
+                                Some(index) if index < 0 && (-index) as usize <= vertices.len() => Ok(vertices.len() - (-index) as usize),
+                                _ => Err(invalid_data(line_number, "invalid face vertex index")),
+                            }
+                        })
+                        .collect::<Result<_, _>>()?;
+                    if indices.len() < 3 {
+                        return Err(invalid_data(line_number, "face needs at least three vertices"));
+                    }
+                    for edge in indices[1..].windows(2) {
+                        triangles.push([vertices[indices[0]], vertices[edge[0]], vertices[edge[1]]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if triangles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} contains no faces", path)));
+        }
+        Ok(Self::new(triangles))
+    }
+
+    // Counts intersections of a ray along +x. An odd count means the point is inside.
+    // Points exactly on edges may be counted twice, but jittered particle positions practically never hit those.
+    pub fn contains(&self, p: Point3<f32>) -> bool {
+        if p.x < self.min.x || p.y < self.min.y || p.z < self.min.z || p.x > self.max.x || p.y > self.max.y || p.z > self.max.z {
+            return false;
+        }
+        let bucket_y = (p.y.floor() as i32 - self.bucket_min.0) as usize;
+        let bucket_z = (p.z.floor() as i32 - self.bucket_min.1) as usize;
+        if bucket_y >= self.bucket_count.0 || bucket_z >= self.bucket_count.1 {
+            return false;
+        }
+
+        let mut num_intersections = 0;
+        for &triangle_index in self.buckets[bucket_y + bucket_z * self.bucket_count.0].iter() {
+            let [a, b, c] = self.triangles[triangle_index as usize];
+
+            // Barycentric coordinates of p projected onto the yz-plane.
+            let edge_function = |v0: Point3<f32>, v1: Point3<f32>| (v1.y - v0.y) * (p.z - v0.z) - (v1.z - v0.z) * (p.y - v0.y);
+            let area = (b.y - a.y) * (c.z - a.z) - (b.z - a.z) * (c.y - a.y);
+            if area == 0.0 {
+                continue;
+            }
+            let u = edge_function(b, c) / area;
+            let v = edge_function(c, a) / area;
+            let w = edge_function(a, b) / area;
+            if u < 0.0 || v < 0.0 || w < 0.0 {
+                continue;
+            }
+
+            let x = u * a.x + v * b.x + w * c.x;
+            if x > p.x {
+                num_intersections += 1;
+            }
+        }
+        num_intersections % 2 == 1
+    }
+}
+
+pub enum FluidShape {
+    // Axis aligned box. Snapped to whole grid cells, which are then completely filled.
+    Box {
+        min: Point3<f32>,
+        max: Point3<f32>,
+    },
+    OrientedBox {
+        center: Point3<f32>,
+        half_extent: Vector3<f32>,
+        rotation: Quaternion<f32>,
+    },
+    Sphere {
+        center: Point3<f32>,
+        radius: f32,
+    },
+    // axis is normalized, height is measured along it and centered on center.
+    Cylinder {
+        center: Point3<f32>,
+        axis: Vector3<f32>,
+        radius: f32,
+        height: f32,
+    },
+    Mesh(ClosedMesh),
+}
+
+impl FluidShape {
+    pub fn bounds(&self) -> (Point3<f32>, Point3<f32>) {
+        match self {
+            FluidShape::Box { min, max } => (*min, *max),
+            FluidShape::OrientedBox {
+                center,
+                half_extent,
+                rotation,
+            } => {
+                // Extent of the rotated box along each world axis.
+                let axes = [
+                    rotation.rotate_vector(Vector3::unit_x() * half_extent.x),
+                    rotation.rotate_vector(Vector3::unit_y() * half_extent.y),
+                    rotation.rotate_vector(Vector3::unit_z() * half_extent.z),
+                ];
+                let extent = axes.iter().fold(Vector3::zero(), |extent: Vector3<f32>, axis| {
+                    extent + Vector3::new(axis.x.abs(), axis.y.abs(), axis.z.abs())
+                });
+                (*center - extent, *center + extent)
+            }
+            FluidShape::Sphere { center, radius } => (
+                *center - Vector3::new(*radius, *radius, *radius),
+                *center + Vector3::new(*radius, *radius, *radius),
+            ),
+            FluidShape::Cylinder {
+                center,
+                axis,
+                radius,
+                height,
+            } => {
+                // Bounds of the two cap discs.
+                let cap_extent = Vector3::new(
+                    radius * (1.0 - axis.x * axis.x).max(0.0).sqrt(),
+                    radius * (1.0 - axis.y * axis.y).max(0.0).sqrt(),
+                    radius * (1.0 - axis.z * axis.z).max(0.0).sqrt(),
+                );
+                let half_axis = *axis * (height * 0.5);
+                let extent = Vector3::new(half_axis.x.abs(), half_axis.y.abs(), half_axis.z.abs()) + cap_extent;
+                (*center - extent, *center + extent)
+            }
+            FluidShape::Mesh(mesh) => (mesh.min, mesh.max),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        match self {
+            FluidShape::OrientedBox { center, .. } | FluidShape::Sphere { center, .. } | FluidShape::Cylinder { center, .. } => *center,
+            FluidShape::Box { .. } | FluidShape::Mesh(..) => {
+                let (min, max) = self.bounds();
+                min.midpoint(max)
+            }
+        }
+    }
+
+    pub fn contains(&self, p: Point3<f32>) -> bool {
+        match self {
+            FluidShape::Box { min, max } => p.x >= min.x && p.y >= min.y && p.z >= min.z && p.x < max.x && p.y < max.y && p.z < max.z,
+            FluidShape::OrientedBox {
+                center,
+                half_extent,
+                rotation,
+            } => {
+                let local = rotation.conjugate().rotate_vector(p - *center);
+                local.x.abs() <= half_extent.x && local.y.abs() <= half_extent.y && local.z.abs() <= half_extent.z
+            }
+            FluidShape::Sphere { center, radius } => (p - *center).magnitude2() <= radius * radius,
+            FluidShape::Cylinder {
+                center,
+                axis,
+                radius,
+                height,
+            } => {
+                let to_p = p - *center;
+                let along_axis = to_p.dot(*axis);
+                along_axis.abs() <= height * 0.5 && (to_p - *axis * along_axis).magnitude2() <= radius * radius
+            }
+            FluidShape::Mesh(mesh) => mesh.contains(p),
+        }
+    }
+}
+
+// A shape filled with fluid that moves like a rigid body at the start.
+pub struct FluidRegion {
+    pub shape: FluidShape,
+    pub linear_velocity: Vector3<f32>,
+    // Rotation around the center of the shape in radians per second.
+    pub angular_velocity: Vector3<f32>,
+}
+
+impl FluidRegion {
+    pub fn velocity_at(&self, position: Point3<f32>) -> Vector3<f32> {
+        self.linear_velocity + self.angular_velocity.cross(position - self.shape.center())
+    }
+
+    fn clamp_to_grid(grid_dimension: wgpu::Extent3d, grid_cor: Point3<f32>) -> Point3<u32> {
+        // Due to the design of the grid, the 0-1 range is reserved by solid cells and can't be filled.
+        Point3::new(
+            grid_dimension.width.min(grid_cor.x as u32).max(1),
+            grid_dimension.height.min(grid_cor.y as u32).max(1),
+            grid_dimension.depth.min(grid_cor.z as u32).max(1),
+        )
+    }
+
+    // Sample within a cell: for cube numbers of particles per cell the cell is split into cbrt(particles_per_cell)^3 strata
+    // with one jittered particle each, any other count falls back to uniformly random positions within the cell.
+    fn stratified_sample(cell: Point3<f32>, particles_per_cell: u32, sample_idx: u32, rng: &mut rand::rngs::SmallRng) -> Point3<f32> {
+        // Strata only divide the cell evenly for cube numbers (8 = 2x2x2, 27 = 3x3x3, ...)
+        let strata_per_axis = (particles_per_cell as f32).cbrt().round() as u32;
+        if strata_per_axis.pow(3) != particles_per_cell {
+            // pure random
+            return cell + rng.gen::<Vector3<f32>>();
+        }
+        // pure regular
+        // let offset = (cgmath::vec3(
+        //     (sample_idx % strata_per_axis) as f32,
+        //     (sample_idx / strata_per_axis % strata_per_axis) as f32,
+        //     (sample_idx / strata_per_axis / strata_per_axis) as f32,
+        // ) + cgmath::vec3(0.5, 0.5, 0.5)) / strata_per_axis as f32;
+        // stratified
+        let offset = (cgmath::vec3(
+            (sample_idx % strata_per_axis) as f32,
+            (sample_idx / strata_per_axis % strata_per_axis) as f32,
+            (sample_idx / strata_per_axis / strata_per_axis) as f32,
+        ) + rng.gen::<Vector3<f32>>())
+            / strata_per_axis as f32;
+        cell + offset
+    }
+
+    // Number of particles sample_particles produces when there is no limit on the particle count.
+    pub fn count_particles(&self, grid_dimension: wgpu::Extent3d, particles_per_cell: u32, num_existing_particles: u32) -> u64 {
+        match self.shape {
+            FluidShape::Box { min, max } => {
+                let extent_cell = Self::clamp_to_grid(grid_dimension, max) - Self::clamp_to_grid(grid_dimension, min);
+                extent_cell.x as u64 * extent_cell.y as u64 * extent_cell.z as u64 * particles_per_cell as u64
+            }
+            _ => self.sample_shape(grid_dimension, particles_per_cell, num_existing_particles).len() as u64,
+        }
+    }
+
+    fn log_particle_overflow(num_new_particles: u64, num_existing_particles: u32, max_num_particles: u32) {
+        error!(
+            "Can't add {} particles, max is {}, current is {}",
+            num_new_particles, max_num_particles, num_existing_particles
+        );
+    }
+
+    // Generates the particle positions for this region.
+    // Shared with the cpu reference implementation so both start out with exactly the same particles.
+    pub(super) fn sample_particles(
+        &self,
+        grid_dimension: wgpu::Extent3d,
+        particles_per_cell: u32,
+        num_existing_particles: u32,
+        max_num_particles: u32,
+    ) -> Vec<Point3<f32>> {
+        match self.shape {
+            FluidShape::Box { min, max } => {
+                Self::sample_cells(grid_dimension, particles_per_cell, num_existing_particles, max_num_particles, min, max)
+            }
+            _ => {
+                let mut particles = self.sample_shape(grid_dimension, particles_per_cell, num_existing_particles);
+                let num_free_particles = max_num_particles.saturating_sub(num_existing_particles);
+                if (num_free_particles as usize) < particles.len() {
+                    Self::log_particle_overflow(particles.len() as u64, num_existing_particles, max_num_particles);
+                    particles.truncate(num_free_particles as usize);
+                }
+                particles
+            }
+        }
+    }
+
+    // Fills all cells between min and max completely.
+    fn sample_cells(
+        grid_dimension: wgpu::Extent3d,
+        particles_per_cell: u32,
+        num_existing_particles: u32,
+        max_num_particles: u32,
+        min_grid: Point3<f32>,
+        max_grid: Point3<f32>,
+    ) -> Vec<Point3<f32>> {
+        // align to whole cells for simplicity.
+        let min_grid = Self::clamp_to_grid(grid_dimension, min_grid);
+        let max_grid = Self::clamp_to_grid(grid_dimension, max_grid);
+        let extent_cell = max_grid - min_grid;
+
+        let num_particles = extent_cell.x as u64 * extent_cell.y as u64 * extent_cell.z as u64 * particles_per_cell as u64;
+        let num_free_particles = max_num_particles.saturating_sub(num_existing_particles);
+        if (num_free_particles as u64) < num_particles {
+            Self::log_particle_overflow(num_particles, num_existing_particles, max_num_particles);
+        }
+        let num_new_particles = num_particles.min(num_free_particles as u64) as u32;
+
+        let mut rng: rand::rngs::SmallRng = rand::SeedableRng::seed_from_u64((num_existing_particles + num_new_particles) as u64);
+        (0..num_new_particles)
+            .map(|i| {
+                let cell = cgmath::point3(
+                    (min_grid.x + i / particles_per_cell % extent_cell.x) as f32,
+                    (min_grid.y + i / particles_per_cell / extent_cell.x % extent_cell.y) as f32,
+                    (min_grid.z + i / particles_per_cell / extent_cell.x / extent_cell.y) as f32,
+                );
+                Self::stratified_sample(cell, particles_per_cell, i % particles_per_cell, &mut rng)
+            })
+            .collect()
+    }
+
+    // Samples all cells touched by the shape's bounds and keeps only the particles inside the shape.
+    fn sample_shape(&self, grid_dimension: wgpu::Extent3d, particles_per_cell: u32, num_existing_particles: u32) -> Vec<Point3<f32>> {
+        let (min_bound, max_bound) = self.shape.bounds();
+        let min_grid = Self::clamp_to_grid(grid_dimension, min_bound);
+        let max_grid = Self::clamp_to_grid(grid_dimension, max_bound.map(f32::ceil));
+
+        let mut rng: rand::rngs::SmallRng = rand::SeedableRng::seed_from_u64(num_existing_particles as u64);
+        let mut particles = Vec::new();
+        for z in min_grid.z..max_grid.z {
+            for y in min_grid.y..max_grid.y {
+                for x in min_grid.x..max_grid.x {
+                    let cell = cgmath::point3(x as f32, y as f32, z as f32);
+                    for sample_idx in 0..particles_per_cell {
+                        let position = Self::stratified_sample(cell, particles_per_cell, sample_idx, &mut rng);
+                        if self.shape.contains(position) {
+                            particles.push(position);
+                        }
+                    }
+                }
+            }
+        }
+        particles
+    }
+}
@@ -0,0 +1,64 @@
+use super::{HybridFluid, ParticleState, SimulationVolume};
+use crate::wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler};
+use std::time::Duration;
+
+// Common interface of the gpu fluid and its cpu reference implementation.
+// Everything is in grid space, volumes are x varying fastest.
+pub trait FluidSimulation {
+    fn grid_dimension(&self) -> wgpu::Extent3d;
+    fn num_particles(&self) -> u32;
+
+    // Performs a single simulation step and waits until it is done.
+    fn step(&mut self, simulation_delta: Duration);
+
+    fn read_particles(&self) -> Vec<ParticleState>;
+    // Returns None for volumes that aren't available (yet), see SimulationVolume::requires_debug_capture.
+    fn read_volume(&self, volume: SimulationVolume) -> Option<Vec<f32>>;
+}
+
+// Bundles a HybridFluid with everything it needs to be stepped and read back on its own.
+// Note that the shaders take the simulation delta from the per frame resources,
+// so the per frame bind group needs to hold the same delta that is passed to step!
+pub struct GpuFluidSimulation<'a> {
+    pub fluid: &'a mut HybridFluid,
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub pipeline_manager: &'a PipelineManager,
+    pub per_frame_bind_group: &'a wgpu::BindGroup,
+    pub profiler: &'a mut GpuProfiler,
+}
+
+impl<'a> FluidSimulation for GpuFluidSimulation<'a> {
+    fn grid_dimension(&self) -> wgpu::Extent3d {
+        self.fluid.grid_dimension()
+    }
+
+    fn num_particles(&self) -> u32 {
+        self.fluid.num_particles()
+    }
+
+    fn step(&mut self, simulation_delta: Duration) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Encoder: Fluid Step"),
+        });
+        self.fluid.step(
+            simulation_delta,
+            &mut encoder,
+            self.pipeline_manager,
+            self.queue,
+            self.per_frame_bind_group,
+            self.profiler,
+        );
+        self.queue.submit(Some(encoder.finish()));
+        self.fluid.update_statistics();
+        self.device.poll(wgpu::Maintain::Wait);
+    }
+
+    fn read_particles(&self) -> Vec<ParticleState> {
+        self.fluid.read_particles(self.device, self.queue)
+    }
+
+    fn read_volume(&self, volume: SimulationVolume) -> Option<Vec<f32>> {
+        self.fluid.read_volume(self.device, self.queue, volume)
+    }
+}
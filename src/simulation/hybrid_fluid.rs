@@ -1,11 +1,15 @@
+use super::diagnostics::*;
+use super::fluid_region::FluidRegion;
 use super::pressure_solver::*;
 use crate::wgpu_utils;
 use crate::wgpu_utils::binding_builder::*;
 use crate::wgpu_utils::binding_glsl;
 use crate::wgpu_utils::pipelines::*;
+use crate::wgpu_utils::profiler::GpuProfiler;
+use crate::wgpu_utils::readback;
 use crate::wgpu_utils::shader::*;
 use crate::wgpu_utils::uniformbuffer::*;
-use rand::prelude::*;
+use serde::Deserialize;
 use std::{collections::VecDeque, path::Path, rc::Rc, time::Duration};
 
 #[repr(C)]
@@ -13,16 +17,64 @@ use std::{collections::VecDeque, path::Path, rc::Rc, time::Duration};
 struct SimulationPropertiesUniformBufferContent {
     gravity_grid: cgmath::Vector3<f32>,
     num_particles: u32,
+    particles_per_cell: u32,
+    transfer_scheme: u32,
+    padding: [u32; 2],
 }
 unsafe impl bytemuck::Pod for SimulationPropertiesUniformBufferContent {}
 unsafe impl bytemuck::Zeroable for SimulationPropertiesUniformBufferContent {}
 
+// Placement of the fluid in the world, only relevant for rendering.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RenderInfoUniformBufferContent {
+    world_position: cgmath::Point3<f32>,
+    grid_to_world_scale: f32,
+    // Average distance between particles at rest in grid cells.
+    particle_spacing: f32,
+    padding: [f32; 3],
+}
+unsafe impl bytemuck::Pod for RenderInfoUniformBufferContent {}
+unsafe impl bytemuck::Zeroable for RenderInfoUniformBufferContent {}
+
+/// How velocity is transferred between particles and grid.
+// Values need to match the TRANSFER_SCHEME defines in hybrid_fluid.glsl
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferScheme {
+    /// Particles only carry their velocity, very dissipative.
+    Pic = 0,
+    /// Particles additionally carry the local velocity gradient (affine matrix), preserving rotational motion.
+    Apic = 1,
+}
+
+impl Default for TransferScheme {
+    fn default() -> Self {
+        TransferScheme::Pic
+    }
+}
+
+/// Particle/grid hybrid fluid simulation (PIC/APIC transfer, pressure projection for divergence & density) of a single grid, runs entirely on the gpu.
+/// Everything is in grid space: the grid spans from (0, 0, 0) to its dimension with the first layer of cells being solid.
 pub struct HybridFluid {
     grid_dimension: wgpu::Extent3d,
 
     pressure_solver: PressureSolver,
     pressure_field_from_velocity: PressureField,
     pressure_field_from_density: PressureField,
+    diagnostics: SimulationDiagnostics,
+
+    volume_velocity_x: wgpu::Texture,
+    volume_velocity_y: wgpu::Texture,
+    volume_velocity_z: wgpu::Texture,
+    volume_marker: wgpu::Texture,
+
+    // Copies of intermediate states that are overwritten during a step, only updated if debug volume capture is enabled.
+    volume_captured_divergence: wgpu::Texture,
+    volume_captured_divergence_marker: wgpu::Texture,
+    volume_captured_density_error: wgpu::Texture,
+    debug_volume_capture_enabled: bool,
+    debug_volumes_captured: bool,
 
     particles_position_llindex: wgpu::Buffer,
     particles_velocity_x: wgpu::Buffer,
@@ -30,6 +82,7 @@ pub struct HybridFluid {
     particles_velocity_z: wgpu::Buffer,
     simulation_properties_uniformbuffer: UniformBuffer<SimulationPropertiesUniformBufferContent>,
     simulation_properties: SimulationPropertiesUniformBufferContent,
+    render_info_uniformbuffer: UniformBuffer<RenderInfoUniformBufferContent>,
 
     bind_group_uniform: wgpu::BindGroup,
     bind_group_transfer_velocity: [wgpu::BindGroup; 3],
@@ -69,43 +122,125 @@ struct ParticlePositionLl {
 unsafe impl bytemuck::Pod for ParticlePositionLl {}
 unsafe impl bytemuck::Zeroable for ParticlePositionLl {}
 
+// Grid volumes that can be read back from a HybridFluid.
+#[derive(Clone, Copy, Debug, EnumIter, PartialEq, Eq)]
+pub enum SimulationVolume {
+    VelocityX,
+    VelocityY,
+    VelocityZ,
+    Marker,
+    PressureFromVelocity,
+    PressureFromDensity,
+    DensityError,
+    Divergence,
+}
+
+impl SimulationVolume {
+    pub fn name(self) -> &'static str {
+        match self {
+            SimulationVolume::VelocityX => "velocity_x",
+            SimulationVolume::VelocityY => "velocity_y",
+            SimulationVolume::VelocityZ => "velocity_z",
+            SimulationVolume::Marker => "marker",
+            SimulationVolume::PressureFromVelocity => "pressure_from_velocity",
+            SimulationVolume::PressureFromDensity => "pressure_from_density",
+            SimulationVolume::DensityError => "density_error",
+            SimulationVolume::Divergence => "divergence",
+        }
+    }
+
+    // Grid space position of the sample with index (0, 0, 0).
+    // Velocities are stored staggered on the positive faces of a cell, everything else at the cell center.
+    pub fn sample_origin_grid(self) -> cgmath::Point3<f32> {
+        match self {
+            SimulationVolume::VelocityX => cgmath::point3(1.0, 0.5, 0.5),
+            SimulationVolume::VelocityY => cgmath::point3(0.5, 1.0, 0.5),
+            SimulationVolume::VelocityZ => cgmath::point3(0.5, 0.5, 1.0),
+            _ => cgmath::point3(0.5, 0.5, 0.5),
+        }
+    }
+
+    // Whether this volume is only available if debug volume capture was enabled during the last step.
+    pub fn requires_debug_capture(self) -> bool {
+        match self {
+            SimulationVolume::DensityError | SimulationVolume::Divergence => true,
+            _ => false,
+        }
+    }
+}
+
+// Cpu side copy of a single particle, as returned by HybridFluid::read_particles.
+#[derive(Clone, Copy)]
+pub struct ParticleState {
+    // In grid space, same as on the gpu.
+    pub position: cgmath::Point3<f32>,
+    // In grid cells per second.
+    pub velocity: cgmath::Vector3<f32>,
+}
+
 impl HybridFluid {
-    // particles are distributed 2x2x2 within a single gridcell
-    // (seems to be widely accepted as the default. Houdini seems to have this configurable from 4-16, maybe worth experimenting with it! Note however, that the density error computation assumes this constant as well!)
-    pub const PARTICLES_PER_GRID_CELL: u32 = 8;
+    // By default particles are distributed 2x2x2 within a single gridcell
+    // (seems to be widely accepted as the default. Houdini seems to have this configurable from 4-16)
+    pub const DEFAULT_PARTICLES_PER_GRID_CELL: u32 = 8;
+    // The gather shaders keep particles of a whole dual cell in shared memory, too many particles per cell get expensive quickly.
+    pub const MAX_PARTICLES_PER_GRID_CELL: u32 = 27;
+
+    pub(super) const DEFAULT_SOLVER_CONFIG_VELOCITY: SolverConfig = SolverConfig {
+        target_mse: 0.5,
+        mse_check_frequency: 4,
+        max_num_iterations: 32,
+    };
+    pub(super) const DEFAULT_SOLVER_CONFIG_DENSITY: SolverConfig = SolverConfig {
+        target_mse: 0.05,
+        mse_check_frequency: 4,
+        max_num_iterations: 16,
+    };
 
+    /// Creates an empty fluid. Compute pipelines are created from the shaders in shader_dir,
+    /// per_frame_bind_group_layout is the layout of the bind group passed to `step` (see PerFrameResources).
+    /// particles_per_cell is the number of particles a fluid cell starts out with and defines the rest density.
     pub fn new(
         device: &wgpu::Device,
         grid_dimension: wgpu::Extent3d,
         max_num_particles: u32,
+        particles_per_cell: u32,
         shader_dir: &ShaderDirectory,
         pipeline_manager: &mut PipelineManager,
         per_frame_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> Self {
         // Resources
         let simulation_properties_uniformbuffer = UniformBuffer::new(device);
+        let render_info_uniformbuffer = UniformBuffer::new_with_data(
+            device,
+            &RenderInfoUniformBufferContent {
+                world_position: cgmath::point3(0.0, 0.0, 0.0),
+                grid_to_world_scale: 1.0,
+                particle_spacing: Self::particle_spacing(particles_per_cell),
+                padding: [0.0; 3],
+            },
+        );
         let particles_position_llindex = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles position & llindex"),
             size: max_num_particles as u64 * std::mem::size_of::<ParticlePositionLl>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::COPY_SRC,
             mapped_at_creation: false,
         });
         let particles_velocity_x = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles velocity X"),
             size: max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::COPY_SRC,
             mapped_at_creation: false,
         });
         let particles_velocity_y = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles velocity Y"),
             size: max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::COPY_SRC,
             mapped_at_creation: false,
         });
         let particles_velocity_z = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Buffer: Particles velocity Z"),
             size: max_num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64,
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::COPY_SRC,
             mapped_at_creation: false,
         });
 
@@ -128,7 +263,18 @@ impl HybridFluid {
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D3,
                 format,
-                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::STORAGE,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC,
+            }
+        };
+        let create_capture_volume_texture_desc = |label: &'static str, format: wgpu::TextureFormat| -> wgpu::TextureDescriptor {
+            wgpu::TextureDescriptor {
+                label: Some(label),
+                size: grid_dimension,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format,
+                usage: wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::COPY_SRC,
             }
         };
         // TODO: Reuse volumes to safe memory, not all are used simultaneously.
@@ -137,6 +283,16 @@ impl HybridFluid {
         let volume_velocity_z = device.create_texture(&create_volume_texture_desc("Velocity Volume Z", wgpu::TextureFormat::R32Float));
         let volume_linked_lists = device.create_texture(&create_volume_texture_desc("Linked Lists Volume", wgpu::TextureFormat::R32Uint));
         let volume_marker_primary = device.create_texture(&create_volume_texture_desc("Marker Grid", wgpu::TextureFormat::R8Snorm));
+        let volume_captured_divergence =
+            device.create_texture(&create_capture_volume_texture_desc("Captured Divergence", wgpu::TextureFormat::R32Float));
+        let volume_captured_divergence_marker = device.create_texture(&create_capture_volume_texture_desc(
+            "Captured Divergence Marker",
+            wgpu::TextureFormat::R8Snorm,
+        ));
+        let volume_captured_density_error = device.create_texture(&create_capture_volume_texture_desc(
+            "Captured Density Error",
+            wgpu::TextureFormat::R32Float,
+        ));
 
         // Resource views
         let volume_velocity_view_x = volume_velocity_x.create_view(&Default::default());
@@ -200,22 +356,29 @@ impl HybridFluid {
             device,
             grid_dimension,
             &pressure_solver,
-            SolverConfig {
-                target_mse: 0.5,
-                mse_check_frequency: 4,
-                max_num_iterations: 32,
-            },
+            Self::DEFAULT_SOLVER_CONFIG_VELOCITY,
         );
         let pressure_field_from_density = PressureField::new(
             "from density",
             device,
             grid_dimension,
             &pressure_solver,
-            SolverConfig {
-                target_mse: 0.05,
-                mse_check_frequency: 4,
-                max_num_iterations: 16,
-            },
+            Self::DEFAULT_SOLVER_CONFIG_DENSITY,
+        );
+        let diagnostics = SimulationDiagnostics::new(
+            device,
+            grid_dimension,
+            shader_dir,
+            pipeline_manager,
+            per_frame_bind_group_layout,
+            &group_layout_uniform.layout,
+            &volume_marker_view,
+            [&volume_velocity_view_x, &volume_velocity_view_y, &volume_velocity_view_z],
+            pressure_solver.residual_view(),
+            &volume_linked_lists_view,
+            &particles_position_llindex,
+            [&particles_velocity_x, &particles_velocity_y, &particles_velocity_z],
+            max_num_particles,
         );
 
         // Bind groups.
@@ -294,6 +457,7 @@ impl HybridFluid {
             .texture(&volume_marker_view)
             .texture(&pressure_field_from_velocity.pressure_view())
             .texture(&pressure_field_from_density.pressure_view())
+            .resource(render_info_uniformbuffer.binding_resource())
             .create(device, "BindGroup: Fluid Renderers");
 
         // pipeline layouts.
@@ -357,9 +521,21 @@ impl HybridFluid {
         HybridFluid {
             grid_dimension,
 
+            volume_velocity_x,
+            volume_velocity_y,
+            volume_velocity_z,
+            volume_marker: volume_marker_primary,
+
+            volume_captured_divergence,
+            volume_captured_divergence_marker,
+            volume_captured_density_error,
+            debug_volume_capture_enabled: false,
+            debug_volumes_captured: false,
+
             pressure_solver,
             pressure_field_from_velocity,
             pressure_field_from_density,
+            diagnostics,
 
             particles_position_llindex,
             particles_velocity_x,
@@ -369,7 +545,11 @@ impl HybridFluid {
             simulation_properties: SimulationPropertiesUniformBufferContent {
                 num_particles: 0,
                 gravity_grid: cgmath::vec3(0.0, -9.81, 0.0),
+                particles_per_cell,
+                transfer_scheme: TransferScheme::default() as u32,
+                padding: [0; 2],
             },
+            render_info_uniformbuffer,
 
             bind_group_uniform,
             bind_group_transfer_velocity,
@@ -477,66 +657,24 @@ impl HybridFluid {
         }
     }
 
-    fn clamp_to_grid(&self, grid_cor: cgmath::Point3<f32>) -> cgmath::Point3<u32> {
-        // Due to the design of the grid, the 0-1 range is reserved by solid cells and can't be filled.
-        cgmath::Point3::new(
-            self.grid_dimension.width.min(grid_cor.x as u32).max(1),
-            self.grid_dimension.height.min(grid_cor.y as u32).max(1),
-            self.grid_dimension.depth.min(grid_cor.z as u32).max(1),
-        )
-    }
-
-    // Adds a cube of fluid. Coordinates are in grid space! Very slow operation!
-    pub fn add_fluid_cube(&mut self, queue: &wgpu::Queue, min_grid: cgmath::Point3<f32>, max_grid: cgmath::Point3<f32>) {
-        // align to whole cells for simplicity.
-        let min_grid = self.clamp_to_grid(min_grid);
-        let max_grid = self.clamp_to_grid(max_grid);
-        let extent_cell = max_grid - min_grid;
-
-        let mut num_new_particles = (extent_cell.x * extent_cell.y * extent_cell.z * Self::PARTICLES_PER_GRID_CELL) as u32;
-        if self.max_num_particles < num_new_particles + self.simulation_properties.num_particles {
-            error!(
-                "Can't add {} particles, max is {}, current is {}",
-                num_new_particles, self.max_num_particles, self.simulation_properties.num_particles
-            );
-            num_new_particles = self.max_num_particles - self.simulation_properties.num_particles;
-        }
+    /// Adds particles for a region of fluid. Coordinates are in grid space! Very slow operation!
+    pub fn add_fluid_region(&mut self, queue: &wgpu::Queue, region: &FluidRegion) {
+        let positions = region.sample_particles(
+            self.grid_dimension,
+            self.simulation_properties.particles_per_cell,
+            self.simulation_properties.num_particles,
+            self.max_num_particles,
+        );
+        let num_new_particles = positions.len() as u32;
         info!("Adding {} new particles", num_new_particles);
 
-        // Fill buffer with particle data
-        let mut rng: rand::rngs::SmallRng = rand::SeedableRng::seed_from_u64((self.simulation_properties.num_particles + num_new_particles) as u64);
-        let mut new_particles = Vec::new();
-        new_particles.resize(
-            num_new_particles as usize,
-            ParticlePositionLl {
-                position: cgmath::point3(0.0, 0.0, 0.0),
+        let new_particles: Vec<ParticlePositionLl> = positions
+            .iter()
+            .map(|&position| ParticlePositionLl {
+                position,
                 linked_list_next: 0xFFFFFFFF,
-            },
-        );
-        for (i, particle) in new_particles.iter_mut().enumerate() {
-            let cell = cgmath::point3(
-                (min_grid.x + i as u32 / Self::PARTICLES_PER_GRID_CELL % extent_cell.x) as f32,
-                (min_grid.y + i as u32 / Self::PARTICLES_PER_GRID_CELL / extent_cell.x % extent_cell.y) as f32,
-                (min_grid.z + i as u32 / Self::PARTICLES_PER_GRID_CELL / extent_cell.x / extent_cell.y) as f32,
-            );
-
-            let sample_idx = i as u32 % Self::PARTICLES_PER_GRID_CELL;
-
-            // pure random
-            // let offset = rng.gen::<cgmath::Vector3<f32>>();
-            // pure regular
-            // let offset = cgmath::vec3(
-            //     (sample_idx % 2) as f32 + 0.5,
-            //     (sample_idx / 2 % 2) as f32 + 0.5,
-            //     (sample_idx / 4 % 2) as f32 + 0.5,
-            // ) * 0.5;
-            // stratified
-            let offset = cgmath::vec3((sample_idx % 2) as f32, (sample_idx / 2 % 2) as f32, (sample_idx / 4 % 2) as f32) * 0.5
-                + rng.gen::<cgmath::Vector3<f32>>() * 0.5;
-
-            particle.position = cell + offset;
-        }
-
+            })
+            .collect();
         let particle_size = std::mem::size_of::<ParticlePositionLl>() as u64;
         queue.write_buffer(
             &self.particles_position_llindex,
@@ -544,26 +682,147 @@ impl HybridFluid {
             bytemuck::cast_slice(&new_particles),
         );
 
-        // Clear velocities:
-        // wgpu-rs doesn't zero initialize yet (bug/missing feature impl)
-        // https://github.com/gfx-rs/wgpu/issues/563
+        // Initial velocities go to the w component, the affine part (xyz) starts out at zero.
+        // (needs to be written explicitly either way, wgpu-rs doesn't zero initialize yet, https://github.com/gfx-rs/wgpu/issues/563)
         let offset_velocity_buffer = self.simulation_properties.num_particles as u64 * std::mem::size_of::<cgmath::Vector4<f32>>() as u64;
-        let zero_velocity = vec![0 as u8; num_new_particles as usize * std::mem::size_of::<cgmath::Vector4<f32>>()];
-        queue.write_buffer(&self.particles_velocity_x, offset_velocity_buffer, &zero_velocity);
-        queue.write_buffer(&self.particles_velocity_y, offset_velocity_buffer, &zero_velocity);
-        queue.write_buffer(&self.particles_velocity_z, offset_velocity_buffer, &zero_velocity);
+        let velocities: Vec<cgmath::Vector3<f32>> = positions.iter().map(|&position| region.velocity_at(position)).collect();
+        for (component, velocity_buffer) in [&self.particles_velocity_x, &self.particles_velocity_y, &self.particles_velocity_z]
+            .iter()
+            .enumerate()
+        {
+            let component_velocities: Vec<cgmath::Vector4<f32>> = velocities.iter().map(|v| cgmath::vec4(0.0, 0.0, 0.0, v[component])).collect();
+            queue.write_buffer(velocity_buffer, offset_velocity_buffer, bytemuck::cast_slice(&component_velocities));
+        }
 
         self.simulation_properties.num_particles += num_new_particles;
     }
 
+    // If enabled, divergence and density error are copied aside every step (before the pressure solver overwrites them) so they can be read back.
+    pub fn set_debug_volume_capture(&mut self, enabled: bool) {
+        self.debug_volume_capture_enabled = enabled;
+        if !enabled {
+            self.debug_volumes_captured = false;
+        }
+    }
+
+    pub fn debug_volume_capture_enabled(&self) -> bool {
+        self.debug_volume_capture_enabled
+    }
+
+    // Reads back a grid volume to the cpu, x varying fastest. Stalls until the gpu is done with all previously submitted work!
+    // Returns None for volumes that require debug capture if nothing was captured yet.
+    pub fn read_volume(&self, device: &wgpu::Device, queue: &wgpu::Queue, volume: SimulationVolume) -> Option<Vec<f32>> {
+        if volume.requires_debug_capture() && !self.debug_volumes_captured {
+            return None;
+        }
+
+        let read_float_volume = |texture: &wgpu::Texture| -> Vec<f32> {
+            bytemuck::cast_slice(&readback::read_texture_3d(device, queue, texture, self.grid_dimension, 4)).to_vec()
+        };
+        let read_marker_volume = |texture: &wgpu::Texture| -> Vec<f32> {
+            readback::read_texture_3d(device, queue, texture, self.grid_dimension, 1)
+                .iter()
+                .map(|&v| (v as i8 as f32 / 127.0).max(-1.0))
+                .collect()
+        };
+        // Cells that aren't fluid were skipped by the shaders computing these and hold stale values.
+        let mask_non_fluid = |mut values: Vec<f32>, marker: Vec<f32>| -> Vec<f32> {
+            for (value, marker) in values.iter_mut().zip(marker.iter()) {
+                if *marker != 1.0 {
+                    *value = 0.0;
+                }
+            }
+            values
+        };
+
+        Some(match volume {
+            SimulationVolume::VelocityX => read_float_volume(&self.volume_velocity_x),
+            SimulationVolume::VelocityY => read_float_volume(&self.volume_velocity_y),
+            SimulationVolume::VelocityZ => read_float_volume(&self.volume_velocity_z),
+            SimulationVolume::Marker => read_marker_volume(&self.volume_marker),
+            SimulationVolume::PressureFromVelocity => read_float_volume(self.pressure_field_from_velocity.pressure_texture()),
+            SimulationVolume::PressureFromDensity => read_float_volume(self.pressure_field_from_density.pressure_texture()),
+            SimulationVolume::DensityError => mask_non_fluid(
+                read_float_volume(&self.volume_captured_density_error),
+                read_marker_volume(&self.volume_marker),
+            ),
+            SimulationVolume::Divergence => mask_non_fluid(
+                read_float_volume(&self.volume_captured_divergence),
+                read_marker_volume(&self.volume_captured_divergence_marker),
+            ),
+        })
+    }
+
+    fn capture_volume(&self, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Texture, destination: &wgpu::Texture) {
+        encoder.copy_texture_to_texture(
+            wgpu::TextureCopyView {
+                texture: source,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::TextureCopyView {
+                texture: destination,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            self.grid_dimension,
+        );
+    }
+
     pub fn set_gravity_grid(&mut self, gravity: cgmath::Vector3<f32>) {
         self.simulation_properties.gravity_grid = gravity;
     }
 
+    pub fn set_transfer_scheme(&mut self, transfer_scheme: TransferScheme) {
+        self.simulation_properties.transfer_scheme = transfer_scheme as u32;
+    }
+
+    pub fn particles_per_cell(&self) -> u32 {
+        self.simulation_properties.particles_per_cell
+    }
+
+    // Average distance between particles at rest in grid cells.
+    pub fn particle_spacing(particles_per_cell: u32) -> f32 {
+        1.0 / (particles_per_cell as f32).powf(1.0 / 3.0)
+    }
+
+    // Where renderers place the fluid's grid in the world.
+    pub fn set_world_transform(&mut self, queue: &wgpu::Queue, world_position: cgmath::Point3<f32>, grid_to_world_scale: f32) {
+        self.render_info_uniformbuffer.update_content(
+            queue,
+            RenderInfoUniformBufferContent {
+                world_position,
+                grid_to_world_scale,
+                particle_spacing: Self::particle_spacing(self.simulation_properties.particles_per_cell),
+                padding: [0.0; 3],
+            },
+        );
+    }
+
     pub fn num_particles(&self) -> u32 {
         self.simulation_properties.num_particles
     }
 
+    /// Reads back all particles to the cpu. Stalls until the gpu is done with all previously submitted work, very slow operation!
+    pub fn read_particles(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<ParticleState> {
+        let num_particles = self.num_particles() as usize;
+        let positions: Vec<ParticlePositionLl> = readback::read_buffer(device, queue, &self.particles_position_llindex, num_particles);
+        // Velocity buffers store the (APIC) affine row in xyz and the actual velocity component in w.
+        let velocities_x: Vec<[f32; 4]> = readback::read_buffer(device, queue, &self.particles_velocity_x, num_particles);
+        let velocities_y: Vec<[f32; 4]> = readback::read_buffer(device, queue, &self.particles_velocity_y, num_particles);
+        let velocities_z: Vec<[f32; 4]> = readback::read_buffer(device, queue, &self.particles_velocity_z, num_particles);
+
+        positions
+            .iter()
+            .zip(velocities_x.iter().zip(velocities_y.iter().zip(velocities_z.iter())))
+            .map(|(p, (vx, (vy, vz)))| ParticleState {
+                position: p.position,
+                velocity: cgmath::vec3(vx[3], vy[3], vz[3]),
+            })
+            .collect()
+    }
+
+    /// Layout of `bind_group_renderer`.
     pub fn get_or_create_group_layout_renderer(device: &wgpu::Device) -> &BindGroupLayoutWithDesc {
         unsafe {
             GROUP_LAYOUT_RENDERER.get_or_insert_with(|| {
@@ -578,11 +837,13 @@ impl HybridFluid {
                     .next_binding_vertex(binding_glsl::texture3D()) // marker
                     .next_binding_vertex(binding_glsl::texture3D()) // pressure
                     .next_binding_vertex(binding_glsl::texture3D()) // density
+                    .next_binding_vertex(binding_glsl::uniform()) // render info
                     .create(device, "BindGroupLayout: ParticleRenderer")
             })
         }
     }
 
+    /// Particle buffers, velocity, marker, pressure & density volumes and the world transform for rendering, see the shaders in shader/fluid_render_info.glsl.
     pub fn bind_group_renderer(&self) -> &wgpu::BindGroup {
         &self.bind_group_renderer
     }
@@ -614,13 +875,37 @@ impl HybridFluid {
         &self.pressure_field_from_density.stats
     }
 
-    // Necessary to call this to update solver statistics and config.
-    // Do not call while building command buffer!
+    pub fn diagnostics_history(&self) -> &VecDeque<DiagnosticsSample> {
+        self.diagnostics.history()
+    }
+
+    // If enabled, diagnostics of every step are kept until taken with take_recorded_diagnostics.
+    pub fn set_diagnostics_recording(&mut self, enabled: bool) {
+        self.diagnostics.set_recording(enabled);
+    }
+
+    pub fn take_recorded_diagnostics(&mut self) -> Vec<DiagnosticsSample> {
+        self.diagnostics.take_recorded_samples()
+    }
+
+    // Diagnostics are read back asynchronously, this waits until the ones of all previous steps are available.
+    // Stalls until the gpu is done with all previously submitted work!
+    pub fn wait_for_pending_diagnostics(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Wait);
+        self.diagnostics
+            .retrieve_new_samples(self.simulation_properties.particles_per_cell as f32);
+    }
+
+    /// Necessary to call this to update solver statistics and config.
+    /// Do not call while building command buffer!
     pub fn update_statistics(&mut self) {
         self.pressure_field_from_density.start_error_buffer_readbacks();
         self.pressure_field_from_velocity.start_error_buffer_readbacks();
+        self.diagnostics.start_readbacks();
     }
 
+    /// Records a single simulation step into the given encoder.
+    /// Expects the per frame resources (see PerFrameResources) with the simulation delta already filled in.
     pub fn step(
         &mut self,
         simulation_delta: Duration,
@@ -628,10 +913,14 @@ impl HybridFluid {
         pipeline_manager: &PipelineManager,
         queue: &wgpu::Queue,
         per_frame_bind_group: &wgpu::BindGroup,
+        profiler: &mut GpuProfiler,
     ) {
-        wgpu_scope!(encoder, "HybridFluid.step");
+        wgpu_scope!(encoder, profiler, "HybridFluid.step");
+
+        self.diagnostics
+            .retrieve_new_samples(self.simulation_properties.particles_per_cell as f32);
 
-        wgpu_scope!(encoder, "update uniforms", || {
+        wgpu_scope!(encoder, profiler, "update uniforms", || {
             self.pressure_field_from_density.update_uniforms(queue, simulation_delta);
             self.pressure_field_from_velocity.update_uniforms(queue, simulation_delta);
             self.simulation_properties_uniformbuffer.update_content(queue, self.simulation_properties);
@@ -645,45 +934,62 @@ impl HybridFluid {
             cpass.set_bind_group(0, per_frame_bind_group, &[]);
             cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
 
-            wgpu_scope!(cpass, "transfer particle velocity to grid", || {
+            wgpu_scope!(cpass, profiler, "transfer particle velocity to grid", || {
                 for i in 0..3 {
-                    wgpu_scope!(cpass, &format!("dimension {}", ["x", "y", "z"][i]), || {
+                    wgpu_scope!(cpass, profiler, &format!("dimension {}", ["x", "y", "z"][i]), || {
                         cpass.set_bind_group(2, &self.bind_group_transfer_velocity[i], &[]);
-                        wgpu_scope!(cpass, &format!("clear linked list grid{}", if i == 0 { " & marker" } else { "" }), || {
-                            cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_clear));
-                            cpass.set_push_constants(0, bytemuck::bytes_of(&[i as u32]));
-                            cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
-                        });
+                        wgpu_scope!(
+                            cpass,
+                            profiler,
+                            &format!("clear linked list grid{}", if i == 0 { " & marker" } else { "" }),
+                            || {
+                                cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_clear));
+                                cpass.set_push_constants(0, bytemuck::bytes_of(&[i as u32]));
+                                cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                            }
+                        );
 
-                        wgpu_scope!(cpass, "create particle linked lists", || {
+                        wgpu_scope!(cpass, profiler, "create particle linked lists", || {
                             cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_build_linkedlist));
                             cpass.dispatch(particle_work_groups, 1, 1);
                         });
 
                         if i == 0 {
-                            wgpu_scope!(cpass, "set boundary marker", || {
+                            wgpu_scope!(cpass, profiler, "set boundary marker", || {
                                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_set_boundary_marker));
                                 cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
                             });
                         }
 
-                        wgpu_scope!(cpass, "gather velocity & apply global forces", || {
+                        wgpu_scope!(cpass, profiler, "gather velocity & apply global forces", || {
                             cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_gather_velocity));
                             cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
                         });
                     });
                 }
             });
-            wgpu_scope!(cpass, "compute divergence", || {
+            wgpu_scope!(cpass, profiler, "compute divergence", || {
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_divergence_compute));
                 cpass.set_bind_group(1, &self.bind_group_divergence_compute, &[]); // Writes directly into Residual of the pressure solver.
                 cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
             });
         }
 
+        if self.debug_volume_capture_enabled {
+            wgpu_scope!(encoder, profiler, "capture divergence", || {
+                self.capture_volume(&mut encoder, self.pressure_solver.residual_texture(), &self.volume_captured_divergence);
+                self.capture_volume(&mut encoder, &self.volume_marker, &self.volume_captured_divergence_marker);
+            });
+        }
+
         // Solve for pressure
-        self.pressure_solver
-            .solve(simulation_delta, &mut self.pressure_field_from_velocity, &mut encoder, pipeline_manager);
+        self.pressure_solver.solve(
+            simulation_delta,
+            &mut self.pressure_field_from_velocity,
+            &mut encoder,
+            pipeline_manager,
+            profiler,
+        );
 
         {
             let mut cpass = encoder.begin_compute_pass();
@@ -693,53 +999,79 @@ impl HybridFluid {
             {
                 cpass.set_bind_group(2, &self.bind_group_write_velocity, &[]);
 
-                wgpu_scope!(cpass, "make velocity grid divergence free", || {
+                wgpu_scope!(cpass, profiler, "make velocity grid divergence free", || {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_divergence_remove));
                     cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
                 });
 
-                wgpu_scope!(cpass, "extrapolate velocity grid", || {
+                wgpu_scope!(cpass, profiler, "extrapolate velocity grid", || {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_extrapolate_velocity));
                     cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
                 });
             }
-            wgpu_scope!(cpass, "clear marker & linked list grids", || {
+            wgpu_scope!(cpass, profiler, "diagnostics: gather divergence", || {
+                self.diagnostics.gather_divergence(&mut cpass, pipeline_manager);
+            });
+            wgpu_scope!(cpass, profiler, "clear marker & linked list grids", || {
                 cpass.set_bind_group(2, &self.bind_group_transfer_velocity[0], &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_clear));
                 cpass.set_push_constants(0, &bytemuck::bytes_of(&[0 as u32]));
                 cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
             });
-            wgpu_scope!(cpass, "advect particles & write new linked list grid", || {
+            wgpu_scope!(cpass, profiler, "advect particles & write new linked list grid", || {
                 cpass.set_bind_group(2, &self.bind_group_advect_particles, &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_advect_particles));
                 cpass.dispatch(particle_work_groups, 1, 1);
             });
 
-            wgpu_scope!(cpass, "density projection: set boundary marker", || {
+            wgpu_scope!(cpass, profiler, "density projection: set boundary marker", || {
                 cpass.set_bind_group(2, &self.bind_group_transfer_velocity[0], &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_transfer_set_boundary_marker));
                 cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
             });
-            wgpu_scope!(cpass, "density projection: compute density error via gather", || {
+            wgpu_scope!(cpass, profiler, "density projection: compute density error via gather", || {
                 cpass.set_bind_group(2, &&self.bind_group_density_projection_gather_error, &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_density_projection_gather_error));
                 cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
             });
+            wgpu_scope!(cpass, profiler, "diagnostics: gather density error", || {
+                self.diagnostics.gather_density_error(&mut cpass, pipeline_manager);
+            });
+        }
+
+        if self.debug_volume_capture_enabled {
+            wgpu_scope!(encoder, profiler, "capture density error", || {
+                self.capture_volume(&mut encoder, self.pressure_solver.residual_texture(), &self.volume_captured_density_error);
+            });
+            self.debug_volumes_captured = true;
         }
 
         // Compute pressure from density error.
-        self.pressure_solver
-            .solve(simulation_delta, &mut self.pressure_field_from_density, &mut encoder, pipeline_manager);
+        self.pressure_solver.solve(
+            simulation_delta,
+            &mut self.pressure_field_from_density,
+            &mut encoder,
+            pipeline_manager,
+            profiler,
+        );
 
         {
             let mut cpass = encoder.begin_compute_pass();
-            wgpu_scope!(cpass, "correct particle density error", || {
+            wgpu_scope!(cpass, profiler, "correct particle density error", || {
                 cpass.set_bind_group(0, per_frame_bind_group, &[]);
                 cpass.set_bind_group(1, &self.bind_group_uniform, &[]);
                 cpass.set_bind_group(2, &self.bind_group_density_projection_correct_particles, &[]);
                 cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_density_projection_correct_particles));
                 cpass.dispatch(particle_work_groups, 1, 1);
             });
+            wgpu_scope!(cpass, profiler, "diagnostics: gather particles", || {
+                self.diagnostics
+                    .gather_particles(&mut cpass, pipeline_manager, self.simulation_properties.num_particles);
+            });
+            wgpu_scope!(cpass, profiler, "diagnostics: reduce", || {
+                self.diagnostics.reduce(&mut cpass, pipeline_manager);
+            });
         }
+        self.diagnostics.enqueue_readback(&mut encoder, simulation_delta);
     }
 }
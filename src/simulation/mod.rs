@@ -1,5 +1,15 @@
+mod comparison;
+mod cpu_hybrid_fluid;
+mod diagnostics;
+mod fluid_region;
+mod fluid_simulation;
 mod hybrid_fluid;
 mod pressure_solver;
 
-pub use hybrid_fluid::HybridFluid;
+pub use comparison::{compare_in_lockstep, ComparisonTolerances, SimulationComparison};
+pub use cpu_hybrid_fluid::CpuHybridFluid;
+pub use diagnostics::DiagnosticsSample;
+pub use fluid_region::{ClosedMesh, FluidRegion, FluidShape};
+pub use fluid_simulation::{FluidSimulation, GpuFluidSimulation};
+pub use hybrid_fluid::{HybridFluid, ParticleState, SimulationVolume, TransferScheme};
 pub use pressure_solver::{SolverConfig, SolverStatisticSample};
@@ -1,4 +1,4 @@
-use crate::wgpu_utils::{self, binding_builder::*, binding_glsl, pipelines::*, shader::ShaderDirectory};
+use crate::wgpu_utils::{self, binding_builder::*, binding_glsl, pipelines::*, profiler::GpuProfiler, shader::ShaderDirectory};
 use futures::Future;
 use futures::*;
 use std::collections::VecDeque;
@@ -14,7 +14,7 @@ fn create_volume_texture_desc(label: &str, grid_dimension: wgpu::Extent3d, forma
         sample_count: 1,
         dimension: wgpu::TextureDimension::D3,
         format,
-        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::STORAGE,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC,
     }
 }
 
@@ -41,6 +41,7 @@ pub struct PressureSolver {
 
     group_layout_pressure_field: BindGroupLayoutWithDesc,
 
+    volume_residual: wgpu::Texture,
     volume_residual_view: wgpu::TextureView,
 }
 
@@ -52,6 +53,7 @@ struct PendingErrorBuffer {
     resulting_sample: SolverStatisticSample,
 }
 
+#[derive(Clone, Copy)]
 pub struct SolverConfig {
     pub target_mse: f32,
     pub max_num_iterations: i32,
@@ -80,6 +82,7 @@ type SolverConfigUniformBuffer = UniformBuffer<SolverConfigUniformBufferContent>
 // Pressure solver instance keeps track of pressure result from last step/frame in order to speed up the solve.
 pub struct PressureField {
     bind_group_pressure_field: wgpu::BindGroup,
+    volume_pressure: wgpu::Texture,
     volume_pressure_view: wgpu::TextureView,
 
     unused_error_buffers: Vec<wgpu::Buffer>,
@@ -94,7 +97,7 @@ pub struct PressureField {
 }
 
 impl PressureField {
-    const SOLVER_STATISTIC_HISTORY_LENGTH: usize = 100;
+    pub(super) const SOLVER_STATISTIC_HISTORY_LENGTH: usize = 100;
 
     pub fn new(name: &'static str, device: &wgpu::Device, grid_dimension: wgpu::Extent3d, solver: &PressureSolver, config: SolverConfig) -> Self {
         let volume_pressure = device.create_texture(&create_volume_texture_desc(
@@ -123,6 +126,7 @@ impl PressureField {
 
         PressureField {
             bind_group_pressure_field,
+            volume_pressure,
             volume_pressure_view,
             unused_error_buffers,
             unscheduled_error_readbacks: Vec::new(),
@@ -140,6 +144,10 @@ impl PressureField {
         &self.volume_pressure_view
     }
 
+    pub fn pressure_texture(&self) -> &wgpu::Texture {
+        &self.volume_pressure
+    }
+
     fn retrieve_new_error_samples(&mut self, simulation_delta: Duration) {
         // Check if there's any new data samples
         while let Some(mut readback) = self.pending_error_readbacks.pop_front() {
@@ -500,6 +508,7 @@ impl PressureSolver {
 
             dotproduct_reduce_result_and_dispatch_buffer,
 
+            volume_residual,
             volume_residual_view,
         }
     }
@@ -508,8 +517,18 @@ impl PressureSolver {
         &self.volume_residual_view
     }
 
-    fn reduce_add<'a, 'b: 'a>(&'b self, cpass: &mut wgpu::ComputePass<'a>, pipeline_manager: &'a PipelineManager, result_mode: u32) {
-        wgpu_scope!(cpass, &format!("PressureSolver.reduce_add - mode {}", result_mode));
+    pub fn residual_texture(&self) -> &wgpu::Texture {
+        &self.volume_residual
+    }
+
+    fn reduce_add<'a, 'b: 'a>(
+        &'b self,
+        cpass: &mut wgpu::ComputePass<'a>,
+        pipeline_manager: &'a PipelineManager,
+        profiler: &mut GpuProfiler,
+        result_mode: u32,
+    ) {
+        wgpu_scope!(cpass, profiler, &format!("PressureSolver.reduce_add - mode {}", result_mode));
 
         let mut num_entries_remaining = (self.grid_dimension.width * self.grid_dimension.height * self.grid_dimension.depth) as u32;
         assert!(num_entries_remaining > Self::REDUCE_REDUCTION_PER_STEP);
@@ -557,8 +576,9 @@ impl PressureSolver {
         pressure_field: &'a mut PressureField,
         encoder: &mut wgpu::CommandEncoder,
         pipeline_manager: &'a PipelineManager,
+        profiler: &mut GpuProfiler,
     ) {
-        wgpu_scope!(encoder, "PressureSolver.solve");
+        wgpu_scope!(encoder, profiler, "PressureSolver.solve");
 
         let mut cpass = encoder.begin_compute_pass();
 
@@ -577,7 +597,7 @@ impl PressureSolver {
 
         // For optimization various steps are collapsed as far as possible to avoid expensive buffer/texture read/writes
         // This makes the algorithm a lot faster but also a bit harder to read.
-        wgpu_scope!(cpass, "init", || {
+        wgpu_scope!(cpass, profiler, "init", || {
             let grid_work_groups = wgpu_utils::compute_group_size(self.grid_dimension, Self::COMPUTE_LOCAL_SIZE_VOLUME);
 
             // We use pressure from last frame, but set explicitly set all pressure values to zero wherever there is not fluid right now.
@@ -600,26 +620,31 @@ impl PressureSolver {
 
             // Apply preconditioner on (r), store result to search vector (s) and start dotproduct of <s; r>
             // Note that we don't use the auxillary vector here as in-between storage!
-            wgpu_scope!(cpass, "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>", || {
-                cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
-                cpass.set_push_constants(0, &bytemuck::bytes_of(&[0 as u32]));
-                cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
-                cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
-                cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
-                cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
-                cpass.set_bind_group(2, &self.bind_group_preconditioner[2], &[]);
-                cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
-            });
+            wgpu_scope!(
+                cpass,
+                profiler,
+                "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>",
+                || {
+                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
+                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[0 as u32]));
+                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
+                    cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
+                    cpass.set_bind_group(2, &self.bind_group_preconditioner[2], &[]);
+                    cpass.dispatch(grid_work_groups.width, grid_work_groups.height, grid_work_groups.depth);
+                }
+            );
             // Init sigma to dotproduct of search vector (s) and residual (r)
-            self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_INIT);
+            self.reduce_add(&mut cpass, pipeline_manager, profiler, Self::REDUCE_RESULTMODE_INIT);
         });
 
-        wgpu_scope!(cpass, "solver iterations", || {
+        wgpu_scope!(cpass, profiler, "solver iterations", || {
             const DISPATCH_BUFFER_OFFSET: u64 = 4 * 4;
 
             let mut i = 0;
-            while wgpu_scope!(cpass, &format!("iteration {}", i), || {
-                wgpu_scope!(cpass, "multiply search vector (s) with coefficients (A)", || {
+            while wgpu_scope!(cpass, profiler, &format!("iteration {}", i), || {
+                wgpu_scope!(cpass, profiler, "multiply search vector (s) with coefficients (A)", || {
                     // The dot product is applied to the result (denoted as z in Bridson's book) and the search vector (s), i.e. compute <s; As>
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_coeff));
                     cpass.set_bind_group(2, &self.bind_group_apply_coeff, &[]);
@@ -627,12 +652,12 @@ impl PressureSolver {
                     cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
                 });
                 // finish dotproduct of auxiliary field (z) and search field (s)
-                self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_ALPHA);
+                self.reduce_add(&mut cpass, pipeline_manager, profiler, Self::REDUCE_RESULTMODE_ALPHA);
 
                 let iteration_with_mse_computation =
                     pressure_field.config.max_num_iterations == i || (i > 0 && i % pressure_field.config.mse_check_frequency == 0);
 
-                wgpu_scope!(cpass, "update pressure field (p) and residual field (r)", || {
+                wgpu_scope!(cpass, profiler, "update pressure field (p) and residual field (r)", || {
                     const PRUPDATE_COMPUTE_MSE: u32 = 1;
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_update_pressure_and_residual));
                     if iteration_with_mse_computation {
@@ -648,27 +673,32 @@ impl PressureSolver {
                 if iteration_with_mse_computation {
                     // Compute remaining error.
                     // Used for statistics. If below target, makes all upcoming dispatch_indirect no-ops.
-                    self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_MSE + i as u32);
+                    self.reduce_add(&mut cpass, pipeline_manager, profiler, Self::REDUCE_RESULTMODE_MSE + i as u32);
 
                     if pressure_field.config.max_num_iterations == i {
                         return false;
                     }
                 }
 
-                wgpu_scope!(cpass, "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>", || {
-                    cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
-                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
-                    cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                    cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
-                    cpass.set_bind_group(2, &self.bind_group_preconditioner[1], &[]);
-                    cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
-                });
+                wgpu_scope!(
+                    cpass,
+                    profiler,
+                    "preconditioner on (r), store to auxillary (z), start dotproduct of <z; r>",
+                    || {
+                        cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_apply_preconditioner));
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS0]));
+                        cpass.set_bind_group(2, &self.bind_group_preconditioner[0], &[]);
+                        cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                        cpass.set_push_constants(0, &bytemuck::bytes_of(&[PRECONDITIONER_PASS1, reduce_pass_initial_group_size]));
+                        cpass.set_bind_group(2, &self.bind_group_preconditioner[1], &[]);
+                        cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
+                    }
+                );
 
                 // finish dotproduct of auxiliary field (z) and residual field (r)
-                self.reduce_add(&mut cpass, pipeline_manager, Self::REDUCE_RESULTMODE_BETA);
+                self.reduce_add(&mut cpass, pipeline_manager, profiler, Self::REDUCE_RESULTMODE_BETA);
 
-                wgpu_scope!(cpass, "Update search vector", || {
+                wgpu_scope!(cpass, profiler, "Update search vector", || {
                     cpass.set_pipeline(pipeline_manager.get_compute(&self.pipeline_update_search));
                     cpass.set_bind_group(2, &self.bind_group_update_search, &[]);
                     cpass.dispatch_indirect(&self.dotproduct_reduce_result_and_dispatch_buffer, DISPATCH_BUFFER_OFFSET);
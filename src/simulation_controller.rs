@@ -1,13 +1,13 @@
-use crate::scene::Scene;
+use crate::scene::{Scene, SimulationSettings};
 use crate::{
     timer::{SimulationStepResult, Timer},
-    wgpu_utils::pipelines::PipelineManager,
+    wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler},
 };
 use std::time::{Duration, Instant};
 
 // The simulation controller orchestrates simulation steps.
 // It holds the central timer and as such is responsible for glueing rendering frames and simulation together.
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum SimulationControllerStatus {
     Realtime,
     RecordingWithFixedFrameLength(Duration),
@@ -30,6 +30,12 @@ fn delta_from_steps_per_second(steps_per_second: u64) -> Duration {
     Duration::from_nanos(1000 * 1000 * 1000 / steps_per_second)
 }
 
+impl Default for SimulationController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SimulationController {
     pub fn new() -> Self {
         const DEFAULT_SIMULATION_STEPS_PER_SECOND: u64 = 120;
@@ -86,6 +92,16 @@ impl SimulationController {
             .set_simulation_delta(delta_from_steps_per_second(self.simulation_steps_per_second));
     }
 
+    // Takes over everything the scene's settings specify.
+    pub fn apply_scene_settings(&mut self, settings: &SimulationSettings) {
+        if let Some(steps_per_second) = settings.steps_per_second {
+            self.set_simulation_steps_per_second(steps_per_second);
+        }
+        if let Some(time_scale) = settings.time_scale {
+            self.time_scale = time_scale;
+        }
+    }
+
     pub fn restart(&mut self) {
         self.timer = Timer::new(delta_from_steps_per_second(self.simulation_steps_per_second));
     }
@@ -101,6 +117,7 @@ impl SimulationController {
         scene: &mut Scene,
         pipeline_manager: &PipelineManager,
         per_frame_bind_group: &wgpu::BindGroup,
+        profiler: &mut GpuProfiler,
     ) {
         // After every batch we wait until the gpu is done.
         // This is not optimal for performance but is necessary because:
@@ -127,7 +144,7 @@ impl SimulationController {
                 let mut batch_size = MAX_FAST_FORWARD_SIMULATION_BATCH_SIZE;
                 {
                     for i in 0..MAX_FAST_FORWARD_SIMULATION_BATCH_SIZE {
-                        if !self.single_step(scene, device, queue, pipeline_manager, per_frame_bind_group) {
+                        if !self.single_step(scene, device, queue, pipeline_manager, per_frame_bind_group, profiler) {
                             batch_size = i;
                             break;
                         }
@@ -159,12 +176,13 @@ impl SimulationController {
         queue: &wgpu::Queue,
         pipeline_manager: &PipelineManager,
         per_frame_bind_group: &wgpu::BindGroup,
+        profiler: &mut GpuProfiler,
     ) {
         if !self.start_simulation_frame() {
             return;
         }
 
-        while self.single_step(scene, device, queue, pipeline_manager, per_frame_bind_group) {}
+        while self.single_step(scene, device, queue, pipeline_manager, per_frame_bind_group, profiler) {}
     }
 
     fn start_simulation_frame(&mut self) -> bool {
@@ -191,6 +209,7 @@ impl SimulationController {
         queue: &wgpu::Queue,
         pipeline_manager: &'a PipelineManager,
         per_frame_bind_group: &wgpu::BindGroup,
+        profiler: &mut GpuProfiler,
     ) -> bool {
         // frame drops are only relevant in realtime mode.
         let max_total_step_per_frame = if self.status == SimulationControllerStatus::Realtime {
@@ -204,8 +223,17 @@ impl SimulationController {
             return false;
         }
 
+        let simulation_time = self.timer.total_simulated_time();
         if self.timer.simulation_frame_loop(max_total_step_per_frame) == SimulationStepResult::PerformStepAndCallAgain {
-            scene.step(self.timer.simulation_delta(), device, pipeline_manager, queue, per_frame_bind_group);
+            scene.step(
+                simulation_time,
+                self.timer.simulation_delta(),
+                device,
+                pipeline_manager,
+                queue,
+                per_frame_bind_group,
+                profiler,
+            );
             return true;
         }
         return false;
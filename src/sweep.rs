@@ -0,0 +1,402 @@
+use crate::{
+    export::fluid_file_prefix,
+    headless::{HeadlessApplication, HeadlessConfig, HeadlessRunLength, HeadlessRunSummary},
+    simulation::DiagnosticsSample,
+};
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Parameter sweeps: runs one scene headlessly with every combination of a set of varied scene file values.
+// Meant for research runs, e.g. comparing grid resolutions or solver tolerances. Run with --sweep <file>.
+//
+// Output directory layout:
+// * summary.csv: one row per variant with the parameter values, timings and the final diagnostics of every fluid domain
+// * variant<index>/scene.json: the scene file the variant ran with
+// * variant<index>/diagnostics.csv (and screenshot0.png if a screenshot resolution is given): outputs of the headless run
+
+// A scene file value that is varied over the sweep.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepParameter {
+    // Column name in the summary.
+    pub name: String,
+    // JSON pointers into the scene file, e.g. "/fluids/0/grid_dimension". Missing object members are created.
+    // Several targets are set together, e.g. grid dimension and grid to world scale to keep the domain's size.
+    pub targets: Vec<String>,
+    // One entry per variation. With several targets, every value is an array with one value per target.
+    pub values: Vec<serde_json::Value>,
+}
+
+impl SweepParameter {
+    // Values for all targets of the given variation.
+    fn target_values(&self, value_index: usize) -> Vec<serde_json::Value> {
+        if self.targets.len() == 1 {
+            vec![self.values[value_index].clone()]
+        } else {
+            self.values[value_index].as_array().cloned().unwrap_or_default()
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.targets.is_empty() {
+            problems.push("needs at least one target".to_owned());
+        }
+        if self.values.is_empty() {
+            problems.push("needs at least one value".to_owned());
+        }
+        for target in self.targets.iter().filter(|target| !target.starts_with('/')) {
+            problems.push(format!("target \"{}\" is not a JSON pointer, needs to start with /", target));
+        }
+        if self.targets.len() > 1 {
+            for (value_index, value) in self.values.iter().enumerate() {
+                if value.as_array().map_or(true, |values| values.len() != self.targets.len()) {
+                    problems.push(format!(
+                        "values[{}]: needs to be an array with one value for each of the {} targets",
+                        value_index,
+                        self.targets.len()
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepConfig {
+    // Scene all variants are based on, relative to the sweep file.
+    pub scene: PathBuf,
+    // Simulated seconds per variant.
+    pub duration: f32,
+    // Every combination of values is run, the first parameter changes the slowest.
+    pub parameters: Vec<SweepParameter>,
+    // If given, the final state of every variant is rendered at this resolution, using the scene's camera.
+    #[serde(default)]
+    pub screenshot_resolution: Option<[u32; 2]>,
+}
+
+impl SweepConfig {
+    pub fn load(sweep_path: &Path) -> Result<Self, String> {
+        let file = File::open(sweep_path).map_err(|err| format!("Failed to open sweep file {:?}: {}", sweep_path, err))?;
+        let mut config: SweepConfig =
+            serde_json::from_reader(BufReader::new(file)).map_err(|err| format!("Failed to read sweep file {:?}: {}", sweep_path, err))?;
+        config.scene = sweep_path.parent().unwrap_or_else(|| Path::new("")).join(&config.scene);
+
+        let mut problems = Vec::new();
+        if config.duration <= 0.0 {
+            problems.push(format!("duration needs to be positive, is {}", config.duration));
+        }
+        if let Some(resolution) = config.screenshot_resolution.filter(|resolution| resolution.contains(&0)) {
+            problems.push(format!("screenshot_resolution can't be zero, is {}x{}", resolution[0], resolution[1]));
+        }
+        for (parameter_index, parameter) in config.parameters.iter().enumerate() {
+            problems.extend(
+                parameter
+                    .problems()
+                    .iter()
+                    .map(|problem| format!("parameters[{}] ({}): {}", parameter_index, parameter.name, problem)),
+            );
+        }
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!("Sweep file {:?} has problems:\n    {}", sweep_path, problems.join("\n    ")))
+        }
+    }
+
+    pub fn num_variants(&self) -> usize {
+        self.parameters.iter().map(|parameter| parameter.values.len()).product()
+    }
+
+    // Index into the values of every parameter for the given variant.
+    fn value_indices(&self, variant_index: usize) -> Vec<usize> {
+        let mut remainder = variant_index;
+        let mut value_indices: Vec<usize> = self
+            .parameters
+            .iter()
+            .rev()
+            .map(|parameter| {
+                let value_index = remainder % parameter.values.len();
+                remainder /= parameter.values.len();
+                value_index
+            })
+            .collect();
+        value_indices.reverse();
+        value_indices
+    }
+}
+
+// Sets the value at a JSON pointer (RFC 6901), creating missing object members along the way.
+fn set_json_pointer(root: &mut serde_json::Value, pointer: &str, value: serde_json::Value) -> Result<(), String> {
+    let mut current = root;
+    for token in pointer.split('/').skip(1) {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        if current.is_null() {
+            *current = serde_json::Value::Object(serde_json::Map::new());
+        }
+        current = match current {
+            serde_json::Value::Object(members) => members.entry(token).or_insert(serde_json::Value::Null),
+            serde_json::Value::Array(elements) => {
+                let num_elements = elements.len();
+                match token.parse::<usize>() {
+                    Ok(index) if index < num_elements => &mut elements[index],
+                    _ => {
+                        return Err(format!(
+                            "{}: \"{}\" is not an index into an array of length {}",
+                            pointer, token, num_elements
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "{}: can't look up \"{}\" in a value that is neither object nor array",
+                    pointer, token
+                ))
+            }
+        };
+    }
+    *current = value;
+    Ok(())
+}
+
+// Variant scenes are written to the output directory, so paths in them need to be independent of the scene's location.
+// Paths in scene files are relative to the scene, the only ones so far are mesh files of fluid regions.
+fn make_scene_paths_absolute(scene_json: &mut serde_json::Value, scene_directory: &Path) {
+    let fluids = match scene_json.get_mut("fluids").and_then(|fluids| fluids.as_array_mut()) {
+        Some(fluids) => fluids,
+        None => return,
+    };
+    for fluid in fluids.iter_mut() {
+        let regions = match fluid.get_mut("fluid_regions").and_then(|regions| regions.as_array_mut()) {
+            Some(regions) => regions,
+            None => continue,
+        };
+        for path in regions.iter_mut().filter_map(|region| region.pointer_mut("/shape/mesh/path")) {
+            let absolute_path = path
+                .as_str()
+                .map(Path::new)
+                .filter(|path| path.is_relative())
+                .map(|relative_path| scene_directory.join(relative_path).to_string_lossy().into_owned());
+            if let Some(absolute_path) = absolute_path {
+                *path = serde_json::Value::String(absolute_path);
+            }
+        }
+    }
+}
+
+// Quotes a csv field if necessary. Parameter values are json, which may contain commas.
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+pub struct SweepRunner {
+    config: SweepConfig,
+    output_dir: PathBuf,
+    base_scene_json: serde_json::Value,
+    // Paths in the base scene and in parameter values are relative to this.
+    scene_directory: PathBuf,
+    // Number of fluid domains of the base scene, determines the diagnostics columns of the summary.
+    num_fluids: usize,
+}
+
+impl SweepRunner {
+    pub fn new(sweep_path: &Path, output_dir: &Path) -> Result<Self, String> {
+        let config = SweepConfig::load(sweep_path)?;
+
+        let scene_file = File::open(&config.scene).map_err(|err| format!("Failed to open scene file {:?}: {}", config.scene, err))?;
+        let base_scene_json: serde_json::Value =
+            serde_json::from_reader(BufReader::new(scene_file)).map_err(|err| format!("Failed to read scene file {:?}: {}", config.scene, err))?;
+        let scene_directory = config
+            .scene
+            .parent()
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .canonicalize()
+            .map_err(|err| format!("Failed to resolve directory of scene {:?}: {}", config.scene, err))?;
+        let num_fluids = base_scene_json
+            .get("fluids")
+            .and_then(|fluids| fluids.as_array())
+            .map_or(0, |fluids| fluids.len());
+
+        Ok(SweepRunner {
+            config,
+            output_dir: output_dir.to_path_buf(),
+            base_scene_json,
+            scene_directory,
+            num_fluids,
+        })
+    }
+
+    // Runs all variants one after another, a failing variant doesn't stop the sweep.
+    // Returns an error if any of them failed, the summary lists the reason.
+    pub fn run(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&self.output_dir).map_err(|err| format!("Failed to create output directory {:?}: {}", self.output_dir, err))?;
+        let summary_path = self.output_dir.join("summary.csv");
+        let mut summary = File::create(&summary_path)
+            .map(BufWriter::new)
+            .map_err(|err| format!("Failed to create summary file {:?}: {}", summary_path, err))?;
+        self.write_summary_header(&mut summary)
+            .map_err(|err| format!("Failed to write summary file {:?}: {}", summary_path, err))?;
+
+        let num_variants = self.config.num_variants();
+        info!(
+            "Sweeping {} variants of {:?}, writing results to {:?}",
+            num_variants, self.config.scene, self.output_dir
+        );
+        let mut num_failed_variants = 0;
+        for variant_index in 0..num_variants {
+            let value_indices = self.config.value_indices(variant_index);
+            let parameter_values: Vec<String> = self
+                .config
+                .parameters
+                .iter()
+                .zip(value_indices.iter())
+                .map(|(parameter, &value_index)| parameter.values[value_index].to_string())
+                .collect();
+            info!(
+                "Sweep variant {}/{}: {}",
+                variant_index + 1,
+                num_variants,
+                self.config
+                    .parameters
+                    .iter()
+                    .zip(parameter_values.iter())
+                    .map(|(parameter, value)| format!("{} = {}", parameter.name, value))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+
+            let result = self.run_variant(variant_index, &value_indices);
+            if let Err(err) = &result {
+                error!("Sweep variant {} failed: {}", variant_index, err);
+                num_failed_variants += 1;
+            }
+            // Written right away, so that results of a long sweep survive if it is cancelled.
+            self.write_summary_row(&mut summary, variant_index, &parameter_values, &result)
+                .and_then(|_| summary.flush())
+                .map_err(|err| format!("Failed to write summary file {:?}: {}", summary_path, err))?;
+        }
+
+        info!("Sweep finished, wrote summary to {:?}", summary_path);
+        if num_failed_variants == 0 {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} of {} sweep variants failed, see {:?}",
+                num_failed_variants, num_variants, summary_path
+            ))
+        }
+    }
+
+    fn variant_dir(&self, variant_index: usize) -> PathBuf {
+        self.output_dir.join(format!("variant{:03}", variant_index))
+    }
+
+    fn run_variant(&self, variant_index: usize, value_indices: &[usize]) -> Result<HeadlessRunSummary, String> {
+        let mut scene_json = self.base_scene_json.clone();
+        for (parameter, &value_index) in self.config.parameters.iter().zip(value_indices.iter()) {
+            for (target, value) in parameter.targets.iter().zip(parameter.target_values(value_index)) {
+                set_json_pointer(&mut scene_json, target, value)?;
+            }
+        }
+        make_scene_paths_absolute(&mut scene_json, &self.scene_directory);
+
+        let variant_dir = self.variant_dir(variant_index);
+        std::fs::create_dir_all(&variant_dir).map_err(|err| format!("Failed to create directory {:?}: {}", variant_dir, err))?;
+        let scene_path = variant_dir.join("scene.json");
+        let scene_content = serde_json::to_vec_pretty(&scene_json).map_err(|err| err.to_string())?;
+        std::fs::write(&scene_path, scene_content).map_err(|err| format!("Failed to write scene file {:?}: {}", scene_path, err))?;
+
+        let config = HeadlessConfig {
+            scene_path,
+            run_length: HeadlessRunLength::SimulatedTime(Duration::from_secs_f32(self.config.duration)),
+            output_dir: variant_dir,
+            output_interval: None,
+            // Steps per second are part of the scene's simulation settings and can be swept as such.
+            simulation_steps_per_second: None,
+            export_surface_mesh: false,
+            export_volumes: false,
+            export_diagnostics: true,
+            render_resolution: self
+                .config
+                .screenshot_resolution
+                .map(|resolution| winit::dpi::PhysicalSize::new(resolution[0], resolution[1])),
+            camera_file: None,
+            camera_path_file: None,
+            render_hdr_format: None,
+            render_hdr_depth: false,
+            fluid_rendering_mode: None,
+        };
+        futures::executor::block_on(HeadlessApplication::new(config)).and_then(|mut application| application.run())
+    }
+
+    fn write_summary_header(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let mut columns = vec!["variant".to_owned()];
+        columns.extend(self.config.parameters.iter().map(|parameter| csv_field(&parameter.name)));
+        columns.extend(
+            ["status", "num_steps", "simulated_time", "computation_time", "computation_time_per_step"]
+                .iter()
+                .map(|name| (*name).to_owned()),
+        );
+        for fluid_index in 0..self.num_fluids {
+            let prefix = fluid_file_prefix(self.num_fluids, fluid_index);
+            columns.extend(
+                DiagnosticsSample::default()
+                    .named_values()
+                    .iter()
+                    .map(|(name, _)| format!("{}final_{}", prefix, name)),
+            );
+        }
+        writeln!(writer, "{}", columns.join(","))
+    }
+
+    // Diagnostics columns are left empty if the variant failed or has a different number of fluid domains than the base scene.
+    fn write_summary_row(
+        &self,
+        writer: &mut impl Write,
+        variant_index: usize,
+        parameter_values: &[String],
+        result: &Result<HeadlessRunSummary, String>,
+    ) -> std::io::Result<()> {
+        let mut fields = vec![variant_index.to_string()];
+        fields.extend(parameter_values.iter().map(|value| csv_field(value)));
+        let num_diagnostics_values = DiagnosticsSample::default().named_values().len();
+        let num_diagnostics_columns = self.num_fluids * num_diagnostics_values;
+        match result {
+            Ok(summary) => {
+                fields.push("ok".to_owned());
+                fields.push(summary.num_steps.to_string());
+                fields.push(summary.simulated_time.as_secs_f64().to_string());
+                fields.push(summary.computation_time.as_secs_f64().to_string());
+                fields.push((summary.computation_time.as_secs_f64() / summary.num_steps.max(1) as f64).to_string());
+                if summary.final_diagnostics.len() == self.num_fluids {
+                    for diagnostics in summary.final_diagnostics.iter() {
+                        match diagnostics {
+                            Some(diagnostics) => fields.extend(diagnostics.named_values().iter().map(|(_, value)| value.to_string())),
+                            None => fields.extend((0..num_diagnostics_values).map(|_| String::new())),
+                        }
+                    }
+                } else {
+                    fields.extend((0..num_diagnostics_columns).map(|_| String::new()));
+                }
+            }
+            Err(err) => {
+                fields.push(csv_field(&format!("failed: {}", err)));
+                fields.extend((0..4 + num_diagnostics_columns).map(|_| String::new()));
+            }
+        }
+        writeln!(writer, "{}", fields.join(","))
+    }
+}
@@ -0,0 +1,237 @@
+// Keyframed animation of scene properties over simulation time.
+//
+// Tracks are part of the scene file and are evaluated before every simulation step.
+
+use serde::Deserialize;
+use std::ops::RangeInclusive;
+
+// Properties that can be animated.
+// Gravity is given in world space like the scene's gravity, solver settings apply to a single fluid domain or all of them.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AnimatedProperty {
+    Gravity,
+    VelocitySolverTargetMse,
+    VelocitySolverMaxIterations,
+    DensitySolverTargetMse,
+    DensitySolverMaxIterations,
+}
+
+impl AnimatedProperty {
+    pub fn is_vector(self) -> bool {
+        self == AnimatedProperty::Gravity
+    }
+
+    pub fn is_per_fluid(self) -> bool {
+        self != AnimatedProperty::Gravity
+    }
+
+    // Values (of every component) the property can take on.
+    // Keyframes outside are rejected, interpolated values are clamped to it since Catmull-Rom can overshoot between keyframes.
+    fn valid_range(self) -> RangeInclusive<f32> {
+        match self {
+            AnimatedProperty::Gravity => f32::MIN..=f32::MAX,
+            AnimatedProperty::VelocitySolverTargetMse | AnimatedProperty::DensitySolverTargetMse => f32::MIN_POSITIVE..=f32::MAX,
+            // The pressure solver loop only ends at exactly this iteration count (or once the target mse is reached).
+            AnimatedProperty::VelocitySolverMaxIterations | AnimatedProperty::DensitySolverMaxIterations => 1.0..=i32::MAX as f32,
+        }
+    }
+
+    // valid_range for humans.
+    fn valid_range_description(self) -> &'static str {
+        match self {
+            AnimatedProperty::Gravity => "finite",
+            AnimatedProperty::VelocitySolverTargetMse | AnimatedProperty::DensitySolverTargetMse => "positive and finite",
+            AnimatedProperty::VelocitySolverMaxIterations | AnimatedProperty::DensitySolverMaxIterations => "at least 1",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    // Holds the value of the previous keyframe.
+    Step,
+    Linear,
+    // Ease in & out between keyframes (smoothstep), comes to a halt at every keyframe.
+    Smooth,
+    // Goes smoothly through all keyframes without stopping.
+    CatmullRom,
+}
+
+fn default_interpolation() -> Interpolation {
+    Interpolation::Linear
+}
+
+// Uniform Catmull-Rom spline segment between p1 (t=0) and p2 (t=1).
+pub fn catmull_rom(
+    p0: cgmath::Vector3<f32>,
+    p1: cgmath::Vector3<f32>,
+    p2: cgmath::Vector3<f32>,
+    p3: cgmath::Vector3<f32>,
+    t: f32,
+) -> cgmath::Vector3<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0 + (p2 - p0) * t + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2 + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(untagged)]
+pub enum KeyframeValue {
+    Scalar(f32),
+    Vector(cgmath::Vector3<f32>),
+}
+
+impl KeyframeValue {
+    fn components(self) -> [f32; 3] {
+        match self {
+            KeyframeValue::Scalar(value) => [value, 0.0, 0.0],
+            KeyframeValue::Vector(value) => value.into(),
+        }
+    }
+
+    // Same kind of value as self, but with different components.
+    fn with_components(self, components: [f32; 3]) -> KeyframeValue {
+        match self {
+            KeyframeValue::Scalar(_) => KeyframeValue::Scalar(components[0]),
+            KeyframeValue::Vector(_) => KeyframeValue::Vector(components.into()),
+        }
+    }
+
+    fn is_within(self, range: &RangeInclusive<f32>) -> bool {
+        match self {
+            KeyframeValue::Scalar(value) => range.contains(&value),
+            KeyframeValue::Vector(value) => range.contains(&value.x) && range.contains(&value.y) && range.contains(&value.z),
+        }
+    }
+
+    fn clamp(self, range: &RangeInclusive<f32>) -> KeyframeValue {
+        let components = self.components();
+        self.with_components([
+            components[0].max(*range.start()).min(*range.end()),
+            components[1].max(*range.start()).min(*range.end()),
+            components[2].max(*range.start()).min(*range.end()),
+        ])
+    }
+
+    pub fn scalar(self) -> f32 {
+        self.components()[0]
+    }
+
+    pub fn vector(self) -> cgmath::Vector3<f32> {
+        self.components().into()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    // Simulated time in seconds.
+    pub time: f32,
+    pub value: KeyframeValue,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackConfig {
+    pub property: AnimatedProperty,
+    // Index of the fluid domain for per fluid properties, all domains if not given.
+    #[serde(default)]
+    pub fluid: Option<usize>,
+    #[serde(default = "default_interpolation")]
+    pub interpolation: Interpolation,
+    // Sorted by time.
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl TrackConfig {
+    // Parameters that don't make up a valid track for a scene with the given number of fluid domains.
+    pub fn problems(&self, num_fluids: usize) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.keyframes.is_empty() {
+            problems.push("needs at least one keyframe".to_owned());
+        }
+        match self.fluid {
+            Some(_) if !self.property.is_per_fluid() => problems.push(format!("{:?} applies to all fluids, fluid can't be set", self.property)),
+            Some(fluid) if fluid >= num_fluids => problems.push(format!("fluid {} doesn't exist, scene has {} fluids", fluid, num_fluids)),
+            _ => {}
+        }
+        for (keyframe_index, keyframe) in self.keyframes.iter().enumerate() {
+            if keyframe.time < 0.0 {
+                problems.push(format!("keyframes[{}]: time can't be negative, is {}", keyframe_index, keyframe.time));
+            }
+            if keyframe_index > 0 && keyframe.time <= self.keyframes[keyframe_index - 1].time {
+                problems.push(format!("keyframes[{}]: times need to be increasing", keyframe_index));
+            }
+            match (keyframe.value, self.property.is_vector()) {
+                (KeyframeValue::Scalar(_), true) => problems.push(format!("keyframes[{}]: {:?} needs a vector value", keyframe_index, self.property)),
+                (KeyframeValue::Vector(_), false) => {
+                    problems.push(format!("keyframes[{}]: {:?} needs a scalar value", keyframe_index, self.property))
+                }
+                _ => {}
+            }
+            if !keyframe.value.is_within(&self.property.valid_range()) {
+                problems.push(format!(
+                    "keyframes[{}]: {:?} needs to be {}, is {:?}",
+                    keyframe_index,
+                    self.property,
+                    self.property.valid_range_description(),
+                    keyframe.value
+                ));
+            }
+        }
+        problems
+    }
+
+    // Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Value of the track at the given time in seconds, within the property's valid range.
+    // Constant before the first and after the last keyframe.
+    pub fn evaluate(&self, time: f32) -> KeyframeValue {
+        self.evaluate_unclamped(time).clamp(&self.property.valid_range())
+    }
+
+    fn evaluate_unclamped(&self, time: f32) -> KeyframeValue {
+        let next_index = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time)
+            .unwrap_or(self.keyframes.len());
+        if next_index == 0 {
+            return self.keyframes[0].value;
+        }
+        if next_index == self.keyframes.len() || self.interpolation == Interpolation::Step {
+            return self.keyframes[next_index - 1].value;
+        }
+
+        let previous = &self.keyframes[next_index - 1];
+        let next = &self.keyframes[next_index];
+        let t = (time - previous.time) / (next.time - previous.time);
+        let p1 = previous.value.vector();
+        let p2 = next.value.vector();
+        let result = match self.interpolation {
+            Interpolation::Step => unreachable!(),
+            Interpolation::Linear => p1 + (p2 - p1) * t,
+            Interpolation::Smooth => p1 + (p2 - p1) * (t * t * (3.0 - 2.0 * t)),
+            Interpolation::CatmullRom => {
+                // Missing neighbors at the ends are replaced by the keyframes themselves.
+                let p0 = self.keyframes[next_index.saturating_sub(2)].value.vector();
+                let p3 = self.keyframes[(next_index + 1).min(self.keyframes.len() - 1)].value.vector();
+                catmull_rom(p0, p1, p2, p3, t)
+            }
+        };
+        previous.value.with_components(result.into())
+    }
+
+    pub fn label(&self) -> String {
+        match self.fluid {
+            Some(fluid) => format!("{:?} (fluid domain {})", self.property, fluid),
+            None if self.property.is_per_fluid() => format!("{:?} (all fluid domains)", self.property),
+            None => format!("{:?}", self.property),
+        }
+    }
+}
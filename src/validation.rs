@@ -0,0 +1,448 @@
+use crate::{
+    headless,
+    per_frame_resources::PerFrameResources,
+    scene::Scene,
+    simulation::{HybridFluid, ParticleState, SimulationVolume},
+    simulation_controller::SimulationController,
+    timer::Timer,
+    wgpu_utils::{pipelines::PipelineManager, profiler::GpuProfiler, shader::ShaderDirectory},
+};
+use cgmath::InnerSpace;
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use strum::IntoEnumIterator;
+
+// Validation suite: steps scenes with analytic or experimental reference data and checks measured quantities against tolerances.
+// Meant to objectively check changes to the solver or shaders, run with --validate.
+//
+// All measurements happen in grid space (see ParticleState), the scenes in scenes/validation are set up so that the fluid touches the lower walls
+// of the grid, which are at grid coordinate 1 since the first layer of cells is always solid.
+
+const VALIDATION_SCENE_DIRECTORY: &str = "scenes/validation";
+
+// Surge front position of a collapsing water column over time, Martin & Moyce 1952, column with height twice its width (n² = 2, a = 2.25in).
+// Columns are T = t * sqrt(2g/a), Z = z/a where z is the distance of the front from the wall the column started at.
+// Values as commonly reproduced in the literature on particle based fluid simulation.
+const MARTIN_MOYCE_SURGE_FRONT: [(f32, f32); 15] = [
+    (0.41, 1.11),
+    (0.84, 1.22),
+    (1.19, 1.44),
+    (1.43, 1.67),
+    (1.63, 1.89),
+    (1.83, 2.11),
+    (1.98, 2.33),
+    (2.20, 2.56),
+    (2.32, 2.78),
+    (2.51, 3.00),
+    (2.65, 3.22),
+    (2.83, 3.44),
+    (2.98, 3.67),
+    (3.11, 3.89),
+    (3.33, 4.11),
+];
+
+#[derive(Clone, Copy, Debug, EnumIter, PartialEq, Eq)]
+pub enum ValidationCase {
+    // Tank filled with fluid at rest, which needs to stay at rest with pressure linear in depth.
+    HydrostaticTank,
+    // Tank with the free surface approximating the first standing wave mode, oscillates with an analytically known period.
+    SloshingTank,
+    // Collapsing water column, surge front is compared to the experiment of Martin & Moyce.
+    DamBreak,
+}
+
+impl ValidationCase {
+    pub fn name(self) -> &'static str {
+        match self {
+            ValidationCase::HydrostaticTank => "hydrostatic_tank",
+            ValidationCase::SloshingTank => "sloshing_tank",
+            ValidationCase::DamBreak => "dam_break",
+        }
+    }
+
+    pub fn scene_path(self) -> PathBuf {
+        Path::new(VALIDATION_SCENE_DIRECTORY).join(format!("{}.json", self.name()))
+    }
+
+    fn simulation_length(self) -> Duration {
+        match self {
+            ValidationCase::HydrostaticTank => Duration::from_secs(2),
+            // A bit more than two periods.
+            ValidationCase::SloshingTank => Duration::from_secs(4),
+            // Last reference value is at T = 3.33, which is at about 0.3s for the column in the scene.
+            ValidationCase::DamBreak => Duration::from_millis(350),
+        }
+    }
+
+    // Particles are read back & measured every n steps.
+    fn measurement_interval_steps(self) -> u32 {
+        match self {
+            ValidationCase::HydrostaticTank => 12,
+            ValidationCase::SloshingTank | ValidationCase::DamBreak => 1,
+        }
+    }
+
+    // Quantity tracked over time, in grid space.
+    fn measure(self, particles: &[ParticleState], grid_dimension: wgpu::Extent3d) -> f32 {
+        match self {
+            // Root mean square particle speed.
+            ValidationCase::HydrostaticTank => {
+                (particles.iter().map(|p| p.velocity.magnitude2()).sum::<f32>() / particles.len().max(1) as f32).sqrt()
+            }
+            // Difference of the mean particle height in the left and right half of the tank.
+            // Proportional to the amplitude of the first mode, the tank is symmetric so it is zero at rest.
+            ValidationCase::SloshingTank => {
+                let center = (grid_dimension.width + 1) as f32 * 0.5;
+                let mean_height = |left: bool| {
+                    let (sum, count) = particles
+                        .iter()
+                        .filter(|p| (p.position.x < center) == left)
+                        .fold((0.0, 0), |(sum, count), p| (sum + p.position.y, count + 1));
+                    sum / count.max(1) as f32
+                };
+                mean_height(true) - mean_height(false)
+            }
+            // Distance of the surge front from the left wall, taken close to the floor.
+            // Ignores the foremost 0.1% of particles to be robust against single particles splashing ahead.
+            ValidationCase::DamBreak => {
+                let mut front_positions: Vec<f32> = particles.iter().filter(|p| p.position.y < 3.0).map(|p| p.position.x - 1.0).collect();
+                if front_positions.is_empty() {
+                    return std::f32::NAN;
+                }
+                front_positions.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                front_positions[(front_positions.len() - 1) * 999 / 1000]
+            }
+        }
+    }
+
+    // Evaluates the measurements (pairs of simulated seconds and measured value) and the final state of the fluid.
+    fn evaluate(
+        self,
+        measurements: &[(f32, f32)],
+        fluid: &HybridFluid,
+        gravity_grid: f32,
+        simulation_delta: Duration,
+        grid_to_world_scale: f32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<ValidationCheck> {
+        match self {
+            ValidationCase::HydrostaticTank => {
+                let particles = fluid.read_particles(device, queue);
+                let max_speed = particles.iter().map(|p| p.velocity.magnitude()).fold(0.0, f32::max);
+                let rms_speed = measurements.last().map_or(std::f32::NAN, |(_, rms_speed)| *rms_speed);
+                let (pressure_gradient, pressure_fit_quality) = Self::fit_pressure_over_depth(fluid, device, queue);
+
+                vec![
+                    ValidationCheck::new("rms particle speed [m/s]", rms_speed * grid_to_world_scale, 0.0..=0.02),
+                    ValidationCheck::new("max particle speed [m/s]", max_speed * grid_to_world_scale, 0.0..=0.2),
+                    // The pressure we compute is premultiplied with dTime/density, so at rest it has to grow by gravity * dTime per cell.
+                    ValidationCheck::new(
+                        "pressure gradient over depth / (gravity * dTime)",
+                        pressure_gradient.abs() / (gravity_grid * simulation_delta.as_secs_f32()),
+                        0.9..=1.1,
+                    ),
+                    ValidationCheck::new("R² of linear fit of pressure over depth", pressure_fit_quality, 0.99..=1.0),
+                ]
+            }
+            ValidationCase::SloshingTank => {
+                // Linear wave theory for the first mode: omega² = g k tanh(k h), wave number k = pi / tank length, h the depth at rest.
+                let grid_dimension = fluid.grid_dimension();
+                let tank_length = (grid_dimension.width - 1) as f32;
+                let tank_width = (grid_dimension.depth - 1) as f32;
+                let depth = fluid.num_particles() as f32 / (fluid.particles_per_cell() as f32 * tank_length * tank_width);
+                let wave_number = std::f32::consts::PI / tank_length;
+                let analytic_period = 2.0 * std::f32::consts::PI / (gravity_grid * wave_number * (wave_number * depth).tanh()).sqrt();
+
+                // Each zero crossing of the height difference is half a period.
+                let zero_crossings: Vec<f32> = measurements
+                    .windows(2)
+                    .filter(|w| (w[0].1 > 0.0) != (w[1].1 > 0.0))
+                    .map(|w| w[0].0 + (w[1].0 - w[0].0) * w[0].1 / (w[0].1 - w[1].1))
+                    .collect();
+                let measured_period = if zero_crossings.len() >= 2 {
+                    2.0 * (zero_crossings.last().unwrap() - zero_crossings[0]) / (zero_crossings.len() - 1) as f32
+                } else {
+                    std::f32::NAN
+                };
+
+                vec![
+                    ValidationCheck::new(
+                        "number of zero crossings of the surface tilt",
+                        zero_crossings.len() as f32,
+                        3.0..=std::f32::MAX,
+                    ),
+                    ValidationCheck::new("sloshing period / analytic period", measured_period / analytic_period, 0.9..=1.1),
+                ]
+            }
+            ValidationCase::DamBreak => {
+                let column_width = measurements.first().map_or(std::f32::NAN, |(_, front)| *front);
+                let time_scale = (2.0 * gravity_grid / column_width).sqrt();
+
+                // Compare at the reference points by linearly interpolating between measurements.
+                let relative_errors: Vec<f32> = MARTIN_MOYCE_SURGE_FRONT
+                    .iter()
+                    .filter_map(|&(reference_time, reference_front)| {
+                        let time = reference_time / time_scale;
+                        measurements.windows(2).find(|w| w[0].0 <= time && time <= w[1].0).map(|w| {
+                            let front = w[0].1 + (w[1].1 - w[0].1) * (time - w[0].0) / (w[1].0 - w[0].0);
+                            (front / column_width - reference_front) / reference_front
+                        })
+                    })
+                    .collect();
+                let rms_error = (relative_errors.iter().map(|e| e * e).sum::<f32>() / relative_errors.len() as f32).sqrt();
+                let max_error = relative_errors.iter().map(|e| e.abs()).fold(0.0, f32::max);
+
+                vec![
+                    ValidationCheck::new(
+                        "number of compared reference points",
+                        relative_errors.len() as f32,
+                        MARTIN_MOYCE_SURGE_FRONT.len() as f32..=MARTIN_MOYCE_SURGE_FRONT.len() as f32,
+                    ),
+                    ValidationCheck::new("rms relative surge front error", rms_error, 0.0..=0.15),
+                    ValidationCheck::new("max relative surge front error", max_error, 0.0..=0.3),
+                ]
+            }
+        }
+    }
+
+    // Least squares fit of the mean pressure of each horizontal layer of fluid cells over height.
+    // The topmost layer is excluded since it is usually only partially filled.
+    // Returns the gradient per cell and the coefficient of determination.
+    fn fit_pressure_over_depth(fluid: &HybridFluid, device: &wgpu::Device, queue: &wgpu::Queue) -> (f32, f32) {
+        let grid_dimension = fluid.grid_dimension();
+        let (marker, pressure) = match (
+            fluid.read_volume(device, queue, SimulationVolume::Marker),
+            fluid.read_volume(device, queue, SimulationVolume::PressureFromVelocity),
+        ) {
+            (Some(marker), Some(pressure)) => (marker, pressure),
+            _ => return (std::f32::NAN, std::f32::NAN),
+        };
+
+        let mut layers: Vec<(f32, f32)> = Vec::new();
+        for y in 0..grid_dimension.height as usize {
+            let mut sum = 0.0;
+            let mut count = 0;
+            for z in 0..grid_dimension.depth as usize {
+                for x in 0..grid_dimension.width as usize {
+                    let index = x + grid_dimension.width as usize * (y + grid_dimension.height as usize * z);
+                    if marker[index] == 1.0 {
+                        sum += pressure[index];
+                        count += 1;
+                    }
+                }
+            }
+            if count > 0 {
+                layers.push((y as f32, sum / count as f32));
+            }
+        }
+        layers.pop();
+        if layers.len() < 2 {
+            return (std::f32::NAN, std::f32::NAN);
+        }
+
+        let n = layers.len() as f32;
+        let mean_y = layers.iter().map(|(y, _)| y).sum::<f32>() / n;
+        let mean_p = layers.iter().map(|(_, p)| p).sum::<f32>() / n;
+        let covariance: f32 = layers.iter().map(|(y, p)| (y - mean_y) * (p - mean_p)).sum();
+        let variance_y: f32 = layers.iter().map(|(y, _)| (y - mean_y) * (y - mean_y)).sum();
+        let gradient = covariance / variance_y;
+
+        let total_sum_of_squares: f32 = layers.iter().map(|(_, p)| (p - mean_p) * (p - mean_p)).sum();
+        let residual_sum_of_squares: f32 = layers
+            .iter()
+            .map(|(y, p)| {
+                let residual = p - (mean_p + gradient * (y - mean_y));
+                residual * residual
+            })
+            .sum();
+        (gradient, 1.0 - residual_sum_of_squares / total_sum_of_squares)
+    }
+}
+
+pub struct ValidationCheck {
+    pub name: &'static str,
+    pub measured: f32,
+    pub accepted: RangeInclusive<f32>,
+}
+
+impl ValidationCheck {
+    fn new(name: &'static str, measured: f32, accepted: RangeInclusive<f32>) -> Self {
+        ValidationCheck { name, measured, accepted }
+    }
+
+    // NaN never passes.
+    pub fn passed(&self) -> bool {
+        self.accepted.contains(&self.measured)
+    }
+}
+
+pub struct ValidationResult {
+    pub case: ValidationCase,
+    pub checks: Vec<ValidationCheck>,
+    pub num_steps: u32,
+    pub computation_time: Duration,
+}
+
+impl ValidationResult {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed())
+    }
+}
+
+impl fmt::Display for ValidationResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({} steps, took {:?})",
+            self.case.name(),
+            if self.passed() { "PASSED" } else { "FAILED" },
+            self.num_steps,
+            self.computation_time
+        )?;
+        for check in self.checks.iter() {
+            write!(
+                f,
+                "\n    [{}] {}: {} (accepted {} to {})",
+                if check.passed() { "ok" } else { "FAIL" },
+                check.name,
+                check.measured,
+                check.accepted.start(),
+                check.accepted.end()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ValidationRunner {
+    device: wgpu::Device,
+    command_queue: wgpu::Queue,
+
+    shader_dir: ShaderDirectory,
+    pipeline_manager: PipelineManager,
+    per_frame_resources: PerFrameResources,
+    profiler: GpuProfiler,
+}
+
+impl ValidationRunner {
+    pub async fn new() -> Result<ValidationRunner, String> {
+        let (device, command_queue) = headless::create_device().await?;
+        let per_frame_resources = PerFrameResources::new(&device);
+        let profiler = GpuProfiler::new(&device, &command_queue);
+        Ok(ValidationRunner {
+            device,
+            command_queue,
+
+            shader_dir: ShaderDirectory::new(Path::new("shader")),
+            pipeline_manager: PipelineManager::new(),
+            per_frame_resources,
+            profiler,
+        })
+    }
+
+    // Runs all validation cases with the default simulation settings. Returns an error listing the failed cases if any failed.
+    pub fn run_all(&mut self) -> Result<(), String> {
+        let mut failed_cases = Vec::new();
+        for case in ValidationCase::iter() {
+            match self.run(case) {
+                Ok(result) => {
+                    if result.passed() {
+                        info!("{}", result);
+                    } else {
+                        error!("{}", result);
+                        failed_cases.push(case.name());
+                    }
+                }
+                Err(err) => {
+                    error!("{}: {}", case.name(), err);
+                    failed_cases.push(case.name());
+                }
+            }
+        }
+
+        if failed_cases.is_empty() {
+            info!("All {} validation cases passed", ValidationCase::iter().count());
+            Ok(())
+        } else {
+            Err(format!("Validation failed for {}", failed_cases.join(", ")))
+        }
+    }
+
+    pub fn run(&mut self, case: ValidationCase) -> Result<ValidationResult, String> {
+        let scene_path = case.scene_path();
+        info!("Running validation case {} ({:?})", case.name(), scene_path);
+
+        let mut scene = Scene::new(
+            &scene_path,
+            &self.device,
+            &self.command_queue,
+            &self.shader_dir,
+            &mut self.pipeline_manager,
+            self.per_frame_resources.bind_group_layout(),
+        )
+        .map_err(|err| err.to_string())?;
+        if scene.fluids().len() != 1 {
+            return Err(format!("Validation scene {:?} needs to have exactly one fluid", scene_path));
+        }
+        let gravity_grid = scene.config().gravity.magnitude() / scene.config().fluids[0].grid_to_world_scale;
+        let grid_to_world_scale = scene.config().fluids[0].grid_to_world_scale;
+
+        let simulation_delta = SimulationController::new().timer().simulation_delta();
+        // Simulation shaders only read the simulation delta from the per frame resources.
+        self.per_frame_resources
+            .update_gpu_data_simulation_only(&self.command_queue, Timer::new(simulation_delta).fill_global_uniform_buffer());
+
+        let start_time = Instant::now();
+        let num_steps = (case.simulation_length().as_nanos() / simulation_delta.as_nanos()) as u32;
+        let mut measurements = vec![(
+            0.0,
+            case.measure(
+                &scene.fluids()[0].read_particles(&self.device, &self.command_queue),
+                scene.fluids()[0].grid_dimension(),
+            ),
+        )];
+        for step in 1..=num_steps {
+            scene.step(
+                simulation_delta * (step - 1),
+                simulation_delta,
+                &self.device,
+                &self.pipeline_manager,
+                &self.command_queue,
+                self.per_frame_resources.bind_group(),
+                &mut self.profiler,
+            );
+            // Reading back waits for the gpu, otherwise give it some breathing space regularly (see SimulationController::fast_forward_steps)
+            if step % case.measurement_interval_steps() == 0 {
+                let particles = scene.fluids()[0].read_particles(&self.device, &self.command_queue);
+                measurements.push((
+                    simulation_delta.as_secs_f32() * step as f32,
+                    case.measure(&particles, scene.fluids()[0].grid_dimension()),
+                ));
+            } else if step % 16 == 0 {
+                self.device.poll(wgpu::Maintain::Wait);
+            }
+        }
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let checks = case.evaluate(
+            &measurements,
+            &scene.fluids()[0],
+            gravity_grid,
+            simulation_delta,
+            grid_to_world_scale,
+            &self.device,
+            &self.command_queue,
+        );
+        Ok(ValidationResult {
+            case,
+            checks,
+            num_steps,
+            computation_time: start_time.elapsed(),
+        })
+    }
+}
@@ -8,6 +8,12 @@ pub struct BindGroupLayoutBuilder {
     next_binding_index: u32,
 }
 
+impl Default for BindGroupLayoutBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BindGroupLayoutBuilder {
     pub fn new() -> Self {
         BindGroupLayoutBuilder {
@@ -3,9 +3,24 @@ pub mod binding_builder;
 #[allow(non_snake_case)]
 pub mod binding_glsl;
 pub mod pipelines;
+pub mod profiler;
+pub mod readback;
 pub mod shader;
 pub mod uniformbuffer;
 
+// Device features & limits all our pipelines rely on, for both windowed and headless operation.
+// Optional features are enabled if the adapter supports them.
+pub fn device_descriptor(adapter: &wgpu::Adapter) -> wgpu::DeviceDescriptor {
+    wgpu::DeviceDescriptor {
+        features: wgpu::Features::PUSH_CONSTANTS | (adapter.features() & wgpu::Features::TIMESTAMP_QUERY),
+        limits: wgpu::Limits {
+            max_push_constant_size: 8,
+            ..Default::default()
+        },
+        shader_validation: true,
+    }
+}
+
 pub fn compute_group_size(resource_size: wgpu::Extent3d, group_local_size: wgpu::Extent3d) -> wgpu::Extent3d {
     wgpu::Extent3d {
         width: (resource_size.width + group_local_size.width - 1) / group_local_size.width,
@@ -18,16 +33,22 @@ pub fn compute_group_size_1d(resource_size: u32, group_local_size: u32) -> u32 {
     (resource_size + group_local_size - 1) / group_local_size
 }
 
+// Marks a profiler scope (see profiler::GpuProfiler), which is also a debug group.
+// Without code, the scope lasts until the end of the enclosing block. Note that encoder/pass and profiler are shadowed by references to themselves then.
 macro_rules! wgpu_scope {
-    ($encoder_or_pass:ident, $label:expr) => {
-        $encoder_or_pass.push_debug_group($label);
-        #[allow(unused_mut)]
-        let mut $encoder_or_pass = scopeguard::guard($encoder_or_pass, |mut encoder_or_pass| encoder_or_pass.pop_debug_group());
+    ($encoder_or_pass:ident, $profiler:ident, $label:expr) => {
+        $crate::wgpu_utils::profiler::ProfilerCommandRecorder::begin_profiler_scope(&mut *$encoder_or_pass, $label, $profiler);
+        let mut scope_guard = scopeguard::guard(($encoder_or_pass, $profiler), |(encoder_or_pass, profiler)| {
+            $crate::wgpu_utils::profiler::ProfilerCommandRecorder::end_profiler_scope(encoder_or_pass, profiler)
+        });
+        #[allow(unused_variables)]
+        let (ref mut $encoder_or_pass, ref mut $profiler) = *scope_guard;
     };
-    ($encoder_or_pass:ident, $label:expr, $code:expr) => {{
-        $encoder_or_pass.push_debug_group($label);
+    ($encoder_or_pass:ident, $profiler:ident, $label:expr, $code:expr) => {{
+        use $crate::wgpu_utils::profiler::ProfilerCommandRecorder;
+        $encoder_or_pass.begin_profiler_scope($label, $profiler);
         let ret = $code();
-        $encoder_or_pass.pop_debug_group();
+        $encoder_or_pass.end_profiler_scope($profiler);
         ret
     }};
 }
@@ -166,6 +166,12 @@ pub struct PipelineManager {
     render_pipelines: Vec<ReloadableRenderPipeline>,
 }
 
+impl Default for PipelineManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PipelineManager {
     pub fn new() -> Self {
         PipelineManager {
@@ -0,0 +1,428 @@
+use futures::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    pin::Pin,
+    time::Instant,
+};
+
+// Profiler for hierarchical scopes as marked by wgpu_scope!
+//
+// Every scope records how long it took to record on the cpu and, if the adapter supports timestamp queries, how long it took to execute on the gpu.
+// Scopes are still debug groups as well, so they keep showing up in external debuggers.
+//
+// Timestamps of a frame go into a query set which is resolved at the end of the frame (see resolve_queries) and read back asynchronously.
+// Results become available a few frames later via process_finished_frames.
+
+// Anything that scopes can be recorded on.
+pub trait ProfilerCommandRecorder {
+    fn write_timestamp(&mut self, query_set: &wgpu::QuerySet, query_index: u32);
+    fn push_debug_group(&mut self, label: &str);
+    fn pop_debug_group(&mut self);
+
+    // Method syntax makes wgpu_scope! work no matter if it's given a recorder or a reference to one.
+    fn begin_profiler_scope(&mut self, label: &str, profiler: &mut GpuProfiler)
+    where
+        Self: Sized,
+    {
+        profiler.begin_scope(label, self);
+    }
+
+    fn end_profiler_scope(&mut self, profiler: &mut GpuProfiler)
+    where
+        Self: Sized,
+    {
+        profiler.end_scope(self);
+    }
+}
+
+impl ProfilerCommandRecorder for wgpu::CommandEncoder {
+    fn write_timestamp(&mut self, query_set: &wgpu::QuerySet, query_index: u32) {
+        wgpu::CommandEncoder::write_timestamp(self, query_set, query_index)
+    }
+    fn push_debug_group(&mut self, label: &str) {
+        wgpu::CommandEncoder::push_debug_group(self, label)
+    }
+    fn pop_debug_group(&mut self) {
+        wgpu::CommandEncoder::pop_debug_group(self)
+    }
+}
+
+impl<'a> ProfilerCommandRecorder for wgpu::ComputePass<'a> {
+    fn write_timestamp(&mut self, query_set: &wgpu::QuerySet, query_index: u32) {
+        wgpu::ComputePass::write_timestamp(self, query_set, query_index)
+    }
+    fn push_debug_group(&mut self, label: &str) {
+        wgpu::ComputePass::push_debug_group(self, label)
+    }
+    fn pop_debug_group(&mut self) {
+        wgpu::ComputePass::pop_debug_group(self)
+    }
+}
+
+impl<'a> ProfilerCommandRecorder for wgpu::RenderPass<'a> {
+    fn write_timestamp(&mut self, query_set: &wgpu::QuerySet, query_index: u32) {
+        wgpu::RenderPass::write_timestamp(self, query_set, query_index)
+    }
+    fn push_debug_group(&mut self, label: &str) {
+        wgpu::RenderPass::push_debug_group(self, label)
+    }
+    fn pop_debug_group(&mut self) {
+        wgpu::RenderPass::pop_debug_group(self)
+    }
+}
+
+#[derive(Clone)]
+pub struct ProfilerScope {
+    pub label: String,
+    // In seconds since the profiler was created.
+    pub cpu: Range<f64>,
+    // In seconds on the gpu's own timeline. None if timestamp queries aren't supported or the frame ran out of queries.
+    pub gpu: Option<Range<f64>>,
+    pub children: Vec<ProfilerScope>,
+}
+
+pub struct ProfilerFrame {
+    pub frame_index: u64,
+    // In seconds since the profiler was created.
+    pub cpu: Range<f64>,
+    pub scopes: Vec<ProfilerScope>,
+}
+
+struct UnfinishedScope {
+    label: String,
+    cpu: Range<f64>,
+    // Start timestamp, end timestamp is the next query.
+    query_index: Option<u32>,
+    children: Vec<UnfinishedScope>,
+}
+
+struct QueryPool {
+    query_set: wgpu::QuerySet,
+    // Queries are resolved directly into this buffer.
+    readback_buffer: wgpu::Buffer,
+    num_used_queries: u32,
+}
+
+struct PendingFrame {
+    frame_index: u64,
+    cpu: Range<f64>,
+    scopes: Vec<UnfinishedScope>,
+    query_pool: Option<QueryPool>,
+    map_operation: Option<Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>>>>>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct ScopeTotals {
+    cpu: f64,
+    gpu: f64,
+    num_frames: u32,
+    num_frames_gpu: u32,
+}
+
+pub struct GpuProfiler {
+    // Changes take effect with the next frame.
+    pub enabled: bool,
+
+    start_time: Instant,
+    // Seconds per timestamp tick, None if timestamp queries aren't supported.
+    timestamp_period: Option<f64>,
+
+    frame_index: u64,
+    profiling_current_frame: bool,
+    current_frame_cpu_start: f64,
+    current_query_pool: Option<QueryPool>,
+    current_frame_scopes: Vec<UnfinishedScope>,
+    open_scopes: Vec<UnfinishedScope>,
+    warned_about_query_overflow: bool,
+
+    unused_query_pools: Vec<QueryPool>,
+    pending_frames: VecDeque<PendingFrame>,
+
+    finished_frames: VecDeque<ProfilerFrame>,
+    // Totals over all finished frames, by scope path.
+    scope_totals: HashMap<String, ScopeTotals>,
+}
+
+impl GpuProfiler {
+    // Every scope needs two queries. The pressure solver alone has a few hundred scopes per simulation step!
+    const MAX_QUERIES_PER_FRAME: u32 = 8192;
+    // Frames in flight, if the gpu falls further behind, frames are only profiled on the cpu.
+    const NUM_QUERY_POOLS: usize = 4;
+    const FINISHED_FRAME_HISTORY_LENGTH: usize = 120;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let timestamp_period = if device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            Some(queue.get_timestamp_period() as f64 * 1.0e-9)
+        } else {
+            info!("Adapter doesn't support timestamp queries, profiler will only record cpu timings");
+            None
+        };
+
+        let mut unused_query_pools = Vec::new();
+        if timestamp_period.is_some() {
+            for i in 0..Self::NUM_QUERY_POOLS {
+                unused_query_pools.push(QueryPool {
+                    query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                        ty: wgpu::QueryType::Timestamp,
+                        count: Self::MAX_QUERIES_PER_FRAME,
+                    }),
+                    readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(&format!("Buffer: Profiler timestamps {}", i)),
+                        size: Self::MAX_QUERIES_PER_FRAME as u64 * std::mem::size_of::<u64>() as u64,
+                        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    num_used_queries: 0,
+                });
+            }
+        }
+
+        GpuProfiler {
+            enabled: false,
+
+            start_time: Instant::now(),
+            timestamp_period,
+
+            frame_index: 0,
+            profiling_current_frame: false,
+            current_frame_cpu_start: 0.0,
+            current_query_pool: None,
+            current_frame_scopes: Vec::new(),
+            open_scopes: Vec::new(),
+            warned_about_query_overflow: false,
+
+            unused_query_pools,
+            pending_frames: VecDeque::new(),
+
+            finished_frames: VecDeque::new(),
+            scope_totals: HashMap::new(),
+        }
+    }
+
+    pub fn timestamps_supported(&self) -> bool {
+        self.timestamp_period.is_some()
+    }
+
+    fn seconds_since_start(&self) -> f64 {
+        self.start_time.elapsed().as_secs_f64()
+    }
+
+    // Use wgpu_scope! instead of calling this directly.
+    pub fn begin_scope<Recorder: ProfilerCommandRecorder>(&mut self, label: &str, recorder: &mut Recorder) {
+        recorder.push_debug_group(label);
+        if !self.profiling_current_frame {
+            return;
+        }
+
+        let mut query_index = None;
+        if let Some(query_pool) = &mut self.current_query_pool {
+            if query_pool.num_used_queries + 2 <= Self::MAX_QUERIES_PER_FRAME {
+                recorder.write_timestamp(&query_pool.query_set, query_pool.num_used_queries);
+                query_index = Some(query_pool.num_used_queries);
+                query_pool.num_used_queries += 2;
+            } else if !self.warned_about_query_overflow {
+                warn!("Ran out of timestamp queries, remaining scopes of this frame are only profiled on the cpu");
+                self.warned_about_query_overflow = true;
+            }
+        }
+
+        let now = self.seconds_since_start();
+        self.open_scopes.push(UnfinishedScope {
+            label: label.to_string(),
+            cpu: now..now,
+            query_index,
+            children: Vec::new(),
+        });
+    }
+
+    // Use wgpu_scope! instead of calling this directly.
+    pub fn end_scope<Recorder: ProfilerCommandRecorder>(&mut self, recorder: &mut Recorder) {
+        if self.profiling_current_frame {
+            if let Some(mut scope) = self.open_scopes.pop() {
+                if let (Some(query_index), Some(query_pool)) = (scope.query_index, &self.current_query_pool) {
+                    recorder.write_timestamp(&query_pool.query_set, query_index + 1);
+                }
+                scope.cpu.end = self.seconds_since_start();
+                match self.open_scopes.last_mut() {
+                    Some(parent) => parent.children.push(scope),
+                    None => self.current_frame_scopes.push(scope),
+                }
+            }
+        }
+        recorder.pop_debug_group();
+    }
+
+    // Needs to be called with the last encoder submitted in a frame, after all scopes of the frame.
+    pub fn resolve_queries(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(query_pool) = &self.current_query_pool {
+            if query_pool.num_used_queries > 0 {
+                encoder.resolve_query_set(&query_pool.query_set, 0..query_pool.num_used_queries, &query_pool.readback_buffer, 0);
+            }
+        }
+    }
+
+    // Call after submitting the encoder passed to resolve_queries.
+    pub fn end_frame(&mut self) {
+        let now = self.seconds_since_start();
+
+        if self.profiling_current_frame {
+            if !self.open_scopes.is_empty() {
+                warn!("Profiler frame ended with {} open scopes, discarding them", self.open_scopes.len());
+                self.open_scopes.clear();
+            }
+
+            let query_pool = self.current_query_pool.take();
+            let map_operation = match &query_pool {
+                Some(query_pool) if query_pool.num_used_queries > 0 => Some(
+                    query_pool
+                        .readback_buffer
+                        .slice(0..query_pool.num_used_queries as u64 * std::mem::size_of::<u64>() as u64)
+                        .map_async(wgpu::MapMode::Read)
+                        .boxed(),
+                ),
+                _ => None,
+            };
+            self.pending_frames.push_back(PendingFrame {
+                frame_index: self.frame_index,
+                cpu: self.current_frame_cpu_start..now,
+                scopes: std::mem::take(&mut self.current_frame_scopes),
+                query_pool,
+                map_operation,
+            });
+        }
+
+        self.frame_index += 1;
+        self.current_frame_cpu_start = now;
+        self.warned_about_query_overflow = false;
+        self.profiling_current_frame = self.enabled;
+        if self.profiling_current_frame {
+            self.current_query_pool = self.unused_query_pools.pop().map(|mut query_pool| {
+                query_pool.num_used_queries = 0;
+                query_pool
+            });
+        }
+    }
+
+    // Checks for frames whose timestamps arrived on the cpu and moves them into the history of finished frames.
+    pub fn process_finished_frames(&mut self) {
+        while let Some(mut frame) = self.pending_frames.pop_front() {
+            let timestamps: Vec<u64> = match frame.map_operation.as_mut().map(|map_operation| map_operation.now_or_never()) {
+                Some(None) => {
+                    self.pending_frames.push_front(frame);
+                    break;
+                }
+                Some(Some(Ok(()))) => {
+                    let query_pool = frame.query_pool.as_ref().unwrap();
+                    let timestamps = query_pool
+                        .readback_buffer
+                        .slice(0..query_pool.num_used_queries as u64 * std::mem::size_of::<u64>() as u64)
+                        .get_mapped_range()
+                        .chunks_exact(std::mem::size_of::<u64>())
+                        .map(|bytes| *bytemuck::from_bytes::<u64>(bytes))
+                        .collect();
+                    query_pool.readback_buffer.unmap();
+                    timestamps
+                }
+                Some(Some(Err(_))) | None => Vec::new(),
+            };
+            if let Some(query_pool) = frame.query_pool.take() {
+                self.unused_query_pools.push(query_pool);
+            }
+
+            let timestamp_period = self.timestamp_period.unwrap_or(0.0);
+            let finished_frame = ProfilerFrame {
+                frame_index: frame.frame_index,
+                cpu: frame.cpu,
+                scopes: frame
+                    .scopes
+                    .into_iter()
+                    .map(|scope| Self::finish_scope(scope, &timestamps, timestamp_period))
+                    .collect(),
+            };
+            Self::accumulate_scope_totals(&mut self.scope_totals, &finished_frame, 1.0);
+            self.finished_frames.push_back(finished_frame);
+            while self.finished_frames.len() > Self::FINISHED_FRAME_HISTORY_LENGTH {
+                let oldest_frame = self.finished_frames.pop_front().unwrap();
+                Self::accumulate_scope_totals(&mut self.scope_totals, &oldest_frame, -1.0);
+            }
+        }
+    }
+
+    fn finish_scope(scope: UnfinishedScope, timestamps: &[u64], timestamp_period: f64) -> ProfilerScope {
+        let gpu = scope.query_index.and_then(
+            |query_index| match (timestamps.get(query_index as usize), timestamps.get(query_index as usize + 1)) {
+                (Some(&start), Some(&end)) => Some(start as f64 * timestamp_period..end as f64 * timestamp_period),
+                _ => None,
+            },
+        );
+        ProfilerScope {
+            label: scope.label,
+            cpu: scope.cpu,
+            gpu,
+            children: scope
+                .children
+                .into_iter()
+                .map(|child| Self::finish_scope(child, timestamps, timestamp_period))
+                .collect(),
+        }
+    }
+
+    // Adds (sign 1) or removes (sign -1) a frame from the scope totals.
+    // Scopes with the same path that occur several times in a frame (e.g. several simulation steps) are summed up.
+    fn accumulate_scope_totals(scope_totals: &mut HashMap<String, ScopeTotals>, frame: &ProfilerFrame, sign: f64) {
+        fn sum_scopes(frame_totals: &mut HashMap<String, (f64, Option<f64>)>, scopes: &[ProfilerScope], parent_path: &str) {
+            for scope in scopes.iter() {
+                let path = GpuProfiler::scope_path(parent_path, &scope.label);
+                let totals = frame_totals.entry(path.clone()).or_insert((0.0, None));
+                totals.0 += scope.cpu.end - scope.cpu.start;
+                if let Some(gpu) = &scope.gpu {
+                    totals.1 = Some(totals.1.unwrap_or(0.0) + (gpu.end - gpu.start));
+                }
+                sum_scopes(frame_totals, &scope.children, &path);
+            }
+        }
+        let mut frame_totals = HashMap::new();
+        sum_scopes(&mut frame_totals, &frame.scopes, "");
+
+        for (path, (cpu, gpu)) in frame_totals.into_iter() {
+            let totals = scope_totals.entry(path.clone()).or_default();
+            totals.cpu += cpu * sign;
+            totals.num_frames = (totals.num_frames as f64 + sign) as u32;
+            if let Some(gpu) = gpu {
+                totals.gpu += gpu * sign;
+                totals.num_frames_gpu = (totals.num_frames_gpu as f64 + sign) as u32;
+            }
+            if totals.num_frames == 0 {
+                scope_totals.remove(&path);
+            }
+        }
+    }
+
+    // Identifies a scope within a frame, see scope_average.
+    pub fn scope_path(parent_path: &str, label: &str) -> String {
+        if parent_path.is_empty() {
+            label.to_string()
+        } else {
+            format!("{}/{}", parent_path, label)
+        }
+    }
+
+    // Average cpu & gpu time in seconds a scope took per frame, over all recent frames it occurred in.
+    pub fn scope_average(&self, path: &str) -> Option<(f64, Option<f64>)> {
+        self.scope_totals.get(path).map(|totals| {
+            (
+                totals.cpu / totals.num_frames.max(1) as f64,
+                if totals.num_frames_gpu > 0 {
+                    Some(totals.gpu / totals.num_frames_gpu as f64)
+                } else {
+                    None
+                },
+            )
+        })
+    }
+
+    pub fn finished_frames(&self) -> &VecDeque<ProfilerFrame> {
+        &self.finished_frames
+    }
+}
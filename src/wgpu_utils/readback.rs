@@ -0,0 +1,84 @@
+// Synchronous readback of gpu resources.
+// These stall until the gpu caught up with all previous work, so they are meant for exports & debugging, not for per-frame use!
+
+// Copies the first num_elements elements of a buffer (needs COPY_SRC usage) back to the cpu.
+pub fn read_buffer<T: bytemuck::Pod>(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, num_elements: usize) -> Vec<T> {
+    let mut result = vec![T::zeroed(); num_elements];
+    let size = (num_elements * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+    if size == 0 {
+        return result;
+    }
+
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Buffer: Readback staging"),
+        size,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Encoder: Buffer readback"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    {
+        let staging_buffer_slice = staging_buffer.slice(..);
+        let mapping = staging_buffer_slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).unwrap();
+        bytemuck::cast_slice_mut::<T, u8>(&mut result).copy_from_slice(&staging_buffer_slice.get_mapped_range());
+    }
+    staging_buffer.unmap();
+
+    result
+}
+
+// Copies an entire single mip 3D texture (needs COPY_SRC usage) back to the cpu.
+// Returns tightly packed texels, x varying fastest, then y, then z.
+pub fn read_texture_3d(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, size: wgpu::Extent3d, bytes_per_texel: u32) -> Vec<u8> {
+    let bytes_per_row = size.width * bytes_per_texel;
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = (bytes_per_row + alignment - 1) / alignment * alignment;
+    let num_rows = (size.height * size.depth) as usize;
+
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Buffer: Readback staging"),
+        size: padded_bytes_per_row as u64 * num_rows as u64,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Encoder: Texture readback"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::TextureCopyView {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::BufferCopyView {
+            buffer: &staging_buffer,
+            layout: wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: padded_bytes_per_row,
+                rows_per_image: size.height,
+            },
+        },
+        size,
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let mut result = Vec::with_capacity(bytes_per_row as usize * num_rows);
+    {
+        let staging_buffer_slice = staging_buffer.slice(..);
+        let mapping = staging_buffer_slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).unwrap();
+        for padded_row in staging_buffer_slice.get_mapped_range().chunks(padded_bytes_per_row as usize) {
+            result.extend_from_slice(&padded_row[..bytes_per_row as usize]);
+        }
+    }
+    staging_buffer.unmap();
+
+    result
+}